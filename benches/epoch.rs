@@ -0,0 +1,158 @@
+//! Benchmarks of pinning, deferral, and collection, including contended scenarios.
+//!
+//! Run with `cargo bench`. Arguments select the benchmarks whose name matches, e.g.
+//! `cargo bench --bench epoch -- collect`. To compare a change against a baseline, record the
+//! results before the change with `cargo bench --bench epoch -- --save-baseline NAME`, and then
+//! run `cargo bench --bench epoch -- --baseline NAME` after it.
+
+#[macro_use]
+extern crate criterion;
+extern crate crossbeam_epoch as epoch;
+extern crate crossbeam_utils;
+
+use std::sync::{Arc, Barrier, Mutex};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{BenchmarkId, Criterion};
+use crossbeam_utils::scoped;
+use epoch::Owned;
+
+/// Numbers of threads in contended benchmarks.
+const THREADS: &[usize] = &[1, 8, 64];
+
+/// Times `iters` calls of `f`, split evenly across `threads` threads starting at the same time.
+fn repeat_on<F: Fn() + Sync>(threads: usize, iters: u64, f: F) -> Duration {
+    let per_thread = iters.div_ceil(threads as u64);
+    let barrier = Barrier::new(threads + 1);
+    let f = &f;
+
+    scoped::scope(|s| {
+        let handles = (0..threads)
+            .map(|_| {
+                let barrier = &barrier;
+                s.spawn(move || {
+                    barrier.wait();
+                    let start = Instant::now();
+                    for _ in 0..per_thread {
+                        f();
+                    }
+                    start.elapsed()
+                })
+            })
+            .collect::<Vec<_>>();
+        barrier.wait();
+        handles.into_iter().map(|h| h.join()).max().unwrap()
+    })
+}
+
+/// Runs `f` with `count` other threads registered as mutators, which stay unpinned.
+fn with_participants<F: FnOnce()>(count: usize, f: F) {
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let done_rx = Arc::new(Mutex::new(done_rx));
+    let barrier = Arc::new(Barrier::new(count + 1));
+
+    let threads = (0..count)
+        .map(|_| {
+            let done_rx = done_rx.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                epoch::pin(|_| ());
+                barrier.wait();
+                let _ = done_rx.lock().unwrap().recv();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    barrier.wait();
+    f();
+    drop(done_tx);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+fn pin(c: &mut Criterion) {
+    c.bench_function("pin", |b| b.iter(|| epoch::pin(|_| ())));
+    c.bench_function("pin_nested", |b| epoch::pin(|_| b.iter(|| epoch::pin(|_| ()))));
+}
+
+fn defer(c: &mut Criterion) {
+    // `defer_drop` stores the object in the bag as is, while `defer` boxes the closure.
+    c.bench_function("defer_inline", |b| {
+        b.iter(|| epoch::pin(|scope| unsafe {
+            scope.defer_drop(Owned::new(1u64).into_ptr(scope))
+        }))
+    });
+    c.bench_function("defer_boxed", |b| {
+        b.iter(|| epoch::pin(|scope| unsafe {
+            let p = Box::new(1u64);
+            scope.defer(move || drop(p))
+        }))
+    });
+
+    // A batch moves its objects into the bag a bagful at a time, instead of one by one. Freed
+    // objects aren't coalesced, so every one of them takes a place in the bag.
+    c.bench_function("defer_each/64", |b| {
+        b.iter(|| epoch::pin(|scope| unsafe {
+            for _ in 0..64 {
+                scope.defer_free(Owned::new(1u64).into_ptr(scope));
+            }
+        }))
+    });
+    c.bench_function("defer_batch/64", |b| {
+        b.iter(|| epoch::pin(|scope| unsafe {
+            let mut batch = scope.defer_batch();
+            for _ in 0..64 {
+                batch.defer_free(Owned::new(1u64).into_ptr(scope));
+            }
+        }))
+    });
+
+    c.bench_function("flush", |b| b.iter(|| epoch::pin(|scope| scope.flush())));
+}
+
+fn contended(c: &mut Criterion) {
+    // Advancing the epoch walks the list of all registered mutators.
+    let mut group = c.benchmark_group("advance");
+    for &n in THREADS {
+        with_participants(n, || {
+            group.bench_function(BenchmarkId::from_parameter(n), |b| {
+                b.iter(|| epoch::pin(|scope| scope.flush()))
+            });
+        });
+    }
+    group.finish();
+
+    // Every iteration defers an object on one of the threads, which collect each other's garbage.
+    let mut group = c.benchmark_group("collect");
+    for &n in THREADS {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter_custom(|iters| {
+                repeat_on(n, iters, || epoch::pin(|scope| unsafe {
+                    scope.defer_drop(Owned::new(1u64).into_ptr(scope))
+                }))
+            })
+        });
+    }
+    group.finish();
+
+    // Every iteration pushes a bag onto the global garbage queues, which are sharded so that
+    // threads flushing at the same time mostly don't contend on the same queue.
+    let mut group = c.benchmark_group("retire_flush");
+    for &n in THREADS {
+        group.bench_function(BenchmarkId::from_parameter(n), |b| {
+            b.iter_custom(|iters| {
+                repeat_on(n, iters, || epoch::pin(|scope| unsafe {
+                    scope.defer_drop(Owned::new(1u64).into_ptr(scope));
+                    scope.flush();
+                }))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pin, defer, contended);
+criterion_main!(benches);
@@ -0,0 +1,308 @@
+//! A soak test of the collector under a configurable mix of work.
+//!
+//! Run with `cargo run --release --example stress -- [OPTION=VALUE]...`, e.g.
+//! `cargo run --release --features strict --example stress -- --secs=3600` for an hour with every
+//! runtime check enabled. The options, with their defaults:
+//!
+//! - `--readers=4`: threads that keep loading and validating nodes,
+//! - `--writers=4`: threads that keep replacing nodes and retiring the old ones,
+//! - `--churn=2`: threads that keep spawning short-lived threads, which register, replace a few
+//!   nodes, and exit,
+//! - `--slots=1024`: number of atomic pointers the nodes are stored in,
+//! - `--clear-ms=500`: interval between bulk clears of all slots, or 0 for none,
+//! - `--secs=10`: how long to run,
+//! - `--report-secs=1`: interval between reports of the collector's stats.
+//!
+//! Every node carries a magic number and a checksum of its value, and poisons them when dropped,
+//! so a reader that gets to see a destroyed node fails validation. Once all threads are done and
+//! the collector is dropped, every node created must have been dropped exactly once. Any broken
+//! invariant aborts the run with a nonzero exit code.
+
+extern crate crossbeam_epoch as epoch;
+
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, SeqCst};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use epoch::{Atomic, Collector, LocalHandle, Owned, Ptr, Scope};
+
+/// The magic number of a live node.
+const MAGIC: u64 = 0x5eed_cafe_f00d_b0a7;
+
+/// What a dropped node's magic number is overwritten with.
+const POISON: u64 = 0xdead_dead_dead_dead;
+
+/// Number of nodes created so far.
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of nodes dropped so far.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// A node stored in a slot.
+struct Node {
+    magic: u64,
+    value: u64,
+    check: u64,
+}
+
+impl Node {
+    fn new(value: u64) -> Self {
+        CREATED.fetch_add(1, Relaxed);
+        Node {
+            magic: MAGIC,
+            value,
+            check: !value,
+        }
+    }
+
+    /// Panics unless the node is live and intact.
+    fn validate(&self) {
+        assert_eq!(self.magic, MAGIC, "read a destroyed node");
+        assert_eq!(self.check, !self.value, "read a corrupted node");
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.validate();
+        // Volatile, so that the poisoning isn't optimized away as a dead store.
+        unsafe {
+            (&mut self.magic as *mut u64).write_volatile(POISON);
+            (&mut self.check as *mut u64).write_volatile(self.value);
+        }
+        DROPPED.fetch_add(1, Relaxed);
+    }
+}
+
+/// The mix of work to run.
+#[derive(Debug)]
+struct Config {
+    readers: usize,
+    writers: usize,
+    churn: usize,
+    slots: usize,
+    clear_ms: u64,
+    secs: u64,
+    report_secs: u64,
+}
+
+impl Config {
+    /// Parses the options, or exits with a usage message.
+    fn from_args() -> Self {
+        let mut config = Config {
+            readers: 4,
+            writers: 4,
+            churn: 2,
+            slots: 1024,
+            clear_ms: 500,
+            secs: 10,
+            report_secs: 1,
+        };
+
+        for arg in env::args().skip(1) {
+            let (name, value) = match arg.strip_prefix("--").and_then(|a| a.split_once('=')) {
+                Some((name, value)) => (name, value.parse::<u64>().ok()),
+                None => usage(&arg),
+            };
+            let value = value.unwrap_or_else(|| usage(&arg));
+            match name {
+                "readers" => config.readers = value as usize,
+                "writers" => config.writers = value as usize,
+                "churn" => config.churn = value as usize,
+                "slots" => config.slots = value as usize,
+                "clear-ms" => config.clear_ms = value,
+                "secs" => config.secs = value,
+                "report-secs" => config.report_secs = value,
+                _ => usage(&arg),
+            }
+        }
+        config.slots = config.slots.max(1);
+        config.report_secs = config.report_secs.max(1);
+        config
+    }
+}
+
+/// Exits after complaining about the invalid option `arg`.
+fn usage(arg: &str) -> ! {
+    eprintln!("invalid option `{}`; see the documentation of examples/stress.rs", arg);
+    process::exit(2);
+}
+
+/// A xorshift pseudorandom number generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: usize) -> Self {
+        Rng((seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// State shared by all threads.
+struct Shared {
+    collector: Collector,
+    slots: Vec<Atomic<Node>>,
+    stop: AtomicBool,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl Shared {
+    /// Replaces the node in a random slot with a new one.
+    fn write(&self, handle: &LocalHandle, rng: &mut Rng) {
+        let slot = &self.slots[rng.below(self.slots.len())];
+        handle.pin(|scope| unsafe {
+            let new = Owned::new(Node::new(rng.next())).into_ptr(scope);
+            retire(scope, slot.swap(new, AcqRel, scope));
+        });
+        self.writes.fetch_add(1, Relaxed);
+    }
+
+    /// Validates the nodes in a few random slots.
+    fn read(&self, handle: &LocalHandle, rng: &mut Rng) {
+        handle.pin(|scope| {
+            for _ in 0..16 {
+                let slot = &self.slots[rng.below(self.slots.len())];
+                if let Some(node) = unsafe { slot.load(Acquire, scope).as_ref() } {
+                    node.validate();
+                }
+            }
+        });
+        self.reads.fetch_add(16, Relaxed);
+    }
+
+    /// Retires the nodes in all slots.
+    fn clear(&self, handle: &LocalHandle) {
+        handle.pin(|scope| {
+            for slot in &self.slots {
+                unsafe { retire(scope, slot.swap(Ptr::null(), AcqRel, scope)) };
+            }
+        });
+    }
+}
+
+/// Defers the destruction of the node `ptr` points to, unless it's null.
+unsafe fn retire(scope: &Scope, ptr: Ptr<Node>) {
+    if !ptr.is_null() {
+        scope.defer_drop(ptr);
+    }
+}
+
+fn main() {
+    let config = Config::from_args();
+    println!("{:?}", config);
+
+    let shared = Arc::new(Shared {
+        collector: Collector::new(),
+        slots: (0..config.slots).map(|_| Atomic::null()).collect(),
+        stop: AtomicBool::new(false),
+        reads: AtomicUsize::new(0),
+        writes: AtomicUsize::new(0),
+    });
+
+    let mut threads = Vec::new();
+    let mut spawn = |f: Box<dyn FnOnce(Arc<Shared>, Rng) + Send>| {
+        let shared = shared.clone();
+        let rng = Rng::new(threads.len() + 1);
+        threads.push(thread::spawn(move || f(shared, rng)));
+    };
+
+    for _ in 0..config.readers {
+        spawn(Box::new(|shared, mut rng| {
+            let handle = shared.collector.register();
+            while !shared.stop.load(Relaxed) {
+                shared.read(&handle, &mut rng);
+            }
+        }));
+    }
+    for _ in 0..config.writers {
+        spawn(Box::new(|shared, mut rng| {
+            let handle = shared.collector.register();
+            while !shared.stop.load(Relaxed) {
+                shared.write(&handle, &mut rng);
+            }
+        }));
+    }
+    for _ in 0..config.churn {
+        spawn(Box::new(|shared, mut rng| {
+            while !shared.stop.load(Relaxed) {
+                let s = shared.clone();
+                let mut rng = Rng::new(rng.next() as usize);
+                thread::spawn(move || {
+                    let handle = s.collector.register();
+                    for _ in 0..rng.below(64) {
+                        s.write(&handle, &mut rng);
+                    }
+                    s.read(&handle, &mut rng);
+                }).join()
+                    .unwrap();
+            }
+        }));
+    }
+    if config.clear_ms > 0 {
+        let interval = Duration::from_millis(config.clear_ms);
+        spawn(Box::new(move |shared, _| {
+            let handle = shared.collector.register();
+            while !shared.stop.load(Relaxed) {
+                thread::sleep(interval);
+                shared.clear(&handle);
+            }
+        }));
+    }
+
+    let start = Instant::now();
+    let end = start + Duration::from_secs(config.secs);
+    let mut failed = false;
+    while Instant::now() < end {
+        thread::sleep(Duration::from_secs(config.report_secs).min(end - Instant::now()));
+        println!(
+            "{:>6}s reads={} writes={} created={} dropped={} {:?}",
+            start.elapsed().as_secs(),
+            shared.reads.load(Relaxed),
+            shared.writes.load(Relaxed),
+            CREATED.load(Relaxed),
+            DROPPED.load(Relaxed),
+            shared.collector.stats(),
+        );
+        if threads.iter().any(|t| t.is_finished()) {
+            failed = true;
+            break;
+        }
+    }
+
+    shared.stop.store(true, SeqCst);
+    for t in threads {
+        failed |= t.join().is_err();
+    }
+
+    // All threads have been joined, so this drops the last reference to the collector, which
+    // destroys all remaining garbage.
+    shared.clear(&shared.collector.register());
+    drop(shared);
+
+    let (created, dropped) = (CREATED.load(SeqCst), DROPPED.load(SeqCst));
+    println!("created={} dropped={}", created, dropped);
+    if created != dropped {
+        eprintln!("{} nodes were leaked", created as isize - dropped as isize);
+        failed = true;
+    }
+    if failed {
+        eprintln!("FAILED");
+        process::exit(1);
+    }
+    println!("OK");
+}
@@ -0,0 +1,141 @@
+//! Drives random sequences of pinnings, retirements, and protocol steps over several simulated
+//! mutators of a collector, all on the fuzzing thread, and checks that no object is destroyed
+//! while a mutator that loaded it is still pinned.
+//!
+//! Run with `cargo fuzz run collector` from the root of the repository.
+
+#![no_main]
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering::{AcqRel, Acquire};
+
+use crossbeam_epoch::{
+    fuzzing, Atomic, Collector, CollectorConfig, Owned, PinnedScope, Ptr, Scope,
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Number of simulated mutators.
+const MUTATORS: usize = 4;
+
+/// Number of shared slots the mutators load from and retire objects out of.
+const SLOTS: usize = 4;
+
+thread_local! {
+    /// The ids of the payloads destroyed so far.
+    static DESTROYED: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+}
+
+/// An object that records its destruction.
+struct Payload(u64);
+
+impl Drop for Payload {
+    fn drop(&mut self) {
+        DESTROYED.with(|d| d.borrow_mut().insert(self.0));
+    }
+}
+
+/// A simulated mutator.
+#[derive(Default)]
+struct Mutator {
+    /// The pinning, and the epoch it started in.
+    pinned: Option<(PinnedScope, usize)>,
+    /// The ids of the payloads loaded during the pinning.
+    held: Vec<u64>,
+}
+
+impl Mutator {
+    fn scope(&self) -> Option<&Scope> {
+        self.pinned.as_ref().map(|(p, _)| p.scope())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    DESTROYED.with(|d| d.borrow_mut().clear());
+
+    // Small bags make sealing and collection frequent.
+    let mut config = CollectorConfig::default();
+    config.bag_capacity = 4;
+    let collector = Collector::with_config(config);
+    let driver = collector.register();
+    let slots = (0..SLOTS).map(|_| Atomic::<Payload>::null()).collect::<Vec<_>>();
+    let mut mutators = (0..MUTATORS).map(|_| Mutator::default()).collect::<Vec<_>>();
+    let mut next_id = 0;
+
+    for &byte in data {
+        let m = &mut mutators[(byte as usize >> 3) % MUTATORS];
+        let slot = &slots[(byte as usize >> 5) % SLOTS];
+
+        match byte % 8 {
+            0 => {
+                if m.pinned.is_none() {
+                    let pinned = collector.pin_owned();
+                    m.pinned = Some((pinned, collector.epoch()));
+                }
+            }
+            1 => {
+                m.held.clear();
+                m.pinned = None;
+            }
+            2 => {
+                if let Some(scope) = m.scope() {
+                    let p = slot.load(Acquire, scope);
+                    if let Some(payload) = unsafe { p.as_ref() } {
+                        let id = payload.0;
+                        m.held.push(id);
+                    }
+                }
+            }
+            3 => {
+                if let Some(scope) = m.scope() {
+                    next_id += 1;
+                    let new = Owned::new(Payload(next_id)).into_ptr(scope);
+                    let old = slot.swap(new, AcqRel, scope);
+                    if !old.is_null() {
+                        unsafe { scope.defer_drop(old) }
+                    }
+                }
+            }
+            4 => {
+                if let Some(scope) = m.scope() {
+                    fuzzing::seal_bag_now(scope);
+                }
+            }
+            5 => {
+                let _ = match m.scope() {
+                    Some(scope) => fuzzing::advance_step(scope),
+                    None => driver.pin(fuzzing::advance_step),
+                };
+            }
+            6 => {
+                if let Some(scope) = m.scope() {
+                    scope.flush();
+                }
+            }
+            _ => driver.pin(|scope| scope.flush()),
+        }
+
+        // A pinned mutator holds the epoch back, so garbage retired while it's pinned can't
+        // expire, and nothing it loaded gets destroyed.
+        let epoch = collector.epoch();
+        for m in &mutators {
+            if let Some((_, pinned_in)) = m.pinned {
+                assert!(!fuzzing::expire_check(pinned_in, epoch), "the epoch ran ahead of a pin");
+            }
+            for id in &m.held {
+                let destroyed = DESTROYED.with(|d| d.borrow().contains(id));
+                assert!(!destroyed, "payload {} destroyed while a pinned mutator holds it", id);
+            }
+        }
+    }
+
+    drop(mutators);
+    driver.pin(|scope| {
+        for slot in &slots {
+            let p = slot.swap(Ptr::null(), AcqRel, scope);
+            if !p.is_null() {
+                unsafe { scope.defer_drop(p) }
+            }
+        }
+    });
+});
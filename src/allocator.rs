@@ -0,0 +1,275 @@
+//! Objects and collectors backed by custom allocators
+//!
+//! Objects created with [`Owned::new`] live on the global heap, and are freed there once
+//! reclaimed. An arena or a pool can't be plugged in that way, since by the time an object gets
+//! destroyed, only its pointer is known. [`Owned::new_in`] instead allocates an [`Allocated`]
+//! object from the given [`GlobalAlloc`], and keeps a reference to the allocator in front of the
+//! value, so that whichever thread ends up destroying the object frees it with the same
+//! allocator.
+//!
+//! A [`Collector`] created with [`Collector::with_allocator`] likewise allocates the nodes of its
+//! garbage queues, which hold the bags of garbage waiting to be reclaimed, from the given
+//! allocator. The local bag of each mutator is still allocated on the global heap.
+//!
+//! [`Owned::new`]: struct.Owned.html#method.new
+//! [`Owned::new_in`]: struct.Owned.html#method.new_in
+//! [`Allocated`]: struct.Allocated.html
+//! [`GlobalAlloc`]: https://doc.rust-lang.org/std/alloc/trait.GlobalAlloc.html
+//! [`Collector`]: struct.Collector.html
+//! [`Collector::with_allocator`]: struct.Collector.html#method.with_allocator
+
+use std::alloc::{self, GlobalAlloc, Layout};
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+
+use atomic::Owned;
+use raw::Pointable;
+
+/// A value allocated from a custom allocator.
+///
+/// Created with [`Owned::new_in`], and retired with [`Scope::defer_destroy`], which frees it with
+/// the allocator it came from. It dereferences to the value.
+///
+/// The type is unsized only so that it can be allocated differently from other objects; its size
+/// is always that of the value plus a reference to the allocator.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Allocated, Atomic, Owned, Ptr};
+/// use std::alloc::System;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// static ARENA: System = System;
+///
+/// let a = Atomic::from_owned(Owned::new_in(String::from("old"), &ARENA));
+///
+/// epoch::pin(|scope| {
+///     let new = Owned::new_in(String::from("new"), &ARENA).into_ptr(scope);
+///     let old = a.swap(new, SeqCst, scope);
+///     unsafe {
+///         assert_eq!(**old.deref(), "old");
+///         scope.defer_destroy(old);
+///     }
+/// });
+/// # epoch::pin(|scope| unsafe { scope.defer_destroy(a.swap(Ptr::null(), SeqCst, scope)) });
+/// ```
+///
+/// [`Owned::new_in`]: struct.Owned.html#method.new_in
+/// [`Scope::defer_destroy`]: struct.Scope.html#method.defer_destroy
+#[repr(C)]
+pub struct Allocated<T, A: ?Sized + 'static> {
+    alloc: &'static A,
+    value: T,
+    /// Makes the type unsized, so that it can implement `Pointable` on its own.
+    _unsized: [()],
+}
+
+/// The allocation behind a pointer to `Allocated<T, A>`, which has the same layout.
+#[repr(C)]
+struct Allocation<T, A: ?Sized + 'static> {
+    alloc: &'static A,
+    value: T,
+}
+
+impl<T, A: ?Sized + 'static> Allocated<T, A> {
+    /// Returns the allocator the object was allocated from.
+    pub fn allocator(&self) -> &'static A {
+        self.alloc
+    }
+}
+
+impl<T, A: ?Sized + 'static> Deref for Allocated<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, A: ?Sized + 'static> DerefMut for Allocated<T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Debug, A: ?Sized + 'static> fmt::Debug for Allocated<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Allocated")
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+unsafe impl<T, A: ?Sized + GlobalAlloc + 'static> Pointable for Allocated<T, A> {
+    const ALIGN: usize = mem::align_of::<Allocation<T, A>>();
+
+    /// The value, and the allocator to allocate it from.
+    type Init = (T, &'static A);
+
+    unsafe fn init((value, alloc): Self::Init) -> *mut () {
+        let layout = Layout::new::<Allocation<T, A>>();
+        let raw = alloc.alloc(layout) as *mut Allocation<T, A>;
+        if raw.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        raw.write(Allocation { alloc, value });
+        raw as *mut ()
+    }
+
+    unsafe fn deref<'a>(ptr: *mut ()) -> &'a Self {
+        &*(ptr::slice_from_raw_parts(ptr as *const (), 0) as *const Self)
+    }
+
+    unsafe fn deref_mut<'a>(ptr: *mut ()) -> &'a mut Self {
+        &mut *(ptr::slice_from_raw_parts_mut(ptr, 0) as *mut Self)
+    }
+
+    unsafe fn drop(ptr: *mut ()) {
+        let raw = ptr as *mut Allocation<T, A>;
+        let alloc = (*raw).alloc;
+        ptr::drop_in_place(raw);
+        alloc.dealloc(ptr as *mut u8, Layout::new::<Allocation<T, A>>());
+    }
+}
+
+impl<T, A: ?Sized + GlobalAlloc + 'static> Owned<Allocated<T, A>> {
+    /// Allocates `value` from `alloc` and returns a new owned pointer pointing to it.
+    ///
+    /// The object is freed with `alloc` when the owned pointer is dropped, or when it's reclaimed
+    /// after being retired with [`Scope::defer_destroy`]. See [`Allocated`] for an example.
+    ///
+    /// # Panics
+    ///
+    /// Aborts if `alloc` fails to allocate, like `Box::new` does.
+    ///
+    /// [`Scope::defer_destroy`]: struct.Scope.html#method.defer_destroy
+    /// [`Allocated`]: struct.Allocated.html
+    pub fn new_in(value: T, alloc: &'static A) -> Self {
+        Self::init((value, alloc))
+    }
+}
+
+/// The allocator of the internal structures of a collector: either the global one, or a custom
+/// one passed to `Collector::with_allocator`.
+#[derive(Clone, Copy)]
+pub(crate) struct AllocRef(Option<&'static (dyn GlobalAlloc + Sync)>);
+
+impl AllocRef {
+    /// Returns the global allocator.
+    pub fn global() -> Self {
+        AllocRef(None)
+    }
+
+    /// Returns the custom allocator `alloc`.
+    pub fn custom(alloc: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        AllocRef(Some(alloc))
+    }
+
+    /// Returns `true` if this is the global allocator.
+    pub fn is_global(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Allocates memory with `layout`, returning null on failure.
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.0 {
+            None => alloc::alloc(layout),
+            Some(alloc) => alloc.alloc(layout),
+        }
+    }
+
+    /// Frees memory allocated with `alloc` and `layout`.
+    pub unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.0 {
+            None => alloc::dealloc(ptr, layout),
+            Some(alloc) => alloc.dealloc(ptr, layout),
+        }
+    }
+}
+
+impl fmt::Debug for AllocRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("Global"),
+            Some(alloc) => write!(f, "Custom({:p})", alloc as *const _ as *const u8),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use {Collector, Owned};
+    use testkit::Drops;
+
+    /// An allocator that counts what it allocates and frees.
+    struct Counting {
+        allocs: AtomicUsize,
+        deallocs: AtomicUsize,
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.allocs.fetch_add(1, SeqCst);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.deallocs.fetch_add(1, SeqCst);
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn objects_are_freed_with_their_allocator() {
+        static OBJECTS: Counting = Counting {
+            allocs: AtomicUsize::new(0),
+            deallocs: AtomicUsize::new(0),
+        };
+
+        let collector = Collector::new();
+        let drops = Drops::new();
+        let handle = collector.register();
+
+        drop(Owned::new_in(String::from("dropped"), &OBJECTS));
+        assert_eq!(OBJECTS.deallocs.load(SeqCst), 1);
+
+        handle.pin(|scope| unsafe {
+            let o = Owned::new_in(drops.wrap(vec![1, 2, 3]), &OBJECTS).into_ptr(scope);
+            assert_eq!(o.deref().len(), 3);
+            scope.defer_destroy(o);
+        });
+        ::assert_reclaimed!(drops, 1, in: handle);
+        assert_eq!(OBJECTS.allocs.load(SeqCst), 2);
+        assert_eq!(OBJECTS.deallocs.load(SeqCst), 2);
+    }
+
+    #[test]
+    fn collector_allocates_queue_nodes() {
+        static NODES: Counting = Counting {
+            allocs: AtomicUsize::new(0),
+            deallocs: AtomicUsize::new(0),
+        };
+
+        let collector = Collector::with_allocator(Default::default(), &NODES);
+        let handle = collector.register();
+        for _ in 0..16 {
+            handle.pin(|scope| {
+                unsafe { scope.defer_drop(Owned::new(0).into_ptr(scope)) };
+                scope.flush();
+            });
+        }
+        assert!(NODES.allocs.load(SeqCst) > 0);
+        assert!(NODES.deallocs.load(SeqCst) > 0);
+
+        drop(handle);
+        drop(collector);
+        assert_eq!(NODES.allocs.load(SeqCst), NODES.deallocs.load(SeqCst));
+    }
+}
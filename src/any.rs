@@ -0,0 +1,180 @@
+use std::sync::atomic::Ordering;
+
+use atomic::{Atomic, Owned, Ptr};
+use raw::CompareAndSetOrdering;
+use debug;
+use global::unprotected;
+use mutator::Scope;
+
+/// An atomic pointer to an object of any type.
+///
+/// This is useful for heterogeneous data structures, e.g. tries whose nodes are either internal
+/// nodes or leaves. Loading the pointer yields an [`AnyPtr`], which must be downcast to the right
+/// type before it can be dereferenced. The type is not stored in release builds, so the right type
+/// has to be known from elsewhere, but in debug builds downcasting to the wrong type panics. The
+/// type is then kept as a fingerprint in the high tag bits of the pointer, so the check misses one
+/// in 255 wrong types, and all of them on targets without high tag bits (see [`HIGH_TAG_BITS`]).
+///
+/// Unlike [`Atomic`], pointers stored into `AtomicAny` can't carry tags.
+///
+/// [`AnyPtr`]: struct.AnyPtr.html
+/// [`Atomic`]: struct.Atomic.html
+/// [`HIGH_TAG_BITS`]: constant.HIGH_TAG_BITS.html
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AtomicAny};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = AtomicAny::new(7u64);
+///
+/// epoch::pin(|scope| unsafe {
+///     let p = a.load(SeqCst, scope).downcast::<u64>();
+///     assert_eq!(*p.deref(), 7);
+///     scope.defer_drop(p);
+/// });
+/// ```
+#[derive(Debug)]
+pub struct AtomicAny {
+    inner: Atomic<u8, true>,
+}
+
+/// A pointer to an object of any type, loaded from an [`AtomicAny`].
+///
+/// [`AtomicAny`]: struct.AtomicAny.html
+#[derive(Clone, Copy, Debug)]
+pub struct AnyPtr<'scope> {
+    ptr: Ptr<'scope, u8, true>,
+}
+
+impl<'scope> AnyPtr<'scope> {
+    /// Returns a new null pointer.
+    pub fn null() -> Self {
+        AnyPtr { ptr: Ptr::default() }
+    }
+
+    /// Returns `true` if the pointer is null.
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    /// Returns the raw pointer to the object.
+    pub fn as_raw(&self) -> *const u8 {
+        self.ptr.as_raw()
+    }
+
+    /// Converts the pointer to a pointer to `T`.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the object is not of type `T`.
+    pub fn downcast<T: 'static>(&self) -> Ptr<'scope, T> {
+        let ptr = unsafe { self.ptr.with_tag(0).with_low_tags().cast_unchecked::<T>() };
+        debug::check_type(ptr.as_raw(), self.ptr.tag());
+        ptr
+    }
+}
+
+/// Erases the type of `ptr`, tagging it with the fingerprint of `T` in debug builds.
+fn erase<T: 'static>(ptr: Ptr<T>) -> Ptr<u8, true> {
+    let erased = ptr.cast::<u8>().with_high_tags();
+    if erased.is_null() {
+        erased
+    } else {
+        erased.with_tag(debug::type_tag::<T>())
+    }
+}
+
+impl AtomicAny {
+    /// Returns a new null atomic pointer.
+    pub fn null() -> Self {
+        AtomicAny { inner: Atomic::default() }
+    }
+
+    /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
+    pub fn new<T: 'static>(value: T) -> Self {
+        Self::from_owned(Owned::new(value))
+    }
+
+    /// Returns a new atomic pointer pointing to `owned`.
+    pub fn from_owned<T: 'static>(owned: Owned<T>) -> Self {
+        let a = Self::null();
+        a.store_owned(owned, Ordering::Relaxed);
+        a
+    }
+
+    /// Loads an `AnyPtr` from the atomic pointer.
+    pub fn load<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> AnyPtr<'scope> {
+        AnyPtr { ptr: self.inner.load(ord, scope) }
+    }
+
+    /// Stores `new` into the atomic pointer.
+    pub fn store<T: 'static>(&self, new: Ptr<T>, ord: Ordering) {
+        self.inner.store(erase(new), ord);
+    }
+
+    /// Stores `new` into the atomic pointer.
+    pub fn store_owned<T: 'static>(&self, new: Owned<T>, ord: Ordering) {
+        // Converting to a pointer doesn't touch the object, so any scope will do.
+        unsafe { unprotected(|scope| self.store(new.into_ptr(scope), ord)) }
+    }
+
+    /// Stores `new` into the atomic pointer, returning the previous value.
+    pub fn swap<'scope, T: 'static>(
+        &self,
+        new: Ptr<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> AnyPtr<'scope> {
+        AnyPtr { ptr: self.inner.swap(erase(new), ord, scope) }
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new pointer was written. On failure the
+    /// actual current value is returned.
+    pub fn compare_and_set<'scope, T: 'static, O: CompareAndSetOrdering>(
+        &self,
+        current: AnyPtr,
+        new: Ptr<T>,
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<(), AnyPtr<'scope>> {
+        self.inner
+            .compare_and_set(current.ptr, erase(new), ord, scope)
+            .map_err(|ptr| AnyPtr { ptr })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use pin;
+    use super::*;
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "to the wrong type")]
+    fn downcast_to_wrong_type() {
+        let a = AtomicAny::new(7u64);
+        pin(|scope| {
+            a.load(SeqCst, scope).downcast::<u32>();
+        });
+    }
+
+    #[test]
+    fn swap_types() {
+        let a = AtomicAny::new(7u64);
+        pin(|scope| unsafe {
+            let old = a.swap(Owned::new("leaf").into_ptr(scope), SeqCst, scope);
+            scope.defer_drop(old.downcast::<u64>());
+
+            let leaf = a.load(SeqCst, scope).downcast::<&'static str>();
+            assert_eq!(*leaf.deref(), "leaf");
+            scope.defer_drop(leaf);
+        });
+    }
+}
@@ -1,88 +1,29 @@
+use std::alloc::{self, Layout};
 use std::borrow::{Borrow, BorrowMut};
 use std::marker::PhantomData;
+use std::error::Error;
+use std::fmt;
 use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
+use std::panic::Location;
+use std::ptr;
+use primitive::atomic::{fence, AtomicUsize};
+use primitive::atomic::Ordering;
 
 use mutator::Scope;
-
-/// Given ordering for the success case in a compare-exchange operation, returns the strongest
-/// appropriate ordering for the failure case.
-#[inline]
-fn strongest_failure_ordering(ord: Ordering) -> Ordering {
-    use self::Ordering::*;
-    match ord {
-        Relaxed | Release => Relaxed,
-        Acquire | AcqRel => Acquire,
-        _ => SeqCst,
-    }
-}
-
-/// Memory orderings for compare-and-set operations.
-///
-/// A compare-and-set operation can have different memory orderings depending on whether it
-/// succeeds or fails. This trait generalizes different ways of specifying memory orderings.
-///
-/// The two ways of specifying orderings for compare-and-set are:
-///
-/// 1. Just one `Ordering` for the success case. In case of failure, the strongest appropriate
-///    ordering is chosen.
-/// 2. A pair of `Ordering`s. The first one is for the success case, while the second one is
-///    for the failure case.
-pub trait CompareAndSetOrdering {
-    /// The ordering of the operation when it succeeds.
-    fn success(&self) -> Ordering;
-
-    /// The ordering of the operation when it fails.
-    ///
-    /// The failure ordering can't be `Release` or `AcqRel` and must be equivalent or weaker than
-    /// the success ordering.
-    fn failure(&self) -> Ordering;
-}
-
-impl CompareAndSetOrdering for Ordering {
-    #[inline]
-    fn success(&self) -> Ordering {
-        *self
-    }
-
-    #[inline]
-    fn failure(&self) -> Ordering {
-        strongest_failure_ordering(*self)
-    }
-}
-
-impl CompareAndSetOrdering for (Ordering, Ordering) {
-    #[inline]
-    fn success(&self) -> Ordering {
-        self.0
-    }
-
-    #[inline]
-    fn failure(&self) -> Ordering {
-        self.1
-    }
-}
-
-/// Panics if the pointer is not properly unaligned.
-#[inline]
-fn ensure_aligned<T>(raw: *const T) {
-    assert_eq!(raw as usize & low_bits::<T>(), 0, "unaligned pointer");
-}
-
-/// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
-#[inline]
-fn low_bits<T>() -> usize {
-    (1 << mem::align_of::<T>().trailing_zeros()) - 1
-}
-
-/// Given a tagged pointer `data`, returns the same pointer, but tagged with `tag`.  `tag` is
-/// truncated to be fit into the unused bits of the pointer to `T`.
-#[inline]
-fn data_with_tag<T>(data: usize, tag: usize) -> usize {
-    (data & !low_bits::<T>()) | (tag & low_bits::<T>())
-}
+use epoch_safe::EpochSafe;
+use project::EpochNode;
+use prefetch;
+use debug;
+use misuse::{self, MisuseCheck};
+use raw::{CompareAndSetOrdering, Pointable, check_tag, data_address, data_retagged, data_tag,
+          data_with_tag, ensure_aligned, low_bits, tag_layout, validate};
+#[cfg(feature = "stale_ptr_check")]
+use debug::Generation;
+#[cfg(feature = "store_tracking")]
+use debug::StoreSite;
+#[cfg(feature = "profiler")]
+use profiler;
 
 /// An atomic pointer that can be safely shared between threads.
 ///
@@ -92,25 +33,75 @@ fn data_with_tag<T>(data: usize, tag: usize) -> usize {
 ///
 /// Any method that loads the pointer must be passed a reference to a [`Scope`].
 ///
+/// In debug builds every value loaded from or stored into the atomic pointer is checked to be a
+/// plausible tagged pointer, and a panic with the address of the slot is raised otherwise.
+///
+/// # High tags
+///
+/// Byte-aligned objects have no unused low bits at all. With `HIGH_TAG` set to `true`, the tag is
+/// stored above the address instead, giving [`HIGH_TAG_BITS`] bits of tag regardless of the
+/// alignment of `T`. Pointers of this kind are obtained with [`Owned::with_high_tags`] and
+/// [`Ptr::with_high_tags`], and every [`Owned`] and [`Ptr`] stored into the atomic must use the
+/// same layout.
+///
+/// Addresses above 2<sup>48</sup>, which only occur with 5-level paging when asked for explicitly,
+/// can't be stored with high tags. Intel LAM_U48 reuses the same bits, so high tags must not be
+/// used for pointers handed out with such metadata.
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::from_owned(Owned::new(7u8).with_high_tags().with_tag(200));
+/// epoch::pin(|scope| {
+///     let p = a.load(SeqCst, scope);
+///     # if epoch::HIGH_TAG_BITS > 0 {
+///     assert_eq!(p.tag(), 200);
+///     # }
+///     assert_eq!(unsafe { *p.deref() }, 7);
+///     # unsafe { scope.defer_drop(p) }
+/// });
+/// ```
+///
 /// [`Scope`]: struct.Scope.html
+/// [`HIGH_TAG_BITS`]: constant.HIGH_TAG_BITS.html
+/// [`Owned`]: struct.Owned.html
+/// [`Owned::with_high_tags`]: struct.Owned.html#method.with_high_tags
+/// [`Ptr`]: struct.Ptr.html
+/// [`Ptr::with_high_tags`]: struct.Ptr.html#method.with_high_tags
 #[derive(Debug)]
-pub struct Atomic<T> {
+pub struct Atomic<T: ?Sized + Pointable, const HIGH_TAG: bool = false> {
     data: AtomicUsize,
+    /// Call site of the last store into the atomic pointer.
+    #[cfg(feature = "store_tracking")]
+    stored_at: StoreSite,
     _marker: PhantomData<*mut T>,
 }
 
-unsafe impl<T: Send + Sync> Send for Atomic<T> {}
-unsafe impl<T: Send + Sync> Sync for Atomic<T> {}
+unsafe impl<T, const HIGH_TAG: bool> Send for Atomic<T, HIGH_TAG>
+where
+    T: ?Sized + Pointable + Send + Sync,
+{
+}
+unsafe impl<T, const HIGH_TAG: bool> Sync for Atomic<T, HIGH_TAG>
+where
+    T: ?Sized + Pointable + Send + Sync,
+{
+}
 
-impl<T> Atomic<T> {
-    /// Returns a new atomic pointer pointing to the tagged pointer `data`.
-    fn from_data(data: usize) -> Self {
-        Atomic {
-            data: AtomicUsize::new(data),
-            _marker: PhantomData,
-        }
+/// Makes the current value returned by a failed compare-and-set acquired, whatever `failure` is.
+///
+/// A failed compare-and-set is just a load with the failure ordering. If that's weaker than
+/// `Acquire`, a fence makes up for it. On x86 the fence compiles to nothing, as every load acquires
+/// there anyway, and elsewhere it's still cheaper than the extra load callers would do otherwise.
+#[inline]
+fn acquire_failure(failure: Ordering) {
+    if let Ordering::Relaxed = failure {
+        fence(Ordering::Acquire);
     }
+}
 
+impl<T: ?Sized + Pointable> Atomic<T> {
     /// Returns a new null atomic pointer.
     ///
     /// # Examples
@@ -120,31 +111,55 @@ impl<T> Atomic<T> {
     ///
     /// let a = Atomic::<i32>::null();
     /// ```
-    #[cfg(not(feature = "nightly"))]
+    #[cfg(loom)]
     pub fn null() -> Self {
         Atomic {
             data: AtomicUsize::new(0),
+            #[cfg(feature = "store_tracking")]
+            stored_at: StoreSite::new(),
             _marker: PhantomData,
         }
     }
 
     /// Returns a new null atomic pointer.
     ///
+    /// This can initialize statics, e.g. the links of a static sentinel node.
+    ///
     /// # Examples
     ///
     /// ```
     /// use crossbeam_epoch::Atomic;
     ///
-    /// let a = Atomic::<i32>::null();
+    /// static A: Atomic<i32> = Atomic::null();
     /// ```
-    #[cfg(feature = "nightly")]
+    #[cfg(not(loom))]
     pub const fn null() -> Self {
         Atomic {
             data: AtomicUsize::new(0),
+            #[cfg(feature = "store_tracking")]
+            stored_at: StoreSite::new(),
             _marker: PhantomData,
         }
     }
 
+    /// Allocates an object initialized with `init` and returns a new atomic pointer pointing to
+    /// it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Atomic;
+    /// use std::mem::MaybeUninit;
+    ///
+    /// let a = Atomic::<[MaybeUninit<i32>]>::init(10);
+    /// # unsafe { drop(a.into_owned()) }
+    /// ```
+    pub fn init(init: T::Init) -> Self {
+        Self::from_owned(Owned::init(init))
+    }
+}
+
+impl<T> Atomic<T> {
     /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
     ///
     /// # Examples
@@ -158,6 +173,81 @@ impl<T> Atomic<T> {
         Self::from_owned(Owned::new(value))
     }
 
+    /// Returns a new atomic pointer pointing to the static object `object`.
+    ///
+    /// This is meant for sentinel nodes of data structures, which can then be statics instead of
+    /// being allocated at runtime. Static objects must never be deferred for destruction; in debug
+    /// builds and with the `strict` feature, doing so panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// struct Node {
+    ///     value: u32,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// static SENTINEL: Node = Node { value: 0, next: Atomic::null() };
+    ///
+    /// let head = Atomic::from_static(&SENTINEL);
+    /// epoch::pin(|scope| {
+    ///     let sentinel = unsafe { head.load(SeqCst, scope).deref() };
+    ///     assert_eq!(sentinel.value, 0);
+    ///     assert!(sentinel.next.load(SeqCst, scope).is_null());
+    /// });
+    /// ```
+    pub fn from_static(object: &'static T) -> Self {
+        Self::from_ptr(Ptr::from_static(object))
+    }
+}
+
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Atomic<T, HIGH_TAG> {
+    /// Returns a new atomic pointer pointing to the tagged pointer `data`.
+    fn from_data(data: usize) -> Self {
+        Atomic {
+            data: AtomicUsize::new(data),
+            #[cfg(feature = "store_tracking")]
+            stored_at: StoreSite::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the tagged pointer word this atomic pointer is made of.
+    pub(crate) fn data(&self) -> &AtomicUsize {
+        &self.data
+    }
+
+    /// Validates the tagged pointer `data` that was loaded from or is about to be stored into
+    /// this atomic pointer.
+    #[inline]
+    fn validate(&self, data: usize) -> usize {
+        validate::<T, HIGH_TAG>(&self.data as *const _ as *const (), data, misuse::report_raw)
+    }
+
+    /// Counts `access` for the hot slot report, if it's due for sampling.
+    #[cfg(feature = "profiler")]
+    #[inline]
+    fn sample(&self, access: profiler::Access) {
+        profiler::sample_slot(self as *const Self as usize, access);
+    }
+
+    /// Attaches the site of the last store into the atomic pointer to `ptr`, loaded from it.
+    #[cfg(feature = "store_tracking")]
+    #[inline]
+    fn tracked<'scope>(&self, mut ptr: Ptr<'scope, T, HIGH_TAG>) -> Ptr<'scope, T, HIGH_TAG> {
+        ptr.stored_at = self.stored_at.get();
+        ptr
+    }
+
+    #[cfg(not(feature = "store_tracking"))]
+    #[inline]
+    fn tracked<'scope>(&self, ptr: Ptr<'scope, T, HIGH_TAG>) -> Ptr<'scope, T, HIGH_TAG> {
+        ptr
+    }
+
     /// Returns a new atomic pointer pointing to `owned`.
     ///
     /// # Examples
@@ -167,7 +257,7 @@ impl<T> Atomic<T> {
     ///
     /// let a = Atomic::from_owned(Owned::new(1234));
     /// ```
-    pub fn from_owned(owned: Owned<T>) -> Self {
+    pub fn from_owned(owned: Owned<T, HIGH_TAG>) -> Self {
         let data = owned.data;
         mem::forget(owned);
         Self::from_data(data)
@@ -182,7 +272,7 @@ impl<T> Atomic<T> {
     ///
     /// let a = Atomic::from_ptr(Ptr::<i32>::null());
     /// ```
-    pub fn from_ptr(ptr: Ptr<T>) -> Self {
+    pub fn from_ptr(ptr: Ptr<T, HIGH_TAG>) -> Self {
         Self::from_data(ptr.data)
     }
 
@@ -204,16 +294,160 @@ impl<T> Atomic<T> {
     ///     let p = a.load(SeqCst, scope);
     /// });
     /// ```
-    pub fn load<'scope>(&self, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.load(ord))
+    pub fn load<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> Ptr<'scope, T, HIGH_TAG> {
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::Load);
+
+        self.tracked(Ptr::from_data(self.validate(self.data.load(ord))).stamp(scope))
+    }
+
+    /// Loads a `Ptr` from the atomic pointer, and prefetches the node after the loaded one.
+    ///
+    /// This is meant for traversing a linked list, where `T` is the node type, and `next` is the
+    /// offset in bytes of the field of type `Atomic<T>` that links a node to the next one, e.g.
+    /// `mem::offset_of!(Node, next)`. If the loaded pointer isn't null, its next pointer is loaded
+    /// with `Relaxed` ordering, and the node it points to is prefetched with
+    /// [`Ptr::prefetch_read`], so that it's in the cache by the time the traversal gets there.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of loading
+    /// the pointer itself.
+    ///
+    /// # Safety
+    ///
+    /// If the loaded pointer isn't null, it must point to a valid `T` that has an `Atomic<T>`
+    /// field, with the same tag layout, at offset `next`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+    /// use std::mem;
+    /// use std::sync::atomic::Ordering::{Acquire, Relaxed};
+    ///
+    /// struct Node {
+    ///     value: u64,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// let mut head = Atomic::null();
+    /// for value in 0..8 {
+    ///     let next = head;
+    ///     head = Atomic::from_owned(Owned::new(Node { value, next }));
+    /// }
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let offset = mem::offset_of!(Node, next);
+    ///     let mut sum = 0;
+    ///     let mut link = &head;
+    ///     while let Some(node) = link.load_and_prefetch_next(Acquire, offset, scope).as_ref() {
+    ///         sum += node.value;
+    ///         link = &node.next;
+    ///     }
+    ///     assert_eq!(sum, 28);
+    /// #   let mut p = head.load(Relaxed, scope);
+    /// #   while !p.is_null() {
+    /// #       let next = p.deref().next.load(Relaxed, scope);
+    /// #       drop(p.into_owned());
+    /// #       p = next;
+    /// #   }
+    /// });
+    /// ```
+    ///
+    /// [`Ptr::prefetch_read`]: struct.Ptr.html#method.prefetch_read
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    pub unsafe fn load_and_prefetch_next<'scope>(
+        &self,
+        ord: Ordering,
+        next: usize,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        let ptr = self.load(ord, scope);
+        if !ptr.is_null() {
+            let link = &*((ptr.address() + next) as *const Atomic<T, HIGH_TAG>);
+            prefetch::read(data_address::<T, HIGH_TAG>(link.data.load(Ordering::Relaxed)));
+        }
+        ptr
+    }
+
+    /// Loads a `Ptr` from every atomic pointer of `atomics`, e.g. a small array of buckets.
+    ///
+    /// This is like loading each of them with `Acquire` ordering, except that the loads are
+    /// relaxed and followed by a single fence, which saves a fence per atomic pointer on weakly
+    /// ordered architectures. The atomic pointers are not loaded all at the same moment; any of
+    /// them may change while the others are being loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    ///
+    /// let buckets = [Atomic::new(1), Atomic::null(), Atomic::new(3)];
+    /// epoch::pin(|scope| {
+    ///     let ptrs = Atomic::load_all(&buckets, scope);
+    ///     let sum: i32 = ptrs.iter().filter_map(|p| unsafe { p.as_ref() }).sum();
+    ///     assert_eq!(sum, 4);
+    /// #   unsafe { ptrs.iter().filter(|p| !p.is_null()).for_each(|&p| scope.defer_drop(p)) }
+    /// });
+    /// ```
+    pub fn load_all<'scope, const N: usize>(
+        atomics: &[Self; N],
+        scope: &'scope Scope,
+    ) -> [Ptr<'scope, T, HIGH_TAG>; N] {
+        let ptrs = ::std::array::from_fn(|i| atomics[i].load(Ordering::Relaxed, scope));
+        fence(Ordering::Acquire);
+        ptrs
+    }
+
+    /// Loads a `Ptr` from every atomic pointer of `atomics` into `out`, e.g. a range of buckets of
+    /// a larger array.
+    ///
+    /// Like [`load_all`], the loads are relaxed and followed by a single `Acquire` fence.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `atomics` and `out` have different lengths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    ///
+    /// let buckets = (0..64).map(Atomic::new).collect::<Vec<_>>();
+    /// epoch::pin(|scope| {
+    ///     let mut ptrs = [Ptr::null(); 16];
+    ///     Atomic::load_range(&buckets[16..32], &mut ptrs, scope);
+    ///     assert_eq!(unsafe { ptrs[0].deref() }, &16);
+    /// #   use std::sync::atomic::Ordering::Relaxed;
+    /// #   unsafe { buckets.iter().for_each(|b| scope.defer_drop(b.load(Relaxed, scope))) }
+    /// });
+    /// ```
+    ///
+    /// [`load_all`]: struct.Atomic.html#method.load_all
+    pub fn load_range<'scope>(
+        atomics: &[Self],
+        out: &mut [Ptr<'scope, T, HIGH_TAG>],
+        scope: &'scope Scope,
+    ) {
+        assert_eq!(atomics.len(), out.len(), "lengths of atomics and out differ");
+        for (atomic, ptr) in atomics.iter().zip(out.iter_mut()) {
+            *ptr = atomic.load(Ordering::Relaxed, scope);
+        }
+        fence(Ordering::Acquire);
     }
 
     /// Stores a `Ptr` into the atomic pointer.
     ///
+    /// This follows the default tag policy, [`TagPolicy::New`]: the tag of `new` replaces the tag
+    /// of the current value, so a mark bit set on the current value is lost. To keep it instead,
+    /// use [`store_keeping_tag`], or [`store_tagged`] to pick another policy.
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    /// [`TagPolicy::New`]: enum.TagPolicy.html#variant.New
+    /// [`store_keeping_tag`]: struct.Atomic.html#method.store_keeping_tag
+    /// [`store_tagged`]: struct.Atomic.html#method.store_tagged
     ///
     /// # Examples
     ///
@@ -224,16 +458,22 @@ impl<T> Atomic<T> {
     /// let a = Atomic::new(1234);
     /// a.store(Ptr::null(), SeqCst);
     /// ```
-    pub fn store(&self, new: Ptr<T>, ord: Ordering) {
-        self.data.store(new.data, ord);
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store(&self, new: Ptr<T, HIGH_TAG>, ord: Ordering) {
+        self.store_data(new.data, TagPolicy::New, ord);
     }
 
     /// Stores an `Owned` into the atomic pointer.
     ///
+    /// This follows the default tag policy, [`TagPolicy::New`]: the tag of `new` replaces the tag
+    /// of the current value. To pick another policy, use [`store_owned_tagged`].
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    /// [`TagPolicy::New`]: enum.TagPolicy.html#variant.New
+    /// [`store_owned_tagged`]: struct.Atomic.html#method.store_owned_tagged
     ///
     /// # Examples
     ///
@@ -244,10 +484,174 @@ impl<T> Atomic<T> {
     /// let a = Atomic::null();
     /// a.store_owned(Owned::new(1234), SeqCst);
     /// ```
-    pub fn store_owned(&self, new: Owned<T>, ord: Ordering) {
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_owned(&self, new: Owned<T, HIGH_TAG>, ord: Ordering) {
+        let data = new.data;
+        mem::forget(new);
+        self.store_data(data, TagPolicy::New, ord);
+    }
+
+    /// Stores a `Ptr` into the atomic pointer, with the tag chosen by `policy`.
+    ///
+    /// [`store`] is the same as this method with [`TagPolicy::New`]. Spelling out the policy makes
+    /// it clear whether a mark bit of the current value survives the store.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation. With [`TagPolicy::Keep`], the store is a compare-and-set loop, and the ordering
+    /// applies to the successful attempt.
+    ///
+    /// [`store`]: struct.Atomic.html#method.store
+    /// [`TagPolicy::New`]: enum.TagPolicy.html#variant.New
+    /// [`TagPolicy::Keep`]: enum.TagPolicy.html#variant.Keep
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, TagPolicy};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::null();
+    /// epoch::pin(|scope| {
+    ///     a.fetch_or(1, SeqCst, scope);
+    ///     let p = Owned::new(1234).into_ptr(scope);
+    ///     a.store_tagged(p, TagPolicy::Keep, SeqCst);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 1);
+    /// #   unsafe { scope.defer_drop(p) }
+    /// });
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_tagged(&self, new: Ptr<T, HIGH_TAG>, policy: TagPolicy, ord: Ordering) {
+        self.store_data(new.data, policy, ord);
+    }
+
+    /// Stores an `Owned` into the atomic pointer, with the tag chosen by `policy`.
+    ///
+    /// [`store_owned`] is the same as this method with [`TagPolicy::New`]. See [`store_tagged`]
+    /// for more.
+    ///
+    /// [`store_owned`]: struct.Atomic.html#method.store_owned
+    /// [`store_tagged`]: struct.Atomic.html#method.store_tagged
+    /// [`TagPolicy::New`]: enum.TagPolicy.html#variant.New
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, TagPolicy};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::null();
+    /// a.store_owned_tagged(Owned::new(1234).with_tag(1), TagPolicy::Clear, SeqCst);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope).tag(), 0));
+    /// # unsafe { drop(a.into_owned()) }
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_owned_tagged(&self, new: Owned<T, HIGH_TAG>, policy: TagPolicy, ord: Ordering) {
         let data = new.data;
         mem::forget(new);
-        self.data.store(data, ord);
+        self.store_data(data, policy, ord);
+    }
+
+    /// Stores a `Ptr` into the atomic pointer, keeping the tag of the current value and ignoring
+    /// the tag of `new`.
+    ///
+    /// This is [`store_tagged`] with [`TagPolicy::Keep`].
+    ///
+    /// [`store_tagged`]: struct.Atomic.html#method.store_tagged
+    /// [`TagPolicy::Keep`]: enum.TagPolicy.html#variant.Keep
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// a.store_keeping_tag(Ptr::null().with_tag(2), SeqCst);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope).tag(), 1));
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_keeping_tag(&self, new: Ptr<T, HIGH_TAG>, ord: Ordering) {
+        self.store_data(new.data, TagPolicy::Keep, ord);
+    }
+
+    /// Stores a `Ptr` into the atomic pointer, with its tag replaced by `tag`.
+    ///
+    /// This is [`store_tagged`] with [`TagPolicy::Set`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tag` doesn't fit into the unused bits of the pointer, like [`Ptr::with_tag`].
+    ///
+    /// [`store_tagged`]: struct.Atomic.html#method.store_tagged
+    /// [`TagPolicy::Set`]: enum.TagPolicy.html#variant.Set
+    /// [`Ptr::with_tag`]: struct.Ptr.html#method.with_tag
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::null();
+    /// a.store_with_tag(Ptr::null(), 3, SeqCst);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope).tag(), 3));
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_with_tag(&self, new: Ptr<T, HIGH_TAG>, tag: usize, ord: Ordering) {
+        self.store_data(new.data, TagPolicy::Set(tag), ord);
+    }
+
+    /// Stores a `Ptr` into the atomic pointer, with its tag cleared.
+    ///
+    /// This is [`store_tagged`] with [`TagPolicy::Clear`].
+    ///
+    /// [`store_tagged`]: struct.Atomic.html#method.store_tagged
+    /// [`TagPolicy::Clear`]: enum.TagPolicy.html#variant.Clear
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::null();
+    /// a.store_clearing_tag(Ptr::null().with_tag(3), SeqCst);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope).tag(), 0));
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn store_clearing_tag(&self, new: Ptr<T, HIGH_TAG>, ord: Ordering) {
+        self.store_data(new.data, TagPolicy::Clear, ord);
+    }
+
+    /// Stores the tagged pointer `data`, with the tag chosen by `policy`.
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    fn store_data(&self, data: usize, policy: TagPolicy, ord: Ordering) {
+        match policy {
+            TagPolicy::New => self.data.store(self.validate(data), ord),
+            TagPolicy::Clear => {
+                self.data.store(self.validate(data_with_tag::<T, HIGH_TAG>(data, 0)), ord);
+            }
+            TagPolicy::Set(tag) => {
+                let tag = check_tag::<T, HIGH_TAG>(tag, misuse::report_raw);
+                self.data.store(self.validate(data_with_tag::<T, HIGH_TAG>(data, tag)), ord);
+            }
+            TagPolicy::Keep => {
+                let mut current = self.data.load(Ordering::Relaxed);
+                loop {
+                    let tag = data_tag::<T, HIGH_TAG>(current);
+                    let new = self.validate(data_with_tag::<T, HIGH_TAG>(data, tag));
+                    match self.data.compare_exchange_weak(current, new, ord, Ordering::Relaxed) {
+                        Ok(_) => break,
+                        Err(previous) => current = previous,
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::Store);
+        #[cfg(feature = "store_tracking")]
+        self.stored_at.record();
     }
 
     /// Stores a `Ptr` into the atomic pointer, returning the previous `Ptr`.
@@ -268,18 +672,34 @@ impl<T> Atomic<T> {
     ///     let p = a.swap(Ptr::null(), SeqCst, scope);
     /// });
     /// ```
-    pub fn swap<'scope>(&self, new: Ptr<T>, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.swap(new.data, ord))
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn swap<'scope>(
+        &self,
+        new: Ptr<T, HIGH_TAG>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        new.check(scope);
+        let previous = self.data.swap(self.validate(new.data), ord);
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::Swap);
+        #[cfg(feature = "store_tracking")]
+        self.stored_at.record();
+        Ptr::from_data(self.validate(previous)).stamp(scope)
     }
 
-    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    /// Stores `new` into the atomic pointer if the tag of the current value is `expected_tag`,
+    /// whatever the current pointer is.
     ///
-    /// The return value is a result indicating whether the new pointer was written. On failure the
-    /// actual current value is returned.
+    /// The return value is a result indicating whether the new pointer was written, and holds the
+    /// previous value either way. On failure it's acquired whatever the failure ordering, like the
+    /// current value returned by [`compare_and_set`].
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
     ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    ///
     /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
     ///
     /// # Examples
@@ -288,45 +708,59 @@ impl<T> Atomic<T> {
     /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let a = Atomic::new(1234);
+    /// const OPEN: usize = 0;
+    /// const CLOSED: usize = 1;
     ///
+    /// let a = Atomic::new(1234);
     /// epoch::pin(|scope| {
-    ///     let mut curr = a.load(SeqCst, scope);
-    ///     let res = a.compare_and_set(curr, Ptr::null(), SeqCst, scope);
+    ///     let closed = Ptr::null().with_tag(CLOSED);
+    ///     let old = a.swap_if_tag(OPEN, closed, SeqCst, scope).unwrap();
+    ///     assert!(a.swap_if_tag(OPEN, closed, SeqCst, scope).is_err());
+    ///     unsafe { scope.defer_drop(old) }
     /// });
     /// ```
-    pub fn compare_and_set<'scope, O>(
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn swap_if_tag<'scope, O>(
         &self,
-        current: Ptr<T>,
-        new: Ptr<T>,
+        expected_tag: usize,
+        new: Ptr<T, HIGH_TAG>,
         ord: O,
-        _: &'scope Scope,
-    ) -> Result<(), Ptr<'scope, T>>
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, Ptr<'scope, T, HIGH_TAG>>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange(
-            current.data,
-            new.data,
-            ord.success(),
-            ord.failure(),
-        ) {
-            Ok(_) => Ok(()),
-            Err(previous) => Err(Ptr::from_data(previous)),
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        new.check(scope);
+        let new = self.validate(new.data);
+        let mut current = self.data.load(ord.failure());
+        loop {
+            if data_tag::<T, HIGH_TAG>(current) != expected_tag {
+                acquire_failure(ord.failure());
+                return Err(Ptr::from_data(self.validate(current)).stamp(scope));
+            }
+            match self.data.compare_exchange_weak(current, new, ord.success(), ord.failure()) {
+                Ok(_) => {
+                    #[cfg(feature = "store_tracking")]
+                    self.stored_at.record();
+                    return Ok(Ptr::from_data(self.validate(current)).stamp(scope));
+                }
+                Err(previous) => current = previous,
+            }
         }
     }
 
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
-    /// Unlike [`compare_and_set`], this method is allowed to spuriously fail even when
-    /// comparison succeeds, which can result in more efficient code on some platforms.
     /// The return value is a result indicating whether the new pointer was written. On failure the
-    /// actual current value is returned.
+    /// actual current value is returned. It's acquired whatever the failure ordering, so it can be
+    /// dereferenced or retried with right away, without loading it again.
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
     ///
-    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
     /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
     ///
     /// # Examples
@@ -339,40 +773,123 @@ impl<T> Atomic<T> {
     ///
     /// epoch::pin(|scope| {
     ///     let mut curr = a.load(SeqCst, scope);
-    ///     loop {
-    ///         match a.compare_and_set(curr, Ptr::null(), SeqCst, scope) {
-    ///             Ok(()) => break,
-    ///             Err(c) => curr = c,
+    ///     let res = a.compare_and_set(curr, Ptr::null(), SeqCst, scope);
+    /// });
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn compare_and_set<'scope, O>(
+        &self,
+        current: Ptr<T, HIGH_TAG>,
+        new: Ptr<T, HIGH_TAG>,
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<(), Ptr<'scope, T, HIGH_TAG>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        current.check(scope);
+        new.check(scope);
+        let result = self.data.compare_exchange(
+            current.data,
+            self.validate(new.data),
+            ord.success(),
+            ord.failure(),
+        );
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::CompareAndSet(result.is_ok()));
+
+        match result {
+            Ok(_) => {
+                #[cfg(feature = "store_tracking")]
+                self.stored_at.record();
+                Ok(())
+            }
+            Err(previous) => {
+                acquire_failure(ord.failure());
+                Err(Ptr::from_data(self.validate(previous)).stamp(scope))
+            }
+        }
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_and_set`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    /// The return value is a result indicating whether the new pointer was written. On failure the
+    /// actual current value is returned. It's acquired whatever the failure ordering, so it can be
+    /// dereferenced or retried with right away, without loading it again.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let mut curr = a.load(SeqCst, scope);
+    ///     loop {
+    ///         match a.compare_and_set(curr, Ptr::null(), SeqCst, scope) {
+    ///             Ok(()) => break,
+    ///             Err(c) => curr = c,
     ///         }
     ///     }
     /// });
     /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
     pub fn compare_and_set_weak<'scope, O>(
         &self,
-        current: Ptr<T>,
-        new: Ptr<T>,
+        current: Ptr<T, HIGH_TAG>,
+        new: Ptr<T, HIGH_TAG>,
         ord: O,
-        _: &'scope Scope,
-    ) -> Result<(), Ptr<'scope, T>>
+        scope: &'scope Scope,
+    ) -> Result<(), Ptr<'scope, T, HIGH_TAG>>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange_weak(
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        current.check(scope);
+        new.check(scope);
+        let result = self.data.compare_exchange_weak(
             current.data,
-            new.data,
+            self.validate(new.data),
             ord.success(),
             ord.failure(),
-        ) {
-            Ok(_) => Ok(()),
-            Err(previous) => Err(Ptr::from_data(previous)),
+        );
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::CompareAndSet(result.is_ok()));
+
+        match result {
+            Ok(_) => {
+                #[cfg(feature = "store_tracking")]
+                self.stored_at.record();
+                Ok(())
+            }
+            Err(previous) => {
+                acquire_failure(ord.failure());
+                Err(Ptr::from_data(self.validate(previous)).stamp(scope))
+            }
         }
     }
 
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// The return value is a result indicating whether the new pointer was written. On success the
-    /// pointer that was written is returned. On failure `new` and the actual current value are
-    /// returned.
+    /// pointer that was written is returned. On failure `new`, still carrying its tag, and the
+    /// actual current value are returned. The current value is acquired whatever the failure
+    /// ordering, so it can be dereferenced or retried with right away, without loading it again.
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
@@ -392,28 +909,42 @@ impl<T> Atomic<T> {
     ///     let res = a.compare_and_set_owned(curr, Owned::new(5678), SeqCst, scope);
     /// });
     /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
     pub fn compare_and_set_owned<'scope, O>(
         &self,
-        current: Ptr<T>,
-        new: Owned<T>,
+        current: Ptr<T, HIGH_TAG>,
+        new: Owned<T, HIGH_TAG>,
         ord: O,
-        _: &'scope Scope,
-    ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)>
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, (Ptr<'scope, T, HIGH_TAG>, Owned<T, HIGH_TAG>)>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange(
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        current.check(scope);
+        let result = self.data.compare_exchange(
             current.data,
-            new.data,
+            self.validate(new.data),
             ord.success(),
             ord.failure(),
-        ) {
+        );
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::CompareAndSet(result.is_ok()));
+
+        match result {
             Ok(_) => {
+                #[cfg(feature = "store_tracking")]
+                self.stored_at.record();
                 let data = new.data;
                 mem::forget(new);
-                Ok(Ptr::from_data(data))
+                Ok(Ptr::from_data(data).stamp(scope))
+            }
+            Err(previous) => {
+                acquire_failure(ord.failure());
+                Err((Ptr::from_data(self.validate(previous)).stamp(scope), new))
             }
-            Err(previous) => Err((Ptr::from_data(previous), new)),
         }
     }
 
@@ -422,8 +953,9 @@ impl<T> Atomic<T> {
     /// Unlike [`compare_and_set_owned`], this method is allowed to spuriously fail even when
     /// comparison succeeds, which can result in more efficient code on some platforms.
     /// The return value is a result indicating whether the new pointer was written. On success the
-    /// pointer that was written is returned. On failure `new` and the actual current value are
-    /// returned.
+    /// pointer that was written is returned. On failure `new`, still carrying its tag, and the
+    /// actual current value are returned. The current value is acquired whatever the failure
+    /// ordering, so it can be dereferenced or retried with right away, without loading it again.
     ///
     /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
     /// ordering of this operation.
@@ -456,28 +988,240 @@ impl<T> Atomic<T> {
     ///     }
     /// });
     /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
     pub fn compare_and_set_weak_owned<'scope, O>(
         &self,
-        current: Ptr<T>,
-        new: Owned<T>,
+        current: Ptr<T, HIGH_TAG>,
+        new: Owned<T, HIGH_TAG>,
         ord: O,
-        _: &'scope Scope,
-    ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)>
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, (Ptr<'scope, T, HIGH_TAG>, Owned<T, HIGH_TAG>)>
     where
         O: CompareAndSetOrdering,
     {
-        match self.data.compare_exchange_weak(
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        current.check(scope);
+        let result = self.data.compare_exchange_weak(
             current.data,
-            new.data,
+            self.validate(new.data),
             ord.success(),
             ord.failure(),
-        ) {
+        );
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::CompareAndSet(result.is_ok()));
+
+        match result {
             Ok(_) => {
+                #[cfg(feature = "store_tracking")]
+                self.stored_at.record();
                 let data = new.data;
                 mem::forget(new);
-                Ok(Ptr::from_data(data))
+                Ok(Ptr::from_data(data).stamp(scope))
+            }
+            Err(previous) => {
+                acquire_failure(ord.failure());
+                Err((Ptr::from_data(self.validate(previous)).stamp(scope), new))
+            }
+        }
+    }
+
+    /// Stores a null pointer into the atomic pointer if the current value is the same as
+    /// `current`, and on success defers destruction of `current`.
+    ///
+    /// This fuses the common pattern of unlinking an object with [`compare_and_set`] and then
+    /// passing it to [`Scope::defer_drop`], so the deferral can't be forgotten. On failure the
+    /// actual current value is returned and nothing is deferred.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`Scope::defer_drop`] apply to `current`: once it's unlinked from this
+    /// atomic pointer, it must not be reachable by other mutators anymore.
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let curr = a.load(SeqCst, scope);
+    ///     unsafe {
+    ///         assert!(a.unlink(curr, SeqCst, scope).is_ok());
+    ///     }
+    ///     assert!(a.load(SeqCst, scope).is_null());
+    /// });
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub unsafe fn unlink<'scope, O>(
+        &self,
+        current: Ptr<T, HIGH_TAG>,
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<(), Ptr<'scope, T, HIGH_TAG>>
+    where
+        T: Sized + Send + EpochSafe + 'static,
+        O: CompareAndSetOrdering,
+    {
+        self.compare_and_set(current, Ptr::from_data(0), ord, scope)?;
+        scope.defer_drop(current);
+        Ok(())
+    }
+
+    /// Moves the pointer out of this atomic pointer into `dst`, if `dst` is null.
+    ///
+    /// The pointer is taken out of this atomic pointer first, and then stored into `dst` with a
+    /// compare-and-set. If `dst` turns out to be occupied, the pointer is put back, unless another
+    /// mutator has stored into this atomic pointer in the meantime. In that case the object isn't
+    /// reachable from either atomic pointer anymore, so its destruction is deferred rather than
+    /// leaked. Each path thus leaves the object either linked exactly once or retired exactly
+    /// once. While the pointer is moved, other mutators may see both atomic pointers null.
+    ///
+    /// On success the moved pointer is returned, with its tag. On failure a [`TransferError`]
+    /// describes which path was taken.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of each step.
+    ///
+    /// # Safety
+    ///
+    /// The object this atomic pointer points to must only be reachable through this atomic
+    /// pointer, so that its destruction may be deferred when it can't be put back. The same rules
+    /// as for [`Scope::defer_drop`] apply to it then.
+    ///
+    /// [`TransferError`]: enum.TransferError.html
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, TransferError};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let src = Atomic::new(1234);
+    /// let dst = Atomic::null();
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let moved = src.transfer_to(&dst, SeqCst, scope).unwrap();
+    ///     assert_eq!(*moved.deref(), 1234);
+    ///     assert!(src.load(SeqCst, scope).is_null());
+    ///
+    ///     match src.transfer_to(&dst, SeqCst, scope) {
+    ///         Err(TransferError::Empty) => {}
+    ///         _ => unreachable!(),
+    ///     }
+    ///     # scope.defer_drop(moved);
+    /// });
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub unsafe fn transfer_to<'scope, O>(
+        &self,
+        dst: &Atomic<T, HIGH_TAG>,
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, TransferError<'scope, T, HIGH_TAG>>
+    where
+        T: Sized + Send + EpochSafe + 'static,
+        O: CompareAndSetOrdering,
+    {
+        let ord = (ord.success(), ord.failure());
+        let taken = self.swap(Ptr::from_data(0), ord.0, scope);
+        if taken.is_null() {
+            return Err(TransferError::Empty);
+        }
+
+        let occupant = match dst.compare_and_set(Ptr::from_data(0), taken, ord, scope) {
+            Ok(()) => return Ok(taken),
+            Err(occupant) => occupant,
+        };
+        match self.compare_and_set(Ptr::from_data(0), taken, ord, scope) {
+            Ok(()) => Err(TransferError::Occupied(occupant)),
+            Err(_) => {
+                scope.defer_drop(taken);
+                Err(TransferError::Retired(occupant))
+            }
+        }
+    }
+
+    /// Replaces the tag of the current value with `new_tag` if the current value is the same as
+    /// `current`.
+    ///
+    /// Both the pointer and the tag are compared, but only the tag is changed: on success the
+    /// atomic pointer holds `current.with_tag(new_tag)`. Tags are truncated to fit into the unused
+    /// bits of the pointer to `T`.
+    ///
+    /// The return value is a result indicating whether the new tag was written. On success the
+    /// previous value, `current`, is returned. On failure the actual current value is returned,
+    /// acquired whatever the failure ordering, like the current value returned by
+    /// [`compare_and_set`].
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation.
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::from_owned(Owned::new(0u64).with_tag(1));
+    ///
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(a.compare_and_set_tag(p, 3, SeqCst, scope).unwrap().tag(), 1);
+    ///     assert_eq!(a.compare_and_set_tag(p, 2, SeqCst, scope).unwrap_err().tag(), 3);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 3);
+    /// });
+    /// ```
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn compare_and_set_tag<'scope, O>(
+        &self,
+        current: Ptr<T, HIGH_TAG>,
+        new_tag: usize,
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, Ptr<'scope, T, HIGH_TAG>>
+    where
+        O: CompareAndSetOrdering,
+    {
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        current.check(scope);
+        let new = current.with_tag(new_tag);
+        let result = self.data.compare_exchange(
+            current.data,
+            self.validate(new.data),
+            ord.success(),
+            ord.failure(),
+        );
+        #[cfg(feature = "profiler")]
+        self.sample(profiler::Access::CompareAndSet(result.is_ok()));
+
+        match result {
+            Ok(previous) => {
+                #[cfg(feature = "store_tracking")]
+                self.stored_at.record();
+                Ok(Ptr::from_data(previous).stamp(scope))
+            }
+            Err(previous) => {
+                acquire_failure(ord.failure());
+                Err(Ptr::from_data(self.validate(previous)).stamp(scope))
             }
-            Err(previous) => Err((Ptr::from_data(previous), new)),
         }
     }
 
@@ -486,6 +1230,10 @@ impl<T> Atomic<T> {
     /// Performs a bitwise "and" operation on the current tag and the argument `val`, and sets the
     /// new tag to the result. Returns the previous pointer.
     ///
+    /// Only the tag is modified: the bits of `val` that don't fit into the tag are ignored, and
+    /// the pointer stays the same. This sets or clears tag bits, e.g. marking a node as logically
+    /// deleted, with a single instruction instead of a CAS loop.
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
@@ -503,8 +1251,19 @@ impl<T> Atomic<T> {
     ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
     /// });
     /// ```
-    pub fn fetch_and<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_and(val | !low_bits::<T>(), ord))
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn fetch_and<'scope>(
+        &self,
+        val: usize,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+        let val = ((val & mask) << shift) | !(mask << shift);
+        let previous = self.data.fetch_and(val, ord);
+        #[cfg(feature = "store_tracking")]
+        self.stored_at.record();
+        Ptr::from_data(self.validate(previous)).stamp(scope)
     }
 
     /// Bitwise "or" with the current tag.
@@ -512,6 +1271,9 @@ impl<T> Atomic<T> {
     /// Performs a bitwise "or" operation on the current tag and the argument `val`, and sets the
     /// new tag to the result. Returns the previous pointer.
     ///
+    /// Only the tag is modified: the bits of `val` that don't fit into the tag are ignored, and
+    /// the pointer stays the same.
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
@@ -529,8 +1291,18 @@ impl<T> Atomic<T> {
     ///     assert_eq!(a.load(SeqCst, scope).tag(), 3);
     /// });
     /// ```
-    pub fn fetch_or<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_or(val & low_bits::<T>(), ord))
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn fetch_or<'scope>(
+        &self,
+        val: usize,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+        let previous = self.data.fetch_or((val & mask) << shift, ord);
+        #[cfg(feature = "store_tracking")]
+        self.stored_at.record();
+        Ptr::from_data(self.validate(previous)).stamp(scope)
     }
 
     /// Bitwise "xor" with the current tag.
@@ -538,6 +1310,9 @@ impl<T> Atomic<T> {
     /// Performs a bitwise "xor" operation on the current tag and the argument `val`, and sets the
     /// new tag to the result. Returns the previous pointer.
     ///
+    /// Only the tag is modified: the bits of `val` that don't fit into the tag are ignored, and
+    /// the pointer stays the same.
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
@@ -555,14 +1330,156 @@ impl<T> Atomic<T> {
     ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
     /// });
     /// ```
-    pub fn fetch_xor<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_xor(val & low_bits::<T>(), ord))
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn fetch_xor<'scope>(
+        &self,
+        val: usize,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+        let previous = self.data.fetch_xor((val & mask) << shift, ord);
+        #[cfg(feature = "store_tracking")]
+        self.stored_at.record();
+        Ptr::from_data(self.validate(previous)).stamp(scope)
+    }
+
+    /// Fetches the pointer, and applies `f` to it to compute the pointer to store instead, until
+    /// storing it succeeds or `f` returns `None`.
+    ///
+    /// If the atomic pointer changed between loading it and storing the result of `f`, `f` is
+    /// called again with the new current pointer. Returns `Ok` with the pointer that was replaced,
+    /// or `Err` with the current pointer if `f` returned `None`.
+    ///
+    /// `set_order` is the ordering of the store, and `fetch_order` that of the loads, like for
+    /// `AtomicPtr::fetch_update` in the standard library. The pointers passed to `f` are loaded
+    /// with `fetch_order`, but the one returned in `Err` is acquired whatever `fetch_order` is,
+    /// like the current value returned by [`compare_and_set`]. To store a newly allocated object,
+    /// use [`fetch_update_owned`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// epoch::pin(|scope| {
+    ///     // Increments the tag, but no further than 3.
+    ///     for tag in 1..4 {
+    ///         let prev = a.fetch_update(SeqCst, SeqCst, scope, |p| {
+    ///             if p.tag() < 3 { Some(p.with_tag(p.tag() + 1)) } else { None }
+    ///         });
+    ///         assert_eq!(prev.map_or_else(|p| p.tag(), |p| p.tag()), tag);
+    ///     }
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 3);
+    /// });
+    /// ```
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`fetch_update_owned`]: struct.Atomic.html#method.fetch_update_owned
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn fetch_update<'scope, F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        scope: &'scope Scope,
+        mut f: F,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, Ptr<'scope, T, HIGH_TAG>>
+    where
+        F: FnMut(Ptr<'scope, T, HIGH_TAG>) -> Option<Ptr<'scope, T, HIGH_TAG>>,
+    {
+        let mut prev = self.load(fetch_order, scope);
+        while let Some(next) = f(prev) {
+            match self.compare_and_set_weak(prev, next, (set_order, fetch_order), scope) {
+                Ok(()) => return Ok(prev),
+                Err(current) => prev = current,
+            }
+        }
+        acquire_failure(fetch_order);
+        Err(prev)
+    }
+
+    /// Fetches the pointer, and applies `f` to it to compute a new object to store instead, until
+    /// storing it succeeds or `f` returns `None`.
+    ///
+    /// This is like [`fetch_update`], except that `f` returns an [`Owned`]. If storing it fails
+    /// because the atomic pointer changed in the meantime, the object is dropped, since nobody else
+    /// has seen it, and `f` is called again with the new current pointer. The pointer returned in
+    /// `Err` is acquired the same way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1);
+    /// epoch::pin(|scope| unsafe {
+    ///     let prev = a.fetch_update_owned(SeqCst, SeqCst, scope, |p| {
+    ///         Some(Owned::new(p.deref() + 1))
+    ///     }).unwrap();
+    ///     scope.defer_drop(prev);
+    ///     assert_eq!(a.load(SeqCst, scope).deref(), &2);
+    /// #   scope.defer_drop(a.load(SeqCst, scope));
+    /// });
+    /// ```
+    ///
+    /// [`fetch_update`]: struct.Atomic.html#method.fetch_update
+    /// [`Owned`]: struct.Owned.html
+    #[cfg_attr(feature = "store_tracking", track_caller)]
+    pub fn fetch_update_owned<'scope, F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        scope: &'scope Scope,
+        mut f: F,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, Ptr<'scope, T, HIGH_TAG>>
+    where
+        F: FnMut(Ptr<'scope, T, HIGH_TAG>) -> Option<Owned<T, HIGH_TAG>>,
+    {
+        let mut prev = self.load(fetch_order, scope);
+        while let Some(next) = f(prev) {
+            match self.compare_and_set_weak_owned(prev, next, (set_order, fetch_order), scope) {
+                Ok(_) => return Ok(prev),
+                Err((current, _)) => prev = current,
+            }
+        }
+        acquire_failure(fetch_order);
+        Err(prev)
+    }
+
+    /// Takes ownership of the pointee.
+    ///
+    /// This consumes the atomic pointer and converts it into [`Owned`]. As [`Atomic`] doesn't have
+    /// a destructor and doesn't drop the pointee while [`Owned`] does, this is suitable for
+    /// destructors of data structures.
+    ///
+    /// # Safety
+    ///
+    /// The atomic pointer must not be null, and no other thread may be accessing the object, nor
+    /// may it be deferred for destruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Atomic;
+    ///
+    /// let a = Atomic::new(1234);
+    /// let o = unsafe { a.into_owned() };
+    /// assert_eq!(*o, 1234);
+    /// ```
+    ///
+    /// [`Owned`]: struct.Owned.html
+    /// [`Atomic`]: struct.Atomic.html
+    pub unsafe fn into_owned(self) -> Owned<T, HIGH_TAG> {
+        Owned::from_data(self.data.into_inner())
     }
 }
 
-impl<T> Default for Atomic<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Default for Atomic<T, HIGH_TAG> {
     fn default() -> Self {
-        Atomic::null()
+        Atomic::from_data(0)
     }
 }
 
@@ -578,14 +1495,17 @@ impl<T> From<Box<T>> for Atomic<T> {
     }
 }
 
-impl<T> From<Owned<T>> for Atomic<T> {
-    fn from(owned: Owned<T>) -> Self {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> From<Owned<T, HIGH_TAG>> for Atomic<T, HIGH_TAG> {
+    fn from(owned: Owned<T, HIGH_TAG>) -> Self {
         Atomic::from_owned(owned)
     }
 }
 
-impl<'scope, T> From<Ptr<'scope, T>> for Atomic<T> {
-    fn from(ptr: Ptr<T>) -> Self {
+impl<'scope, T, const HIGH_TAG: bool> From<Ptr<'scope, T, HIGH_TAG>> for Atomic<T, HIGH_TAG>
+where
+    T: ?Sized + Pointable,
+{
+    fn from(ptr: Ptr<T, HIGH_TAG>) -> Self {
         Atomic::from_ptr(ptr)
     }
 }
@@ -595,22 +1515,33 @@ impl<'scope, T> From<Ptr<'scope, T>> for Atomic<T> {
 /// This type is very similar to `Box<T>`.
 ///
 /// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
+/// least significant bits of the address, or above it with `HIGH_TAG` set (see [`Atomic`]).
+///
+/// [`Atomic`]: struct.Atomic.html
 #[derive(Debug)]
-pub struct Owned<T> {
+pub struct Owned<T: ?Sized + Pointable, const HIGH_TAG: bool = false> {
     data: usize,
     _marker: PhantomData<Box<T>>,
 }
 
-impl<T> Owned<T> {
-    /// Returns a new owned pointer pointing to the tagged pointer `data`.
-    unsafe fn from_data(data: usize) -> Self {
-        Owned {
-            data: data,
-            _marker: PhantomData,
-        }
+impl<T: ?Sized + Pointable> Owned<T> {
+    /// Allocates an object initialized with `init` and returns a new owned pointer pointing to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Owned;
+    /// use std::mem::MaybeUninit;
+    ///
+    /// let o = Owned::<[MaybeUninit<i32>]>::init(10);
+    /// assert_eq!(o.len(), 10);
+    /// ```
+    pub fn init(init: T::Init) -> Self {
+        unsafe { Self::from_data(T::init(init) as usize) }
     }
+}
 
+impl<T> Owned<T> {
     /// Allocates `value` on the heap and returns a new owned pointer pointing to it.
     ///
     /// # Examples
@@ -624,6 +1555,32 @@ impl<T> Owned<T> {
         Self::from_box(Box::new(value))
     }
 
+    /// Allocates `value` on the heap and returns a new owned pointer pointing to it, or returns
+    /// `value` back if the allocation fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Owned;
+    ///
+    /// let o = Owned::try_new(1234).unwrap();
+    /// ```
+    pub fn try_new(value: T) -> Result<Self, T> {
+        let layout = Layout::new::<T>();
+        if layout.size() == 0 {
+            return Ok(Self::new(value));
+        }
+
+        unsafe {
+            let raw = alloc::alloc(layout) as *mut T;
+            if raw.is_null() {
+                return Err(value);
+            }
+            ptr::write(raw, value);
+            Ok(Self::from_raw(raw))
+        }
+    }
+
     /// Returns a new owned pointer pointing to `b`.
     ///
     /// # Panics
@@ -647,6 +1604,10 @@ impl<T> Owned<T> {
     /// must be a valid pointer. Also, a double-free may occur if the function is called twice on
     /// the same raw pointer.
     ///
+    /// # Safety
+    ///
+    /// `raw` must have been allocated by a `Box<T>` and must not be owned by anyone else.
+    ///
     /// # Panics
     ///
     /// Panics if `raw` is not properly aligned.
@@ -659,14 +1620,24 @@ impl<T> Owned<T> {
     /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
     /// ```
     pub unsafe fn from_raw(raw: *mut T) -> Self {
-        ensure_aligned(raw);
+        ensure_aligned(raw, misuse::report_raw);
         Self::from_data(raw as usize)
     }
+}
 
-    /// Converts the owned pointer to a [`Ptr`].
-    ///
-    /// # Examples
-    ///
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Owned<T, HIGH_TAG> {
+    /// Returns a new owned pointer pointing to the tagged pointer `data`.
+    unsafe fn from_data(data: usize) -> Self {
+        Owned {
+            data,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Converts the owned pointer to a [`Ptr`].
+    ///
+    /// # Examples
+    ///
     /// ```
     /// use crossbeam_epoch::{self as epoch, Owned};
     ///
@@ -677,12 +1648,86 @@ impl<T> Owned<T> {
     /// ```
     ///
     /// [`Ptr`]: struct.Ptr.html
-    pub fn into_ptr<'scope>(self, _: &'scope Scope) -> Ptr<'scope, T> {
+    pub fn into_ptr<'scope>(self, scope: &'scope Scope) -> Ptr<'scope, T, HIGH_TAG> {
+        let data = self.data;
+        mem::forget(self);
+        Ptr::from_data(data).stamp(scope)
+    }
+
+    /// Leaks the owned pointer, and returns a [`Ptr`] to the object that is valid forever.
+    ///
+    /// This is meant for objects that are intentionally never reclaimed, like the sentinel nodes
+    /// of a data structure. The returned pointer is not tied to any scope, so it can be stored in
+    /// the data structure and used with any scope later on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let sentinel: Ptr<'static, i32> = Owned::new(0).leak();
+    /// let head = Atomic::from_ptr(sentinel);
+    ///
+    /// epoch::pin(|scope| assert_eq!(head.load(SeqCst, scope).as_raw(), sentinel.as_raw()));
+    /// ```
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub fn leak(self) -> Ptr<'static, T, HIGH_TAG> {
         let data = self.data;
         mem::forget(self);
         Ptr::from_data(data)
     }
 
+    /// Converts the owned pointer into a raw pointer to the object, without the tag.
+    ///
+    /// The tag is stripped, since a raw pointer carrying it couldn't be dereferenced. To
+    /// round-trip it, read it with [`tag`] first and put it back with [`with_tag`] after
+    /// [`Owned::from_raw`]. The object is not dropped; the caller becomes responsible for it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Owned;
+    ///
+    /// let o = Owned::new(1234u64).with_tag(3);
+    /// let tag = o.tag();
+    /// let raw = o.into_raw();
+    /// assert_eq!(unsafe { *raw }, 1234);
+    ///
+    /// let o = unsafe { Owned::from_raw(raw) }.with_tag(tag);
+    /// assert_eq!((*o, o.tag()), (1234, 3));
+    /// ```
+    ///
+    /// [`tag`]: struct.Owned.html#method.tag
+    /// [`with_tag`]: struct.Owned.html#method.with_tag
+    /// [`Owned::from_raw`]: struct.Owned.html#method.from_raw
+    pub fn into_raw(self) -> *mut T
+    where
+        T: Sized,
+    {
+        let data = self.data;
+        mem::forget(self);
+        data_address::<T, HIGH_TAG>(data) as *mut T
+    }
+
+    /// Converts the owned pointer into a `Box`, dropping the tag.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Owned;
+    ///
+    /// let b = Owned::new(1234u64).with_tag(3).into_box();
+    /// assert_eq!(*b, 1234);
+    /// ```
+    pub fn into_box(self) -> Box<T>
+    where
+        T: Sized,
+    {
+        unsafe { Box::from_raw(self.into_raw()) }
+    }
+
     /// Returns the tag stored within the pointer.
     ///
     /// # Examples
@@ -693,7 +1738,7 @@ impl<T> Owned<T> {
     /// assert_eq!(Owned::new(1234).tag(), 0);
     /// ```
     pub fn tag(&self) -> usize {
-        self.data & low_bits::<T>()
+        data_tag::<T, HIGH_TAG>(self.data)
     }
 
     /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
@@ -710,32 +1755,63 @@ impl<T> Owned<T> {
     /// assert_eq!(o.tag(), 5);
     /// ```
     pub fn with_tag(self, tag: usize) -> Self {
+        let tag = check_tag::<T, HIGH_TAG>(tag, misuse::report_raw);
         let data = self.data;
         mem::forget(self);
-        unsafe { Self::from_data(data_with_tag::<T>(data, tag)) }
+        unsafe { Self::from_data(data_with_tag::<T, HIGH_TAG>(data, tag)) }
+    }
+
+    /// Returns the same pointer, but storing its tag in the high bits above the address. The tag
+    /// is truncated to fit into [`HIGH_TAG_BITS`] bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address itself uses the high bits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{Owned, HIGH_TAG_BITS};
+    ///
+    /// let o = Owned::new(0u64).with_tag(1).with_high_tags();
+    /// if HIGH_TAG_BITS > 0 {
+    ///     assert_eq!(o.with_tag(255).tag(), 255);
+    /// }
+    /// ```
+    ///
+    /// [`HIGH_TAG_BITS`]: constant.HIGH_TAG_BITS.html
+    pub fn with_high_tags(self) -> Owned<T, true> {
+        let data = self.data;
+        mem::forget(self);
+        unsafe { Owned::from_data(data_retagged::<T, HIGH_TAG, true>(data, misuse::report_raw)) }
+    }
+
+    /// Returns the same pointer, but storing its tag in the unused least significant bits of the
+    /// address. The tag is truncated to fit.
+    pub fn with_low_tags(self) -> Owned<T> {
+        let data = self.data;
+        mem::forget(self);
+        unsafe { Owned::from_data(data_retagged::<T, HIGH_TAG, false>(data, misuse::report_raw)) }
     }
 }
 
-impl<T> Drop for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Drop for Owned<T, HIGH_TAG> {
     fn drop(&mut self) {
-        let raw = (self.data & !low_bits::<T>()) as *mut T;
-        unsafe {
-            drop(Box::from_raw(raw));
-        }
+        unsafe { T::drop(data_address::<T, HIGH_TAG>(self.data) as *mut ()) }
     }
 }
 
-impl<T> Deref for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Deref for Owned<T, HIGH_TAG> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        unsafe { &*((self.data & !low_bits::<T>()) as *const T) }
+        unsafe { T::deref(data_address::<T, HIGH_TAG>(self.data) as *mut ()) }
     }
 }
 
-impl<T> DerefMut for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> DerefMut for Owned<T, HIGH_TAG> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *((self.data & !low_bits::<T>()) as *mut T) }
+        unsafe { T::deref_mut(data_address::<T, HIGH_TAG>(self.data) as *mut ()) }
     }
 }
 
@@ -751,27 +1827,27 @@ impl<T> From<Box<T>> for Owned<T> {
     }
 }
 
-impl<T> Borrow<T> for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Borrow<T> for Owned<T, HIGH_TAG> {
     fn borrow(&self) -> &T {
-        &**self
+        self
     }
 }
 
-impl<T> BorrowMut<T> for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> BorrowMut<T> for Owned<T, HIGH_TAG> {
     fn borrow_mut(&mut self) -> &mut T {
-        &mut **self
+        self
     }
 }
 
-impl<T> AsRef<T> for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> AsRef<T> for Owned<T, HIGH_TAG> {
     fn as_ref(&self) -> &T {
-        &**self
+        self
     }
 }
 
-impl<T> AsMut<T> for Owned<T> {
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> AsMut<T> for Owned<T, HIGH_TAG> {
     fn as_mut(&mut self) -> &mut T {
-        &mut **self
+        self
     }
 }
 
@@ -780,50 +1856,59 @@ impl<T> AsMut<T> for Owned<T> {
 /// The pointer is valid for use only within `'scope`.
 ///
 /// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
+/// least significant bits of the address, or above it with `HIGH_TAG` set (see [`Atomic`]).
+///
+/// [`Atomic`]: struct.Atomic.html
 #[derive(Debug)]
-pub struct Ptr<'scope, T: 'scope> {
+pub struct Ptr<'scope, T: 'scope + ?Sized + Pointable, const HIGH_TAG: bool = false> {
     data: usize,
+    /// Generation of the pinning the pointer was loaded in.
+    #[cfg(feature = "stale_ptr_check")]
+    generation: Generation,
+    /// Call site of the last store into the atomic pointer the pointer was loaded from.
+    #[cfg(feature = "store_tracking")]
+    stored_at: Option<&'static Location<'static>>,
     _marker: PhantomData<&'scope T>,
 }
 
-impl<'scope, T> Clone for Ptr<'scope, T> {
+impl<'scope, T: ?Sized + Pointable, const HIGH_TAG: bool> Clone for Ptr<'scope, T, HIGH_TAG> {
     fn clone(&self) -> Self {
-        Ptr {
-            data: self.data,
-            _marker: PhantomData,
-        }
+        *self
     }
 }
 
-impl<'scope, T> Copy for Ptr<'scope, T> {}
-
-impl<'scope, T> Ptr<'scope, T> {
-    /// Returns a new pointer pointing to the tagged pointer `data`.
-    fn from_data(data: usize) -> Self {
-        Ptr {
-            data: data,
-            _marker: PhantomData,
-        }
-    }
+impl<'scope, T: ?Sized + Pointable, const HIGH_TAG: bool> Copy for Ptr<'scope, T, HIGH_TAG> {}
 
+impl<'scope, T: ?Sized + Pointable> Ptr<'scope, T> {
     /// Returns a new null pointer.
     ///
+    /// This is a `const fn`, so it can initialize constants, e.g. the expected value of a
+    /// compare-and-set on a static [`Atomic`].
+    ///
     /// # Examples
     ///
     /// ```
-    /// use crossbeam_epoch::Ptr;
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let p = Ptr::<i32>::null();
-    /// assert!(p.is_null());
+    /// const NULL: Ptr<'static, i32> = Ptr::null();
+    /// static HEAD: Atomic<i32> = Atomic::null();
+    ///
+    /// assert!(NULL.is_null());
+    /// epoch::pin(|scope| {
+    ///     let new = Owned::new(1).into_ptr(scope);
+    ///     assert!(HEAD.compare_and_set(NULL, new, SeqCst, scope).is_ok());
+    ///     unsafe { scope.defer_drop(HEAD.swap(NULL, SeqCst, scope)) };
+    /// });
     /// ```
-    pub fn null() -> Self {
-        Ptr {
-            data: 0,
-            _marker: PhantomData,
-        }
+    ///
+    /// [`Atomic`]: struct.Atomic.html
+    pub const fn null() -> Self {
+        Ptr::from_data(0)
     }
+}
 
+impl<'scope, T> Ptr<'scope, T> {
     /// Returns a new pointer pointing to `raw`.
     ///
     /// # Panics
@@ -839,13 +1924,125 @@ impl<'scope, T> Ptr<'scope, T> {
     /// assert!(!p.is_null());
     /// ```
     pub fn from_raw(raw: *const T) -> Self {
-        ensure_aligned(raw);
+        ensure_aligned(raw, misuse::report_raw);
+        Ptr::from_data(raw as usize)
+    }
+
+    /// Returns a new pointer pointing to the static object `object`.
+    ///
+    /// The object is recorded as static in debug builds and with the `strict` feature, so that
+    /// deferring its destruction panics. See [`Atomic::from_static`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Ptr;
+    ///
+    /// static SENTINEL: u64 = 0;
+    /// let p = Ptr::from_static(&SENTINEL);
+    /// assert_eq!(p.as_raw(), &SENTINEL as *const u64);
+    /// ```
+    ///
+    /// [`Atomic::from_static`]: struct.Atomic.html#method.from_static
+    pub fn from_static(object: &'static T) -> Self {
+        debug::record_static(object, mem::size_of::<T>());
+        Self::from_raw(object)
+    }
+
+    /// Returns the number of least significant bits available for tags in pointers to `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Ptr;
+    ///
+    /// assert_eq!(Ptr::<u8>::tag_bits(), 0);
+    /// assert_eq!(Ptr::<u64>::tag_bits(), 3);
+    /// ```
+    pub fn tag_bits() -> u32 {
+        mem::align_of::<T>().trailing_zeros()
+    }
+}
+
+impl<'scope, T: ?Sized + Pointable, const HIGH_TAG: bool> Ptr<'scope, T, HIGH_TAG> {
+    /// Returns a new pointer pointing to the tagged pointer `data`.
+    pub(crate) const fn from_data(data: usize) -> Self {
         Ptr {
-            data: raw as usize,
+            data,
+            #[cfg(feature = "stale_ptr_check")]
+            generation: Generation::unknown(),
+            #[cfg(feature = "store_tracking")]
+            stored_at: None,
             _marker: PhantomData,
         }
     }
 
+    /// Returns the tagged pointer, i.e. the address together with the tag.
+    pub(crate) fn data(&self) -> usize {
+        self.data
+    }
+
+    /// Returns the same pointer with tagged pointer `data`, possibly to another type.
+    fn with_data<U: ?Sized + Pointable, const TO: bool>(&self, data: usize) -> Ptr<'scope, U, TO> {
+        Ptr {
+            data,
+            #[cfg(feature = "stale_ptr_check")]
+            generation: self.generation,
+            #[cfg(feature = "store_tracking")]
+            stored_at: self.stored_at,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Stamps the pointer with the generation of the pinning of `scope`.
+    #[cfg(feature = "stale_ptr_check")]
+    fn stamp(mut self, scope: &Scope) -> Self {
+        self.generation = scope.generation();
+        self
+    }
+
+    #[cfg(not(feature = "stale_ptr_check"))]
+    #[inline]
+    fn stamp(self, _: &Scope) -> Self {
+        self
+    }
+
+    /// Returns the call site of the last store into the atomic pointer the pointer was loaded
+    /// from, if known.
+    #[cfg(feature = "store_tracking")]
+    fn stored_at(&self) -> Option<&'static Location<'static>> {
+        self.stored_at
+    }
+
+    #[cfg(not(feature = "store_tracking"))]
+    #[inline]
+    fn stored_at(&self) -> Option<&'static Location<'static>> {
+        None
+    }
+
+    /// Checks that the pointer may be used with `scope`.
+    ///
+    /// # Panics
+    ///
+    /// With the `stale_ptr_check` feature, panics if the pointer was loaded in an earlier pinning
+    /// of the mutator `scope` belongs to.
+    #[cfg(feature = "stale_ptr_check")]
+    pub(crate) fn check(&self, scope: &Scope) {
+        if !self.generation.may_use_in(scope.generation()) {
+            misuse::report(
+                MisuseCheck::StalePointer,
+                Some(scope.realm_arc()),
+                format_args!(
+                    "pointer loaded in an earlier pinning used with the scope of a later pinning"
+                ),
+            );
+        }
+    }
+
+    #[cfg(not(feature = "stale_ptr_check"))]
+    #[inline]
+    pub(crate) fn check(&self, _: &Scope) {}
+
     /// Returns `true` if the pointer is null.
     ///
     /// # Examples
@@ -862,7 +2059,7 @@ impl<'scope, T> Ptr<'scope, T> {
     /// });
     /// ```
     pub fn is_null(&self) -> bool {
-        self.as_raw().is_null()
+        self.address() == 0
     }
 
     /// Converts the pointer to a raw pointer (without the tag).
@@ -882,13 +2079,126 @@ impl<'scope, T> Ptr<'scope, T> {
     ///     assert_eq!(p.as_raw(), raw);
     /// });
     /// ```
-    pub fn as_raw(&self) -> *const T {
-        (self.data & !low_bits::<T>()) as *const T
+    pub fn as_raw(&self) -> *const T
+    where
+        T: Sized,
+    {
+        data_address::<T, HIGH_TAG>(self.data) as *const T
+    }
+
+    /// Converts the pointer into a raw pointer to the object, without the tag.
+    ///
+    /// This is the same as [`as_raw`], and pairs with [`Ptr::from_raw`]. The tag is stripped; to
+    /// round-trip it, read it with [`tag`] first and put it back with [`with_tag`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Ptr;
+    ///
+    /// let p = Ptr::from_raw(Box::into_raw(Box::new(1234u64))).with_tag(5);
+    /// let (raw, tag) = (p.into_raw(), p.tag());
+    ///
+    /// let q = Ptr::from_raw(raw).with_tag(tag);
+    /// assert_eq!((q.as_raw(), q.tag()), (p.as_raw(), 5));
+    /// # unsafe { drop(Box::from_raw(raw as *mut u64)) };
+    /// ```
+    ///
+    /// [`as_raw`]: struct.Ptr.html#method.as_raw
+    /// [`Ptr::from_raw`]: struct.Ptr.html#method.from_raw
+    /// [`tag`]: struct.Ptr.html#method.tag
+    /// [`with_tag`]: struct.Ptr.html#method.with_tag
+    pub fn into_raw(self) -> *const T
+    where
+        T: Sized,
+    {
+        self.as_raw()
+    }
+
+    /// Returns the address of the object, without the tag.
+    ///
+    /// This is useful e.g. for hashing objects by address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::from_owned(Owned::new(0u64).with_tag(3));
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(p.address(), p.as_raw() as usize);
+    /// #   unsafe { scope.defer_drop(p) }
+    /// });
+    /// ```
+    pub fn address(&self) -> usize {
+        data_address::<T, HIGH_TAG>(self.data)
+    }
+
+    /// Returns `true` if the address of the object is a multiple of `align`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Ptr;
+    ///
+    /// let p = Ptr::from_raw(0x1000 as *const u64);
+    /// assert!(p.is_aligned_to(8));
+    /// assert!(!p.is_aligned_to(0x2000));
+    /// ```
+    pub fn is_aligned_to(&self, align: usize) -> bool {
+        assert!(align.is_power_of_two(), "alignment is not a power of two");
+        self.address() & (align - 1) == 0
+    }
+
+    /// Hints that the object is about to be read, so that the CPU can fetch it into the cache
+    /// ahead of time.
+    ///
+    /// This compiles to a prefetch instruction on x86 and AArch64, and to nothing elsewhere. It
+    /// doesn't dereference the pointer, so it may be called on any pointer, even a null one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new([0u64; 8]);
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     p.prefetch_read();
+    ///     // Do some other work while the object is fetched.
+    ///     assert_eq!(unsafe { p.deref() }.iter().sum::<u64>(), 0);
+    /// #   unsafe { scope.defer_drop(p) }
+    /// });
+    /// ```
+    #[inline]
+    pub fn prefetch_read(&self) {
+        prefetch::read(self.address());
+    }
+
+    /// Hints that the object is about to be written, so that the CPU can fetch it into the cache
+    /// for writing ahead of time.
+    ///
+    /// Like [`prefetch_read`], this may be called on any pointer, even a null one.
+    ///
+    /// [`prefetch_read`]: struct.Ptr.html#method.prefetch_read
+    #[inline]
+    pub fn prefetch_write(&self) {
+        prefetch::write(self.address());
     }
 
     /// Dereferences the pointer.
     ///
-    /// Returns a reference to the pointee that is valid in `'scope`.
+    /// Returns a reference to the pointee that is valid in `'scope`. Since a `Ptr<'scope, T>` can
+    /// only be loaded with a `&'scope Scope`, the reference can't outlive the pinning that keeps
+    /// the object alive, and the borrow checker rejects any attempt to return it from the closure
+    /// passed to [`pin`]. Use [`as_ref_in`] to tie the reference to a particular scope instead.
     ///
     /// # Safety
     ///
@@ -918,8 +2228,11 @@ impl<'scope, T> Ptr<'scope, T> {
     ///     }
     /// });
     /// ```
+    ///
+    /// [`pin`]: fn.pin.html
+    /// [`as_ref_in`]: struct.Ptr.html#method.as_ref_in
     pub unsafe fn deref(&self) -> &'scope T {
-        &*self.as_raw()
+        T::deref(self.address() as *mut ())
     }
 
     /// Converts the pointer to a reference.
@@ -955,7 +2268,177 @@ impl<'scope, T> Ptr<'scope, T> {
     /// });
     /// ```
     pub unsafe fn as_ref(&self) -> Option<&'scope T> {
-        self.as_raw().as_ref()
+        if self.is_null() {
+            None
+        } else {
+            Some(self.deref())
+        }
+    }
+
+    /// Converts the pointer to a reference, or returns an error describing the null pointer.
+    ///
+    /// This is like [`as_ref`], except that the error carries the tag of the pointer, and with
+    /// the `store_tracking` feature, the call site of the last store into the atomic pointer it
+    /// was loaded from. That helps find out why a pointer is unexpectedly null.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`as_ref`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// // A null pointer marked with tag 1, e.g. by a removal.
+    /// let a = Atomic::<i32>::null();
+    /// a.store(Ptr::null().with_tag(1), SeqCst);
+    /// epoch::pin(|scope| {
+    ///     let err = unsafe { a.load(SeqCst, scope).try_deref() }.unwrap_err();
+    ///     assert_eq!(err.tag(), 1);
+    ///     assert!(err.to_string().starts_with("dereferenced a null pointer with tag 1"));
+    /// });
+    /// ```
+    ///
+    /// [`as_ref`]: struct.Ptr.html#method.as_ref
+    pub unsafe fn try_deref(&self) -> Result<&'scope T, DerefError> {
+        if self.is_null() {
+            Err(DerefError {
+                tag: self.tag(),
+                stored_at: self.stored_at(),
+            })
+        } else {
+            Ok(self.deref())
+        }
+    }
+
+    /// Converts the pointer to a reference that is valid as long as `scope` is borrowed, or
+    /// returns `None` if the pointer is null.
+    ///
+    /// This is [`as_ref`] with the lifetime of the reference narrowed down to that of `scope`,
+    /// which is useful when the pointer came from somewhere with a longer lifetime, e.g. from
+    /// [`assume_static`], yet the object should only be accessed while `scope` is pinned. With the
+    /// `stale_ptr_check` feature, it also checks that the pointer was loaded in the current
+    /// pinning of `scope`.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`as_ref`] apply.
+    ///
+    /// # Panics
+    ///
+    /// With the `stale_ptr_check` feature, panics if the pointer was loaded in an earlier pinning
+    /// of the mutator `scope` belongs to.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     unsafe {
+    ///         assert_eq!(p.as_ref_in(scope), Some(&1234));
+    ///         # drop(p.into_owned());
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`as_ref`]: struct.Ptr.html#method.as_ref
+    /// [`assume_static`]: struct.Ptr.html#method.assume_static
+    pub unsafe fn as_ref_in<'g>(&self, scope: &'g Scope) -> Option<&'g T>
+    where
+        'scope: 'g,
+    {
+        self.check(scope);
+        self.as_ref()
+    }
+
+    /// Converts the pointer to a reference, or returns `None` if it is null.
+    ///
+    /// Unlike [`as_ref`], this is safe: implementing [`EpochNode`] for `T` asserts that every
+    /// pointer to `T` can be dereferenced.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, EpochNode};
+    /// use std::sync::atomic::Ordering::Acquire;
+    ///
+    /// struct Node(u32);
+    ///
+    /// unsafe impl EpochNode for Node {}
+    ///
+    /// let a = Atomic::new(Node(1234));
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(Acquire, scope);
+    ///     assert_eq!(p.get().map(|n| n.0), Some(1234));
+    ///     # unsafe { scope.defer_drop(p) }
+    /// });
+    /// ```
+    ///
+    /// [`as_ref`]: struct.Ptr.html#method.as_ref
+    /// [`EpochNode`]: trait.EpochNode.html
+    pub fn get(&self) -> Option<&'scope T>
+    where
+        T: EpochNode,
+    {
+        unsafe { self.as_ref() }
+    }
+
+    /// Extends the lifetime of the pointer to `'static`.
+    ///
+    /// This is meant for pointers to objects that are intentionally never reclaimed, like the
+    /// sentinel nodes of a data structure. Objects created for that purpose are better leaked
+    /// right away with [`Owned::leak`].
+    ///
+    /// # Safety
+    ///
+    /// The object must never be deferred for destruction or otherwise deallocated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let head = Atomic::new(0);
+    /// let sentinel: Ptr<'static, i32> = epoch::pin(|scope| unsafe {
+    ///     head.load(SeqCst, scope).assume_static()
+    /// });
+    /// assert_eq!(unsafe { *sentinel.deref() }, 0);
+    /// ```
+    ///
+    /// [`Owned::leak`]: struct.Owned.html#method.leak
+    pub unsafe fn assume_static(self) -> Ptr<'static, T, HIGH_TAG> {
+        Ptr::from_data(self.data)
+    }
+
+    /// Takes ownership of the pointee.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must not be null, and the object must not be reachable by other mutators
+    /// anymore, nor may it be deferred for destruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.swap(Ptr::null(), SeqCst, scope);
+    ///     // No other thread has access to `a`.
+    ///     assert_eq!(*unsafe { p.into_owned() }, 1234);
+    /// });
+    /// ```
+    pub unsafe fn into_owned(self) -> Owned<T, HIGH_TAG> {
+        Owned::from_data(self.data)
     }
 
     /// Returns the tag stored within the pointer.
@@ -973,7 +2456,7 @@ impl<'scope, T> Ptr<'scope, T> {
     /// });
     /// ```
     pub fn tag(&self) -> usize {
-        self.data & low_bits::<T>()
+        data_tag::<T, HIGH_TAG>(self.data)
     }
 
     /// Returns the same pointer, but tagged with `tag`. `tag` is truncated to be fit into the
@@ -996,19 +2479,151 @@ impl<'scope, T> Ptr<'scope, T> {
     /// });
     /// ```
     pub fn with_tag(&self, tag: usize) -> Self {
-        Self::from_data(data_with_tag::<T>(self.data, tag))
+        let tag = check_tag::<T, HIGH_TAG>(tag, misuse::report_raw);
+        self.with_data(data_with_tag::<T, HIGH_TAG>(self.data, tag))
+    }
+
+    /// Casts to a pointer to type `U`, keeping the tag.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointer is not properly aligned for `U`, or if the tag doesn't fit into the
+    /// unused bits of a pointer to `U`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Ptr;
+    ///
+    /// let p = unsafe { Ptr::from_raw(Box::into_raw(Box::new(1234u64))) };
+    /// let q = p.cast::<u32>();
+    /// assert_eq!(q.as_raw() as usize, p.as_raw() as usize);
+    /// # unsafe { drop(Box::from_raw(p.as_raw() as *mut u64)) };
+    /// ```
+    pub fn cast<U>(&self) -> Ptr<'scope, U, HIGH_TAG> {
+        if self.address() & low_bits::<U>() != 0 {
+            misuse::report(MisuseCheck::UnalignedPointer, None, format_args!("unaligned pointer"));
+        }
+        if self.tag() & !tag_layout::<U, HIGH_TAG>().0 != 0 {
+            misuse::report(MisuseCheck::TagOverflow, None, format_args!("tag doesn't fit"));
+        }
+        self.with_data(self.data)
+    }
+
+    /// Casts to a pointer to type `U`, keeping the tag, without any checks.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be properly aligned for `U`, and the tag must fit into the unused bits of
+    /// a pointer to `U`.
+    pub unsafe fn cast_unchecked<U>(&self) -> Ptr<'scope, U, HIGH_TAG> {
+        self.with_data(self.data)
+    }
+
+    /// Returns the same pointer, but storing its tag in the high bits above the address. The tag
+    /// is truncated to fit into [`HIGH_TAG_BITS`] bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address itself uses the high bits.
+    ///
+    /// [`HIGH_TAG_BITS`]: constant.HIGH_TAG_BITS.html
+    pub fn with_high_tags(&self) -> Ptr<'scope, T, true> {
+        self.with_data(data_retagged::<T, HIGH_TAG, true>(self.data, misuse::report_raw))
+    }
+
+    /// Returns the same pointer, but storing its tag in the unused least significant bits of the
+    /// address. The tag is truncated to fit.
+    pub fn with_low_tags(&self) -> Ptr<'scope, T> {
+        self.with_data(data_retagged::<T, HIGH_TAG, false>(self.data, misuse::report_raw))
     }
 }
 
-impl<'scope, T> Default for Ptr<'scope, T> {
+impl<'scope, T: ?Sized + Pointable, const HIGH_TAG: bool> Default for Ptr<'scope, T, HIGH_TAG> {
     fn default() -> Self {
-        Ptr::null()
+        Ptr::from_data(0)
+    }
+}
+
+/// The error returned by [`Ptr::try_deref`] when the pointer is null.
+///
+/// [`Ptr::try_deref`]: struct.Ptr.html#method.try_deref
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DerefError {
+    tag: usize,
+    stored_at: Option<&'static Location<'static>>,
+}
+
+impl DerefError {
+    /// Returns the tag of the null pointer.
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    /// Returns the call site of the last store into the atomic pointer the null pointer was
+    /// loaded from.
+    ///
+    /// This is `None` unless the `store_tracking` feature is enabled, and the pointer was loaded
+    /// with [`Atomic::load`] from an atomic pointer that has been stored into.
+    ///
+    /// [`Atomic::load`]: struct.Atomic.html#method.load
+    pub fn stored_at(&self) -> Option<&'static Location<'static>> {
+        self.stored_at
+    }
+}
+
+impl fmt::Display for DerefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dereferenced a null pointer with tag {}", self.tag)?;
+        if let Some(site) = self.stored_at {
+            write!(f, ", last stored at {}", site)?;
+        }
+        Ok(())
     }
 }
 
+impl Error for DerefError {}
+
+/// The reason [`Atomic::transfer_to`] didn't move a pointer.
+///
+/// [`Atomic::transfer_to`]: struct.Atomic.html#method.transfer_to
+#[derive(Debug)]
+pub enum TransferError<'scope, T: 'scope + ?Sized + Pointable, const HIGH_TAG: bool = false> {
+    /// The source was null, so there was nothing to move. Nothing was changed.
+    Empty,
+    /// The destination held this pointer, so the object was put back into the source.
+    Occupied(Ptr<'scope, T, HIGH_TAG>),
+    /// The destination held this pointer, and another mutator stored into the source before the
+    /// object could be put back, so its destruction was deferred.
+    Retired(Ptr<'scope, T, HIGH_TAG>),
+}
+
+/// How [`Atomic::store_tagged`] tags the pointer it stores.
+///
+/// [`Atomic::store_tagged`]: struct.Atomic.html#method.store_tagged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TagPolicy {
+    /// Store the tag of the new pointer, as [`Atomic::store`] does.
+    ///
+    /// [`Atomic::store`]: struct.Atomic.html#method.store
+    New,
+    /// Keep the tag of the current value, ignoring the tag of the new pointer.
+    Keep,
+    /// Clear the tag.
+    Clear,
+    /// Store this tag instead of the tag of the new pointer.
+    Set(usize),
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Ptr;
+    use std::mem::MaybeUninit;
+    use std::sync::atomic::Ordering::{Relaxed, Release};
+
+    use crossbeam_utils::scoped;
+
+    use super::{tag_layout, Atomic, Owned, Ptr};
+    use pin;
 
     #[test]
     fn valid_tag_i8() {
@@ -1019,4 +2634,342 @@ mod tests {
     fn valid_tag_i64() {
         Ptr::<i64>::null().with_tag(7);
     }
+
+    #[test]
+    fn validate_zero_sized() {
+        let a = Atomic::new(());
+        pin(|scope| {
+            a.load(Relaxed, scope);
+            a.store_owned(Owned::new(()), Relaxed);
+        });
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "corrupted atomic pointer")]
+    fn validate_store() {
+        let a = Atomic::<u64>::null();
+        a.store(Ptr::from_raw(0x100 as *const u64), Relaxed);
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "corrupted atomic pointer")]
+    fn validate_load() {
+        let a = Atomic::<u64>::from_data(0x18);
+        pin(|scope| {
+            a.load(Relaxed, scope);
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "strict")]
+    #[should_panic(expected = "doesn't fit into the tag bits")]
+    fn strict_tag_out_of_range() {
+        Owned::new(0u64).with_tag(8);
+    }
+
+    #[test]
+    fn compare_and_set_tag_keeps_pointer() {
+        let a = Atomic::new(0u64);
+        pin(|scope| {
+            let p = a.load(Relaxed, scope);
+            assert!(a.compare_and_set_tag(p, 7, Relaxed, scope).is_ok());
+            let q = a.load(Relaxed, scope);
+            assert_eq!(p.as_raw(), q.as_raw());
+            assert_eq!(q.tag(), 7);
+            assert_eq!(a.compare_and_set_tag(p, 1, Relaxed, scope).unwrap_err().tag(), 7);
+        });
+    }
+
+    #[test]
+    fn swap_if_tag_ignores_pointer() {
+        let a = Atomic::new(0u64);
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            let q = Owned::new(1u64).into_ptr(scope);
+            a.store(q, Relaxed);
+
+            let closed = Ptr::null().with_tag(1);
+            assert_eq!(a.swap_if_tag(0, closed, Relaxed, scope).unwrap().as_raw(), q.as_raw());
+            assert_eq!(a.swap_if_tag(0, p, Relaxed, scope).unwrap_err().tag(), 1);
+            assert_eq!(a.load(Relaxed, scope).tag(), 1);
+
+            scope.defer_drop(p);
+            scope.defer_drop(q);
+        });
+    }
+
+    #[test]
+    fn compare_and_set_owned_returns_tag() {
+        let a = Atomic::new(0u64);
+        pin(|scope| unsafe {
+            let new = Owned::new(1u64).with_tag(3);
+            let (_, new) = a.compare_and_set_owned(Ptr::null(), new, Relaxed, scope).unwrap_err();
+            assert_eq!((*new, new.tag()), (1, 3));
+
+            let new = new.with_high_tags().with_tag(200);
+            let b = Atomic::from_owned(Owned::new(0u64).with_high_tags());
+            let result = b.compare_and_set_owned(Ptr::default(), new, Relaxed, scope);
+            let (_, new) = result.unwrap_err();
+            assert_eq!(*new, 1);
+            assert_eq!(new.tag(), 200 & tag_layout::<u64, true>().0);
+
+            let current = b.load(Relaxed, scope);
+            let p = b.compare_and_set_weak_owned(current.with_tag(1), new, Relaxed, scope);
+            assert_eq!(p.unwrap_err().1.tag(), 200 & tag_layout::<u64, true>().0);
+
+            scope.defer_drop(a.load(Relaxed, scope));
+            scope.defer_drop(current);
+        });
+    }
+
+    #[test]
+    fn unlink_fails_on_mismatch() {
+        let a = Atomic::new(0u64);
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            let q = p.with_tag(1);
+            assert_eq!(a.unlink(q, Relaxed, scope).unwrap_err().tag(), 0);
+            assert!(a.unlink(p, Relaxed, scope).is_ok());
+            assert!(a.load(Relaxed, scope).is_null());
+        });
+    }
+
+    #[test]
+    fn high_tags_keep_pointer() {
+        let a = Atomic::from_owned(Owned::new(7u8).with_high_tags());
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            let max = tag_layout::<u8, true>().0;
+            assert!(a.compare_and_set_tag(p, max, Relaxed, scope).is_ok());
+            let q = a.fetch_and(!1, Relaxed, scope);
+            assert_eq!(q.tag(), max);
+            let q = a.load(Relaxed, scope);
+            assert_eq!(q.tag(), max & !1);
+            assert_eq!(q.as_raw(), p.as_raw());
+            assert_eq!(*q.deref(), 7);
+            assert_eq!(q.with_low_tags().with_high_tags().as_raw(), p.as_raw());
+            scope.defer_drop(q);
+        });
+    }
+
+    #[test]
+    fn fetch_tag_ops_keep_pointer() {
+        let a = Atomic::new(7u64);
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            let max = tag_layout::<u64, false>().0;
+            let parts = |q: Ptr<u64>| (q.as_raw(), q.tag());
+
+            assert_eq!(parts(a.fetch_or(!0, Relaxed, scope)), (p.as_raw(), 0));
+            assert_eq!(parts(a.fetch_xor(!0 ^ 1, Relaxed, scope)), (p.as_raw(), max));
+            assert_eq!(parts(a.fetch_and(!1, Relaxed, scope)), (p.as_raw(), 1));
+            assert_eq!(parts(a.load(Relaxed, scope)), (p.as_raw(), 0));
+
+            // Concurrent toggles of different bits don't disturb each other or the pointer.
+            scoped::scope(|s| for bit in 0..3 {
+                let a = &a;
+                s.spawn(move || pin(|scope| for _ in 0..1001 {
+                    a.fetch_xor(1 << bit, Relaxed, scope);
+                }));
+            });
+            let q = a.load(Relaxed, scope);
+            assert_eq!(parts(q), (p.as_raw(), max));
+            assert_eq!(*q.deref(), 7);
+            scope.defer_drop(q);
+        });
+    }
+
+    #[test]
+    fn raw_round_trips_keep_tags() {
+        let o = Owned::new(7u64).with_tag(5).with_high_tags();
+        let tag = o.tag();
+        let raw = o.into_raw();
+        assert_eq!(raw as usize & 7, 0);
+
+        let o = unsafe { Owned::from_raw(raw) }.with_high_tags().with_tag(tag);
+        assert_eq!((*o, o.tag()), (7, 5));
+
+        let p = Ptr::from_raw(Box::into_raw(o.with_tag(3).into_box())).with_tag(6);
+        let q = Ptr::from_raw(p.into_raw()).with_tag(p.tag());
+        assert_eq!((q.as_raw(), q.tag()), (raw as *const u64, 6));
+        unsafe { drop(q.into_owned().into_box()) }
+    }
+
+    #[test]
+    fn load_all_and_range() {
+        let tagged = Atomic::from_owned(Owned::new(2u64).with_tag(5));
+        let buckets = [Atomic::new(0), Atomic::null(), tagged];
+        pin(|scope| unsafe {
+            let ptrs = Atomic::load_all(&buckets, scope);
+            assert_eq!(ptrs[0].as_ref(), Some(&0));
+            assert!(ptrs[1].is_null());
+            assert_eq!((ptrs[2].as_ref(), ptrs[2].tag()), (Some(&2), 5));
+
+            let mut range = [Ptr::null(); 2];
+            Atomic::load_range(&buckets[1..], &mut range, scope);
+            assert_eq!(range[1].as_raw(), ptrs[2].as_raw());
+            assert_eq!(range[1].tag(), 5);
+
+            scope.defer_drop(ptrs[0]);
+            scope.defer_drop(ptrs[2]);
+        });
+    }
+
+    #[test]
+    fn fetch_update_owned_counts() {
+        let a = Atomic::new(0);
+        scoped::scope(|s| for _ in 0..4 {
+            let a = &a;
+            s.spawn(move || for _ in 0..1000 {
+                pin(|scope| unsafe {
+                    let prev = a.fetch_update_owned(Relaxed, Relaxed, scope, |p| {
+                        Some(Owned::new(p.deref() + 1))
+                    });
+                    scope.defer_drop(prev.unwrap());
+                });
+            });
+        });
+        assert_eq!(*unsafe { a.into_owned() }, 4000);
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "deferred destruction of the static object")]
+    fn static_objects_are_never_reclaimed() {
+        static SENTINEL: [u64; 2] = [7, 8];
+        let a = Atomic::from_static(&SENTINEL);
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            assert_eq!(p.deref(), &[7, 8]);
+            scope.defer_drop(p);
+        });
+    }
+
+    #[test]
+    fn arrays() {
+        for len in [0, 1, 100] {
+            let mut o = Owned::<[MaybeUninit<u64>]>::init(len).with_tag(7);
+            assert_eq!(o.len(), len);
+            for (i, slot) in o.iter_mut().enumerate() {
+                *slot = MaybeUninit::new(i as u64);
+            }
+
+            let a = Atomic::from_owned(o);
+            pin(|scope| unsafe {
+                let p = a.load(Relaxed, scope);
+                assert_eq!(p.tag(), 7);
+                assert!(p.is_aligned_to(8));
+                let sum = p.deref().iter().map(|x| x.assume_init()).sum::<u64>();
+                assert_eq!(sum, (0..len as u64).sum::<u64>());
+            });
+            unsafe { drop(a.into_owned()) }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "tag doesn't fit")]
+    fn cast_checks_tag() {
+        let p = Ptr::<u64>::null().with_tag(4);
+        p.cast::<u16>();
+    }
+
+    #[test]
+    #[cfg(feature = "store_tracking")]
+    fn try_deref_reports_store_site() {
+        let a = Atomic::new(0u64);
+        pin(|scope| unsafe {
+            let p = a.load(Relaxed, scope);
+            assert_eq!(p.try_deref(), Ok(&0));
+
+            let line = line!() + 1;
+            let old = a.swap(Ptr::null().with_tag(3), Relaxed, scope);
+            let err = a.load(Relaxed, scope).try_deref().unwrap_err();
+            assert_eq!(err.tag(), 3);
+            let site = err.stored_at().unwrap();
+            assert_eq!((site.file(), site.line()), (file!(), line));
+            drop(old.into_owned());
+        });
+    }
+
+    #[test]
+    fn transfer_to_occupied_puts_back() {
+        use super::TransferError;
+
+        let src = Atomic::from_owned(Owned::new(1).with_tag(1));
+        let dst = Atomic::new(2);
+        pin(|scope| unsafe {
+            match src.transfer_to(&dst, Relaxed, scope) {
+                Err(TransferError::Occupied(occupant)) => assert_eq!(*occupant.deref(), 2),
+                _ => panic!("the destination is occupied"),
+            }
+            let back = src.load(Relaxed, scope);
+            assert_eq!((*back.deref(), back.tag()), (1, 1));
+
+            drop(dst.swap(Ptr::null(), Relaxed, scope).into_owned());
+            let moved = src.transfer_to(&dst, Relaxed, scope).unwrap();
+            assert_eq!(dst.load(Relaxed, scope).as_raw(), moved.as_raw());
+            assert_eq!(moved.tag(), 1);
+            drop(moved.into_owned());
+        });
+    }
+
+    #[test]
+    fn relaxed_failure_value_is_acquired() {
+        let a = Atomic::<Box<u64>>::null();
+        scoped::scope(|s| {
+            s.spawn(|| a.store_owned(Owned::new(Box::new(7)), Release));
+            pin(|scope| loop {
+                // Fails once the box is stored, which must make its contents visible.
+                match a.compare_and_set(Ptr::null(), Ptr::null(), Relaxed, scope) {
+                    Ok(()) => continue,
+                    Err(current) => break assert_eq!(**unsafe { current.deref() }, 7),
+                }
+            });
+        });
+        pin(|scope| drop(unsafe { a.load(Relaxed, scope).into_owned() }));
+    }
+
+    #[test]
+    fn relaxed_tag_failure_value_is_acquired() {
+        let a = Atomic::<Box<u64>>::null();
+        scoped::scope(|s| {
+            let new = Owned::new(Box::new(7)).with_tag(1);
+            s.spawn(|| a.store_owned(new, Release));
+            pin(|scope| loop {
+                // Fails once the tagged box is stored, which must make its contents visible.
+                match a.compare_and_set_tag(Ptr::null(), 0, Relaxed, scope) {
+                    Ok(_) => continue,
+                    Err(current) => break assert_eq!(**unsafe { current.deref() }, 7),
+                }
+            });
+        });
+        pin(|scope| drop(unsafe { a.load(Relaxed, scope).into_owned() }));
+    }
+
+    #[test]
+    fn store_keeping_tag_races_with_marking() {
+        let a = Atomic::<u64>::null();
+        let objects: Vec<_> = (0..1000).map(|i| Owned::new(i).into_box()).collect();
+        scoped::scope(|s| {
+            s.spawn(|| {
+                for object in &objects {
+                    a.store_keeping_tag(Ptr::from_raw(&**object), Release);
+                }
+            });
+            s.spawn(|| {
+                pin(|scope| {
+                    // Mark the pointer while it is being stored into.
+                    while a.load(Relaxed, scope).is_null() {}
+                    a.fetch_or(1, Relaxed, scope);
+                })
+            });
+        });
+        pin(|scope| {
+            let p = a.load(Relaxed, scope);
+            assert_eq!(p.tag(), 1, "the mark was lost");
+            assert_eq!(p.as_raw(), &*objects[999] as *const u64);
+        });
+    }
 }
@@ -0,0 +1,197 @@
+//! Destruction time budget
+//!
+//! Garbage is destroyed by whichever thread happens to collect it, in the middle of its own work.
+//! A single destructor or deferred function that takes long, e.g. because it blocks on a lock or
+//! does I/O, silently turns into a latency spike of that thread, which is hard to trace back to the
+//! garbage that caused it.
+//!
+//! With the `destroy_budget` feature, every piece of garbage is timed while it's destroyed, once a
+//! budget is set with [`set_destroy_budget`]. Garbage that exceeds it is reported to the handler
+//! set with [`set_slow_destroy_handler`], or, if there is none, is reported as misuse, i.e. the
+//! collection panics right after the destruction.
+//!
+//! [`set_destroy_budget`]: fn.set_destroy_budget.html
+//! [`set_slow_destroy_handler`]: fn.set_slow_destroy_handler.html
+
+use std::fmt;
+use std::panic::Location;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread;
+use std::time::Duration;
+
+use hook::Slot;
+use misuse::{self, MisuseCheck};
+use sys::{self, Timestamp};
+
+/// The budget in nanoseconds, or zero if there is none.
+static BUDGET: AtomicU64 = AtomicU64::new(0);
+
+/// The handler set with `set_slow_destroy_handler`, if any.
+static HANDLER: Slot<dyn Fn(&SlowDestroy) + Send + Sync> = Slot::new();
+
+/// A destruction of garbage that exceeded the budget, passed to the handler set with
+/// [`set_slow_destroy_handler`].
+///
+/// [`set_slow_destroy_handler`]: fn.set_slow_destroy_handler.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SlowDestroy {
+    /// How long the destruction took.
+    pub elapsed: Duration,
+    /// The budget it exceeded.
+    pub budget: Duration,
+    /// The call site that retired the garbage, if known.
+    ///
+    /// It's only known for objects retired with `Scope::defer_drop`, not for deferred functions.
+    /// The `garbage_backtrace` feature prints where any garbage was deferred instead.
+    pub site: Option<&'static Location<'static>>,
+}
+
+impl fmt::Display for SlowDestroy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "destroying garbage took {:?}, exceeding the budget of {:?}",
+            self.elapsed,
+            self.budget
+        )?;
+        if let Some(site) = self.site {
+            write!(f, " (retired at {})", site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the budget for destroying a single piece of garbage, if one is set.
+pub fn destroy_budget() -> Option<Duration> {
+    match BUDGET.load(Relaxed) {
+        0 => None,
+        nanos => Some(Duration::from_nanos(nanos)),
+    }
+}
+
+/// Sets the budget for destroying a single piece of garbage, i.e. running the destructor of a
+/// retired object or calling a deferred function, or removes it with `None`.
+///
+/// Objects coalesced into a single entry of a bag are timed together. Without a budget, which is
+/// the default, nothing is timed.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::time::Duration;
+///
+/// epoch::set_destroy_budget(Some(Duration::from_millis(10)));
+/// assert_eq!(epoch::destroy_budget(), Some(Duration::from_millis(10)));
+/// epoch::set_destroy_budget(None);
+/// ```
+pub fn set_destroy_budget(budget: Option<Duration>) {
+    let nanos = budget.map_or(0, |b| b.as_nanos().clamp(1, u64::MAX as u128) as u64);
+    BUDGET.store(nanos, Relaxed);
+}
+
+/// Sets `handler` to be called whenever destroying a piece of garbage exceeds the budget set with
+/// [`set_destroy_budget`].
+///
+/// The handler runs on the thread that destroyed the garbage, right after the destruction, and it
+/// replaces any handler set before. Without a handler, a slow destruction is reported as misuse
+/// with [`MisuseCheck::SlowDestroy`], and panics.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_slow_destroy_handler(|slow| eprintln!("{}", slow));
+/// ```
+///
+/// [`set_destroy_budget`]: fn.set_destroy_budget.html
+/// [`MisuseCheck::SlowDestroy`]: enum.MisuseCheck.html#variant.SlowDestroy
+pub fn set_slow_destroy_handler<F>(handler: F)
+where
+    F: Fn(&SlowDestroy) + Send + Sync + 'static,
+{
+    HANDLER.set(Some(Arc::new(handler)));
+}
+
+/// Returns when a destruction starts, if a budget is set.
+#[inline]
+pub fn start() -> Option<Timestamp> {
+    if BUDGET.load(Relaxed) == 0 {
+        None
+    } else {
+        Some(sys::now())
+    }
+}
+
+/// Reports the destruction of the garbage retired at `site` that started at `start` if it exceeded
+/// the budget.
+#[inline]
+pub fn finish(start: Option<Timestamp>, site: Option<&'static Location<'static>>) {
+    if let Some(start) = start {
+        let elapsed = start.elapsed();
+        let budget = BUDGET.load(Relaxed);
+        if budget != 0 && elapsed.as_nanos() > budget as u128 {
+            report(SlowDestroy {
+                elapsed,
+                budget: Duration::from_nanos(budget),
+                site,
+            });
+        }
+    }
+}
+
+/// Hands `slow` to the handler, or reports it as misuse.
+#[cold]
+#[inline(never)]
+fn report(slow: SlowDestroy) {
+    if let Some(handler) = HANDLER.get() {
+        handler(&slow);
+        return;
+    }
+
+    // Panicking again while unwinding would abort.
+    if !thread::panicking() {
+        misuse::report(MisuseCheck::SlowDestroy, None, format_args!("{}", slow));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use testkit::{self, Recorder};
+    use Collector;
+    use super::*;
+
+    /// How long the slow destructions reported by each thread took.
+    static REPORTED: Recorder<Duration> = Recorder::new();
+
+    #[test]
+    fn reports_slow_deferred_functions() {
+        REPORTED.set_once(|| {
+            set_slow_destroy_handler(|slow| REPORTED.record(slow.elapsed));
+            set_destroy_budget(Some(Duration::from_millis(5)));
+        });
+
+        let collector = Collector::new();
+        let name = "reports_slow_deferred_functions";
+        testkit::on_thread(name, move || {
+            let handle = collector.register();
+            handle.pin(|scope| unsafe {
+                scope.defer(|| thread::sleep(Duration::from_millis(20)));
+                scope.defer(|| ());
+            });
+            drop(handle);
+            drop(collector);
+        });
+
+        let slow = REPORTED.on_thread(name);
+        assert_eq!(slow.len(), 1);
+        assert!(slow[0] >= Duration::from_millis(20));
+    }
+}
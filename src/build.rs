@@ -0,0 +1,219 @@
+//! Building linked structures before publishing them
+//!
+//! A structure made of several nodes, e.g. a chain or a small tree, is usually built privately
+//! and then made visible to other threads by storing its root into an [`Atomic`]. Once a node
+//! is linked into another one through an [`Atomic`] field it is no longer owned by anybody, so if
+//! a later step of the construction fails, all nodes built so far have to be tracked down and
+//! freed by hand.
+//!
+//! [`Atomic::publish_with`] takes care of that: nodes are added to a [`Builder`] as they are
+//! created, and either the whole structure gets published with a single store, or all the nodes
+//! get destroyed.
+//!
+//! [`Atomic`]: struct.Atomic.html
+//! [`Atomic::publish_with`]: struct.Atomic.html#method.publish_with
+//! [`Builder`]: struct.Builder.html
+
+use std::sync::atomic::Ordering::Release;
+
+use atomic::{Atomic, Owned, Ptr};
+use raw::Pointable;
+use epoch_safe::EpochSafe;
+use mutator::Scope;
+
+/// Destroys the node at the given address.
+type Destroy = unsafe fn(*mut ());
+
+/// The nodes of a structure under construction.
+///
+/// Created by [`Atomic::publish_with`]. Unless the structure gets published, all nodes added to
+/// the builder are destroyed when it's dropped, in the reverse order of addition.
+///
+/// Nodes must refer to each other only through [`Atomic`] fields or other pointers that don't own
+/// their targets, since every node is destroyed on its own.
+///
+/// [`Atomic::publish_with`]: struct.Atomic.html#method.publish_with
+/// [`Atomic`]: struct.Atomic.html
+pub struct Builder<'scope> {
+    scope: &'scope Scope,
+    /// Addresses of the nodes added so far, with the functions that destroy them.
+    nodes: Vec<(usize, Destroy)>,
+}
+
+impl<'scope> Builder<'scope> {
+    /// Returns the scope the structure is built in.
+    pub fn scope(&self) -> &'scope Scope {
+        self.scope
+    }
+
+    /// Returns the number of nodes added so far.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if no nodes have been added.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Adds `node` to the structure, and returns a pointer to it that can be stored into other
+    /// nodes.
+    pub fn add<T, const HIGH_TAG: bool>(
+        &mut self,
+        node: Owned<T, HIGH_TAG>,
+    ) -> Ptr<'scope, T, HIGH_TAG>
+    where
+        T: ?Sized + Pointable + Send + EpochSafe + 'static,
+    {
+        let ptr = node.into_ptr(self.scope);
+        self.nodes.push((ptr.address(), T::drop));
+        ptr
+    }
+}
+
+impl<'scope> Drop for Builder<'scope> {
+    fn drop(&mut self) {
+        for &(address, destroy) in self.nodes.iter().rev() {
+            unsafe { destroy(address as *mut ()) }
+        }
+    }
+}
+
+impl<T: ?Sized + Pointable, const HIGH_TAG: bool> Atomic<T, HIGH_TAG> {
+    /// Builds a linked structure with `build` and publishes its root.
+    ///
+    /// `build` adds the nodes of the structure to the given [`Builder`] and returns the root. The
+    /// root is then stored into the atomic pointer with a single `Release` swap, and the previous
+    /// value is returned. If `build` returns an error or panics, all nodes added to the builder
+    /// are destroyed, and the atomic pointer is left untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::{Acquire, Relaxed};
+    ///
+    /// struct Node {
+    ///     value: u32,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// let head = Atomic::null();
+    ///
+    /// epoch::pin(|scope| {
+    ///     // Building fails halfway through, and the nodes built so far are destroyed.
+    ///     let result: Result<_, &str> = head.publish_with(scope, |b| {
+    ///         let mut next = Ptr::null();
+    ///         for value in ["3", "2", "x", "1"].iter() {
+    ///             let value = value.parse().map_err(|_| "not a number")?;
+    ///             next = b.add(Owned::new(Node { value, next: Atomic::from_ptr(next) }));
+    ///         }
+    ///         Ok(next)
+    ///     });
+    ///     assert!(result.is_err());
+    ///     assert!(head.load(Acquire, scope).is_null());
+    ///
+    ///     let result: Result<_, ()> = head.publish_with(scope, |b| {
+    ///         let mut next = Ptr::null();
+    ///         for value in 0..3 {
+    ///             next = b.add(Owned::new(Node { value, next: Atomic::from_ptr(next) }));
+    ///         }
+    ///         Ok(next)
+    ///     });
+    ///     assert!(result.unwrap().is_null());
+    ///
+    ///     let first = unsafe { head.load(Acquire, scope).deref() };
+    ///     assert_eq!(first.value, 2);
+    /// #   let mut p = head.load(Relaxed, scope);
+    /// #   while !p.is_null() {
+    /// #       let next = unsafe { p.deref() }.next.load(Relaxed, scope);
+    /// #       unsafe { scope.defer_drop(p) }
+    /// #       p = next;
+    /// #   }
+    /// });
+    /// ```
+    ///
+    /// [`Builder`]: struct.Builder.html
+    pub fn publish_with<'scope, E, F>(
+        &self,
+        scope: &'scope Scope,
+        build: F,
+    ) -> Result<Ptr<'scope, T, HIGH_TAG>, E>
+    where
+        F: FnOnce(&mut Builder<'scope>) -> Result<Ptr<'scope, T, HIGH_TAG>, E>,
+    {
+        let mut builder = Builder {
+            scope,
+            nodes: Vec::new(),
+        };
+        let root = build(&mut builder)?;
+        let previous = self.swap(root, Release, scope);
+        builder.nodes.clear();
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+    use pin;
+    use super::*;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Node {
+        children: [Atomic<Node>; 2],
+    }
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, SeqCst);
+        }
+    }
+
+    /// Builds a complete binary tree of the given depth, failing once `fail_at` nodes are added.
+    fn tree<'scope>(
+        b: &mut Builder<'scope>,
+        depth: usize,
+        fail_at: usize,
+    ) -> Result<Ptr<'scope, Node>, ()> {
+        if b.len() == fail_at {
+            return Err(());
+        }
+        let node = b.add(Owned::new(Node { children: [Atomic::null(), Atomic::null()] }));
+        if depth > 0 {
+            for child in unsafe { node.deref() }.children.iter() {
+                child.store(tree(b, depth - 1, fail_at)?, Relaxed);
+            }
+        }
+        Ok(node)
+    }
+
+    #[test]
+    fn rolled_back_on_failure() {
+        let root = Atomic::null();
+
+        pin(|scope| {
+            assert!(root.publish_with(scope, |b| tree(b, 3, 10)).is_err());
+            assert_eq!(DROPPED.load(SeqCst), 10);
+
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                root.publish_with::<(), _>(scope, |b| {
+                    tree(b, 3, usize::MAX)?;
+                    panic!("building failed");
+                })
+            }));
+            assert!(result.is_err());
+            assert_eq!(DROPPED.load(SeqCst), 25);
+            assert!(root.load(SeqCst, scope).is_null());
+
+            let old = root.publish_with(scope, |b| tree(b, 3, usize::MAX)).unwrap();
+            assert!(old.is_null());
+            assert_eq!(DROPPED.load(SeqCst), 25);
+            assert!(!root.load(SeqCst, scope).is_null());
+        });
+    }
+}
@@ -0,0 +1,127 @@
+//! Cancellable deferrals
+//!
+//! Speculative algorithms sometimes retire an object and later find out that the retirement must
+//! be undone, e.g. because the operation that unlinked it was rolled back. Functions deferred with
+//! [`Scope::defer_cancellable`] can be cancelled until they are executed.
+//!
+//! A cancelled function stays in its bag as a tombstone. Tombstones are compacted away when a bag
+//! is sealed, so they don't take up room in the global queue or collection work. The numbers of
+//! inspected and compacted entries are counted, see [`compaction_stats`].
+//!
+//! [`Scope::defer_cancellable`]: struct.Scope.html#method.defer_cancellable
+//! [`compaction_stats`]: fn.compaction_stats.html
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+/// The function hasn't been executed or cancelled yet.
+const PENDING: usize = 0;
+/// The function has been cancelled.
+const CANCELLED: usize = 1;
+/// The function is being or has been executed.
+const RUN: usize = 2;
+
+/// Number of garbage entries inspected while sealing bags.
+static SEALED: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of tombstones compacted away while sealing bags.
+static COMPACTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Shared state of a cancellable deferral.
+pub(crate) type State = Arc<AtomicUsize>;
+
+/// A token for cancelling a deferred function.
+///
+/// Created by [`Scope::defer_cancellable`].
+///
+/// [`Scope::defer_cancellable`]: struct.Scope.html#method.defer_cancellable
+#[derive(Clone, Debug)]
+pub struct CancelToken {
+    state: State,
+}
+
+impl CancelToken {
+    /// Returns a new token for a pending function.
+    pub(crate) fn new() -> Self {
+        CancelToken { state: Arc::new(AtomicUsize::new(PENDING)) }
+    }
+
+    /// Returns the state shared with the deferred function.
+    pub(crate) fn state(&self) -> State {
+        self.state.clone()
+    }
+
+    /// Cancels the function, and returns `true` if it hadn't been executed yet.
+    ///
+    /// Once this returns `true`, the function is never executed, but just dropped.
+    pub fn cancel(&self) -> bool {
+        match self.state.compare_exchange(PENDING, CANCELLED, AcqRel, Acquire) {
+            Ok(_) => true,
+            Err(state) => state == CANCELLED,
+        }
+    }
+
+    /// Returns `true` if the function has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        is_cancelled(&self.state)
+    }
+}
+
+/// Returns `true` if the function with `state` has been cancelled.
+#[inline]
+pub(crate) fn is_cancelled(state: &State) -> bool {
+    state.load(Acquire) == CANCELLED
+}
+
+/// Claims the function with `state` for execution, and returns `false` if it has been cancelled.
+#[inline]
+pub(crate) fn start(state: &State) -> bool {
+    state.compare_exchange(PENDING, RUN, AcqRel, Acquire).is_ok()
+}
+
+/// Records that sealing a bag inspected `sealed` entries, of which `compacted` were tombstones.
+pub(crate) fn record_seal(sealed: usize, compacted: usize) {
+    SEALED.fetch_add(sealed, Relaxed);
+    if compacted > 0 {
+        COMPACTED.fetch_add(compacted, Relaxed);
+    }
+}
+
+/// Numbers of garbage entries seen while sealing bags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of entries inspected, including tombstones.
+    pub sealed: usize,
+    /// Number of tombstones of cancelled functions that were compacted away.
+    pub compacted: usize,
+}
+
+impl CompactionStats {
+    /// Returns the fraction of inspected entries that were tombstones, or zero if none were
+    /// inspected.
+    pub fn tombstone_ratio(&self) -> f64 {
+        if self.sealed == 0 {
+            0.0
+        } else {
+            self.compacted as f64 / self.sealed as f64
+        }
+    }
+}
+
+/// Returns the numbers of garbage entries inspected and compacted away while sealing bags so far.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// let stats = epoch::compaction_stats();
+/// println!("{:.1}% of deferred functions were cancelled", stats.tombstone_ratio() * 100.0);
+/// ```
+pub fn compaction_stats() -> CompactionStats {
+    CompactionStats {
+        sealed: SEALED.load(Relaxed),
+        compacted: COMPACTED.load(Relaxed),
+    }
+}
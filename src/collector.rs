@@ -0,0 +1,1257 @@
+//! Collectors
+//!
+//! By default all threads are registered in a single global realm, so the garbage of unrelated
+//! data structures is collected together, and a thread pinned for one of them holds up the garbage
+//! of all of them. A [`Collector`] is a realm of its own: a data structure can own one, and the
+//! threads accessing it register with [`Collector::register`] and pin through the returned
+//! [`LocalHandle`].
+//!
+//! Pointers loaded in a scope of one collector must only be used with objects whose garbage is
+//! deferred in scopes of the same collector.
+//!
+//! When the last handle and the last clone of a collector are dropped, all of its remaining
+//! garbage is destroyed.
+//!
+//! A collector created with [`Collector::scoped`] destroys its garbage when the call returns, so
+//! its deferred functions may borrow data that outlives the call.
+//!
+//! [`Collector`]: struct.Collector.html
+//! [`Collector::register`]: struct.Collector.html#method.register
+//! [`Collector::scoped`]: struct.Collector.html#method.scoped
+//! [`LocalHandle`]: struct.LocalHandle.html
+
+use std::alloc::GlobalAlloc;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use allocator::AllocRef;
+use atomic::{Atomic, Ptr};
+use garbage::{MAX_CLASSES, MAX_OBJECTS};
+use global::{self, COLLECT_BUDGET, MaintenanceStatus, Realm, Stats};
+use grace::GracePeriod;
+use misuse::{self, MisuseCheck};
+use mutator::{self, Mutator, PINS_BETWEEN_COLLECT, Scope};
+use pinned::PinnedScope;
+use random::RandomSource;
+use sys;
+#[cfg(feature = "watchdog")]
+use watchdog::{self, StalledMutator};
+
+/// The collection policy of a [`Collector`].
+///
+/// Small bags and frequent collection bound the amount of garbage waiting for destruction, which
+/// suits latency-sensitive workloads. Large bags and infrequent collection amortize the cost of
+/// reclamation over more objects, which suits batch workloads.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{Collector, CollectorConfig};
+///
+/// let mut config = CollectorConfig::default();
+/// config.bag_capacity = 4;
+/// config.pins_between_collect = 16;
+///
+/// let collector = Collector::with_config(config);
+/// assert_eq!(collector.config().bag_capacity, 4);
+/// ```
+///
+/// [`Collector`]: struct.Collector.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CollectorConfig {
+    /// The number of objects a local bag holds before it's pushed into the global queue, at most
+    /// [`MAX_BAG_CAPACITY`].
+    ///
+    /// [`MAX_BAG_CAPACITY`]: constant.MAX_BAG_CAPACITY.html
+    pub bag_capacity: usize,
+    /// The number of pinnings after which a thread collects some garbage.
+    pub pins_between_collect: usize,
+    /// The maximum number of objects destroyed by a single collection.
+    pub collect_budget: usize,
+    /// The thresholds of the garbage classes, which garbage can be deferred into with
+    /// [`Scope::defer_in_class`].
+    ///
+    /// [`Scope::defer_in_class`]: struct.Scope.html#method.defer_in_class
+    pub classes: [GarbageClass; MAX_GARBAGE_CLASSES],
+    /// The source of random numbers for the heuristics of the collector.
+    ///
+    /// Threads start counting pinnings towards their first collection at a random offset, so
+    /// that threads registered together don't collect in lockstep.
+    pub random: RandomSource,
+    /// The number of objects waiting for destruction above which the garbage of bags being
+    /// sealed is spilled to disk, if it's of a type registered with [`register_spill_type`].
+    ///
+    /// The default is `usize::MAX`, i.e. nothing is ever spilled.
+    ///
+    /// [`register_spill_type`]: fn.register_spill_type.html
+    #[cfg(feature = "spill")]
+    pub spill_threshold: usize,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        CollectorConfig {
+            bag_capacity: MAX_BAG_CAPACITY,
+            pins_between_collect: PINS_BETWEEN_COLLECT,
+            collect_budget: COLLECT_BUDGET,
+            classes: [GarbageClass::default(); MAX_GARBAGE_CLASSES],
+            random: RandomSource::default(),
+            #[cfg(feature = "spill")]
+            spill_threshold: usize::MAX,
+        }
+    }
+}
+
+/// The largest capacity of local bags, which is also the default.
+pub const MAX_BAG_CAPACITY: usize = MAX_OBJECTS;
+
+/// The number of garbage classes of a collector.
+pub const MAX_GARBAGE_CLASSES: usize = MAX_CLASSES;
+
+/// The thresholds of a class of garbage, e.g. file descriptors or large buffers.
+///
+/// Garbage of each class is counted separately in the local bag of every mutator. Once the count
+/// or the size of the garbage of a class reaches one of its thresholds, the bag is flushed
+/// according to the class's [`FlushPolicy`]. This way a few retired scarce resources get
+/// reclaimed promptly, even if there is little other garbage to fill the bag. By default, the
+/// thresholds are never reached.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{Collector, CollectorConfig, FlushPolicy};
+///
+/// const FDS: usize = 1;
+///
+/// let mut config = CollectorConfig::default();
+/// config.classes[FDS].max_count = 4;
+/// config.classes[FDS].policy = FlushPolicy::Collect;
+///
+/// let collector = Collector::with_config(config);
+/// collector.register().pin(|scope| unsafe {
+///     // The fourth file descriptor triggers a collection.
+///     for _ in 0..4 {
+///         scope.defer_in_class(FDS, 0, || { /* close(fd) */ });
+///     }
+/// });
+/// ```
+///
+/// [`FlushPolicy`]: enum.FlushPolicy.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GarbageClass {
+    /// The number of objects of the class in the local bag that triggers a flush.
+    pub max_count: usize,
+    /// The number of bytes of the class in the local bag that triggers a flush.
+    pub max_bytes: usize,
+    /// How the local bag is flushed once a threshold is reached.
+    pub policy: FlushPolicy,
+}
+
+impl Default for GarbageClass {
+    fn default() -> Self {
+        GarbageClass {
+            max_count: usize::MAX,
+            max_bytes: usize::MAX,
+            policy: FlushPolicy::Seal,
+        }
+    }
+}
+
+/// How the local bag is flushed once the garbage of a class reaches a threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// The bag is sealed and pushed into the global queue, to be destroyed once the epoch has
+    /// advanced enough.
+    Seal,
+    /// The bag is flushed like with [`Scope::flush`], which also attempts to advance the epoch and
+    /// collects some garbage.
+    ///
+    /// [`Scope::flush`]: struct.Scope.html#method.flush
+    Collect,
+}
+
+/// A garbage collector with its own epoch and garbage, independent of the global one.
+///
+/// Cloning a collector returns another reference to the same one.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{Atomic, Collector};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let collector = Collector::new();
+/// let handle = collector.register();
+///
+/// let a = Atomic::new(1);
+/// handle.pin(|scope| unsafe {
+///     let old = a.swap(Atomic::new(2).load(SeqCst, scope), SeqCst, scope);
+///     scope.defer_drop(old);
+///     # scope.defer_drop(a.load(SeqCst, scope));
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Collector {
+    realm: Arc<Realm>,
+}
+
+impl Collector {
+    /// Returns a new collector.
+    pub fn new() -> Self {
+        Self::with_config(CollectorConfig::default())
+    }
+
+    /// Returns a new collector with the collection policy `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any setting is zero, or if the bag capacity exceeds [`MAX_BAG_CAPACITY`].
+    ///
+    /// [`MAX_BAG_CAPACITY`]: constant.MAX_BAG_CAPACITY.html
+    pub fn with_config(config: CollectorConfig) -> Self {
+        Self::with_alloc_ref(config, AllocRef::global())
+    }
+
+    /// Returns a new collector with the collection policy `config`, whose internal structures are
+    /// allocated from `alloc`.
+    ///
+    /// The bags of garbage pushed to the collector are kept in nodes allocated from `alloc`, and
+    /// freed with it once collected. The local bags of mutators are still allocated on the global
+    /// heap. Objects can be allocated from a custom allocator as well with [`Owned::new_in`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any setting is zero, or if the bag capacity exceeds [`MAX_BAG_CAPACITY`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{Collector, CollectorConfig};
+    /// use std::alloc::System;
+    ///
+    /// static ARENA: System = System;
+    ///
+    /// let collector = Collector::with_allocator(CollectorConfig::default(), &ARENA);
+    /// collector.register().pin(|scope| scope.flush());
+    /// ```
+    ///
+    /// [`Owned::new_in`]: struct.Owned.html#method.new_in
+    /// [`MAX_BAG_CAPACITY`]: constant.MAX_BAG_CAPACITY.html
+    pub fn with_allocator<A>(config: CollectorConfig, alloc: &'static A) -> Self
+    where
+        A: GlobalAlloc + Sync,
+    {
+        Self::with_alloc_ref(config, AllocRef::custom(alloc))
+    }
+
+    /// Returns a new collector with the collection policy `config`, allocating from `alloc`.
+    fn with_alloc_ref(config: CollectorConfig, alloc: AllocRef) -> Self {
+        let invalid = MisuseCheck::InvalidConfig;
+        if config.bag_capacity == 0 || config.bag_capacity > MAX_BAG_CAPACITY {
+            let message = format_args!("bag capacity must be between 1 and {}", MAX_BAG_CAPACITY);
+            misuse::report(invalid, None, message);
+        }
+        if config.pins_between_collect == 0 {
+            let message = format_args!("pins between collections must not be zero");
+            misuse::report(invalid, None, message);
+        }
+        if config.collect_budget == 0 {
+            let message = format_args!("collection budget must not be zero");
+            misuse::report(invalid, None, message);
+        }
+
+        Collector {
+            realm: Arc::new(Realm::with_allocator(config, alloc)),
+        }
+    }
+
+    /// Returns the collection policy of the collector.
+    pub fn config(&self) -> CollectorConfig {
+        self.realm.config
+    }
+
+    /// Registers the current thread with the collector, returning a handle to pin it with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with [`set_max_mutators`] would be exceeded.
+    ///
+    /// [`set_max_mutators`]: fn.set_max_mutators.html
+    pub fn register(&self) -> LocalHandle {
+        LocalHandle {
+            mutator: Mutator::with_realm(self.realm.clone()),
+            collector: self.clone(),
+        }
+    }
+
+    /// Registers the current thread with the collector as a reader, returning a handle to pin it
+    /// with.
+    ///
+    /// The scopes of a reader handle can only load pointers, not defer garbage. The handle never
+    /// allocates a local bag, and it leaves garbage collection to the handles that produce
+    /// garbage. This documents and enforces at compile time that a subsystem never retires
+    /// memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with [`set_max_mutators`] would be exceeded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{Atomic, Collector};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let collector = Collector::new();
+    /// let a = Atomic::new(7);
+    ///
+    /// let reader = collector.register_reader();
+    /// reader.pin(|scope| {
+    ///     let p = scope.load(&a, SeqCst);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&7));
+    ///     // `scope.defer_drop(p)` doesn't compile.
+    /// });
+    /// # collector.register().pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`set_max_mutators`]: fn.set_max_mutators.html
+    pub fn register_reader(&self) -> ReaderHandle {
+        ReaderHandle {
+            mutator: Mutator::reader_in(self.realm.clone()),
+            collector: self.clone(),
+        }
+    }
+
+    /// Pins a new mutator of the collector that isn't tied to the current thread, and returns its
+    /// scope.
+    ///
+    /// Unlike the scopes of [`LocalHandle::pin`], the returned scope can be sent to other threads,
+    /// e.g. held across an `.await` in a task that may migrate between threads. See
+    /// [`PinnedScope`].
+    ///
+    /// [`LocalHandle::pin`]: struct.LocalHandle.html#method.pin
+    /// [`PinnedScope`]: struct.PinnedScope.html
+    pub fn pin_owned(&self) -> PinnedScope {
+        PinnedScope::new(self.realm.clone())
+    }
+
+    /// Returns the number of bytes of memory used by the collector itself, excluding the objects
+    /// its garbage refers to.
+    ///
+    /// This counts the registry entries of the registered threads, the queues of garbage bags, and
+    /// the local bags of the registered threads. A registered thread costs a registry entry of a
+    /// few hundred bytes. Its local bag takes up about two kilobytes, and is only allocated once it
+    /// first defers garbage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    ///
+    /// let collector = Collector::new();
+    /// let idle = collector.memory_usage();
+    ///
+    /// let handle = collector.register();
+    /// handle.pin(|scope| unsafe { scope.defer(|| ()) });
+    /// assert!(collector.memory_usage() > idle);
+    /// ```
+    pub fn memory_usage(&self) -> usize {
+        Mutator::temporary_in(self.realm.clone()).pin(|scope| self.realm.memory_usage(scope))
+    }
+
+    /// Returns a snapshot of the state of the collector.
+    ///
+    /// See [`Stats`] for what is reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    ///
+    /// let collector = Collector::new();
+    /// let handle = collector.register();
+    ///
+    /// handle.pin(|scope| {
+    ///     let stats = collector.stats();
+    ///     assert_eq!((stats.mutators, stats.pinned), (1, 1));
+    ///     unsafe { scope.defer(|| ()) }
+    /// });
+    /// ```
+    ///
+    /// [`Stats`]: struct.Stats.html
+    pub fn stats(&self) -> Stats {
+        Mutator::temporary_in(self.realm.clone()).pin(|scope| self.realm.stats(scope))
+    }
+
+    /// Destroys all garbage of the collector that can be destroyed right now, and returns the
+    /// number of deferred destructions and functions that can't be yet.
+    ///
+    /// Garbage can't be destroyed while a mutator that was pinned when it was deferred stays
+    /// pinned. Garbage still in the local bags of registered mutators isn't counted. Once the last
+    /// handle to the collector is dropped, no mutator is left, and all remaining garbage is
+    /// destroyed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    ///
+    /// let collector = Collector::new();
+    /// let handle = collector.register();
+    ///
+    /// handle.pin(|scope| {
+    ///     unsafe { scope.defer(|| ()) }
+    ///     scope.flush();
+    ///     assert_eq!(collector.try_drain(), 1);
+    /// });
+    /// assert_eq!(collector.try_drain(), 0);
+    /// ```
+    pub fn try_drain(&self) -> usize {
+        if cfg!(feature = "leak_only") {
+            return 0;
+        }
+
+        let mutator = Mutator::temporary_in(self.realm.clone());
+        // Garbage expires once the epoch has advanced twice, which takes a pinning each.
+        for _ in 0..3 {
+            mutator.pin(global::reclaim);
+        }
+        mutator.pin(|scope| self.realm.stats(scope).deferred)
+    }
+
+    /// Advances the epoch and destroys expired garbage for about `budget`, and returns whether
+    /// work is left.
+    ///
+    /// This is meant to be called on every tick of an event loop, so that a single-threaded
+    /// server decides when reclamation happens and how long it may take. Garbage is destroyed one
+    /// slice of at most [`CollectorConfig::collect_budget`] objects at a time, and the budget is
+    /// checked after each slice, so a call overruns it by at most one slice. Pinnings inside the
+    /// destructors run by the call don't collect garbage on their own.
+    ///
+    /// Garbage still in the local bags of registered threads isn't seen until they flush them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{Collector, MaintenanceStatus};
+    /// use std::time::Duration;
+    ///
+    /// let collector = Collector::new();
+    /// let handle = collector.register();
+    /// handle.pin(|scope| {
+    ///     unsafe { scope.defer(|| ()) }
+    ///     scope.flush();
+    /// });
+    ///
+    /// // On every tick of the event loop:
+    /// let status = collector.poll_maintenance(Duration::from_millis(1));
+    /// # let mut status = status;
+    /// # while status == MaintenanceStatus::Pending {
+    /// #     status = collector.poll_maintenance(Duration::from_millis(1));
+    /// # }
+    /// assert_eq!(status, MaintenanceStatus::Done);
+    /// ```
+    ///
+    /// [`CollectorConfig::collect_budget`]: struct.CollectorConfig.html#structfield.collect_budget
+    pub fn poll_maintenance(&self, budget: Duration) -> MaintenanceStatus {
+        let deadline = sys::now() + budget;
+        mutator::inhibiting_collection(|| {
+            let mutator = Mutator::temporary_in(self.realm.clone());
+            let mut epoch = None;
+            loop {
+                // The epoch advances at most once per pinning, and garbage expires after two.
+                let status = mutator.pin(|scope| global::maintain(scope, deadline));
+                if status != MaintenanceStatus::Blocked || epoch == Some(self.epoch()) {
+                    return status;
+                }
+                if sys::now() >= deadline {
+                    return MaintenanceStatus::Pending;
+                }
+                epoch = Some(self.epoch());
+            }
+        })
+    }
+
+    /// Returns a future that resolves once every mutator of the collector pinned now has
+    /// unpinned.
+    ///
+    /// See [`synchronize_async`] for the global garbage collector.
+    ///
+    /// [`synchronize_async`]: fn.synchronize_async.html
+    pub fn synchronize_async(&self) -> GracePeriod {
+        GracePeriod::new(self.realm.clone())
+    }
+
+    /// Returns the current global epoch of the collector.
+    ///
+    /// Every advancement adds 2 to the epoch, wrapping around on overflow.
+    pub fn epoch(&self) -> usize {
+        self.realm.epoch.load(Ordering::SeqCst)
+    }
+
+    /// Schedules `callback` to run once, the first time the global epoch of the collector is
+    /// `epoch` or later.
+    ///
+    /// If it already is, `callback` runs right away on the current thread. Otherwise it runs on
+    /// the thread that advances the epoch, while that thread is pinned, so it should be quick. This
+    /// lets a multi-phase operation, e.g. a resize of a hash table, move on to the next phase once
+    /// a grace period has elapsed, without polling. Callbacks still waiting when the collector is
+    /// dropped are dropped without being run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    ///
+    /// let collector = Collector::new();
+    /// let done = Arc::new(AtomicBool::new(false));
+    ///
+    /// // Two advancements from now, a grace period has elapsed.
+    /// let d = done.clone();
+    /// collector.on_epoch(collector.epoch().wrapping_add(4), move || {
+    ///     d.store(true, Ordering::SeqCst);
+    /// });
+    /// assert!(!done.load(Ordering::SeqCst));
+    ///
+    /// collector.try_drain();
+    /// assert!(done.load(Ordering::SeqCst));
+    /// ```
+    pub fn on_epoch<F>(&self, epoch: usize, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.realm.epoch.on_epoch(epoch, Box::new(callback));
+    }
+
+    /// Returns the mutators of the collector that have been pinned for at least `min_duration`.
+    ///
+    /// See [`stalled_mutators`] for the global garbage collector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let collector = Collector::new();
+    /// let handle = collector.register();
+    ///
+    /// handle.pin(|_| {
+    ///     // The first call only sees that the mutator is pinned.
+    ///     assert!(collector.stalled_mutators(Duration::from_millis(10)).is_empty());
+    ///     thread::sleep(Duration::from_millis(10));
+    ///     let stalled = collector.stalled_mutators(Duration::from_millis(10));
+    ///     assert_eq!(stalled.len(), 1);
+    ///     assert!(stalled[0].pinned_for >= Duration::from_millis(10));
+    /// });
+    /// ```
+    ///
+    /// [`stalled_mutators`]: fn.stalled_mutators.html
+    #[cfg(feature = "watchdog")]
+    pub fn stalled_mutators(&self, min_duration: Duration) -> Vec<StalledMutator> {
+        Mutator::temporary_in(self.realm.clone())
+            .pin(|scope| watchdog::stalled_in(&self.realm, min_duration, scope))
+    }
+
+    /// Creates a collector whose garbage may borrow data living for `'env`, and calls `f` with it.
+    ///
+    /// All garbage of the collector is destroyed before this returns, even if `f` panics. Handles
+    /// can't escape `f`, and a handle that is leaked instead of dropped leaks its local garbage
+    /// too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    /// use std::sync::Mutex;
+    ///
+    /// let log = Mutex::new(Vec::new());
+    /// Collector::scoped(|collector| {
+    ///     let handle = collector.register();
+    ///     handle.defer(|| log.lock().unwrap().push("retired"));
+    /// });
+    /// assert_eq!(*log.lock().unwrap(), ["retired"]);
+    /// ```
+    pub fn scoped<'env, F, R>(f: F) -> R
+    where
+        F: FnOnce(&ScopedCollector<'env>) -> R,
+    {
+        let collector = ScopedCollector {
+            realm: Arc::new(Realm::new()),
+            env: PhantomData,
+        };
+
+        // Every handle has been dropped or leaked by the time this runs, so none is pinned.
+        // Protected objects are pushed back only at the end, so that they aren't popped again.
+        defer! {
+            Mutator::temporary_in(collector.realm.clone()).pin(|scope| unsafe {
+                for mut bag in global::destroy_all(&collector.realm, scope) {
+                    global::push_bag(&mut bag, scope);
+                }
+            })
+        }
+
+        f(&collector)
+    }
+}
+
+impl Collector {
+    /// Returns a handle to the collector of `realm`.
+    pub(crate) fn from_realm(realm: Arc<Realm>) -> Self {
+        Collector { realm }
+    }
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector::new()
+    }
+}
+
+impl PartialEq for Collector {
+    /// Returns `true` if both are references to the same collector.
+    fn eq(&self, other: &Collector) -> bool {
+        Arc::ptr_eq(&self.realm, &other.realm)
+    }
+}
+
+impl Eq for Collector {}
+
+impl fmt::Debug for Collector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Collector").finish()
+    }
+}
+
+/// A thread registered with a [`Collector`].
+///
+/// The thread is unregistered when the handle is dropped, and its local garbage is handed to the
+/// collector.
+///
+/// [`Collector`]: struct.Collector.html
+pub struct LocalHandle {
+    mutator: Mutator<'static>,
+    collector: Collector,
+}
+
+impl LocalHandle {
+    /// Pins the thread in the collector, and executes `f` with the scope.
+    ///
+    /// This is like [`pin`], except that garbage deferred in the scope is collected by the
+    /// collector of the handle, and only waits for threads pinned in that collector.
+    ///
+    /// [`pin`]: fn.pin.html
+    pub fn pin<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope) -> R,
+    {
+        self.mutator.pin(f)
+    }
+
+    /// Returns `true` if the handle is pinned.
+    pub fn is_pinned(&self) -> bool {
+        self.mutator.is_pinned()
+    }
+
+    /// Returns the number of calls to [`pin`] on the handle that are currently running, counting
+    /// nested ones.
+    ///
+    /// Pinning while already pinned is cheap, but it keeps the thread pinned for as long as the
+    /// outermost call runs. Libraries can use this to check that they aren't called from within
+    /// a long-lived pinning.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    ///
+    /// let handle = Collector::new().register();
+    /// assert_eq!(handle.pin_depth(), 0);
+    /// handle.pin(|_| {
+    ///     handle.pin(|_| assert_eq!(handle.pin_depth(), 2));
+    ///     assert_eq!(handle.pin_depth(), 1);
+    /// });
+    /// ```
+    ///
+    /// [`pin`]: struct.LocalHandle.html#method.pin
+    pub fn pin_depth(&self) -> usize {
+        self.mutator.depth()
+    }
+
+    /// Sets `hook` to be called with the number of bytes and the number of objects freed after
+    /// each collection slice that runs on the thread while the handle is pinned.
+    ///
+    /// Only memory whose size is known is counted as bytes, i.e. objects retired with
+    /// [`Scope::defer_drop`] and the like, but deferred functions count as objects all the same.
+    /// The hook replaces any hook set before on the handle. It runs right after the slice, so it
+    /// should be cheap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::Collector;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::AtomicUsize;
+    /// use std::sync::atomic::Ordering::Relaxed;
+    ///
+    /// let handle = Collector::new().register();
+    /// let freed = Arc::new(AtomicUsize::new(0));
+    /// let f = freed.clone();
+    /// handle.set_reclaim_hook(move |bytes, _objects| {
+    ///     f.fetch_add(bytes, Relaxed);
+    /// });
+    /// ```
+    ///
+    /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+    #[cfg(feature = "reclaim_hook")]
+    pub fn set_reclaim_hook<F>(&self, hook: F)
+    where
+        F: Fn(usize, usize) + Send + Sync + 'static,
+    {
+        self.mutator.set_reclaim_hook(Some(Arc::new(hook)));
+    }
+
+    /// Removes the hook set with [`set_reclaim_hook`], if any.
+    ///
+    /// [`set_reclaim_hook`]: struct.LocalHandle.html#method.set_reclaim_hook
+    #[cfg(feature = "reclaim_hook")]
+    pub fn clear_reclaim_hook(&self) {
+        self.mutator.set_reclaim_hook(None);
+    }
+
+    /// Returns the collector the handle is registered with.
+    pub fn collector(&self) -> &Collector {
+        &self.collector
+    }
+}
+
+impl fmt::Debug for LocalHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LocalHandle").finish()
+    }
+}
+
+/// Runs `f` with the current thread pinning in `collector` instead of the default collector.
+///
+/// Within `f`, the free [`pin`] and [`is_pinned`] functions resolve to a handle registered with
+/// `collector`, so a library that pins through them can be redirected into a private collector
+/// without changing its code. The previous binding is restored once `f` returns or panics. Other
+/// threads, including those spawned by `f`, are unaffected.
+///
+/// [`pin`]: fn.pin.html
+/// [`is_pinned`]: fn.is_pinned.html
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Collector, Owned};
+///
+/// let collector = Collector::new();
+/// epoch::with_collector(&collector, || {
+///     epoch::pin(|scope| unsafe {
+///         // Destroyed by `collector`, not the default collector.
+///         scope.defer_drop(Owned::new(7).into_ptr(scope));
+///     });
+/// });
+/// assert_eq!(collector.try_drain(), 0);
+/// ```
+pub fn with_collector<F, R>(collector: &Collector, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let handle = collector.register();
+    global::with_rebound(&handle.mutator, f)
+}
+
+/// A thread registered with a [`Collector`] as a reader.
+///
+/// See [`Collector::register_reader`].
+///
+/// [`Collector`]: struct.Collector.html
+/// [`Collector::register_reader`]: struct.Collector.html#method.register_reader
+pub struct ReaderHandle {
+    mutator: Mutator<'static>,
+    collector: Collector,
+}
+
+impl ReaderHandle {
+    /// Pins the thread in the collector, and executes `f` with the read-only scope.
+    pub fn pin<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&ReadScope) -> R,
+    {
+        self.mutator.pin(|scope| f(ReadScope::new(scope)))
+    }
+
+    /// Returns `true` if the handle is pinned.
+    pub fn is_pinned(&self) -> bool {
+        self.mutator.is_pinned()
+    }
+
+    /// Returns the collector the handle is registered with.
+    pub fn collector(&self) -> &Collector {
+        &self.collector
+    }
+}
+
+impl fmt::Debug for ReaderHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReaderHandle").finish()
+    }
+}
+
+/// A witness that a [`ReaderHandle`] is pinned, which can load pointers but not defer garbage.
+///
+/// [`ReaderHandle`]: struct.ReaderHandle.html
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ReadScope {
+    scope: Scope,
+}
+
+impl ReadScope {
+    /// Wraps `scope`, hiding the methods that defer garbage.
+    fn new(scope: &Scope) -> &ReadScope {
+        // `ReadScope` is a transparent wrapper around `Scope`.
+        unsafe { &*(scope as *const Scope as *const ReadScope) }
+    }
+
+    /// Loads a pointer from `atomic`, like [`Atomic::load`].
+    ///
+    /// [`Atomic::load`]: struct.Atomic.html#method.load
+    pub fn load<'scope, T, const HIGH_TAG: bool>(
+        &'scope self,
+        atomic: &Atomic<T, HIGH_TAG>,
+        ord: Ordering,
+    ) -> Ptr<'scope, T, HIGH_TAG> {
+        atomic.load(ord, &self.scope)
+    }
+}
+
+/// A collector whose garbage may borrow data living for `'env`.
+///
+/// See [`Collector::scoped`].
+///
+/// [`Collector::scoped`]: struct.Collector.html#method.scoped
+pub struct ScopedCollector<'env> {
+    realm: Arc<Realm>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'env> ScopedCollector<'env> {
+    /// Registers the current thread with the collector, returning a handle to pin it with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with [`set_max_mutators`] would be exceeded.
+    ///
+    /// [`set_max_mutators`]: fn.set_max_mutators.html
+    pub fn register<'c>(&'c self) -> ScopedHandle<'c, 'env> {
+        ScopedHandle {
+            mutator: Mutator::with_realm(self.realm.clone()),
+            collector: PhantomData,
+        }
+    }
+}
+
+impl<'env> fmt::Debug for ScopedCollector<'env> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopedCollector").finish()
+    }
+}
+
+/// A thread registered with a [`ScopedCollector`].
+///
+/// [`ScopedCollector`]: struct.ScopedCollector.html
+pub struct ScopedHandle<'c, 'env: 'c> {
+    mutator: Mutator<'static>,
+    collector: PhantomData<&'c ScopedCollector<'env>>,
+}
+
+impl<'c, 'env> ScopedHandle<'c, 'env> {
+    /// Pins the thread in the collector, and executes `f` with the scope.
+    ///
+    /// See [`LocalHandle::pin`].
+    ///
+    /// [`LocalHandle::pin`]: struct.LocalHandle.html#method.pin
+    pub fn pin<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope) -> R,
+    {
+        self.mutator.pin(f)
+    }
+
+    /// Returns `true` if the handle is pinned.
+    pub fn is_pinned(&self) -> bool {
+        self.mutator.is_pinned()
+    }
+
+    /// Deferred execution of function `f`, which may borrow data living for `'env`.
+    ///
+    /// The function is executed once all threads pinned in the collector at the time of deferral
+    /// have been unpinned, and at the latest when the collector is torn down.
+    pub fn defer<F: FnOnce() + Send + 'env>(&self, f: F) {
+        self.mutator.pin(|scope| unsafe { scope.defer_unchecked(f) })
+    }
+}
+
+impl<'c, 'env> fmt::Debug for ScopedHandle<'c, 'env> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopedHandle").finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use global::pin;
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn collects_independently() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        assert_eq!(handle.collector(), &collector);
+
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let d = destroyed.clone();
+        handle.pin(|scope| unsafe { scope.defer(move || { d.fetch_add(1, SeqCst); }) });
+
+        // A thread pinned in the global realm doesn't hold up the collector.
+        pin(|_| {
+            assert!(!handle.is_pinned());
+            for _ in 0..10 {
+                handle.pin(|scope| scope.flush());
+            }
+        });
+        assert_eq!(destroyed.load(SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn memory_usage_counts_bags() {
+        let collector = Collector::new();
+        let idle = collector.memory_usage();
+
+        let handle = collector.register();
+        let registered = collector.memory_usage();
+        assert!(registered > idle);
+
+        handle.pin(|scope| unsafe { scope.defer(|| ()) });
+        assert!(collector.memory_usage() >= registered + ::std::mem::size_of::<::garbage::Bag>());
+
+        // The local bag is freed along with the handle.
+        drop(handle);
+        assert_eq!(collector.realm.local_bags.load(SeqCst), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn stats_count_sealed_bags() {
+        let collector = Collector::new();
+        let reader = collector.register();
+        let writer = collector.register();
+        assert_eq!(collector.stats().mutators, 2);
+
+        reader.pin(|_| {
+            writer.pin(|scope| unsafe {
+                for _ in 0..3 {
+                    scope.defer_sized(|| (), 10);
+                }
+                scope.flush();
+            });
+
+            // The pinned reader keeps the garbage from being destroyed.
+            let stats = collector.stats();
+            assert_eq!(stats.pinned, 1);
+            assert_eq!(stats.bags, 1);
+            assert_eq!(stats.deferred, 3);
+            if cfg!(feature = "stats") {
+                // The collector's own bookkeeping is deferred too.
+                assert!(stats.total_deferred_bytes.unwrap() >= 30);
+            } else {
+                assert_eq!(stats.total_deferred_bytes, None);
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn drop_drains_garbage() {
+        let collector = Collector::new();
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let reader = collector.register();
+        let writer = collector.register();
+
+        reader.pin(|_| {
+            for _ in 0..10 {
+                let destroyed = destroyed.clone();
+                writer.pin(|scope| unsafe {
+                    scope.defer(move || {
+                        destroyed.fetch_add(1, SeqCst);
+                    });
+                    scope.flush();
+                });
+            }
+            assert_eq!(collector.try_drain(), 10);
+        });
+        assert_eq!(destroyed.load(SeqCst), 0);
+
+        // The writer's garbage is handed over when it's dropped, and destroyed with the collector.
+        drop((reader, writer));
+        drop(collector);
+        assert_eq!(destroyed.load(SeqCst), 10);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn class_threshold_seals_bag() {
+        let mut config = CollectorConfig::default();
+        config.classes[1].max_bytes = 100;
+        let collector = Collector::with_config(config);
+        let reader = collector.register();
+        let writer = collector.register();
+
+        reader.pin(|_| {
+            writer.pin(|scope| unsafe {
+                // Garbage of other classes doesn't count towards the thresholds of class 1.
+                scope.defer_in_class(0, 1000, || ());
+                scope.defer_in_class(1, 60, || ());
+                scope.defer_in_class(1, 60, || ());
+
+                // The classes are counted from zero again in the new bag.
+                scope.defer_in_class(1, 60, || ());
+            });
+
+            let stats = collector.stats();
+            assert_eq!(stats.bags, 1);
+            assert_eq!(stats.deferred, 3);
+            if let Some(bytes) = stats.total_deferred_bytes {
+                assert!(bytes >= 1180);
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn reader_produces_no_garbage() {
+        let collector = Collector::new();
+        let reader = collector.register_reader();
+        let writer = collector.register();
+        let a = Atomic::new(1);
+
+        reader.pin(|scope| {
+            let p = scope.load(&a, SeqCst);
+            writer.pin(|scope| unsafe { scope.defer_drop(a.swap(Ptr::null(), SeqCst, scope)) });
+            writer.pin(|scope| scope.flush());
+
+            // The pinned reader keeps the garbage from being destroyed.
+            assert_eq!(unsafe { p.as_ref() }, Some(&1));
+        });
+        assert_eq!(collector.realm.local_bags.load(SeqCst), 1);
+
+        for _ in 0..PINS_BETWEEN_COLLECT * 2 {
+            reader.pin(|_| ());
+        }
+        drop(reader);
+        assert_eq!(collector.realm.local_bags.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn collect_unlinks_dead_mutators() {
+        let collector = Collector::new();
+        let handles = (0..64).map(|_| collector.register()).collect::<Vec<_>>();
+        let lagging = collector.register();
+
+        lagging.pin(|_| {
+            // The entries of the dropped mutators lie past the pinned one, which soon stops
+            // advancement from scanning any further.
+            drop(handles);
+            let handle = collector.register();
+            handle.pin(|scope| scope.flush());
+            assert_eq!(collector.realm.registries.deleted(), 0);
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn small_bags_overflow_early() {
+        let collector = Collector::with_config(CollectorConfig {
+            bag_capacity: 2,
+            ..Default::default()
+        });
+
+        ::std::thread::spawn(move || {
+            let handle = collector.register();
+            handle.pin(|scope| for _ in 0..5 {
+                unsafe { scope.defer(|| ()) }
+            });
+            assert_eq!(::mutator::bag_overflows(), 2);
+        }).join()
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "bag capacity must be between")]
+    fn bag_capacity_checked() {
+        Collector::with_config(CollectorConfig {
+            bag_capacity: MAX_BAG_CAPACITY + 1,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn teardown_destroys_garbage() {
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let collector = Collector::new();
+        let handle = collector.register();
+
+        for _ in 0..3 {
+            let d = destroyed.clone();
+            handle.pin(|scope| unsafe { scope.defer(move || { d.fetch_add(1, SeqCst); }) });
+        }
+        drop(collector);
+        assert_eq!(destroyed.load(SeqCst), 0);
+
+        drop(handle);
+        assert_eq!(destroyed.load(SeqCst), 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn scoped_garbage_borrows_environment() {
+        let destroyed = AtomicUsize::new(0);
+        Collector::scoped(|collector| {
+            let handle = collector.register();
+            for _ in 0..3 {
+                handle.defer(|| { destroyed.fetch_add(1, SeqCst); });
+            }
+
+            // Garbage of a leaked handle is leaked too.
+            let leaked = collector.register();
+            leaked.defer(|| { destroyed.fetch_add(10, SeqCst); });
+            ::std::mem::forget(leaked);
+        });
+        assert_eq!(destroyed.load(SeqCst), 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn with_collector_redirects_pin() {
+        let collector = Collector::new();
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let d = destroyed.clone();
+
+        with_collector(&collector, || {
+            assert!(!::is_pinned());
+            pin(|scope| unsafe {
+                assert!(Arc::ptr_eq(scope.realm_arc(), &collector.realm));
+                assert!(::is_pinned());
+                scope.defer(move || { d.fetch_add(1, SeqCst); });
+            });
+        });
+        assert_eq!(collector.try_drain(), 0);
+        assert_eq!(destroyed.load(SeqCst), 1);
+
+        // The default collector is used again, even if `f` panics.
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            with_collector(&collector, || panic!("boom"))
+        }));
+        assert!(result.is_err());
+        pin(|scope| assert!(Arc::ptr_eq(scope.realm_arc(), &global::REALM)));
+    }
+
+    #[test]
+    fn pin_depth_survives_panics() {
+        let handle = Collector::new().register();
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            handle.pin(|_| handle.pin(|_| panic!("boom")))
+        }));
+        assert!(result.is_err());
+        assert_eq!(handle.pin_depth(), 0);
+        assert!(!handle.is_pinned());
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn inhibited_pinnings_dont_collect() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let epoch = collector.realm.epoch.load(SeqCst);
+
+        pin(|scope| {
+            scope.inhibit_collection(|| {
+                for _ in 0..1000 {
+                    handle.pin(|_| ());
+                }
+            })
+        });
+        assert_eq!(collector.realm.epoch.load(SeqCst), epoch);
+
+        for _ in 0..1000 {
+            handle.pin(|_| ());
+        }
+        assert_ne!(collector.realm.epoch.load(SeqCst), epoch);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn epoch_callbacks_run_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        let collector = Collector::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let count = |runs: &Arc<AtomicUsize>| {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, SeqCst);
+            }
+        };
+
+        let epoch = collector.epoch();
+        collector.on_epoch(epoch, count(&runs));
+        assert_eq!(runs.load(SeqCst), 1);
+
+        let handle = collector.register();
+        handle.pin(|_| {
+            collector.on_epoch(epoch.wrapping_add(2), count(&runs));
+            collector.on_epoch(epoch.wrapping_add(4), count(&runs));
+            // The epoch can advance once while the handle is pinned, but not twice.
+            collector.try_drain();
+            assert_eq!(runs.load(SeqCst), 2);
+        });
+
+        collector.try_drain();
+        collector.try_drain();
+        assert_eq!(runs.load(SeqCst), 3);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn maintenance_blocked_by_pinned_mutator() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let budget = Duration::from_secs(1);
+
+        handle.pin(|scope| {
+            unsafe { scope.defer(|| ()) }
+            scope.flush();
+            assert_eq!(collector.poll_maintenance(budget), MaintenanceStatus::Blocked);
+        });
+        assert_eq!(collector.poll_maintenance(budget), MaintenanceStatus::Done);
+    }
+}
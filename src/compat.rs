@@ -0,0 +1,223 @@
+//! Drop-in replacements for `std::sync::atomic` types
+//!
+//! Lock-free code written against `AtomicPtr` from [`std::sync::atomic`] usually frees unlinked
+//! objects by hand, with `Box::from_raw`, and has to get the timing of that right on its own. The
+//! types in this module have the same method names and signatures, except that every method
+//! returning a pointer also takes the [`Scope`] that protects it, so such code can be moved over
+//! to epoch-based reclamation one call at a time: wrap the accesses in [`pin`], and replace each
+//! manual free with [`Scope::defer_drop`].
+//!
+//! # Examples
+//!
+//! ```
+//! use crossbeam_epoch::{self as epoch, Ptr};
+//! use crossbeam_epoch::compat::AtomicPtr;
+//! use std::sync::atomic::Ordering::{AcqRel, Acquire};
+//!
+//! let slot = AtomicPtr::new(Box::into_raw(Box::new(1)));
+//!
+//! epoch::pin(|scope| unsafe {
+//!     let old = slot.swap(Box::into_raw(Box::new(2)), AcqRel, scope);
+//!     // Was: `drop(Box::from_raw(old))`.
+//!     scope.defer_drop(Ptr::from_raw(old));
+//!     assert_eq!(*slot.load(Acquire, scope), 2);
+//! });
+//! # unsafe { drop(Box::from_raw(slot.into_inner())) };
+//! ```
+//!
+//! [`std::sync::atomic`]: https://doc.rust-lang.org/std/sync/atomic/
+//! [`Scope`]: ../struct.Scope.html
+//! [`pin`]: ../fn.pin.html
+//! [`Scope::defer_drop`]: ../struct.Scope.html#method.defer_drop
+
+use std::fmt;
+
+use atomic::{Atomic, Ptr};
+use mutator::Scope;
+use primitive::atomic::Ordering;
+
+/// A raw pointer type which can be safely shared between threads, and whose pointees are
+/// reclaimed with epochs.
+///
+/// This has the same API as `AtomicPtr` from [`std::sync::atomic`], except that methods returning
+/// a pointer take a [`Scope`]. The pointer returned may only be dereferenced while the scope is
+/// pinned.
+///
+/// Non-null pointers stored must come from `Box::into_raw`, or equivalently from
+/// [`Owned::into_raw`], so that they can be retired with [`Scope::defer_drop`].
+///
+/// # Panics
+///
+/// The methods storing a pointer panic if it isn't aligned enough to hold a tag, which can only
+/// happen to pointers that didn't come from a `Box`.
+///
+/// [`std::sync::atomic`]: https://doc.rust-lang.org/std/sync/atomic/
+/// [`Scope`]: ../struct.Scope.html
+/// [`Owned::into_raw`]: ../struct.Owned.html#method.into_raw
+/// [`Scope::defer_drop`]: ../struct.Scope.html#method.defer_drop
+pub struct AtomicPtr<T> {
+    inner: Atomic<T>,
+}
+
+impl<T> AtomicPtr<T> {
+    /// Creates a new `AtomicPtr`.
+    pub fn new(p: *mut T) -> Self {
+        AtomicPtr { inner: Atomic::from_ptr(Ptr::from_raw(p)) }
+    }
+
+    /// Consumes the atomic and returns the contained value.
+    pub fn into_inner(self) -> *mut T {
+        self.raw() as *mut T
+    }
+
+    /// Loads a value from the pointer.
+    pub fn load(&self, order: Ordering, scope: &Scope) -> *mut T {
+        self.inner.load(order, scope).as_raw() as *mut T
+    }
+
+    /// Stores a value into the pointer.
+    pub fn store(&self, ptr: *mut T, order: Ordering) {
+        self.inner.store(Ptr::from_raw(ptr), order)
+    }
+
+    /// Stores a value into the pointer, returning the previous value.
+    pub fn swap(&self, ptr: *mut T, order: Ordering, scope: &Scope) -> *mut T {
+        self.inner.swap(Ptr::from_raw(ptr), order, scope).as_raw() as *mut T
+    }
+
+    /// Stores a value into the pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new value was written and containing
+    /// the previous value. On success this value is guaranteed to be equal to `current`.
+    pub fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+        scope: &Scope,
+    ) -> Result<*mut T, *mut T> {
+        let current = Ptr::from_raw(current);
+        self.inner
+            .compare_and_set(current, Ptr::from_raw(new), (success, failure), scope)
+            .map(|()| current.as_raw() as *mut T)
+            .map_err(|p| p.as_raw() as *mut T)
+    }
+
+    /// Stores a value into the pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_exchange`], this function is allowed to spuriously fail even when the
+    /// comparison succeeds.
+    ///
+    /// [`compare_exchange`]: struct.AtomicPtr.html#method.compare_exchange
+    pub fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+        scope: &Scope,
+    ) -> Result<*mut T, *mut T> {
+        let current = Ptr::from_raw(current);
+        self.inner
+            .compare_and_set_weak(current, Ptr::from_raw(new), (success, failure), scope)
+            .map(|()| current.as_raw() as *mut T)
+            .map_err(|p| p.as_raw() as *mut T)
+    }
+
+    /// Fetches the value, and applies a function to it that returns an optional new value.
+    ///
+    /// Returns `Ok(previous_value)` if the function returned `Some(_)`, else
+    /// `Err(previous_value)`.
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        scope: &Scope,
+        mut f: F,
+    ) -> Result<*mut T, *mut T>
+    where
+        F: FnMut(*mut T) -> Option<*mut T>,
+    {
+        self.inner
+            .fetch_update(set_order, fetch_order, scope, |p| {
+                f(p.as_raw() as *mut T).map(|new| Ptr::from_raw(new))
+            })
+            .map(|p| p.as_raw() as *mut T)
+            .map_err(|p| p.as_raw() as *mut T)
+    }
+
+    /// Returns the current value without protecting it.
+    fn raw(&self) -> *const T {
+        Ptr::<T>::from_data(self.inner.data().load(Ordering::Relaxed)).as_raw()
+    }
+}
+
+impl<T> Default for AtomicPtr<T> {
+    fn default() -> Self {
+        AtomicPtr { inner: Atomic::null() }
+    }
+}
+
+impl<T> From<*mut T> for AtomicPtr<T> {
+    fn from(p: *mut T) -> Self {
+        Self::new(p)
+    }
+}
+
+impl<T> fmt::Debug for AtomicPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.raw(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+    use std::thread;
+
+    use {pin, Ptr};
+    use super::AtomicPtr;
+
+    #[test]
+    fn concurrent_replacement() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 1000;
+
+        let slot = AtomicPtr::new(Box::into_raw(Box::new(0usize)));
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..ROUNDS {
+                        pin(|scope| unsafe {
+                            let new = Box::into_raw(Box::new(0));
+                            let updated = slot.fetch_update(AcqRel, Acquire, scope, |old| {
+                                *new = *old + 1;
+                                Some(new)
+                            });
+                            scope.defer_drop(Ptr::from_raw(updated.unwrap()));
+                        });
+                    }
+                });
+            }
+        });
+
+        let last = slot.into_inner();
+        assert_eq!(unsafe { *last }, THREADS * ROUNDS);
+        unsafe { drop(Box::from_raw(last)) };
+    }
+
+    #[test]
+    fn compare_exchange_reports_current() {
+        let a = Box::into_raw(Box::new(1));
+        let slot = AtomicPtr::new(a);
+        pin(|scope| {
+            let b = Box::into_raw(Box::new(2));
+            assert_eq!(slot.compare_exchange(ptr::null_mut(), b, AcqRel, Relaxed, scope), Err(a));
+            assert_eq!(slot.compare_exchange(a, b, AcqRel, Relaxed, scope), Ok(a));
+            unsafe { drop(Box::from_raw(a)) };
+        });
+        unsafe { drop(Box::from_raw(slot.into_inner())) };
+    }
+}
@@ -0,0 +1,150 @@
+//! Cooperative yielding during collection
+//!
+//! Garbage is collected by whichever thread happens to pin or flush, in the middle of its own
+//! work. On a worker thread of an async executor, a collection that destroys lots of garbage keeps
+//! every other task of the worker waiting until it's done.
+//!
+//! Collection already proceeds in slices: a bag at a time, and at most `collect_budget` objects of
+//! it. With [`set_collection_yield`], a hook is asked after every slice whether the collection
+//! should stop there, e.g. because the current task has used up its time, so that it can yield to
+//! the executor instead. The garbage left over is collected by later pinnings and flushes, just
+//! like garbage beyond the budget.
+//!
+//! [`set_collection_yield`]: fn.set_collection_yield.html
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use hook::Slot;
+
+/// A hook deciding whether a collection should stop.
+type Hook = Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// The hook set with `set_collection_yield`, if any.
+static HOOK: Slot<dyn Fn() -> bool + Send + Sync> = Slot::new();
+
+thread_local! {
+    /// The hook as of the version of `HOOK` it was fetched at, which spares collections the lock
+    /// after every slice.
+    static CACHED: RefCell<(usize, Option<Hook>)> = const { RefCell::new((0, None)) };
+}
+
+/// Sets `hook` to be called after every slice of a collection, which stops the collection if the
+/// hook returns `true`.
+///
+/// The hook runs on the thread that is collecting the garbage, and it replaces any hook set
+/// before. It's called often, so it should be cheap, e.g. check a flag or a deadline kept by the
+/// executor for the task that is running. A collection always destroys at least one slice, so that
+/// it makes progress even if the hook keeps returning `true`.
+///
+/// Threads keep their own reference to the hook, so a replaced hook is only dropped once every
+/// thread that called it has collected garbage again.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::cell::Cell;
+///
+/// thread_local! {
+///     // Set by the executor once the running task should yield.
+///     static SHOULD_YIELD: Cell<bool> = const { Cell::new(false) };
+/// }
+///
+/// epoch::set_collection_yield(|| SHOULD_YIELD.with(Cell::get));
+/// ```
+pub fn set_collection_yield<F>(hook: F)
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    HOOK.set(Some(Arc::new(hook)));
+}
+
+/// Returns `true` if the collection running on the current thread should stop after the slice it
+/// just destroyed.
+#[inline]
+pub fn should_yield() -> bool {
+    HOOK.is_set() && CACHED.try_with(ask).unwrap_or(false)
+}
+
+/// Asks the hook in `cached` whether to stop, after fetching it again if it has been replaced.
+fn ask(cached: &RefCell<(usize, Option<Hook>)>) -> bool {
+    let version = HOOK.version();
+    if cached.borrow().0 != version {
+        match cached.try_borrow_mut() {
+            Ok(mut cached) => *cached = (version, HOOK.get()),
+            // The hook is running further up the stack, and collecting garbage itself.
+            Err(_) => return HOOK.get().is_some_and(|hook| hook()),
+        }
+    }
+    let cached = cached.borrow();
+    cached.1.as_ref().is_some_and(|hook| hook())
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use testkit::{self, Recorder};
+    use {Collector, CollectorConfig};
+    use super::*;
+
+    thread_local! {
+        /// Whether collections on the current thread should stop after every slice.
+        static YIELDING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// The collections the hook stopped on threads that were yielding.
+    static STOPPED: Recorder<()> = Recorder::new();
+
+    #[test]
+    fn stops_after_a_slice() {
+        STOPPED.set_once(|| {
+            set_collection_yield(|| {
+                let yielding = YIELDING.with(Cell::get);
+                if yielding {
+                    STOPPED.record(());
+                }
+                yielding
+            })
+        });
+
+        let name = "stops_after_a_slice";
+        let destroyed = testkit::on_thread(name, || {
+            let config = CollectorConfig {
+                bag_capacity: 4,
+                ..CollectorConfig::default()
+            };
+            let collector = Collector::with_config(config);
+            let handle = collector.register();
+            let destroyed = Arc::new(AtomicUsize::new(0));
+
+            handle.pin(|scope| {
+                for _ in 0..12 {
+                    let destroyed = destroyed.clone();
+                    unsafe {
+                        scope.defer(move || {
+                            destroyed.fetch_add(1, SeqCst);
+                        })
+                    }
+                }
+            });
+
+            YIELDING.with(|y| y.set(true));
+            let mut last = 0;
+            for _ in 0..100 {
+                handle.pin(|scope| scope.flush());
+                let now = destroyed.load(SeqCst);
+                assert!(now - last <= 4, "destroyed more than a bag at once");
+                last = now;
+            }
+            last
+        });
+
+        assert_eq!(destroyed, 12);
+        assert!(STOPPED.on_thread(name).len() >= 2);
+    }
+}
@@ -0,0 +1,49 @@
+//! The semver-stable core of the crate
+//!
+//! Libraries that expose epoch-protected types in their own public API need to know which parts of
+//! this crate they can rely on. The items re-exported here form that contract: their signatures and
+//! documented behavior only change in a breaking way with a new major version, and the same holds
+//! for the following methods:
+//!
+//! - [`Atomic`]: `null`, `new`, `from_owned`, `from_ptr`, `load`, `store`, `store_owned`, `swap`,
+//!   and the `compare_and_set` family.
+//! - [`Owned`]: `new`, `from_box`, `into_ptr`, `with_tag`, `tag`.
+//! - [`Ptr`]: `null`, `is_null`, `as_raw`, `deref`, `as_ref`, `tag`, `with_tag`.
+//! - [`Scope`]: `defer`, `defer_free`, `defer_drop`, `flush`.
+//! - [`ScopeRef`]: `new`, `scope`, `get`, `map`, `filter_map`.
+//!
+//! The [`prelude`] re-exports the types and traits among them.
+//!
+//! Everything else in the crate may change in minor versions, as we learn how it's used.
+//! Experimental surfaces, like [`Scope::protect`] and the [`shm`] backend, go further: they are
+//! only compiled with the `unstable` feature enabled, so depending on them is an explicit choice.
+//!
+//! # Examples
+//!
+//! ```
+//! use crossbeam_epoch::core::{self as epoch, Atomic, Owned};
+//! use std::sync::atomic::Ordering::SeqCst;
+//!
+//! let a = Atomic::new(1);
+//!
+//! epoch::pin(|scope| unsafe {
+//!     let old = a.swap(Owned::new(2).into_ptr(scope), SeqCst, scope);
+//!     scope.defer_drop(old);
+//! });
+//! # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+//! ```
+//!
+//! [`Atomic`]: ../struct.Atomic.html
+//! [`Owned`]: ../struct.Owned.html
+//! [`Ptr`]: ../struct.Ptr.html
+//! [`Scope`]: ../struct.Scope.html
+//! [`ScopeRef`]: ../struct.ScopeRef.html
+//! [`prelude`]: ../prelude/index.html
+//! [`Scope::protect`]: ../struct.Scope.html#method.protect
+//! [`shm`]: ../shm/index.html
+
+pub use atomic::{Atomic, Owned, Ptr};
+pub use raw::CompareAndSetOrdering;
+pub use global::{is_pinned, pin, unprotected};
+pub use mutator::{AsScope, Scope};
+pub use scope_ref::ScopeRef;
@@ -0,0 +1,456 @@
+//! Debugging facilities
+//!
+//! # Reachability checks
+//!
+//! Retiring a node that is still reachable from a data structure is one of the most dangerous bugs
+//! in code built on epoch GC, because other threads can still load the node after it has been
+//! destroyed. A data structure can register a checker for its node type with
+//! [`register_reachability_check`], which is called with every node of that type deferred through
+//! [`Scope::defer_drop`] in debug builds. If the checker reports that the node is still reachable,
+//! the deferral panics right away.
+//!
+//! # Type checks
+//!
+//! In debug builds, every pointer stored into an [`AtomicAny`] carries a fingerprint of the type
+//! of its object in its high tag bits, which is checked whenever the pointer is downcast. Two types
+//! share a fingerprint with a chance of 1 in 255, and on targets without high tag bits (see
+//! [`HIGH_TAG_BITS`]) nothing is checked.
+//!
+//! # Garbage origins
+//!
+//! With the `garbage_backtrace` feature, a backtrace is captured whenever garbage is deferred. If
+//! the destructor of the garbage later panics, the backtrace is printed so that the garbage can be
+//! traced back to where it was retired. Objects are also tracked while they await destruction, and
+//! retiring the same object twice panics with the backtrace of the first retirement.
+//!
+//! # Static objects
+//!
+//! In debug builds, objects that atomic pointers are made to point to with [`Atomic::from_static`]
+//! are recorded by address, and deferring the destruction of any object within them panics.
+//!
+//! # Stale pointers
+//!
+//! A [`Ptr`] is only valid within the pinning it was loaded in, but unsafe code can still smuggle
+//! it past the end of that pinning, e.g. by caching it in a struct with an erased lifetime, or by
+//! holding it across a [`Lease::checkpoint`]. With the `stale_ptr_check` feature, every pointer is
+//! stamped with the generation of the pinning it was loaded in, and using it with a scope of a
+//! later pinning of the same mutator (e.g. comparing it in a compare-and-set, or deferring its
+//! destruction) panics.
+//!
+//! # Store sites
+//!
+//! With the `store_tracking` feature, every [`Atomic`] records the call site of the last store
+//! into it, and pointers loaded from it carry that call site along. When [`Ptr::try_deref`] fails
+//! because the pointer is null, the error points to the code that stored it.
+//!
+//! # Strict mode
+//!
+//! The `strict` feature turns on every runtime check at once, so that a crate built on epoch GC can
+//! run its test suite in a maximally checked configuration before a release:
+//!
+//! - all checks described above, including those otherwise performed only in debug builds,
+//!   as well as the validation of pointers read from and written into [`Atomic`]s,
+//! - the `garbage_backtrace` and `stale_ptr_check` features,
+//! - the `watchdog` feature, whose [`watch_stalls`] reports threads that block while pinned,
+//! - tags passed to `with_tag` or `compare_and_set_tag` that don't fit into the unused bits of a
+//!   pointer panic instead of being truncated,
+//! - memory reclaimed through [`Scope::defer_drop`] or [`Scope::defer_free`] is poisoned right
+//!   before it's freed, so that use-after-free bugs read obviously bogus data.
+//!
+//! These checks are slow, so the feature isn't meant to be enabled in production.
+//!
+//! [`register_reachability_check`]: fn.register_reachability_check.html
+//! [`Atomic`]: struct.Atomic.html
+//! [`watch_stalls`]: fn.watch_stalls.html
+//! [`Scope::defer_free`]: struct.Scope.html#method.defer_free
+//! [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+//! [`AtomicAny`]: struct.AtomicAny.html
+//! [`HIGH_TAG_BITS`]: constant.HIGH_TAG_BITS.html
+//! [`Ptr`]: struct.Ptr.html
+//! [`Lease::checkpoint`]: struct.Lease.html#method.checkpoint
+//! [`Atomic::from_static`]: struct.Atomic.html#method.from_static
+//! [`Ptr::try_deref`]: struct.Ptr.html#method.try_deref
+
+use std::any::{Any, TypeId};
+#[cfg(feature = "garbage_backtrace")]
+use std::backtrace::Backtrace;
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "garbage_backtrace")]
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "store_tracking")]
+use std::panic::Location;
+#[cfg(feature = "store_tracking")]
+use std::ptr;
+#[cfg(feature = "garbage_backtrace")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "store_tracking")]
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, Release};
+
+use misuse::{self, MisuseCheck};
+use raw::HIGH_TAG_BITS;
+
+/// Registered reachability checks, each of which is an `fn(&T) -> bool` keyed by `T`'s type id.
+static REACHABILITY_CHECKS: Mutex<Vec<(TypeId, Box<dyn Any + Send>)>> = Mutex::new(Vec::new());
+
+/// Set once the first reachability check is registered.
+static HAS_REACHABILITY_CHECKS: AtomicBool = AtomicBool::new(false);
+
+/// Registers `check` to be called on every object of type `T` when it is deferred for destruction.
+///
+/// `check` must return `true` if the object is still reachable from its data structure. The check
+/// is performed only in debug builds or with the `strict` feature, and replaces any check
+/// previously registered for `T`.
+///
+/// # Examples
+///
+/// ```should_panic
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// struct Node {
+///     linked: bool,
+/// }
+///
+/// epoch::register_reachability_check(|n: &Node| n.linked);
+///
+/// let a = Atomic::new(Node { linked: true });
+/// epoch::pin(|scope| unsafe {
+///     // Oops, the node wasn't unlinked before retiring it.
+///     scope.defer_drop(a.load(SeqCst, scope));
+/// });
+/// # if !cfg!(any(debug_assertions, feature = "strict")) { panic!() }
+/// ```
+pub fn register_reachability_check<T: 'static>(check: fn(&T) -> bool) {
+    let mut checks = REACHABILITY_CHECKS.lock().unwrap_or_else(|e| e.into_inner());
+    let id = TypeId::of::<T>();
+
+    checks.retain(|&(i, _)| i != id);
+    checks.push((id, Box::new(check)));
+    HAS_REACHABILITY_CHECKS.store(true, Release);
+}
+
+/// Panics if `object` is still reachable according to the check registered for `T`.
+#[inline]
+pub fn check_unreachable<T: 'static>(object: *const T) {
+    if !cfg!(any(debug_assertions, feature = "strict")) || !HAS_REACHABILITY_CHECKS.load(Relaxed) {
+        return;
+    }
+
+    // The check may well defer garbage or register checks of its own.
+    let check = REACHABILITY_CHECKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|&&(i, _)| i == TypeId::of::<T>())
+        .and_then(|(_, c)| c.downcast_ref::<fn(&T) -> bool>())
+        .copied();
+
+    if let Some(check) = check {
+        if let Some(object) = unsafe { object.as_ref() } {
+            if check(object) {
+                misuse::report(
+                    MisuseCheck::ReachableObject,
+                    None,
+                    format_args!("deferred destruction of a reachable object at {:p}", object),
+                );
+            }
+        }
+    }
+}
+
+/// Returns the fingerprint of `T` that `AtomicAny` stores in the tag of a pointer to a `T`, or zero
+/// if types aren't checked. Zero is left for pointers without a fingerprint, like null ones.
+#[inline]
+pub fn type_tag<T: 'static>() -> usize {
+    if cfg!(any(debug_assertions, feature = "strict")) && HIGH_TAG_BITS > 0 {
+        let mut hasher = DefaultHasher::new();
+        TypeId::of::<T>().hash(&mut hasher);
+        (hasher.finish() % ((1 << HIGH_TAG_BITS) - 1)) as usize + 1
+    } else {
+        0
+    }
+}
+
+/// Panics if `tag` is the fingerprint of a type other than `T`.
+#[inline]
+pub fn check_type<T: 'static>(object: *const T, tag: usize) {
+    if tag != 0 && tag != type_tag::<T>() {
+        misuse::report(
+            MisuseCheck::WrongType,
+            None,
+            format_args!("downcast of the object at {:p} to the wrong type", object),
+        );
+    }
+}
+
+/// Static objects pointed to by atomic pointers, as the addresses they start and end at.
+static STATIC_OBJECTS: Mutex<BTreeSet<(usize, usize)>> = Mutex::new(BTreeSet::new());
+
+/// The lowest address and the highest end of the static objects, between which `STATIC_OBJECTS`
+/// has to be looked up. Statics are laid out apart from the heap, where garbage usually lives.
+static STATIC_START: AtomicUsize = AtomicUsize::new(usize::MAX);
+static STATIC_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Records that the `size` bytes at `object` are a static object, which must never be destroyed.
+#[inline]
+pub fn record_static<T>(object: *const T, size: usize) {
+    if cfg!(any(debug_assertions, feature = "strict")) {
+        let (start, end) = (object as usize, object as usize + size);
+        let mut objects = STATIC_OBJECTS.lock().unwrap_or_else(|e| e.into_inner());
+        objects.insert((start, end));
+        STATIC_START.fetch_min(start, Release);
+        STATIC_END.fetch_max(end, Release);
+    }
+}
+
+/// Panics if `object` lies within a static object.
+#[inline]
+pub fn check_not_static(object: *const u8) {
+    let address = object as usize;
+    if !cfg!(any(debug_assertions, feature = "strict")) ||
+        address < STATIC_START.load(Relaxed) ||
+        address >= STATIC_END.load(Relaxed)
+    {
+        return;
+    }
+
+    let objects = STATIC_OBJECTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(&(start, end)) = objects.range(..=(address, usize::MAX)).next_back() {
+        if address < end {
+            drop(objects);
+            misuse::report(
+                MisuseCheck::StaticObject,
+                None,
+                format_args!("deferred destruction of the static object at {:#x}", start),
+            );
+        }
+    }
+}
+
+/// Objects awaiting destruction, keyed by address, along with where they were deferred.
+#[cfg(feature = "garbage_backtrace")]
+static PENDING: Mutex<BTreeMap<usize, Arc<Backtrace>>> = Mutex::new(BTreeMap::new());
+
+/// Where a piece of garbage was deferred.
+#[cfg(feature = "garbage_backtrace")]
+pub struct Origin {
+    /// Address of the deferred object, or zero if it isn't tracked.
+    object: usize,
+    backtrace: Arc<Backtrace>,
+}
+
+#[cfg(feature = "garbage_backtrace")]
+impl Origin {
+    /// Captures the current backtrace as the origin of deferring the `size` bytes at `object`.
+    ///
+    /// Pass a null `object` for garbage that isn't an object, e.g. deferred functions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the object is already awaiting destruction.
+    pub fn capture(object: *const u8, size: usize) -> Self {
+        let backtrace = Arc::new(Backtrace::force_capture());
+
+        // Zero-sized objects all share the same dangling address, so they can't be told apart.
+        let object = if size == 0 { 0 } else { object as usize };
+        if object != 0 {
+            let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(first) = pending.get(&object).cloned() {
+                drop(pending);
+                misuse::report(
+                    MisuseCheck::DoubleRetire,
+                    None,
+                    format_args!(
+                        "object at {:#x} deferred for destruction twice, first deferred at:\n{}",
+                        object,
+                        first
+                    ),
+                );
+            }
+            pending.insert(object, backtrace.clone());
+        }
+
+        Origin { object, backtrace }
+    }
+
+    /// Marks the garbage as being destroyed.
+    ///
+    /// The object stops being tracked, and if the destruction panics before the returned guard is
+    /// dropped, the origin of the garbage is printed. Panics caught during the destruction are
+    /// reported with `panicked` instead.
+    pub fn destroying(&self) -> impl Drop + '_ {
+        if self.object != 0 {
+            PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.object);
+        }
+
+        let panicking = ::std::thread::panicking();
+        ::scopeguard::guard((), move |_| if !panicking && ::std::thread::panicking() {
+            eprintln!("panicked while destroying garbage deferred at:\n{}", self.backtrace);
+        })
+    }
+
+    /// Prints the origin of the garbage after a panic during its destruction was caught.
+    pub fn panicked(&self) {
+        eprintln!("panicked while destroying garbage deferred at:\n{}", self.backtrace);
+    }
+}
+
+/// Number of mutators created so far.
+#[cfg(feature = "stale_ptr_check")]
+static MUTATORS: AtomicUsize = AtomicUsize::new(0);
+
+/// The call site of the last store into an atomic pointer.
+#[cfg(feature = "store_tracking")]
+#[derive(Debug)]
+pub struct StoreSite(AtomicPtr<Location<'static>>);
+
+#[cfg(feature = "store_tracking")]
+impl StoreSite {
+    /// Returns a new store site, unknown until the first store.
+    pub const fn new() -> Self {
+        StoreSite(AtomicPtr::new(ptr::null_mut()))
+    }
+
+    /// Records the caller as the site of the last store.
+    #[track_caller]
+    pub fn record(&self) {
+        let site = Location::caller() as *const Location<'static> as *mut Location<'static>;
+        self.0.store(site, Relaxed);
+    }
+
+    /// Returns the site of the last store, if any.
+    pub fn get(&self) -> Option<&'static Location<'static>> {
+        unsafe { self.0.load(Relaxed).as_ref() }
+    }
+}
+
+/// Identifies a pinning of a mutator.
+#[cfg(feature = "stale_ptr_check")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Generation {
+    /// Id of the mutator, or zero if unknown.
+    mutator: usize,
+    /// Number of the pinning.
+    pin: usize,
+}
+
+#[cfg(feature = "stale_ptr_check")]
+impl Generation {
+    /// Returns the generation of pointers not loaded in any known pinning.
+    pub const fn unknown() -> Self {
+        Generation { mutator: 0, pin: 0 }
+    }
+
+    /// Returns the generation of a new mutator before its first pinning.
+    pub fn new_mutator() -> Self {
+        Generation {
+            mutator: MUTATORS.fetch_add(1, Relaxed) + 1,
+            pin: 0,
+        }
+    }
+
+    /// Returns the generation of the next pinning of the same mutator.
+    pub fn next(self) -> Self {
+        Generation {
+            mutator: self.mutator,
+            pin: self.pin.wrapping_add(1),
+        }
+    }
+
+    /// Returns `false` if a pointer loaded in this generation may not be used in generation
+    /// `scope`, because both are pinnings of the same mutator, but `scope` is a different one.
+    pub fn may_use_in(self, scope: Generation) -> bool {
+        self.mutator == 0 || self.mutator != scope.mutator || self.pin == scope.pin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Linked(bool);
+
+    struct Outer;
+
+    #[test]
+    fn unreachable_passes() {
+        register_reachability_check(|n: &Linked| n.0);
+        check_unreachable(&Linked(false));
+        check_unreachable::<Linked>(::std::ptr::null());
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "deferred destruction of a reachable object")]
+    fn reachable_panics() {
+        register_reachability_check(|n: &Linked| n.0);
+        check_unreachable(&Linked(true));
+    }
+
+    #[test]
+    fn check_may_check_others() {
+        register_reachability_check(|_: &Outer| {
+            check_unreachable(&Linked(false));
+            false
+        });
+        check_unreachable(&Outer);
+    }
+
+    #[test]
+    fn heap_objects_pass() {
+        static SENTINEL: [u8; 16] = [0; 16];
+        record_static(&SENTINEL, 16);
+
+        let object = Box::new(0u8);
+        check_not_static(&*object);
+    }
+
+    #[test]
+    #[cfg(feature = "garbage_backtrace")]
+    #[should_panic(expected = "deferred for destruction twice")]
+    fn double_retire_panics() {
+        use garbage::Garbage;
+
+        let object = Box::into_raw(Box::new(7u64));
+        let _first = Garbage::new_drop(object, 1);
+        let _second = Garbage::new_drop(object, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "stale_ptr_check")]
+    #[should_panic(expected = "used with the scope of a later pinning")]
+    fn stale_ptr_panics() {
+        use std::mem;
+        use std::sync::atomic::Ordering::SeqCst;
+        use {pin_for_checkpoints, Atomic, Ptr};
+
+        let a = Atomic::new(7u64);
+        pin_for_checkpoints(1, |lease| unsafe {
+            let p = mem::transmute::<Ptr<u64>, Ptr<'static, u64>>(a.load(SeqCst, lease.scope()));
+            assert!(lease.checkpoint());
+            let _ = a.compare_and_set(p, Ptr::null(), SeqCst, lease.scope());
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "stale_ptr_check")]
+    fn same_pinning_passes() {
+        use std::sync::atomic::Ordering::SeqCst;
+        use {pin, Atomic, Ptr};
+
+        let a = Atomic::new(7u64);
+        pin(|outer| {
+            let p = a.load(SeqCst, outer);
+            pin(|inner| unsafe {
+                assert!(a.compare_and_set(p, Ptr::null(), SeqCst, inner).is_ok());
+                inner.defer_drop(p);
+            });
+        });
+    }
+}
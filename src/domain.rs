@@ -0,0 +1,257 @@
+//! Shared reclamation domains
+//!
+//! Two versions of this crate linked into the same binary each run their own epoch. If a data
+//! structure is shared between code using both, objects retired through one version may be
+//! destroyed while a thread pinned only in the other is still reading them.
+//!
+//! A domain is described by [`DynCollector`], and [`adopt_domain`] makes this crate run inside
+//! another one: pinning also pins the current thread in the adopted domain, and deferred garbage
+//! is handed to it instead of being collected here. Since trait objects of different crate
+//! versions are incompatible, domains are exchanged as a [`RawDomain`], a C-compatible table of
+//! functions. [`raw_domain`] returns the one for this crate's own domain.
+//!
+//! [`DynCollector`]: trait.DynCollector.html
+//! [`adopt_domain`]: fn.adopt_domain.html
+//! [`RawDomain`]: struct.RawDomain.html
+//! [`raw_domain`]: fn.raw_domain.html
+
+use std::cell::Cell;
+use std::sync::Arc;
+
+use garbage::Garbage;
+use global::{self, pin};
+use hook::Slot;
+
+/// Version of the layout of [`RawDomain`].
+///
+/// [`RawDomain`]: struct.RawDomain.html
+pub const RAW_DOMAIN_VERSION: u32 = 1;
+
+/// A reclamation domain: a set of threads that pin, and garbage that waits for them.
+pub trait DynCollector: Send + Sync {
+    /// Pins the current thread in the domain, returning a token to pass to `unpin`.
+    ///
+    /// Pinning must be reentrant.
+    fn pin(&self) -> usize;
+
+    /// Undoes the pinning that returned `token`.
+    fn unpin(&self, token: usize);
+
+    /// Defers `f` until all threads pinned in the domain at the time have been unpinned.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`Scope::defer`] apply.
+    ///
+    /// [`Scope::defer`]: struct.Scope.html#method.defer
+    unsafe fn defer(&self, f: Box<dyn FnOnce() + Send>);
+
+    /// Tries to collect garbage of the domain.
+    fn flush(&self);
+}
+
+/// A C-compatible description of a reclamation domain, for sharing it across crate versions.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RawDomain {
+    /// Version of the layout, [`RAW_DOMAIN_VERSION`].
+    ///
+    /// [`RAW_DOMAIN_VERSION`]: constant.RAW_DOMAIN_VERSION.html
+    pub version: u32,
+    /// Pins the current thread, returning a token to pass to `unpin`.
+    pub pin: extern "C" fn() -> usize,
+    /// Undoes the pinning that returned the token.
+    pub unpin: extern "C" fn(usize),
+    /// Defers a call of the function with the data pointer.
+    pub defer: unsafe extern "C" fn(*mut (), unsafe extern "C" fn(*mut ())),
+    /// Tries to collect garbage.
+    pub flush: extern "C" fn(),
+}
+
+impl DynCollector for RawDomain {
+    fn pin(&self) -> usize {
+        (self.pin)()
+    }
+
+    fn unpin(&self, token: usize) {
+        (self.unpin)(token)
+    }
+
+    unsafe fn defer(&self, f: Box<dyn FnOnce() + Send>) {
+        unsafe extern "C" fn call(data: *mut ()) {
+            let f = Box::from_raw(data as *mut Box<dyn FnOnce() + Send>);
+            f()
+        }
+
+        (self.defer)(Box::into_raw(Box::new(f)) as *mut (), call)
+    }
+
+    fn flush(&self) {
+        (self.flush)()
+    }
+}
+
+extern "C" fn raw_pin() -> usize {
+    global::with_mutator(|mutator| mutator.pin_raw() as usize)
+}
+
+extern "C" fn raw_unpin(token: usize) {
+    global::with_mutator(|mutator| mutator.unpin_raw(token != 0))
+}
+
+unsafe extern "C" fn raw_defer(data: *mut (), call: unsafe extern "C" fn(*mut ())) {
+    let data = data as usize;
+    pin(|scope| scope.defer(move || call(data as *mut ())))
+}
+
+extern "C" fn raw_flush() {
+    pin(|scope| scope.flush())
+}
+
+/// Returns the description of this crate's own domain, for adoption by other crates.
+pub fn raw_domain() -> RawDomain {
+    RawDomain {
+        version: RAW_DOMAIN_VERSION,
+        pin: raw_pin,
+        unpin: raw_unpin,
+        defer: raw_defer,
+        flush: raw_flush,
+    }
+}
+
+/// The adopted domain.
+static ADOPTED: Slot<dyn DynCollector> = Slot::new();
+
+thread_local! {
+    /// Whether the current thread is calling into the adopted domain. If that domain calls back
+    /// into this crate, e.g. because it is this crate's own domain, the call is not routed again.
+    static CALLING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns the adopted domain, if any.
+pub fn adopted_domain() -> Option<Arc<dyn DynCollector>> {
+    ADOPTED.get()
+}
+
+/// Makes this crate run inside `domain`, or in its own domain again with `None`.
+///
+/// Once adopted, pinning also pins the current thread in `domain`, and garbage deferred from then
+/// on is handed to `domain`. Garbage deferred earlier is still collected here.
+///
+/// # Safety
+///
+/// No thread may be pinned while the domain is changed.
+///
+/// # Examples
+///
+/// With another version of the crate imported as `epoch_old`:
+///
+/// ```ignore
+/// let domain = epoch_old::raw_domain();
+/// unsafe { crossbeam_epoch::adopt_domain(Some(Arc::new(domain))) };
+/// ```
+pub unsafe fn adopt_domain(domain: Option<Arc<dyn DynCollector>>) {
+    ADOPTED.set(domain);
+}
+
+/// Calls `f` with the adopted domain, unless there is none or the current thread is already
+/// calling into it.
+#[inline]
+fn with_adopted<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&Arc<dyn DynCollector>) -> R,
+{
+    if !ADOPTED.is_set() || CALLING.try_with(Cell::get).unwrap_or(true) {
+        return None;
+    }
+
+    let domain = adopted_domain()?;
+    CALLING.with(|c| c.set(true));
+    defer! { CALLING.with(|c| c.set(false)) }
+    Some(f(&domain))
+}
+
+/// Pins the current thread in the adopted domain, if any.
+#[inline]
+pub fn pin_adopted() -> Option<(Arc<dyn DynCollector>, usize)> {
+    with_adopted(|d| (d.clone(), d.pin()))
+}
+
+/// Undoes a pinning in the adopted domain returned by `pin_adopted`.
+#[inline]
+pub fn unpin_adopted(pinned: Option<(Arc<dyn DynCollector>, usize)>) {
+    if let Some((domain, token)) = pinned {
+        domain.unpin(token);
+    }
+}
+
+/// Hands `garbage` to the adopted domain, or returns it back if there is none.
+#[inline]
+pub unsafe fn defer_adopted(garbage: Garbage) -> Result<(), Garbage> {
+    let mut garbage = Some(garbage);
+    let deferred = with_adopted(|d| {
+        let garbage = garbage.take().unwrap();
+        d.defer(Box::new(move || drop(garbage)))
+    });
+    match deferred {
+        Some(()) => Ok(()),
+        None => Err(garbage.unwrap()),
+    }
+}
+
+/// Hands all of `batch` to the adopted domain as a single piece of garbage, or returns it back if
+/// there is none.
+pub unsafe fn defer_batch_adopted(batch: Vec<Garbage>) -> Result<(), Vec<Garbage>> {
+    let mut batch = Some(batch);
+    let deferred = with_adopted(|d| {
+        let batch = batch.take().unwrap();
+        d.defer(Box::new(move || drop(batch)))
+    });
+    match deferred {
+        Some(()) => Ok(()),
+        None => Err(batch.unwrap()),
+    }
+}
+
+/// Tries to collect garbage of the adopted domain, if any.
+#[inline]
+pub fn flush_adopted() {
+    with_adopted(|d| d.flush());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use global::is_pinned;
+    use super::*;
+
+    #[test]
+    fn raw_domain_pins_and_defers() {
+        let domain: &dyn DynCollector = &raw_domain();
+
+        assert!(!is_pinned());
+        let token = domain.pin();
+        assert!(is_pinned());
+        let nested = domain.pin();
+        domain.unpin(nested);
+        assert!(is_pinned());
+        domain.unpin(token);
+        assert!(!is_pinned());
+
+        let executed = Arc::new(AtomicBool::new(false));
+        let e = executed.clone();
+        unsafe { domain.defer(Box::new(move || e.store(true, SeqCst))) }
+
+        for _ in 0..100_000 {
+            if executed.load(SeqCst) {
+                break;
+            }
+            domain.flush();
+        }
+        assert!(executed.load(SeqCst));
+        assert!(adopted_domain().is_none());
+    }
+}
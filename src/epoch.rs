@@ -6,20 +6,97 @@
 //!
 //! If an object became garbage in some epoch, then we can be sure that after two advancements no
 //! mutator will hold a reference to it. That is the crux of safe memory reclamation.
+//!
+//! # Ordering
+//!
+//! By default, pinning, sealing garbage, and advancing are ordered with `SeqCst` fences (or an
+//! equivalent `SeqCst` compare-and-swap): a pinning mutator must not load shared pointers before
+//! its announcement is visible, which is a store followed by loads of other locations, and no
+//! weaker ordering forbids reordering those.
+//!
+//! With the `release_acquire` feature, no `SeqCst` operation is used. Instead, each of these steps
+//! reads the global epoch with a read-modify-write (`fetch_add(0, AcqRel)`), and the epoch is only
+//! ever written by read-modify-writes. All of them are totally ordered by the modification order of
+//! the epoch, and each one reads from the release sequence of every earlier one, so for any two of
+//! them, everything before the earlier one happens before everything after the later one. Then:
+//!
+//! 1. A mutator `P` pins by loading the epoch, storing its state with `Release`, and reading the
+//!    epoch with a read-modify-write `p`. A mutator `R` unlinks an object `g` and seals it in the
+//!    epoch `e` read by a read-modify-write `r`. The object is destroyed only after the epoch has
+//!    been advanced from `e` by a compare-and-swap `a1`, and then once more by `a2`. Both read the
+//!    epoch with a read-modify-write before inspecting the mutators, and `a2` did so after `a1`.
+//! 2. If `p` comes before `r`, then `P` announced an epoch no later than `e`, and its announcement
+//!    happens before the read-modify-write preceding `a2`. So the advancer either sees `P` pinned
+//!    in an epoch other than `e + 2` and gives up, or sees a later `Release` store of `P` and
+//!    acquires it, in which case everything `P` did while pinned happens before `a2`. The collector
+//!    destroying `g` acquires `a2` (or a later advance) through the epoch it compares against, so
+//!    `g` is destroyed after `P` is done with it.
+//! 3. If `p` comes after `r`, then unlinking `g` happens before every load of `P` while pinned, so
+//!    `P` can't load `g` at all.
+//!
+//! If `P` announced an epoch older than the one `p` reads, it only holds the epoch back longer.
+//! Callbacks waiting in `on_epoch` follow the same pattern: registering one is followed by a
+//! read-modify-write, which either comes before an advance that then sees it, or reads the
+//! advanced epoch and runs it.
+//!
+//! The price is that every pin writes to the cache line of the global epoch, which is otherwise
+//! mostly read, so pinning from many cores contends on it. Whether that is cheaper than a full
+//! fence depends on the workload and the platform, which is what the feature is there to measure.
+//! Shared memory segments (the `shm` feature) keep their own `SeqCst` protocol.
 
+use std::fmt;
+use std::mem;
 use std::ops::Deref;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering::{Relaxed, Acquire, Release, SeqCst};
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::task::Waker;
+use primitive::atomic::{self, AtomicUsize};
+use primitive::atomic::Ordering::{AcqRel, Relaxed, Acquire, Release, SeqCst};
 
 use mutator::LocalEpoch;
 use mutator::Scope;
 use sync::list::{List, IterResult};
 use crossbeam_utils::cache_padded::CachePadded;
 
+/// A hook called with the old and new epoch whenever the global epoch advances.
+type AdvanceHook = Box<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Registered advancement hooks.
+static ADVANCE_HOOKS: Mutex<Vec<AdvanceHook>> = Mutex::new(Vec::new());
+
+/// Set once the first advancement hook is registered.
+static HAS_ADVANCE_HOOKS: AtomicBool = AtomicBool::new(false);
+
+/// A callback to run once the epoch reaches `epoch`.
+struct Barrier {
+    epoch: usize,
+    callback: Box<dyn FnOnce() + Send>,
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier").field("epoch", &self.epoch).finish()
+    }
+}
+
+/// Returns `true` if epoch `current` is `target` or later, taking wrapping around into account.
+#[inline]
+fn has_reached(current: usize, target: usize) -> bool {
+    current.wrapping_sub(target) as isize >= 0
+}
+
 /// The global epoch is a (cache-padded) integer.
 #[derive(Default, Debug)]
 pub struct Epoch {
     epoch: CachePadded<AtomicUsize>,
+    /// Tasks waiting for the epoch to advance.
+    waiters: Mutex<Vec<Waker>>,
+    /// Whether `waiters` is nonempty.
+    has_waiters: AtomicBool,
+    /// Callbacks waiting for the epoch to reach some value.
+    barriers: Mutex<Vec<Barrier>>,
+    /// Whether `barriers` is nonempty.
+    has_barriers: AtomicBool,
 }
 
 impl Epoch {
@@ -27,6 +104,15 @@ impl Epoch {
         Self::default()
     }
 
+    /// Reads the epoch with a read-modify-write, which orders the current thread after every
+    /// earlier read-modify-write of the epoch, and before every later one.
+    ///
+    /// This is what the `release_acquire` mode uses in place of `SeqCst` fences.
+    #[inline]
+    pub fn sync(&self) -> usize {
+        self.epoch.fetch_add(0, AcqRel)
+    }
+
     /// Attempts to advance the global epoch.
     ///
     /// The global epoch can advance only if all currently pinned mutators have been pinned in the
@@ -34,9 +120,19 @@ impl Epoch {
     ///
     /// Returns the current global epoch.
     #[cold]
-    pub fn try_advance<'scope>(&self, registries: &List<LocalEpoch>, scope: &Scope) -> usize {
-        let epoch = self.epoch.load(Relaxed);
-        ::std::sync::atomic::fence(SeqCst);
+    pub fn try_advance(&self, registries: &List<LocalEpoch>, scope: &Scope) -> usize {
+        // No garbage ever expires, so the epoch stays put.
+        if cfg!(feature = "leak_only") {
+            return self.epoch.load(Relaxed);
+        }
+
+        let epoch = if cfg!(feature = "release_acquire") {
+            self.sync()
+        } else {
+            let epoch = self.epoch.load(Relaxed);
+            atomic::fence(SeqCst);
+            epoch
+        };
 
         // Traverse the linked list of mutator registries.
         let mut registries = registries.iter(scope);
@@ -49,24 +145,180 @@ impl Epoch {
                 }
                 IterResult::None => break,
                 IterResult::Some(local_epoch) => {
-                    let (mutator_is_pinned, mutator_epoch) = local_epoch.get_state();
-
-                    // If the mutator was pinned in a different epoch, we cannot advance the global
-                    // epoch just yet.
-                    if mutator_is_pinned && mutator_epoch != epoch {
+                    if blocks_advance(local_epoch.get_state(), epoch) {
                         return epoch;
                     }
                 }
             }
         }
-        ::std::sync::atomic::fence(Acquire);
+        atomic::fence(Acquire);
 
         // All pinned mutators were pinned in the current global epoch.  Try advancing the epoch. We
         // increment by 2 and simply wrap around on overflow.
         let epoch_new = epoch.wrapping_add(2);
-        self.epoch.store(epoch_new, Release);
+        // In the release-acquire mode, collectors acquire the epoch they compare garbage against,
+        // and callbacks registered by `on_epoch` before the advance are seen.
+        let (success, failure) = if cfg!(feature = "release_acquire") {
+            (AcqRel, Acquire)
+        } else {
+            (Release, Relaxed)
+        };
+        if let Err(current) = self.epoch.compare_exchange(epoch, epoch_new, success, failure) {
+            // Another mutator has advanced the epoch in the meantime.
+            return current;
+        }
+
+        if HAS_ADVANCE_HOOKS.load(Relaxed) {
+            let hooks = ADVANCE_HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+            for hook in hooks.iter() {
+                hook(epoch, epoch_new);
+            }
+        }
+        // Pairs with the fence in `on_epoch`: either the callback registered there is seen here,
+        // or the new epoch is seen there.
+        if !cfg!(feature = "release_acquire") {
+            atomic::fence(SeqCst);
+        }
+        if self.has_barriers.load(Relaxed) {
+            self.run_barriers(epoch_new);
+        }
+        self.wake_waiters();
         epoch_new
     }
+
+    /// Schedules `callback` to run once, the first time the epoch is `epoch` or later.
+    ///
+    /// If it already is, `callback` runs right away. Otherwise it runs on the thread that advances
+    /// the epoch to `epoch`, while that thread is pinned.
+    pub fn on_epoch(&self, epoch: usize, callback: Box<dyn FnOnce() + Send>) {
+        {
+            let mut barriers = self.barriers.lock().unwrap_or_else(|e| e.into_inner());
+            if !has_reached(self.epoch.load(Relaxed), epoch) {
+                barriers.push(Barrier { epoch, callback });
+                self.has_barriers.store(true, Relaxed);
+                drop(barriers);
+
+                // The epoch may have advanced right before the callback got registered, without
+                // the advancing thread noticing it.
+                let epoch = if cfg!(feature = "release_acquire") {
+                    self.sync()
+                } else {
+                    atomic::fence(SeqCst);
+                    self.epoch.load(Relaxed)
+                };
+                self.run_barriers(epoch);
+                return;
+            }
+        }
+        callback();
+    }
+
+    /// Runs the callbacks waiting for `epoch` or an earlier one.
+    #[cold]
+    fn run_barriers(&self, epoch: usize) {
+        let due = {
+            let mut barriers = self.barriers.lock().unwrap_or_else(|e| e.into_inner());
+            let (due, waiting) = mem::take(&mut *barriers)
+                .into_iter()
+                .partition::<Vec<_>, _>(|b| has_reached(epoch, b.epoch));
+            *barriers = waiting;
+            self.has_barriers.store(!barriers.is_empty(), Relaxed);
+            due
+        };
+
+        // Callbacks may schedule more of them, so they run once the lock is released.
+        for barrier in due {
+            (barrier.callback)();
+        }
+    }
+
+    /// Registers `waker` to be woken the next time the epoch advances, or a mutator lagging
+    /// behind unpins.
+    pub fn register_waker(&self, waker: &Waker) {
+        let mut waiters = self.waiters.lock().unwrap_or_else(|e| e.into_inner());
+        if !waiters.iter().any(|w| w.will_wake(waker)) {
+            waiters.push(waker.clone());
+        }
+        self.has_waiters.store(true, SeqCst);
+        // Pairs with the fence in `unpinned`: either the caller sees the states of mutators that
+        // unpinned before this point, or they see that a waiter is registered.
+        atomic::fence(SeqCst);
+    }
+
+    /// Wakes all registered wakers.
+    #[inline]
+    pub fn wake_waiters(&self) {
+        if self.has_waiters.load(Relaxed) {
+            self.wake_waiters_slow();
+        }
+    }
+
+    /// Notes that a mutator pinned in `pinned` has unpinned, which may let the epoch advance if
+    /// it was lagging behind.
+    #[inline]
+    pub fn unpinned(&self, pinned: usize) {
+        if cfg!(feature = "leak_only") {
+            return;
+        }
+
+        // Pairs with the fence in `register_waker`.
+        atomic::fence(SeqCst);
+        if self.has_waiters.load(Relaxed) && self.epoch.load(Relaxed) != pinned {
+            self.wake_waiters_slow();
+        }
+    }
+
+    #[cold]
+    fn wake_waiters_slow(&self) {
+        let waiters = {
+            let mut waiters = self.waiters.lock().unwrap_or_else(|e| e.into_inner());
+            self.has_waiters.store(false, Relaxed);
+            mem::take(&mut *waiters)
+        };
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+/// Registers `hook` to be called with the old and new epoch whenever the global epoch advances.
+///
+/// Once the epoch has advanced twice past some epoch `e`, everything that was deferred in `e` or
+/// earlier may be destroyed, i.e. a grace period has elapsed. Hooks let external systems, e.g.
+/// caches of data derived from shared objects, follow along without polling.
+///
+/// Hooks are called by the thread that advanced the epoch, in the middle of pinning. They must be
+/// quick, must not block, and must not register further hooks. Hooks can't be unregistered.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+///
+/// static ADVANCES: AtomicUsize = AtomicUsize::new(0);
+///
+/// epoch::on_epoch_advance(|old, new| {
+///     assert_eq!(new, old.wrapping_add(2));
+///     ADVANCES.fetch_add(1, Relaxed);
+/// });
+/// ```
+pub fn on_epoch_advance<F>(hook: F)
+where
+    F: Fn(usize, usize) + Send + Sync + 'static,
+{
+    ADVANCE_HOOKS.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(hook));
+    HAS_ADVANCE_HOOKS.store(true, Release);
+}
+
+/// Returns `true` if a mutator in `state` (as returned by `LocalEpoch::get_state`) prevents the
+/// global epoch from advancing past `epoch`.
+#[inline]
+pub fn blocks_advance(state: (bool, usize), epoch: usize) -> bool {
+    let (mutator_is_pinned, mutator_epoch) = state;
+
+    // If the mutator was pinned in a different epoch, we cannot advance the global epoch just yet.
+    mutator_is_pinned && mutator_epoch != epoch
 }
 
 impl Deref for Epoch {
@@ -79,4 +331,182 @@ impl Deref for Epoch {
 
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    //! A model of the epoch advancement protocol, whose state space is explored exhaustively.
+    //!
+    //! The model abstracts mutators into small state machines and checks the invariants that make
+    //! memory reclamation safe in every reachable state. It uses the same advancement and
+    //! expiration conditions as the real implementation, so changes to them are checked too.
+
+    use std::collections::{HashSet, VecDeque};
+
+    use global::is_expired;
+    use super::blocks_advance;
+
+    /// Number of mutators in the model.
+    const MUTATORS: usize = 3;
+    /// Maximum number of epoch advancements explored.
+    const MAX_ADVANCES: usize = 4;
+    /// Maximum number of garbage objects explored.
+    const MAX_GARBAGE: usize = 2;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    enum Mutator {
+        Unpinned,
+        /// Has loaded the global epoch, but not yet announced that it is pinned.
+        Pinning(usize),
+        /// Is pinned in the first epoch, and announced that when the global epoch was the second.
+        Pinned(usize, usize),
+    }
+
+    impl Mutator {
+        /// The state as seen by other mutators.
+        fn state(self) -> (bool, usize) {
+            match self {
+                Mutator::Pinned(e, _) => (true, e),
+                _ => (false, 0),
+            }
+        }
+    }
+
+    /// An object that was deferred for destruction.
+    #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+    struct Garbage {
+        /// The global epoch at the time of deferral.
+        epoch: usize,
+        /// Mutators that might still hold a reference to the object.
+        holders: Vec<bool>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+    struct State {
+        epoch: usize,
+        advances: usize,
+        mutators: Vec<Mutator>,
+        garbage: Vec<Garbage>,
+    }
+
+    impl State {
+        fn new(epoch: usize) -> Self {
+            State {
+                epoch,
+                advances: 0,
+                mutators: vec![Mutator::Unpinned; MUTATORS],
+                garbage: Vec::new(),
+            }
+        }
+
+        /// Returns the state after advancing the global epoch, if it may be advanced.
+        fn advance(&self) -> Option<State> {
+            if self.mutators.iter().any(|m| blocks_advance(m.state(), self.epoch)) {
+                return None;
+            }
+            let mut s = self.clone();
+            s.epoch = s.epoch.wrapping_add(2);
+            s.advances += 1;
+            Some(s)
+        }
+
+        fn successors(&self) -> Vec<State> {
+            let mut succ = Vec::new();
+
+            for i in 0..MUTATORS {
+                let mut s = self.clone();
+                match self.mutators[i] {
+                    Mutator::Unpinned => s.mutators[i] = Mutator::Pinning(self.epoch),
+                    Mutator::Pinning(e) => s.mutators[i] = Mutator::Pinned(e, self.epoch),
+                    Mutator::Pinned(..) => {
+                        s.mutators[i] = Mutator::Unpinned;
+                        for g in &mut s.garbage {
+                            g.holders[i] = false;
+                        }
+                    }
+                }
+                succ.push(s);
+
+                // A pinned mutator unlinks an object and defers its destruction. Every mutator that
+                // has announced it is pinned might have loaded the object before it was unlinked.
+                // Mutators that are just pinning will load only after announcing, when the object
+                // is not reachable anymore.
+                if let Mutator::Pinned(..) = self.mutators[i] {
+                    if self.garbage.len() < MAX_GARBAGE {
+                        let mut s = self.clone();
+                        let holders = self.mutators.iter().map(|&m| m.state().0);
+                        s.garbage.push(Garbage {
+                            epoch: self.epoch,
+                            holders: holders.collect(),
+                        });
+                        succ.push(s);
+                    }
+                }
+            }
+
+            if self.advances < MAX_ADVANCES {
+                succ.extend(self.advance());
+            }
+            succ
+        }
+
+        /// Checks the safety invariants.
+        fn check(&self) {
+            for &m in &self.mutators {
+                if let Mutator::Pinned(_, announced) = m {
+                    // A pinned mutator witnesses at most one epoch advancement.
+                    assert!(self.epoch.wrapping_sub(announced) <= 2, "{:?}", self);
+                }
+            }
+
+            for g in &self.garbage {
+                // Garbage that might still be referenced is never destroyed.
+                if g.holders.iter().any(|&h| h) {
+                    assert!(!is_expired(g.epoch, self.epoch), "{:?}", self);
+                }
+            }
+        }
+
+        /// Checks that the epoch advances and all garbage expires once every mutator is unpinned.
+        fn check_progress(&self) {
+            let mut s = self.clone();
+            for (i, m) in s.mutators.iter_mut().enumerate() {
+                *m = Mutator::Unpinned;
+                for g in &mut s.garbage {
+                    g.holders[i] = false;
+                }
+            }
+
+            for _ in 0..2 {
+                s = s.advance().expect("quiescent mutators must not block advancement");
+            }
+            for g in &s.garbage {
+                assert!(is_expired(g.epoch, s.epoch), "{:?}", s);
+            }
+        }
+    }
+
+    fn explore(initial_epoch: usize) {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(State::new(initial_epoch));
+
+        while let Some(s) = queue.pop_front() {
+            if !seen.insert(s.clone()) {
+                continue;
+            }
+            s.check();
+            s.check_progress();
+            queue.extend(s.successors());
+        }
+
+        assert!(seen.len() > 1000);
+    }
+
+    #[test]
+    fn model() {
+        explore(0);
+    }
+
+    #[test]
+    fn model_wrapping() {
+        explore(0usize.wrapping_sub(4));
+    }
+}
@@ -0,0 +1,78 @@
+use std::ops::{Deref, DerefMut};
+
+/// Defines `EpochSafe`, as an auto trait if `auto` is given, so that stable and nightly builds
+/// share its docs.
+macro_rules! epoch_safe_trait {
+    ($($auto:ident)*) => {
+        /// Types whose destructors may be run by the garbage collector.
+        ///
+        /// Objects retired with [`Scope::defer_drop`] are destroyed by whichever thread happens to
+        /// collect them, in the middle of pinning. If a destructor blocks, e.g. by joining a thread
+        /// or acquiring a lock, it stalls that thread and all the garbage collection it was doing.
+        /// Such destructors are usually found in types with interior mutability.
+        ///
+        /// With the `nightly` feature, `EpochSafe` is an auto trait that isn't implemented for
+        /// types containing an `UnsafeCell` (and thus locks, `Cell`, or `RefCell`) or a
+        /// `JoinHandle`. Atomic integers and pointers are exempt. On stable Rust, it is implemented
+        /// for all types.
+        ///
+        /// If the destructor of a type is known not to block, there are two escape hatches: on
+        /// nightly, the trait can be implemented for the type, and on either, the object can be
+        /// wrapped in [`AssertEpochSafe`].
+        ///
+        /// # Safety
+        ///
+        /// Implementing the trait asserts that the destructor of the type doesn't block.
+        ///
+        /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+        /// [`AssertEpochSafe`]: struct.AssertEpochSafe.html
+        pub unsafe $($auto)* trait EpochSafe {}
+    };
+}
+
+#[cfg(feature = "nightly")]
+mod nightly;
+
+#[cfg(feature = "nightly")]
+pub use self::nightly::EpochSafe;
+
+#[cfg(not(feature = "nightly"))]
+epoch_safe_trait!();
+
+#[cfg(not(feature = "nightly"))]
+unsafe impl<T: ?Sized> EpochSafe for T {}
+
+/// A wrapper asserting that the destructor of `T` may be run by the garbage collector.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AssertEpochSafe, Atomic};
+/// use std::sync::Mutex;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// // Dropping a mutex doesn't lock it, so this never blocks.
+/// let a = Atomic::new(AssertEpochSafe(Mutex::new(vec![1, 2, 3])));
+///
+/// epoch::pin(|scope| unsafe {
+///     let p = a.load(SeqCst, scope);
+///     p.deref().lock().unwrap().push(4);
+///     scope.defer_drop(p);
+/// });
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AssertEpochSafe<T>(pub T);
+
+impl<T> Deref for AssertEpochSafe<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for AssertEpochSafe<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
@@ -0,0 +1,29 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicI16, AtomicI32, AtomicI8, AtomicIsize, AtomicPtr,
+                        AtomicU16, AtomicU32, AtomicU8, AtomicUsize};
+#[cfg(target_has_atomic = "64")]
+use std::sync::atomic::{AtomicI64, AtomicU64};
+use std::thread::JoinHandle;
+
+use super::AssertEpochSafe;
+
+epoch_safe_trait!(auto);
+
+impl<T: ?Sized> !EpochSafe for UnsafeCell<T> {}
+impl<T> !EpochSafe for JoinHandle<T> {}
+
+unsafe impl EpochSafe for AtomicBool {}
+unsafe impl EpochSafe for AtomicI8 {}
+unsafe impl EpochSafe for AtomicI16 {}
+unsafe impl EpochSafe for AtomicI32 {}
+#[cfg(target_has_atomic = "64")]
+unsafe impl EpochSafe for AtomicI64 {}
+unsafe impl EpochSafe for AtomicIsize {}
+unsafe impl EpochSafe for AtomicU8 {}
+unsafe impl EpochSafe for AtomicU16 {}
+unsafe impl EpochSafe for AtomicU32 {}
+#[cfg(target_has_atomic = "64")]
+unsafe impl EpochSafe for AtomicU64 {}
+unsafe impl EpochSafe for AtomicUsize {}
+unsafe impl<T> EpochSafe for AtomicPtr<T> {}
+unsafe impl<T> EpochSafe for AssertEpochSafe<T> {}
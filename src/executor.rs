@@ -0,0 +1,104 @@
+//! Asynchronous deferred functions
+//!
+//! Some cleanup involves asynchronous I/O, e.g. notifying a remote cache or releasing a
+//! distributed lease. Such work must not block whichever thread happens to collect the garbage.
+//! Once an executor is configured with [`set_executor`], functions deferred with
+//! [`Scope::defer_async`] return futures, which are spawned onto the executor instead of being
+//! awaited on the collecting thread.
+//!
+//! The crate doesn't depend on any async runtime. Instead, the executor is anything implementing
+//! [`Spawn`], which is usually a thin wrapper around the handle of a runtime.
+//!
+//! [`set_executor`]: fn.set_executor.html
+//! [`Scope::defer_async`]: struct.Scope.html#method.defer_async
+//! [`Spawn`]: trait.Spawn.html
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// A future spawned by the garbage collector.
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// An executor that futures returned by asynchronous deferred functions are spawned onto.
+///
+/// # Examples
+///
+/// A wrapper around the handle of a runtime would look like this:
+///
+/// ```ignore
+/// struct Tokio(tokio::runtime::Handle);
+///
+/// impl crossbeam_epoch::Spawn for Tokio {
+///     fn spawn(&self, task: crossbeam_epoch::Task) {
+///         self.0.spawn(task);
+///     }
+/// }
+/// ```
+pub trait Spawn: Send + Sync {
+    /// Spawns `task`, without waiting for it to complete.
+    ///
+    /// This is called by whichever thread collects the garbage, so it must not block.
+    fn spawn(&self, task: Task);
+}
+
+/// The configured executor.
+static EXECUTOR: Mutex<Option<Arc<dyn Spawn>>> = Mutex::new(None);
+
+/// Returns the executor, if one is configured.
+pub fn executor() -> Option<Arc<dyn Spawn>> {
+    EXECUTOR.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Sets the executor that asynchronous deferred functions spawn their futures onto, or removes it
+/// with `None`.
+///
+/// Functions deferred before the executor is changed still use the previous one.
+pub fn set_executor(executor: Option<Arc<dyn Spawn>>) {
+    *EXECUTOR.lock().unwrap_or_else(|e| e.into_inner()) = executor;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::future;
+    use std::task::{Context, Poll, Waker};
+
+    use global::{self, pin};
+    use super::*;
+
+    struct Collect(Mutex<Vec<Task>>);
+
+    impl Spawn for Collect {
+        fn spawn(&self, task: Task) {
+            self.0.lock().unwrap().push(task);
+        }
+    }
+
+    #[test]
+    fn deferred_future_is_spawned() {
+        let collect = Arc::new(Collect(Mutex::new(Vec::new())));
+        set_executor(Some(collect.clone()));
+
+        let done = Arc::new(AtomicBool::new(false));
+        let d = done.clone();
+        pin(|scope| unsafe {
+            scope.defer_async(move || future::poll_fn(move |_| {
+                d.store(true, SeqCst);
+                Poll::Ready(())
+            }))
+        });
+        set_executor(None);
+        global::drain();
+
+        let mut tasks = collect.0.lock().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert!(!done.load(SeqCst));
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(tasks[0].as_mut().poll(&mut cx), Poll::Ready(()));
+        assert!(done.load(SeqCst));
+    }
+}
@@ -0,0 +1,109 @@
+//! Fuzzing hooks
+//!
+//! The collector decides on its own when to advance the epoch and when to hand a local bag over
+//! to the global queue, based on counters and on how full the bag is. A fuzzer that drives random
+//! sequences of pinnings and deferrals wants to take these steps explicitly instead, so that every
+//! step of the protocol is reachable from the input, and an input replays the same way.
+//!
+//! The hooks in this module take single steps of the protocol on behalf of a scope. The
+//! `cargo-fuzz` targets in the `fuzz` directory of the repository use them to simulate several
+//! mutators of a collector on a single thread, and check that no object is destroyed while a
+//! simulated mutator may still hold on to it.
+//!
+//! This module is only available with the `fuzzing` feature enabled.
+
+use global;
+use mutator::Scope;
+
+/// Tries to advance the epoch of the collector of `scope` by one step, without collecting any
+/// garbage, and returns the epoch.
+///
+/// The epoch only advances if every pinned mutator is pinned in the current epoch, including the
+/// one `scope` belongs to.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{fuzzing, Collector};
+///
+/// let collector = Collector::new();
+/// let handle = collector.register();
+///
+/// let epoch = collector.epoch();
+/// assert_eq!(handle.pin(|scope| fuzzing::advance_step(scope)), epoch.wrapping_add(2));
+///
+/// // A mutator pinned in an older epoch holds it back.
+/// let pinned = collector.pin_owned();
+/// handle.pin(|scope| fuzzing::advance_step(scope));
+/// assert_eq!(handle.pin(|scope| fuzzing::advance_step(scope)), epoch.wrapping_add(4));
+/// # drop(pinned);
+/// ```
+pub fn advance_step(scope: &Scope) -> usize {
+    let realm = scope.realm();
+    realm.epoch.try_advance(&realm.registries, scope)
+}
+
+/// Hands the local bag of the mutator `scope` belongs to over to the global queue of its
+/// collector, sealed in the current epoch, without collecting any garbage.
+///
+/// Does nothing if the bag is empty, or if `scope` is unprotected.
+pub fn seal_bag_now(scope: &Scope) {
+    if let Some(bag) = scope.local_bag() {
+        if !bag.is_empty() {
+            global::push_bag(bag, scope);
+        }
+    }
+}
+
+/// Returns `true` if garbage sealed in `garbage_epoch` may be destroyed once the epoch is `epoch`.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::fuzzing;
+///
+/// // Garbage expires once the epoch has advanced twice, by 2 each time.
+/// assert!(!fuzzing::expire_check(8, 10));
+/// assert!(fuzzing::expire_check(8, 12));
+/// ```
+pub fn expire_check(garbage_epoch: usize, epoch: usize) -> bool {
+    global::is_expired(garbage_epoch, epoch)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use {Collector, CollectorConfig};
+    use super::*;
+
+    #[test]
+    fn sealed_bag_expires_after_two_steps() {
+        let config = CollectorConfig {
+            pins_between_collect: usize::MAX,
+            ..CollectorConfig::default()
+        };
+        let collector = Collector::with_config(config);
+        let handle = collector.register();
+        let destroyed = Arc::new(AtomicBool::new(false));
+
+        let d = destroyed.clone();
+        let sealed = handle.pin(|scope| {
+            unsafe { scope.defer(move || d.store(true, SeqCst)) }
+            seal_bag_now(scope);
+            collector.epoch()
+        });
+
+        for _ in 0..2 {
+            assert!(!expire_check(sealed, collector.epoch()));
+            handle.pin(advance_step);
+        }
+        assert!(expire_check(sealed, collector.epoch()));
+        assert!(!destroyed.load(SeqCst));
+
+        handle.pin(|scope| scope.flush());
+        assert!(destroyed.load(SeqCst));
+    }
+}
@@ -15,6 +15,16 @@
 //! store garbages in thread-local storages for amortizing the synchronization cost of pushing the
 //! garbages to a global queue.
 //!
+//! Consecutive objects of the same type retired from the same call site with `Scope::defer_drop`
+//! are coalesced into a single entry of the bag, which holds their addresses and destroys them one
+//! after another. This saves room in the bag when e.g. a whole list is retired in a loop, and runs
+//! the same destructor over and over, which keeps its code hot.
+//!
+//! Objects dropped with their own destructor that didn't get coalesced, e.g. because they were
+//! retired from different call sites or interleaved with other garbage, are still grouped by type
+//! when their bag is destroyed. Each group is destroyed by a single loop in which the destructor is
+//! called directly, so it can be inlined, instead of through a function pointer per object.
+//!
 //! # Garbage queues
 //!
 //! Whenever a bag is pushed into a queue, some garbage in the queue is collected and destroyed
@@ -23,31 +33,158 @@
 //! structure may have its own queue that gets fully destroyed as soon as the data structure gets
 //! dropped.
 
-use std::mem;
+use std::cell::Cell;
+use std::panic::Location;
+use std::mem::{self, MaybeUninit};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+#[cfg(feature = "spill")]
+use std::slice;
 use boxfnonce::SendBoxFnOnce;
 use arrayvec::ArrayVec;
+use raw::Pointable;
+use cancel;
+use debug;
+use headed::Header;
+use primitive::thread_local;
+use unwind;
+#[cfg(feature = "garbage_backtrace")]
+use debug::Origin;
+#[cfg(feature = "destroy_budget")]
+use budget;
+#[cfg(feature = "alloc_trim")]
+use trim;
 
 /// Maximum number of objects a bag can contain.
 #[cfg(not(feature = "strict_gc"))]
-const MAX_OBJECTS: usize = 64;
+pub const MAX_OBJECTS: usize = 64;
 #[cfg(feature = "strict_gc")]
-const MAX_OBJECTS: usize = 4;
+pub const MAX_OBJECTS: usize = 4;
+
+/// Maximum number of objects coalesced into a single garbage object, so that destroying one stays
+/// within the collection budget.
+const MAX_COALESCED: usize = MAX_OBJECTS;
+
+/// Number of garbage classes a bag keeps track of.
+pub const MAX_CLASSES: usize = 4;
+
+/// Number of words a deferred closure may take up to be stored inline, without boxing it.
+#[cfg(not(feature = "large_inline_garbage"))]
+const INLINE_WORDS: usize = 4;
+#[cfg(feature = "large_inline_garbage")]
+const INLINE_WORDS: usize = 8;
+
+/// Maximum size in bytes of a deferred closure that is stored inline, without boxing it.
+///
+/// This is 4 words, or 8 with the `large_inline_garbage` feature. Larger closures, or closures
+/// aligned to more than a word, are moved to the heap; [`spilled_closures`] counts how often that
+/// happens.
+///
+/// [`spilled_closures`]: fn.spilled_closures.html
+pub const INLINE_CLOSURE_SIZE: usize = INLINE_WORDS * mem::size_of::<usize>();
+
+/// Storage for a closure stored inline.
+type InlineData = MaybeUninit<[usize; INLINE_WORDS]>;
+
+thread_local! {
+    /// Number of closures deferred on the current thread that had to be boxed.
+    static SPILLED_CLOSURES: Cell<usize> = const { Cell::new(0) };
+
+    /// A group of coalesced objects that was destroyed on the current thread, kept for reuse so
+    /// that coalescing doesn't allocate every time.
+    static SPARE_GROUP: Cell<Option<Box<Group>>> = const { Cell::new(None) };
+}
+
+/// The byte reclaimed memory is overwritten with before it's freed, with the `strict` feature.
+const POISON: u8 = 0xdd;
+
+/// Overwrites the `size` bytes at `object` with `POISON`, if the `strict` feature is enabled.
+#[inline]
+unsafe fn poison(object: *mut u8, size: usize) {
+    if cfg!(feature = "strict") {
+        ptr::write_bytes(object, POISON, size);
+    }
+}
 
+pub struct Garbage {
+    kind: Kind,
+    /// Where the garbage was deferred.
+    #[cfg(feature = "garbage_backtrace")]
+    origin: Origin,
+    /// The number of bytes of memory the garbage frees, as far as known.
+    #[cfg(any(feature = "alloc_trim", feature = "reclaim_hook"))]
+    bytes: usize,
+}
 
-pub enum Garbage {
+enum Kind {
     Destroy {
         object: *mut u8,
         size: usize,
-        destroy: unsafe fn(*mut u8, usize),
+        destroy: Destroy,
+        /// Destroys objects batched with this one, if it may be batched with others.
+        batch: Option<Batch>,
+        /// The call site that retired the object, if it may be coalesced with others.
+        site: Option<&'static Location<'static>>,
     },
+    Coalesced { group: Box<Group> },
     Free { object: *mut u8, size: usize },
+    Reclaim {
+        object: *mut u8,
+        hook: *const (),
+        reclaim: unsafe fn(*mut u8, *const ()),
+    },
+    Headed { header: *mut Header },
+    Inline {
+        data: InlineData,
+        call: unsafe fn(*mut u8),
+    },
     Fn { f: Option<SendBoxFnOnce<(), ()>> },
+    Cancellable {
+        f: Option<SendBoxFnOnce<(), ()>>,
+        state: cancel::State,
+    },
+}
+
+/// Destroys an array of elements at the given address and of the given length.
+pub type Destroy = unsafe fn(*mut u8, usize);
+
+/// Destroys all objects of a batch, which must have been retired with the same `Batch`.
+type Batch = unsafe fn(&[Batched]);
+
+/// An object destroyed in a batch with other objects of the same type when its bag is destroyed.
+#[derive(Clone, Copy)]
+struct Batched {
+    batch: Batch,
+    object: *mut u8,
+    size: usize,
+}
+
+/// Objects retired from the same call site, destroyed one after another.
+struct Group {
+    objects: Vec<*mut u8>,
+    size: usize,
+    destroy: unsafe fn(*mut u8, usize),
+    site: &'static Location<'static>,
 }
 
 unsafe impl Sync for Garbage {}
 unsafe impl Send for Garbage {}
 
 impl Garbage {
+    /// Wraps `kind`, which is the garbage for the `size` bytes at `object`.
+    #[inline]
+    #[allow(unused_variables)]
+    fn from_kind(kind: Kind, object: *const u8, size: usize) -> Self {
+        debug::check_not_static(object);
+        Garbage {
+            kind,
+            #[cfg(feature = "garbage_backtrace")]
+            origin: Origin::capture(object, size),
+            #[cfg(any(feature = "alloc_trim", feature = "reclaim_hook"))]
+            bytes: size,
+        }
+    }
+
     /// Make a garbage object that will later be destroyed using `destroy`.
     ///
     /// The specified object is an array allocated at address `object` and consists of `size`
@@ -55,13 +192,16 @@ impl Garbage {
     ///
     /// Note: The object must be `Send + 'static`.
     pub fn new_destroy<T>(object: *mut T, size: usize, destroy: unsafe fn(*mut T, usize)) -> Self {
-        Garbage::Destroy {
+        let kind = Kind::Destroy {
             object: object as *mut u8,
-            size: size,
+            size,
             // FIXME(jeehoonkang): here we unsafely assume that `fn(*mut T, usize)` and `fn(*mut u8,
             // usize)` have the same size.
-            destroy: unsafe { mem::transmute(destroy) },
-        }
+            destroy: unsafe { mem::transmute::<unsafe fn(*mut T, usize), unsafe fn(*mut u8, usize)>(destroy) },
+            batch: None,
+            site: None,
+        };
+        Self::from_kind(kind, object as *const u8, mem::size_of::<T>() * size)
     }
 
     /// Make a garbage object that will later be freed.
@@ -69,10 +209,11 @@ impl Garbage {
     /// The specified object is an array allocated at address `object` and consists of `size`
     /// elements of type `T`.
     pub fn new_free<T>(object: *mut T, size: usize) -> Self {
-        Garbage::Free {
+        let kind = Kind::Free {
             object: object as *mut u8,
             size: mem::size_of::<T>() * size,
-        }
+        };
+        Self::from_kind(kind, object as *const u8, mem::size_of::<T>() * size)
     }
 
     /// Make a garbage object that will later be dropped and freed.
@@ -82,44 +223,490 @@ impl Garbage {
     ///
     /// Note: The object must be `Send + 'static`.
     pub fn new_drop<T>(object: *mut T, size: usize) -> Self {
-        unsafe fn destruct<T>(object: *mut T, size: usize) {
-            // Run the destructors and free the memory.
-            drop(Vec::from_raw_parts(object, size, size));
+        let mut garbage = Self::new_destroy(object, size, drop_of::<T>);
+        if let Kind::Destroy { ref mut batch, .. } = garbage.kind {
+            *batch = Some(drop_all_of::<T>);
+        }
+        garbage
+    }
+
+    /// Make a garbage object that will later be dropped and freed, and may be coalesced with
+    /// other objects of type `T` retired from the same call `site`.
+    ///
+    /// Note: The object must be `Send + 'static`.
+    pub fn new_drop_at<T>(object: *mut T, site: &'static Location<'static>) -> Self {
+        let mut garbage = Self::new_drop(object, 1);
+        // Objects tracked individually by their origin can't be coalesced.
+        let site = if cfg!(feature = "garbage_backtrace") { None } else { Some(site) };
+        if let Kind::Destroy { site: ref mut s, .. } = garbage.kind {
+            *s = site;
+        }
+        garbage
+    }
+
+    /// Make a garbage object that will later be dropped and freed through [`Pointable::drop`].
+    ///
+    /// The specified object takes `size` bytes and was allocated at address `object` with
+    /// [`Pointable::init`].
+    ///
+    /// Note: The object must be `Send + 'static`.
+    ///
+    /// [`Pointable::drop`]: trait.Pointable.html#tymethod.drop
+    /// [`Pointable::init`]: trait.Pointable.html#tymethod.init
+    pub fn new_pointable<T: ?Sized + Pointable>(object: *mut (), size: usize) -> Self {
+        unsafe fn destruct<T: ?Sized + Pointable>(object: *mut u8, _: usize) {
+            T::drop(object as *mut ());
+        }
+        let kind = Kind::Destroy {
+            object: object as *mut u8,
+            size,
+            destroy: destruct::<T>,
+            batch: None,
+            site: None,
+        };
+        Self::from_kind(kind, object as *const u8, size)
+    }
+
+    /// Make a garbage object that will later be passed to `hook` instead of being dropped.
+    ///
+    /// Note: The object must be `Send + 'static`.
+    pub fn new_reclaim<T>(object: *mut T, hook: fn(Box<T>)) -> Self {
+        unsafe fn reclaim<T>(object: *mut u8, hook: *const ()) {
+            let hook = mem::transmute::<*const (), fn(Box<T>)>(hook);
+            hook(Box::from_raw(object as *mut T));
+        }
+        let kind = Kind::Reclaim {
+            object: object as *mut u8,
+            hook: hook as *const (),
+            reclaim: reclaim::<T>,
+        };
+        Self::from_kind(kind, object as *const u8, mem::size_of::<T>())
+    }
+
+    /// Make a garbage object that will later be destroyed through its header.
+    ///
+    /// # Safety
+    ///
+    /// `header` must be the header of an object allocated with `Owned::new_with_header`.
+    pub unsafe fn new_headed(header: *mut Header) -> Self {
+        let size = (*header).size();
+        Self::from_kind(Kind::Headed { header }, header as *const u8, size)
+    }
+
+    /// Returns the address of the object, or null if the garbage is a closure.
+    pub fn object(&self) -> *const u8 {
+        match self.kind {
+            Kind::Destroy { object, .. } |
+            Kind::Free { object, .. } |
+            Kind::Reclaim { object, .. } => object,
+            Kind::Headed { header } => header as *const u8,
+            Kind::Coalesced { ref group } => group.objects[0],
+            Kind::Inline { .. } | Kind::Fn { .. } | Kind::Cancellable { .. } => ptr::null(),
+        }
+    }
+
+    /// Returns the number of objects and closures the garbage destroys or calls.
+    pub fn count(&self) -> usize {
+        match self.kind {
+            Kind::Coalesced { ref group } => group.objects.len(),
+            _ => 1,
+        }
+    }
+
+    /// Merges `other` into this garbage if both are objects of the same type retired from the
+    /// same call site, or returns it back.
+    pub fn try_coalesce(&mut self, other: Garbage) -> Result<(), Garbage> {
+        let (object, site) = match other.kind {
+            Kind::Destroy {
+                object,
+                size,
+                destroy,
+                site: Some(site),
+                ..
+            } if self.coalesces_with(size, destroy, site) => (object, site),
+            _ => return Err(other),
+        };
+        #[cfg(any(feature = "alloc_trim", feature = "reclaim_hook"))]
+        {
+            self.bytes += other.bytes;
+        }
+        mem::forget(other);
+
+        if let Kind::Destroy {
+            object: first,
+            size,
+            destroy,
+            ..
+        } = self.kind
+        {
+            let group = match SPARE_GROUP.try_with(Cell::take).ok().flatten() {
+                Some(mut group) => {
+                    group.objects.push(first);
+                    group.size = size;
+                    group.destroy = destroy;
+                    group.site = site;
+                    group
+                }
+                None => {
+                    let mut objects = Vec::with_capacity(MAX_COALESCED);
+                    objects.push(first);
+                    Box::new(Group {
+                        objects,
+                        size,
+                        destroy,
+                        site,
+                    })
+                }
+            };
+            self.kind = Kind::Coalesced { group };
+        }
+        if let Kind::Coalesced { ref mut group } = self.kind {
+            group.objects.push(object);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if an object of `size` elements, destroyed with `destroy`, and retired from
+    /// `site` may be merged into this garbage.
+    fn coalesces_with(
+        &self,
+        size: usize,
+        destroy: unsafe fn(*mut u8, usize),
+        site: &'static Location<'static>,
+    ) -> bool {
+        match self.kind {
+            Kind::Destroy {
+                size: s,
+                destroy: d,
+                site: Some(l),
+                ..
+            } => s == size && d as usize == destroy as usize && l == site,
+            Kind::Coalesced { ref group } => {
+                group.objects.len() < MAX_COALESCED && group.size == size &&
+                    group.destroy as usize == destroy as usize && group.site == site
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the object of the garbage, if it may be destroyed in a batch with others.
+    ///
+    /// Garbage that is timed or tracked by its origin is always destroyed on its own.
+    fn batched(&self) -> Option<Batched> {
+        if cfg!(any(feature = "garbage_backtrace", feature = "destroy_budget")) {
+            return None;
+        }
+        match self.kind {
+            Kind::Destroy {
+                object,
+                size,
+                batch: Some(batch),
+                ..
+            } => Some(Batched { batch, object, size }),
+            _ => None,
+        }
+    }
+
+    /// Returns the function destroying the objects, their number of elements, and the objects, if
+    /// the garbage consists of objects destroyed by a function alone.
+    #[cfg(feature = "spill")]
+    pub fn destroy_parts(&self) -> Option<(Destroy, usize, &[*mut u8])> {
+        // Objects tracked individually by their origin must be destroyed through their garbage.
+        if cfg!(feature = "garbage_backtrace") {
+            return None;
+        }
+        match self.kind {
+            Kind::Destroy {
+                destroy,
+                ref object,
+                size,
+                ..
+            } => Some((destroy, size, slice::from_ref(object))),
+            Kind::Coalesced { ref group } => Some((group.destroy, group.size, &group.objects)),
+            _ => None,
+        }
+    }
+
+    /// Forgets the garbage without destroying its objects, leaving their destruction to the caller.
+    #[cfg(feature = "spill")]
+    pub fn forget(self) {
+        let kind = unsafe { ptr::read(&self.kind) };
+        mem::forget(self);
+        match kind {
+            // Free the list of objects, but not the objects.
+            Kind::Coalesced { group } => drop(group),
+            kind => mem::forget(kind),
+        }
+    }
+
+    /// Make a garbage object for one of the objects returned by `destroy_parts`.
+    ///
+    /// # Safety
+    ///
+    /// The parts must have been returned by `destroy_parts` for garbage that was then forgotten,
+    /// and each object must be passed here once.
+    #[cfg(feature = "spill")]
+    pub unsafe fn from_destroy_parts(destroy: Destroy, object: *mut u8, size: usize) -> Self {
+        let kind = Kind::Destroy {
+            object,
+            size,
+            destroy,
+            batch: None,
+            site: None,
+        };
+        // The size of the elements isn't known anymore.
+        Self::from_kind(kind, object, 0)
+    }
+
+    /// Returns the call site that retired the garbage, if known.
+    #[cfg(feature = "destroy_budget")]
+    fn site(&self) -> Option<&'static Location<'static>> {
+        match self.kind {
+            Kind::Destroy { site, .. } => site,
+            Kind::Coalesced { ref group } => Some(group.site),
+            _ => None,
+        }
+    }
+
+    /// Moves the coalesced objects satisfying `condition` out of this garbage into a new one, if
+    /// some but not all of them do.
+    fn split_coalesced<F: Fn(*const u8) -> bool>(&mut self, condition: &F) -> Option<Garbage> {
+        let group = match self.kind {
+            Kind::Coalesced { ref mut group } => group,
+            _ => return None,
+        };
+        let (matching, rest): (Vec<_>, Vec<_>) =
+            group.objects.iter().partition(|&&o| condition(o));
+        if matching.is_empty() || rest.is_empty() {
+            return None;
+        }
+        group.objects = rest;
+        Some(self.detach(matching))
+    }
+
+    /// Moves the coalesced objects after the first `n` out of this garbage into a new one, if
+    /// there are any.
+    fn split_coalesced_at(&mut self, n: usize) -> Option<Garbage> {
+        let group = match self.kind {
+            Kind::Coalesced { ref mut group } if n > 0 && n < group.objects.len() => group,
+            _ => return None,
+        };
+        let rest = group.objects.split_off(n);
+        Some(self.detach(rest))
+    }
+
+    /// Returns a new garbage for `objects`, which were just removed from the coalesced objects of
+    /// this garbage.
+    fn detach(&mut self, objects: Vec<*mut u8>) -> Garbage {
+        let group = match self.kind {
+            Kind::Coalesced { ref group } => group,
+            _ => unreachable!("only coalesced objects can be detached"),
+        };
+        // All objects of a group are of the same size.
+        #[cfg(any(feature = "alloc_trim", feature = "reclaim_hook"))]
+        let bytes = self.bytes / (group.objects.len() + objects.len()) * objects.len();
+        let group = Group {
+            objects,
+            size: group.size,
+            destroy: group.destroy,
+            site: group.site,
+        };
+        #[allow(unused_mut)]
+        let mut part = Self::from_kind(Kind::Coalesced { group: Box::new(group) }, ptr::null(), 0);
+        #[cfg(any(feature = "alloc_trim", feature = "reclaim_hook"))]
+        {
+            part.bytes = bytes;
+            self.bytes -= bytes;
+        }
+        part
+    }
+
+    /// Returns `true` if the garbage is a cancelled closure.
+    pub fn is_cancelled(&self) -> bool {
+        match self.kind {
+            Kind::Cancellable { ref state, .. } => cancel::is_cancelled(state),
+            _ => false,
         }
-        Self::new_destroy(object, size, destruct)
     }
 
     /// Make a closure that will later be called.
+    ///
+    /// The closure is stored inline if it fits into [`INLINE_CLOSURE_SIZE`] bytes, and boxed
+    /// otherwise.
+    ///
+    /// [`INLINE_CLOSURE_SIZE`]: constant.INLINE_CLOSURE_SIZE.html
     pub fn new<F: FnOnce() + Send + 'static>(f: F) -> Self {
-        Garbage::Fn { f: Some(SendBoxFnOnce::from(f)) }
+        unsafe { Self::new_unchecked(f) }
+    }
+
+    /// Make a closure that will later be called, which may borrow non-`'static` data.
+    ///
+    /// # Safety
+    ///
+    /// Everything `f` borrows must outlive the garbage.
+    pub unsafe fn new_unchecked<'a, F: FnOnce() + Send + 'a>(f: F) -> Self {
+        if mem::size_of::<F>() <= mem::size_of::<InlineData>() &&
+            mem::align_of::<F>() <= mem::align_of::<InlineData>()
+        {
+            unsafe fn call<F: FnOnce()>(data: *mut u8) {
+                ptr::read(data as *mut F)()
+            }
+            let mut data = InlineData::uninit();
+            ptr::write(data.as_mut_ptr() as *mut F, f);
+            let kind = Kind::Inline {
+                data,
+                call: call::<F>,
+            };
+            return Self::from_kind(kind, ptr::null(), 0);
+        }
+
+        let _ = SPILLED_CLOSURES.try_with(|c| c.set(c.get().wrapping_add(1)));
+        let f: Box<dyn FnOnce() + Send + 'a> = Box::new(f);
+        let f = mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Box<dyn FnOnce() + Send>>(f);
+        Self::from_kind(Kind::Fn { f: Some(SendBoxFnOnce::from(f)) }, ptr::null(), 0)
+    }
+
+    /// Make a closure that will later be called, unless it is cancelled through `state`.
+    pub fn new_cancellable<F: FnOnce() + Send + 'static>(f: F, state: cancel::State) -> Self {
+        let kind = Kind::Cancellable {
+            f: Some(SendBoxFnOnce::from(f)),
+            state,
+        };
+        Self::from_kind(kind, ptr::null(), 0)
+    }
+}
+
+/// Drops and frees the array of `size` elements of type `T` at `object`.
+///
+/// Garbage destroyed by this function is recognized as such, which lets consecutive objects of the
+/// same type be coalesced.
+unsafe fn drop_of<T>(object: *mut T, size: usize) {
+    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(object, size));
+    poison(object as *mut u8, mem::size_of::<T>() * size);
+    drop(Vec::from_raw_parts(object, 0, size));
+}
+
+/// Returns the function with which garbage retired with `new_drop::<T>` is destroyed.
+#[cfg(feature = "spill")]
+pub fn drop_destroy<T>() -> Destroy {
+    unsafe { mem::transmute::<unsafe fn(*mut T, usize), Destroy>(drop_of::<T>) }
+}
+
+/// Drops and frees the arrays of elements of type `T` in `batched`, one after another.
+unsafe fn drop_all_of<T>(batched: &[Batched]) {
+    for b in batched {
+        let object = b.object as *mut T;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| drop_of(object, b.size)));
+        if let Err(payload) = result {
+            unwind::handle(payload);
+        }
+    }
+}
+
+/// Keeps `group`, whose objects were destroyed, for reuse by the next coalescing on the current
+/// thread.
+fn recycle(mut group: Box<Group>) {
+    if group.objects.capacity() >= MAX_COALESCED {
+        group.objects.clear();
+        let _ = SPARE_GROUP.try_with(|s| s.set(Some(group)));
     }
 }
 
 impl Drop for Garbage {
     fn drop(&mut self) {
-        match *self {
-            Garbage::Destroy {
+        #[cfg(feature = "garbage_backtrace")]
+        let _origin = self.origin.destroying();
+        #[cfg(feature = "destroy_budget")]
+        let start = budget::start();
+
+        // Catch panics so that a panicking destructor doesn't skip the rest of the bag.
+        let kind = &mut self.kind;
+        let result = panic::catch_unwind(AssertUnwindSafe(move || match *kind {
+            Kind::Destroy {
                 destroy,
                 object,
                 size,
+                ..
             } => unsafe {
                 (destroy)(object, size);
             },
-            Garbage::Free { object, size } => unsafe { drop(Vec::from_raw_parts(object, 0, size)) },
-            Garbage::Fn { ref mut f } => {
+            Kind::Coalesced { ref group } => {
+                for &object in &group.objects {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+                        (group.destroy)(object, group.size)
+                    }));
+                    if let Err(payload) = result {
+                        unwind::handle(payload);
+                    }
+                }
+            }
+            Kind::Free { object, size } => unsafe {
+                poison(object, size);
+                drop(Vec::from_raw_parts(object, 0, size));
+            },
+            Kind::Reclaim {
+                object,
+                hook,
+                reclaim,
+            } => unsafe {
+                (reclaim)(object, hook);
+            },
+            Kind::Headed { header } => unsafe { Header::destroy(header) },
+            Kind::Inline { ref mut data, call } => unsafe {
+                (call)(data.as_mut_ptr() as *mut u8);
+            },
+            Kind::Fn { ref mut f } => {
                 let f = f.take().unwrap();
                 f.call();
             }
+            Kind::Cancellable { ref mut f, ref state } => {
+                let f = f.take().unwrap();
+                if cancel::start(state) {
+                    f.call();
+                }
+            }
+        }));
+
+        if let Err(payload) = result {
+            #[cfg(feature = "garbage_backtrace")]
+            self.origin.panicked();
+            unwind::handle(payload);
         }
+
+        if let Kind::Coalesced { .. } = self.kind {
+            if let Kind::Coalesced { group } = mem::replace(&mut self.kind, Kind::Fn { f: None }) {
+                recycle(group);
+            }
+        }
+
+        #[cfg(feature = "destroy_budget")]
+        budget::finish(start, self.site());
+        #[cfg(feature = "alloc_trim")]
+        trim::freed(self.bytes);
     }
 }
 
+/// Returns the number of closures deferred on the current thread that were too large to be stored
+/// inline, and had to be moved to the heap.
+///
+/// Closures of up to [`INLINE_CLOSURE_SIZE`] bytes are stored inline in the bag. If this number
+/// keeps growing on a hot path, capturing less or enabling the `large_inline_garbage` feature
+/// avoids an allocation per deferral.
+///
+/// [`INLINE_CLOSURE_SIZE`]: constant.INLINE_CLOSURE_SIZE.html
+pub fn spilled_closures() -> usize {
+    SPILLED_CLOSURES.with(|c| c.get())
+}
 
 /// Bag of garbages.
 #[derive(Default)]
 pub struct Bag {
     /// Removed objects.
     objects: ArrayVec<[Garbage; MAX_OBJECTS]>,
+    /// The global epoch in which the first garbage was deferred into the bag, if known.
+    started: Option<usize>,
+    /// Number of objects and bytes deferred into the bag, per garbage class.
+    classes: [(usize, usize); MAX_CLASSES],
 }
 
 impl Bag {
@@ -128,13 +715,255 @@ impl Bag {
         Self::default()
     }
 
+    /// Returns a new bag holding just `garbage`.
+    pub fn with_garbage(garbage: Garbage) -> Self {
+        let mut bag = Self::new();
+        if bag.try_push(garbage).is_err() {
+            unreachable!("a new bag must have room for garbage");
+        }
+        bag
+    }
+
     /// Returns `true` if the bag is empty.
     pub fn is_empty(&self) -> bool {
         self.objects.is_empty()
     }
 
+    /// Returns the number of garbage objects in the bag.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Returns the number of objects and closures deferred into the bag, counting each of the
+    /// coalesced objects.
+    pub fn deferred(&self) -> usize {
+        self.objects.iter().map(Garbage::count).sum()
+    }
+
+    /// Returns the number of bytes of memory the garbage in the bag frees, as far as known.
+    #[cfg(feature = "reclaim_hook")]
+    pub fn bytes(&self) -> usize {
+        self.objects.iter().map(|g| g.bytes).sum()
+    }
+
+    /// Returns `true` if the bag is full.
+    pub fn is_full(&self) -> bool {
+        self.objects.is_full()
+    }
+
+    /// Moves the garbage whose object satisfies `condition` out of the bag into a new bag.
+    pub fn split_off<F: Fn(*const u8) -> bool>(&mut self, condition: F) -> Bag {
+        let mut matching = Bag::new();
+        let mut rest = Bag::new();
+        matching.started = self.started;
+        rest.started = self.started;
+
+        for mut garbage in self.objects.drain(..) {
+            if let Some(part) = garbage.split_coalesced(&condition) {
+                if matching.try_push(part).is_err() {
+                    unreachable!("the bag was split from a bag of the same capacity");
+                }
+                if rest.try_push(garbage).is_err() {
+                    unreachable!("the bag was split from a bag of the same capacity");
+                }
+                continue;
+            }
+            let bag = if condition(garbage.object()) { &mut matching } else { &mut rest };
+            if bag.try_push(garbage).is_err() {
+                unreachable!("the bag was split from a bag of the same capacity");
+            }
+        }
+
+        *self = rest;
+        matching
+    }
+
+    /// Removes cancelled closures from the bag, and returns how many were removed.
+    pub fn compact(&mut self) -> usize {
+        let len = self.objects.len();
+        self.objects.retain(|g| !g.is_cancelled());
+        len - self.objects.len()
+    }
+
+    /// Moves the garbage after the first `n` objects and closures out of the bag into a new bag,
+    /// counting each of the coalesced objects.
+    pub fn split_at(&mut self, n: usize) -> Bag {
+        let mut rest = Bag::new();
+        rest.started = self.started;
+        let mut left = n;
+        let mut split = self.objects.len();
+        for (i, garbage) in self.objects.iter_mut().enumerate() {
+            if left == 0 {
+                split = i;
+                break;
+            }
+            let count = garbage.count();
+            if count > left {
+                // Split the coalesced objects that straddle the boundary.
+                rest.objects.extend(garbage.split_coalesced_at(left));
+                split = i + 1;
+                break;
+            }
+            left -= count;
+        }
+        rest.objects.extend(self.objects.drain(split..));
+        rest
+    }
+
+    /// Records that garbage is being deferred into the bag in global epoch `epoch`, unless some
+    /// already was.
+    pub fn start(&mut self, epoch: usize) {
+        if self.started.is_none() {
+            self.started = Some(epoch);
+        }
+    }
+
+    /// Returns the global epoch in which the first garbage was deferred into the bag, if known.
+    pub fn started(&self) -> Option<usize> {
+        self.started
+    }
+
+    /// Counts an object of `size` bytes towards `class`, and returns the number of objects and
+    /// bytes of the class deferred into the bag so far.
+    pub fn add_to_class(&mut self, class: usize, size: usize) -> (usize, usize) {
+        let (count, bytes) = &mut self.classes[class];
+        *count += 1;
+        *bytes = bytes.saturating_add(size);
+        (*count, *bytes)
+    }
+
+    /// Returns an iterator over the garbage in the bag.
+    #[cfg(feature = "spill")]
+    pub fn iter(&self) -> slice::Iter<'_, Garbage> {
+        self.objects.iter()
+    }
+
+    /// Removes the garbage satisfying `condition` from the bag without destroying it, leaving its
+    /// destruction to the caller.
+    #[cfg(feature = "spill")]
+    pub fn forget_if<F: FnMut(&Garbage) -> bool>(&mut self, mut condition: F) {
+        let objects = mem::replace(&mut self.objects, ArrayVec::new());
+        for garbage in objects {
+            if condition(&garbage) {
+                garbage.forget();
+            } else if self.objects.try_push(garbage).is_err() {
+                unreachable!("the garbage came from the bag itself");
+            }
+        }
+    }
+
+    /// Attempts to merge a garbage object into the last one in the bag, and returns it back if it
+    /// can't be coalesced.
+    pub fn try_coalesce(&mut self, garbage: Garbage) -> Result<(), Garbage> {
+        match self.objects.last_mut() {
+            Some(last) => last.try_coalesce(garbage),
+            None => Err(garbage),
+        }
+    }
+
+    /// Moves all of `garbage` into the bag, which must have room for it.
+    pub fn extend<I: IntoIterator<Item = Garbage>>(&mut self, garbage: I) {
+        self.objects.extend(garbage);
+    }
+
     /// Attempts to insert a garbage object into the bag and returns `true` if succeeded.
     pub fn try_push(&mut self, garbage: Garbage) -> Result<(), Garbage> {
         self.objects.try_push(garbage).map_err(|e| e.element())
     }
 }
+
+impl Drop for Bag {
+    fn drop(&mut self) {
+        // Destroy the garbage that can't be batched in order, and set the rest aside.
+        let mut batched = ArrayVec::<[Batched; MAX_OBJECTS]>::new();
+        for garbage in self.objects.drain(..) {
+            if let Some(b) = garbage.batched() {
+                #[cfg(feature = "alloc_trim")]
+                trim::freed(garbage.bytes);
+                batched.push(b);
+                mem::forget(garbage);
+            }
+        }
+
+        // The sort is stable, so objects of the same type are still destroyed in order.
+        batched.sort_by_key(|b| b.batch as usize);
+        for group in batched.chunk_by(|a, b| a.batch as usize == b.batch as usize) {
+            unsafe { (group[0].batch)(group) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    thread_local! {
+        /// The objects dropped on the current thread, in order.
+        static DROPPED: RefCell<Vec<(char, usize)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Node<const C: char>(usize);
+
+    impl<const C: char> Drop for Node<C> {
+        fn drop(&mut self) {
+            DROPPED.with(|d| d.borrow_mut().push((C, self.0)));
+        }
+    }
+
+    fn retire<T>(object: T) -> Garbage {
+        Garbage::new_drop(Box::into_raw(Box::new(object)), 1)
+    }
+
+    #[test]
+    fn batches_interleaved_types() {
+        // Fill the bag with as many rounds of the three kinds as fit.
+        let rounds = MAX_OBJECTS / 3;
+        let mut bag = Bag::new();
+        for i in 0..rounds {
+            bag.try_push(retire(Node::<'a'>(i))).ok().unwrap();
+            bag.try_push(retire(Node::<'b'>(i))).ok().unwrap();
+            bag.try_push(Garbage::new(move || DROPPED.with(|d| d.borrow_mut().push(('f', i)))))
+                .ok()
+                .unwrap();
+        }
+        drop(bag);
+
+        let dropped = DROPPED.with(|d| d.borrow_mut().split_off(0));
+        assert_eq!(dropped.len(), 3 * rounds);
+        for c in ['a', 'b', 'f'].iter() {
+            let order = dropped.iter().filter(|d| d.0 == *c).map(|d| d.1).collect::<Vec<_>>();
+            assert_eq!(order, (0..rounds).collect::<Vec<_>>());
+        }
+        if !cfg!(any(feature = "garbage_backtrace", feature = "destroy_budget")) {
+            // Each type is destroyed in one go.
+            let a = dropped.iter().position(|d| d.0 == 'a').unwrap();
+            assert!(dropped[a..a + rounds].iter().all(|d| d.0 == 'a'));
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "garbage_backtrace"))]
+    fn split_at_counts_coalesced_objects() {
+        let site = Location::caller();
+        let mut bag = Bag::new();
+        for i in 0..MAX_OBJECTS {
+            let garbage = Garbage::new_drop_at(Box::into_raw(Box::new(Node::<'c'>(i))), site);
+            if let Err(garbage) = bag.try_coalesce(garbage) {
+                bag.try_push(garbage).ok().unwrap();
+            }
+        }
+        assert_eq!(bag.len(), 1);
+
+        let rest = bag.split_at(MAX_OBJECTS / 2);
+        assert_eq!(bag.deferred(), MAX_OBJECTS / 2);
+        assert_eq!(rest.deferred(), MAX_OBJECTS - MAX_OBJECTS / 2);
+        drop(bag);
+        drop(rest);
+
+        let dropped = DROPPED.with(|d| d.borrow_mut().split_off(0));
+        let order = dropped.iter().map(|d| d.1).collect::<Vec<_>>();
+        assert_eq!(order, (0..MAX_OBJECTS).collect::<Vec<_>>());
+    }
+}
@@ -5,83 +5,1159 @@
 //! thread's mutator will get destructed on thread exit, which in turn unregisters the thread.
 //!
 //! `registries` is the list is the registered mutators, and `epoch` is the global epoch.
+//!
+//! # Fairness
+//!
+//! Sealed bags are pushed into one of several garbage queues, chosen by the mutator the bag
+//! originates from. Collection visits the queues in round-robin order, so a mutator that produces
+//! lots of garbage can't starve the garbage of other mutators.
+//!
+//! Objects of at least [`large_garbage_threshold`] bytes skip the local bags altogether. Each of
+//! them is sealed in its own bag and pushed into a separate queue, which collection visits first.
+//! This way a large object is destroyed as soon as its epoch expires instead of waiting behind a
+//! backlog of small ones.
+//!
+//! # Collection slicing
+//!
+//! A single collection destroys at most `collect_budget` objects, so that a thread that happens to
+//! collect while a large backlog is pending isn't stuck destroying all of it. If the budget runs
+//! out in the middle of a bag, the rest of the bag is set aside in a shared queue of partially
+//! destroyed bags, and the next collection on any thread continues with it before anything else.
+//!
+//! [`large_garbage_threshold`]: ../fn.large_garbage_threshold.html
+//!
+//! # Allocation failure
+//!
+//! Pushing a bag into a garbage queue allocates a queue node. What happens when that allocation
+//! fails is decided by the [`AllocFailurePolicy`] set with [`set_alloc_failure_policy`].
+//!
+//! [`AllocFailurePolicy`]: ../enum.AllocFailurePolicy.html
+//! [`set_alloc_failure_policy`]: ../fn.set_alloc_failure_policy.html
 
+use std::alloc::{self, Layout};
+use std::cell::Cell;
 use std::cmp;
-use std::sync::atomic::Ordering::{Relaxed, SeqCst};
-use mutator::{Mutator, LocalEpoch, Scope, unprotected_with_bag};
-use garbage::Bag;
+use std::error::Error;
+use std::fmt;
+use std::iter;
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic as std_atomic;
+use primitive::atomic::{self, AtomicBool, AtomicUsize};
+use primitive::atomic::Ordering::{Relaxed, SeqCst};
+use primitive::thread_local;
+use std::thread;
+use allocator::AllocRef;
+use collector::CollectorConfig;
 use epoch::Epoch;
-use sync::list::List;
+use mutator::{LocalEpoch, Mutator, Scope, unprotected_static, unprotected_with_bag};
+#[cfg(feature = "unstable")]
+use domain;
+use garbage::{Bag, Garbage};
+use protect;
+use cancel;
+use cooperate;
+use pause;
+use sync::list::{IterResult, List};
 use sync::queue::Queue;
+use sys::{self, Timestamp};
+#[cfg(feature = "spill")]
+use spill::Spill;
+#[cfg(feature = "alloc_trim")]
+use trim;
+#[cfg(feature = "reclaim_hook")]
+use reclaim;
+
+
+/// Maximum number of objects destroyed by a single collection, by default.
+pub const COLLECT_BUDGET: usize = 256;
+
+/// Minimum number of global garbage queues.
+const MIN_GARBAGE_SHARDS: usize = 8;
 
+/// Maximum number of global garbage queues.
+const MAX_GARBAGE_SHARDS: usize = 64;
 
-/// Number of bags to destroy.
-const COLLECT_STEPS: usize = 8;
+/// Default size in bytes from which deferred objects are considered large.
+const DEFAULT_LARGE_GARBAGE_THRESHOLD: usize = 1 << 20;
 
+/// Size in bytes from which deferred objects are considered large.
+static LARGE_GARBAGE_THRESHOLD: std_atomic::AtomicUsize =
+    std_atomic::AtomicUsize::new(DEFAULT_LARGE_GARBAGE_THRESHOLD);
+
+/// The current `AllocFailurePolicy`, as its discriminant.
+static ALLOC_FAILURE_POLICY: std_atomic::AtomicUsize =
+    std_atomic::AtomicUsize::new(AllocFailurePolicy::Abort as usize);
+
+
+/// A garbage collection realm: the registered mutators, the epoch, and the garbage they deferred.
+pub struct Realm {
+    /// The list of mutator registries.
+    pub registries: List<LocalEpoch>,
+    /// The garbage queues, sharded by the mutator the bags originate from. There are as many as
+    /// returned by `garbage_shards`.
+    pub garbages: Vec<Queue<(usize, Bag)>>,
+    /// The queue of large garbages.
+    pub large_garbages: Queue<(usize, Bag)>,
+    /// The rest of bags whose collection ran out of budget.
+    pub partial_garbages: Queue<(usize, Bag)>,
+    /// The expired bags set aside because sealed scopes hold on to them.
+    pub held_garbages: Queue<(usize, Bag)>,
+    /// The epochs of the live sealed scopes of the realm.
+    seals: Mutex<Vec<usize>>,
+    /// The number of live sealed scopes, i.e. the length of `seals`.
+    seal_count: AtomicUsize,
+    /// The garbage queue the next collection starts from.
+    pub collect_cursor: AtomicUsize,
+    /// The epoch of the realm.
+    pub epoch: Epoch,
+    /// The number of local bags allocated by the mutators of the realm.
+    pub local_bags: AtomicUsize,
+    /// The collection policy of the realm.
+    pub config: CollectorConfig,
+    /// The number of bytes of memory deferred for destruction in the realm so far, including
+    /// memory that has been freed since.
+    #[cfg(feature = "stats")]
+    pub total_deferred_bytes: AtomicUsize,
+    /// The garbage of the realm spilled to disk.
+    #[cfg(feature = "spill")]
+    pub spill: Spill,
+}
+
+impl Realm {
+    /// Returns a new realm with no mutators and no garbage.
+    pub fn new() -> Self {
+        Self::with_config(CollectorConfig::default())
+    }
+
+    /// Returns a new realm with no mutators and no garbage, collected according to `config`.
+    pub fn with_config(config: CollectorConfig) -> Self {
+        Self::with_allocator(config, AllocRef::global())
+    }
+
+    /// Returns a new realm with no mutators and no garbage, collected according to `config`,
+    /// whose garbage queues are allocated from `alloc`.
+    pub fn with_allocator(config: CollectorConfig, alloc: AllocRef) -> Self {
+        Realm {
+            registries: List::new(),
+            garbages: (0..garbage_shards()).map(|_| Queue::new_in(alloc)).collect(),
+            large_garbages: Queue::new_in(alloc),
+            partial_garbages: Queue::new_in(alloc),
+            held_garbages: Queue::new_in(alloc),
+            seals: Mutex::new(Vec::new()),
+            seal_count: AtomicUsize::new(0),
+            collect_cursor: AtomicUsize::new(0),
+            epoch: Epoch::new(),
+            local_bags: AtomicUsize::new(0),
+            config,
+            #[cfg(feature = "stats")]
+            total_deferred_bytes: AtomicUsize::new(0),
+            #[cfg(feature = "spill")]
+            spill: Spill::new(),
+        }
+    }
+
+    /// Registers a sealed scope created in `epoch`.
+    pub fn seal(&self, epoch: usize) {
+        self.seals.lock().unwrap_or_else(|e| e.into_inner()).push(epoch);
+        self.seal_count.fetch_add(1, SeqCst);
+    }
+
+    /// Unregisters a sealed scope created in `epoch`.
+    pub fn unseal(&self, epoch: usize) {
+        let mut seals = self.seals.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = seals.iter().position(|&e| e == epoch) {
+            seals.swap_remove(i);
+            self.seal_count.fetch_sub(1, Relaxed);
+        }
+    }
+
+    /// Returns `true` if a sealed scope holds on to the garbage in `entry`.
+    ///
+    /// A scope sealed in epoch `e` could have loaded any object that wasn't destroyable yet, i.e.
+    /// objects in bags pushed in `e` or later. Of those, it holds on to the ones retired while
+    /// mutators were pinned in `e` or earlier, during which the global epoch was at most `e + 2`.
+    pub fn is_held(&self, entry: &(usize, Bag)) -> bool {
+        if self.seal_count.load(SeqCst) == 0 {
+            return false;
+        }
+        let (pushed, ref bag) = *entry;
+        let started = bag.started().unwrap_or(pushed);
+        let seals = self.seals.lock().unwrap_or_else(|e| e.into_inner());
+        seals
+            .iter()
+            .any(|&e| !is_before(pushed, e) && !is_before(e.wrapping_add(2), started))
+    }
+
+    /// Returns all garbage queues of the realm: the rest of bags whose collection ran out of
+    /// budget, the held bags, and large garbage, which are destroyed first, and then the shards.
+    pub fn queues(&self) -> impl Iterator<Item = &Queue<(usize, Bag)>> {
+        iter::once(&self.partial_garbages)
+            .chain(iter::once(&self.held_garbages))
+            .chain(iter::once(&self.large_garbages))
+            .chain(self.garbages.iter())
+    }
+
+    /// Returns the number of bytes of memory used by the realm itself, excluding the objects its
+    /// garbage refers to.
+    pub fn memory_usage(&self, scope: &Scope) -> usize {
+        // Every queue also has a sentinel node.
+        let nodes: usize = self.queues().map(|q| q.len(scope) + 1).sum();
+
+        mem::size_of::<Realm>() +
+            self.garbages.capacity() * mem::size_of::<Queue<(usize, Bag)>>() +
+            nodes * Queue::<(usize, Bag)>::node_size() +
+            self.registries.count(scope) * List::<LocalEpoch>::node_size() +
+            self.local_bags.load(Relaxed) * mem::size_of::<Bag>()
+    }
+
+    /// Returns a snapshot of the state of the realm.
+    ///
+    /// The mutator `scope` belongs to is not counted.
+    pub fn stats(&self, scope: &Scope) -> Stats {
+        let mut stats = Stats {
+            epoch: self.epoch.load(Relaxed),
+            ..Stats::default()
+        };
+
+        let mut registries = self.registries.iter(scope);
+        while let IterResult::Some(local_epoch) = registries.next() {
+            if !::std::ptr::eq(local_epoch, scope.local_epoch()) {
+                stats.mutators += 1;
+                if local_epoch.get_state().0 {
+                    stats.pinned += 1;
+                }
+            }
+        }
+
+        stats.dead_mutators = self.registries.deleted();
+
+        for queue in self.queues() {
+            stats.bags += queue.len(scope);
+            stats.deferred += queue.sum_by(|(_, bag)| bag.deferred(), scope);
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            stats.total_deferred_bytes = Some(self.total_deferred_bytes.load(Relaxed));
+        }
+        stats
+    }
+}
+
+impl Drop for Realm {
+    fn drop(&mut self) {
+        // Every mutator holds on to the realm, so none is left, and all garbage can be destroyed
+        // right away. Protected objects are leaked instead: there's no realm left to push them
+        // back into, and a live `Protected` may still read them.
+        unsafe {
+            unprotected(|scope| {
+                for bag in destroy_all(self, scope) {
+                    mem::forget(bag);
+                }
+            })
+        }
+    }
+}
+
+/// A snapshot of the state of a garbage collector.
+///
+/// Returned by [`stats`] for the global garbage collector, and by [`Collector::stats`]. Since
+/// mutators keep working while the snapshot is taken, its numbers may be slightly inconsistent
+/// with each other.
+///
+/// [`stats`]: fn.stats.html
+/// [`Collector::stats`]: struct.Collector.html#method.stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The global epoch.
+    pub epoch: usize,
+    /// The number of registered mutators.
+    pub mutators: usize,
+    /// The number of pinned mutators.
+    pub pinned: usize,
+    /// The number of unregistered mutators whose entries haven't been unlinked from the list of
+    /// mutators yet.
+    pub dead_mutators: usize,
+    /// The number of sealed bags of garbage awaiting collection.
+    ///
+    /// Garbage that is still in the local bags of mutators is not counted.
+    pub bags: usize,
+    /// The number of deferred destructions and functions in the sealed bags.
+    pub deferred: usize,
+    /// The number of bytes of memory deferred for destruction so far, as far as their size is
+    /// known, e.g. through [`Scope::defer_drop`] or [`Scope::defer_destroy`].
+    ///
+    /// The count only ever grows: memory is still counted after it has been freed. The difference
+    /// between two snapshots is how much memory was deferred in between.
+    ///
+    /// This is only counted with the `stats` feature, and is `None` otherwise.
+    ///
+    /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`Scope::defer_destroy`]: struct.Scope.html#method.defer_destroy
+    pub total_deferred_bytes: Option<usize>,
+}
 
 // FIXME(jeehoonkang): accessing globals in `lazy_static!` is blocking.
 //
 // Since static globals defined in `lazy_static!` are never dropped
 // (https://github.com/rust-lang/rfcs/blob/master/text/1440-drop-types-in-const.md), it is safe to
 // use `unprotected()` in `List`'s destructor.
-lazy_static! {
-    /// REGISTRIES is the head pointer of the list of mutator registries.
-    pub static ref REGISTRIES: List<LocalEpoch> = List::new();
-    /// GARBAGES is a reference to the global queue of garbages.
-    pub static ref GARBAGES: Queue<(usize, Bag)> = Queue::new();
-    /// EPOCH is a reference to the global epoch.
-    pub static ref EPOCH: Epoch = Epoch::new();
+//
+// The statics live in their own module because `lazy_static` 0.2 expands to the deprecated
+// `ONCE_INIT`.
+#[allow(deprecated)]
+mod statics {
+    use std::sync::Arc;
+
+    use super::Realm;
+
+    #[cfg(not(loom))]
+    lazy_static! {
+        /// REALM is the default realm, which the mutators of all threads are registered in.
+        pub static ref REALM: Arc<Realm> = Arc::new(Realm::new());
+    }
+
+    // Under `loom`, the realm is created anew in each execution of a model.
+    #[cfg(loom)]
+    ::loom::lazy_static! {
+        /// REALM is the default realm, which the mutators of all threads are registered in.
+        pub static ref REALM: Arc<Realm> = Arc::new(Realm::new());
+    }
+}
+
+pub use self::statics::REALM;
+
+
+/// Returns the number of global garbage queues of a new realm.
+///
+/// Flushing threads mostly push onto queues of their own, so there is one queue per CPU, within
+/// bounds: collection visits every queue, and more of them only pays off if there are threads to
+/// spread over them.
+///
+/// Models checked with `loom` get a single queue, since the queue bags go to depends on where
+/// mutators are allocated, which `loom` can't replay.
+fn garbage_shards() -> usize {
+    if cfg!(loom) {
+        return 1;
+    }
+    thread::available_parallelism()
+        .map_or(MIN_GARBAGE_SHARDS, |n| n.get().next_power_of_two())
+        .clamp(MIN_GARBAGE_SHARDS, MAX_GARBAGE_SHARDS)
+}
+
+/// Returns the index among `shards` garbage queues for bags flushed from the local bag at `bag`.
+///
+/// Every mutator has its own local bag, so its address identifies the mutator.
+#[inline]
+fn shard_of(bag: *const Bag, shards: usize) -> usize {
+    // Fibonacci hashing spreads the (highly aligned) addresses evenly across the queues.
+    let hash = (bag as usize as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    (hash >> 32) as usize % shards
 }
 
+/// Returns the epoch to seal garbage in, which the current thread unlinked before, and orders the
+/// unlinking before every later pinning and advance.
+#[inline]
+pub fn seal_epoch(realm: &Realm) -> usize {
+    if cfg!(feature = "release_acquire") {
+        realm.epoch.sync()
+    } else {
+        let epoch = realm.epoch.load(Relaxed);
+        atomic::fence(SeqCst);
+        epoch
+    }
+}
 
 /// Pushes the bag onto the global queue and replaces the bag with a new empty bag.
 #[inline]
-pub fn push_bag<'scope>(bag: &mut Bag, scope: &'scope Scope) {
-    let epoch = EPOCH.load(Relaxed);
-    let bag = ::std::mem::replace(bag, Bag::new());
-    ::std::sync::atomic::fence(SeqCst);
-    GARBAGES.push((epoch, bag), scope);
+pub fn push_bag(bag: &mut Bag, scope: &Scope) {
+    let epoch = seal_epoch(scope.realm());
+    push_bag_at(bag, epoch, scope);
+}
+
+/// Pushes the bag onto the global queue and replaces the bag with a new empty bag, or leaves the
+/// bag as it is if allocation fails and the policy permits returning an error.
+pub fn try_push_bag(bag: &mut Bag, scope: &Scope) -> Result<(), AllocError> {
+    let epoch = seal_epoch(scope.realm());
+    try_push_bag_at(bag, epoch, scope)
+}
+
+/// Pushes the bag marked with `epoch` onto the global queue and replaces the bag with a new empty
+/// bag.
+#[inline]
+fn push_bag_at(bag: &mut Bag, epoch: usize, scope: &Scope) {
+    if try_push_bag_at(bag, epoch, scope).is_err() {
+        alloc::handle_alloc_error(Layout::new::<(usize, Bag)>());
+    }
+}
+
+/// Pushes the bag marked with `epoch` onto the global queue and replaces the bag with a new empty
+/// bag, or leaves the bag as it is if allocation fails.
+fn try_push_bag_at(bag: &mut Bag, epoch: usize, scope: &Scope) -> Result<(), AllocError> {
+    // Sealing the bag is the last chance to drop tombstones before they take up collection work.
+    let sealed = bag.len();
+    cancel::record_seal(sealed, bag.compact());
+    if bag.is_empty() {
+        // Still start over, so that the garbage classes are counted from zero.
+        *bag = Bag::new();
+        return Ok(());
+    }
+
+    #[cfg(feature = "spill")]
+    {
+        let realm = scope.realm();
+        if realm.spill.pending() > realm.config.spill_threshold {
+            realm.spill.spill(bag, epoch);
+            if bag.is_empty() {
+                *bag = Bag::new();
+                return Ok(());
+            }
+        }
+    }
+    #[cfg(feature = "spill")]
+    let deferred = bag.deferred();
+
+    let garbages = &scope.realm().garbages;
+    let queue = &garbages[shard_of(bag, garbages.len())];
+    let entry = (epoch, mem::replace(bag, Bag::new()));
+
+    let pushed = try_push_entry(queue, entry, scope);
+    #[cfg(feature = "spill")]
+    if pushed.is_ok() {
+        scope.realm().spill.sealed(deferred);
+    }
+    pushed.map_err(|(_, b)| {
+        *bag = b;
+        AllocError
+    })
+}
+
+/// Seals `garbage` in a bag of its own and pushes it onto the global queue of large garbages.
+pub fn push_large(garbage: Garbage, scope: &Scope) {
+    let realm = scope.realm();
+    let bag = Bag::with_garbage(garbage);
+    let epoch = seal_epoch(realm);
+    #[cfg(feature = "spill")]
+    let deferred = bag.deferred();
+
+    if let Err(entry) = try_push_entry(&realm.large_garbages, (epoch, bag), scope) {
+        mem::forget(entry);
+        alloc::handle_alloc_error(Layout::new::<(usize, Bag)>());
+    }
+    // The bag is counted as destroyed like any other.
+    #[cfg(feature = "spill")]
+    realm.spill.sealed(deferred);
+}
+
+/// Pushes `entry` onto `queue`, handling allocation failure according to the current policy.
+///
+/// On failure the entry is returned back, and it must not be dropped: that would destroy its
+/// garbage right away.
+#[allow(clippy::result_large_err)]
+fn try_push_entry(
+    queue: &Queue<(usize, Bag)>,
+    entry: (usize, Bag),
+    scope: &Scope,
+) -> Result<(), (usize, Bag)> {
+    #[cfg(feature = "testkit")]
+    let _quiet = ::testkit::Quiet::new();
+
+    let entry = match queue.try_push(entry, scope) {
+        Ok(()) => return Ok(()),
+        Err(entry) => entry,
+    };
+
+    match alloc_failure_policy() {
+        AllocFailurePolicy::Abort => {
+            mem::forget(entry);
+            alloc::handle_alloc_error(Layout::new::<(usize, Bag)>())
+        }
+        AllocFailurePolicy::Error => Err(entry),
+        AllocFailurePolicy::Reclaim => {
+            reclaim(scope);
+            queue.try_push(entry, scope)
+        }
+    }
+}
+
+/// Destroys all garbage that can be destroyed right now, to free up memory.
+pub fn reclaim(scope: &Scope) {
+    let realm = scope.realm();
+    let epoch = realm.epoch.try_advance(&realm.registries, scope);
+
+    #[cfg(feature = "reclaim_hook")]
+    let outer = reclaim::start();
+    for queue in realm.queues() {
+        while let Some((_, bag)) = pop_expired(queue, epoch, scope) {
+            destroy_bag(bag, scope);
+        }
+    }
+    #[cfg(feature = "reclaim_hook")]
+    reclaim::finish(outer);
+}
+
+/// Destroys all garbage queued in `realm`, whatever epoch it was deferred in, oldest first, except
+/// for protected objects, whose bags are returned.
+///
+/// # Safety
+///
+/// No mutator other than that of `scope` may be pinned in the realm.
+#[must_use]
+pub unsafe fn destroy_all(realm: &Realm, scope: &Scope) -> Vec<Bag> {
+    let mut protected = Vec::new();
+    let mut destroy = |mut bag: Bag| {
+        #[cfg(feature = "spill")]
+        realm.spill.destroyed(bag.deferred());
+        if protect::any_protected() {
+            let spared = bag.split_off(protect::is_protected);
+            if !spared.is_empty() {
+                protected.push(spared);
+            }
+        }
+        drop(bag);
+    };
+    reclaiming_in(realm.epoch.load(Relaxed), || {
+        for queue in realm.queues() {
+            while let Some((_, bag)) = queue.try_pop_if(|_| true, scope) {
+                destroy(bag);
+            }
+        }
+        #[cfg(feature = "spill")]
+        loop {
+            let bags = realm.spill.replay(None, realm.config.collect_budget);
+            if bags.is_empty() {
+                break;
+            }
+            for (_, bag) in bags {
+                destroy(bag);
+            }
+        }
+    });
+    protected
+}
+
+/// Destroys all garbage deferred so far, including the local bag of the current thread.
+///
+/// This waits for the global epoch to advance, so it doesn't return while another thread stays
+/// pinned. With the `leak_only` feature nothing is ever destroyed, so this returns right away.
+pub fn drain() {
+    if cfg!(feature = "leak_only") {
+        return;
+    }
+
+    let drained = Arc::new(AtomicBool::new(false));
+    let d = drained.clone();
+
+    // The marker is deferred last, so once it's destroyed all earlier garbage is expired too.
+    pin(|scope| unsafe {
+        scope.defer(move || d.store(true, SeqCst));
+        scope.flush();
+    });
+
+    while !drained.load(SeqCst) {
+        pin(reclaim);
+        thread::yield_now();
+    }
+
+    // Another thread may have been collecting concurrently, and set aside the rest of a bag.
+    pin(reclaim);
+}
+
+/// The error returned when the garbage collector fails to allocate memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl Error for AllocError {}
+
+/// What the garbage collector does when it fails to allocate memory for its own bookkeeping.
+///
+/// The policy applies to the nodes allocated when bags of garbage are pushed into the global
+/// queues. Other allocations, like boxing deferred closures or registering threads, always abort
+/// on failure, just like the standard library does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocFailurePolicy {
+    /// Abort the process. This is the default.
+    Abort,
+    /// Make fallible operations like [`Scope::try_defer`] return an [`AllocError`]. Infallible
+    /// operations still abort, except that a collection leaks garbage it fails to set aside for a
+    /// sealed scope.
+    ///
+    /// [`Scope::try_defer`]: struct.Scope.html#method.try_defer
+    /// [`AllocError`]: struct.AllocError.html
+    Error,
+    /// Destroy all garbage that can be destroyed right away, and retry the allocation once. If it
+    /// fails again, proceed as with `Error`.
+    Reclaim,
+}
+
+/// Returns the current allocation failure policy.
+pub fn alloc_failure_policy() -> AllocFailurePolicy {
+    match ALLOC_FAILURE_POLICY.load(Relaxed) {
+        p if p == AllocFailurePolicy::Error as usize => AllocFailurePolicy::Error,
+        p if p == AllocFailurePolicy::Reclaim as usize => AllocFailurePolicy::Reclaim,
+        _ => AllocFailurePolicy::Abort,
+    }
+}
+
+/// Sets the allocation failure policy.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AllocFailurePolicy};
+///
+/// epoch::set_alloc_failure_policy(AllocFailurePolicy::Reclaim);
+/// assert_eq!(epoch::alloc_failure_policy(), AllocFailurePolicy::Reclaim);
+/// ```
+pub fn set_alloc_failure_policy(policy: AllocFailurePolicy) {
+    ALLOC_FAILURE_POLICY.store(policy as usize, Relaxed);
+}
+
+/// Returns the size in bytes from which deferred objects are considered large.
+///
+/// Large objects are not batched into bags with other garbage. Instead they are destroyed as soon
+/// as possible once it is safe to do so, ahead of any other garbage.
+pub fn large_garbage_threshold() -> usize {
+    LARGE_GARBAGE_THRESHOLD.load(Relaxed)
+}
+
+/// Sets the size in bytes from which deferred objects are considered large.
+///
+/// The default is 1 MiB. Setting the threshold to `usize::MAX` disables the special treatment.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_large_garbage_threshold(64 * 1024);
+/// assert_eq!(epoch::large_garbage_threshold(), 64 * 1024);
+/// ```
+pub fn set_large_garbage_threshold(bytes: usize) {
+    LARGE_GARBAGE_THRESHOLD.store(bytes, Relaxed);
+}
+
+/// Returns `true` if epoch `a` comes before epoch `b`, taking wrapping around into account.
+#[inline]
+fn is_before(a: usize, b: usize) -> bool {
+    (b.wrapping_sub(a) as isize) > 0
+}
+
+/// Returns `true` if garbage deferred in epoch `garbage_epoch` can be destroyed in `epoch`.
+#[inline]
+pub fn is_expired(garbage_epoch: usize, epoch: usize) -> bool {
+    // A pinned thread can witness at most one epoch advancement. Therefore, any garbage that is
+    // within one epoch of the current one cannot be destroyed yet.
+    let diff = epoch.wrapping_sub(garbage_epoch);
+    cmp::min(diff, 0usize.wrapping_sub(diff)) > 2
+}
+
+/// Destroys the garbage in an expired bag, except for objects that are still protected, which
+/// get deferred once more.
+fn destroy_bag(mut bag: Bag, scope: &Scope) {
+    // Protected objects are counted again when they are sealed once more.
+    #[cfg(feature = "spill")]
+    scope.realm().spill.destroyed(bag.deferred());
+    if protect::any_protected() {
+        let mut protected = bag.split_off(protect::is_protected);
+        if !protected.is_empty() {
+            push_bag(&mut protected, scope);
+        }
+    }
+    #[cfg(feature = "reclaim_hook")]
+    reclaim::freed(bag.bytes(), bag.deferred());
+    drop(bag);
+}
+
+/// Destroys up to `budget` objects of the expired `bag` sealed in `epoch`, setting the rest aside
+/// in `partial`. Returns how much of the budget was used.
+///
+/// Even an empty bag uses one unit of the budget, so that collection always makes progress.
+fn destroy_slice(
+    mut bag: Bag,
+    epoch: usize,
+    budget: usize,
+    partial: &Queue<(usize, Bag)>,
+    scope: &Scope,
+) -> usize {
+    #[cfg(feature = "reclaim_hook")]
+    let outer = reclaim::start();
+
+    let rest = bag.split_at(budget);
+    if !rest.is_empty() {
+        // If the rest can't be set aside, it is destroyed right away, exceeding the budget.
+        if let Err((_, rest)) = try_push_entry(partial, (epoch, rest), scope) {
+            destroy_bag(rest, scope);
+        }
+    }
+
+    let used = cmp::max(bag.deferred(), 1);
+    destroy_bag(bag, scope);
+    #[cfg(feature = "reclaim_hook")]
+    reclaim::finish(outer);
+    used
+}
+
+/// Pops the oldest bag of `queue` if it's expired as of `epoch`, first setting aside expired bags
+/// that sealed scopes hold on to.
+fn pop_expired(queue: &Queue<(usize, Bag)>, epoch: usize, scope: &Scope) -> Option<(usize, Bag)> {
+    let realm = scope.realm();
+    let held = &realm.held_garbages;
+    if ptr::eq(queue, held) {
+        return queue.try_pop_if(|bag| is_expired(bag.0, epoch) && !realm.is_held(bag), scope);
+    }
+
+    loop {
+        let entry = queue.try_pop_if(|bag| is_expired(bag.0, epoch), scope)?;
+        if !realm.is_held(&entry) {
+            return Some(entry);
+        }
+        if let Err(entry) = try_push_entry(held, entry, scope) {
+            // The policy isn't to abort. The bag may neither be destroyed nor set aside, so it's
+            // leaked.
+            mem::forget(entry);
+        }
+    }
+}
+
+/// Destroys expired bags popped from `queue` until `budget` runs out, no bag is expired, or the
+/// yield hook stops the collection, setting the rest of the last bag aside in `partial`. Returns
+/// the remaining budget, which is zero if the collection was stopped.
+fn collect_queue(
+    queue: &Queue<(usize, Bag)>,
+    partial: &Queue<(usize, Bag)>,
+    epoch: usize,
+    mut budget: usize,
+    scope: &Scope,
+) -> usize {
+    while budget > 0 {
+        match pop_expired(queue, epoch, scope) {
+            None => break,
+            Some((e, bag)) => {
+                budget -= destroy_slice(bag, e, budget, partial, scope);
+                if cooperate::should_yield() {
+                    return 0;
+                }
+            }
+        }
+    }
+    budget
 }
 
 /// Collect several bags from the global old garbage queue and destroys their objects.
 ///
 /// Note: This may itself produce garbage and in turn allocate new bags.
 pub fn collect(scope: &Scope) {
-    let epoch = EPOCH.try_advance(&REGISTRIES, scope);
+    #[cfg(feature = "testkit")]
+    let _quiet = ::testkit::Quiet::new();
 
-    let condition = |bag: &(usize, Bag)| {
-        // A pinned thread can witness at most one epoch advancement. Therefore, any bag that is
-        // within one epoch of the current one cannot be destroyed yet.
-        let diff = epoch.wrapping_sub(bag.0);
-        cmp::min(diff, 0usize.wrapping_sub(diff)) > 2
-    };
+    let started = pause::start();
+    defer! { pause::finish(started) }
 
-    let garbages = &GARBAGES;
-    for _ in 0..COLLECT_STEPS {
-        match garbages.try_pop_if(&condition, scope) {
-            None => break,
-            Some(bag) => drop(bag),
+    let realm = scope.realm();
+    let epoch = realm.epoch.try_advance(&realm.registries, scope);
+    let partial = &realm.partial_garbages;
+    reclaiming_in(epoch, || collect_in(realm, epoch, partial, scope));
+}
+
+/// Whether [`Collector::poll_maintenance`] left work to do.
+///
+/// [`Collector::poll_maintenance`]: struct.Collector.html#method.poll_maintenance
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MaintenanceStatus {
+    /// No garbage is queued for destruction anymore.
+    Done,
+    /// The budget ran out while garbage could still be destroyed, so the next tick should poll
+    /// again.
+    Pending,
+    /// Garbage is queued, but none of it can be destroyed until a pinned mutator unpins and lets
+    /// the epoch advance.
+    Blocked,
+}
+
+/// Destroys garbage of the realm of `scope` that expired as of the epoch it advances to, until
+/// none is left or `deadline` passes.
+///
+/// Returns `Blocked` if garbage is left that hasn't expired yet.
+pub fn maintain(scope: &Scope, deadline: Timestamp) -> MaintenanceStatus {
+    let realm = scope.realm();
+    let epoch = realm.epoch.try_advance(&realm.registries, scope);
+    let partial = &realm.partial_garbages;
+
+    reclaiming_in(epoch, || {
+        loop {
+            for queue in realm.queues() {
+                while let Some((e, bag)) = pop_expired(queue, epoch, scope) {
+                    destroy_slice(bag, e, realm.config.collect_budget, partial, scope);
+                    if sys::now() >= deadline {
+                        return MaintenanceStatus::Pending;
+                    }
+                }
+            }
+            if !replay_spilled(realm, epoch, partial, scope) {
+                break;
+            }
+        }
+
+        #[cfg(feature = "spill")]
+        let spilled = realm.spill.records() > 0;
+        #[cfg(not(feature = "spill"))]
+        let spilled = false;
+        if !spilled && realm.queues().all(|q| q.peek_with(|_| (), scope).is_none()) {
+            MaintenanceStatus::Done
+        } else {
+            MaintenanceStatus::Blocked
+        }
+    })
+}
+
+/// Reads a budget's worth of garbage spilled to disk back into `partial`, once all of it has
+/// expired as of `epoch`. Returns `true` if any was read back.
+fn replay_spilled(
+    realm: &Realm,
+    epoch: usize,
+    partial: &Queue<(usize, Bag)>,
+    scope: &Scope,
+) -> bool {
+    #[cfg(feature = "spill")]
+    {
+        let entries = realm.spill.replay(Some(epoch), realm.config.collect_budget);
+        let replayed = !entries.is_empty();
+        for entry in entries {
+            if let Err(entry) = try_push_entry(partial, entry, scope) {
+                mem::forget(entry);
+                alloc::handle_alloc_error(Layout::new::<(usize, Bag)>());
+            }
+        }
+        replayed
+    }
+    #[cfg(not(feature = "spill"))]
+    {
+        let _ = (realm, epoch, partial, scope);
+        false
+    }
+}
+
+/// Collects garbage that expired as of `epoch`, the global epoch of `realm`.
+fn collect_in(realm: &Realm, epoch: usize, partial: &Queue<(usize, Bag)>, scope: &Scope) {
+    // Advancement stops at the first mutator lagging behind, so entries of unregistered mutators
+    // past it are left alone. Unlink them here, so that scans stay proportional to the number of
+    // live mutators even after lots of threads came and went.
+    if realm.registries.deleted() > 0 {
+        realm.registries.compact(scope);
+    }
+
+    // Once the epoch has advanced past garbage spilled during a stall, bring some of it back for
+    // collection.
+    replay_spilled(realm, epoch, partial, scope);
+
+    // Continue where earlier collections ran out of budget. Then, large garbage takes priority: it
+    // holds on to the most memory.
+    let mut budget = collect_queue(partial, partial, epoch, realm.config.collect_budget, scope);
+    budget = collect_queue(&realm.held_garbages, partial, epoch, budget, scope);
+    budget = collect_queue(&realm.large_garbages, partial, epoch, budget, scope);
+
+    let start = realm.collect_cursor.fetch_add(1, Relaxed);
+    collect_shards(&realm.garbages, partial, start, epoch, budget, scope);
+}
+
+/// Destroys up to `budget` objects from bags that are old enough with respect to `epoch`, unless
+/// the yield hook stops the collection first, setting the rest of the last bag aside in `partial`.
+///
+/// The queues are visited in round-robin order beginning with `start`, taking at most one bag from
+/// each queue per visit.
+fn collect_shards(
+    garbages: &[Queue<(usize, Bag)>],
+    partial: &Queue<(usize, Bag)>,
+    start: usize,
+    epoch: usize,
+    mut budget: usize,
+    scope: &Scope,
+) {
+    // Number of consecutively visited queues that had no bag to destroy.
+    let mut idle = 0;
+    let mut index = start;
+
+    while budget > 0 && idle < garbages.len() {
+        match pop_expired(&garbages[index % garbages.len()], epoch, scope) {
+            None => idle += 1,
+            Some((e, bag)) => {
+                budget -= destroy_slice(bag, e, budget, partial, scope);
+                if cooperate::should_yield() {
+                    return;
+                }
+                idle = 0;
+            }
         }
+        index = index.wrapping_add(1);
     }
 }
 
 
+/// Returns the number of epoch advancements since the oldest bag in `garbages` was sealed, as of
+/// `epoch`.
+fn oldest_age(garbages: &[Queue<(usize, Bag)>], epoch: usize, scope: &Scope) -> Option<usize> {
+    garbages
+        .iter()
+        .filter_map(|q| q.peek_with(|bag| epoch.wrapping_sub(bag.0) / 2, scope))
+        .max()
+}
+
+/// Returns the number of bytes of memory used by the global garbage collector itself, excluding
+/// the objects its garbage refers to.
+///
+/// See [`Collector::memory_usage`] for what is counted.
+///
+/// [`Collector::memory_usage`]: struct.Collector.html#method.memory_usage
+pub fn memory_usage() -> usize {
+    pin(|scope| REALM.memory_usage(scope))
+}
+
+/// Returns how many times the global epoch has advanced since the oldest bag of garbage still
+/// waiting for destruction was sealed, or `None` if no garbage is waiting.
+///
+/// Garbage can be destroyed once its age reaches 2, so an age much larger than that means that
+/// collection is lagging behind, and it may be worth flushing more aggressively.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// if epoch::oldest_garbage_age().map_or(false, |age| age > 16) {
+///     epoch::pin(|scope| scope.flush());
+/// }
+/// ```
+pub fn oldest_garbage_age() -> Option<usize> {
+    pin(|scope| oldest_age(&REALM.garbages, REALM.epoch.load(Relaxed), scope))
+}
+
+/// Returns a snapshot of the state of the global garbage collector.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::pin(|_| {
+///     let stats = epoch::stats();
+///     assert!(stats.mutators >= 1);
+///     assert!(stats.pinned >= 1);
+/// });
+/// ```
+pub fn stats() -> Stats {
+    let pinned = is_pinned();
+    let mut stats = pin(|scope| REALM.stats(scope));
+
+    // The mutator of the current thread is registered, but may only be pinned for the snapshot.
+    stats.mutators += 1;
+    if pinned {
+        stats.pinned += 1;
+    }
+    stats
+}
+
 thread_local! {
     /// The per-thread mutator.
     static MUTATOR: Mutator<'static> = Mutator::new();
 }
 
+thread_local! {
+    /// The mutator `pin` resolves to instead of the per-thread one, or null if none was rebound.
+    static REBOUND: Cell<*const Mutator<'static>> = const { Cell::new(ptr::null()) };
+}
+
+/// Runs `f` with `pin` and `is_pinned` resolving to `mutator` on the current thread.
+pub fn with_rebound<F, R>(mutator: &Mutator<'static>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let previous = REBOUND.with(|r| r.replace(mutator));
+    defer! { REBOUND.with(|r| r.set(previous)) }
+    f()
+}
+
+/// Executes `f` with the mutator `pin` resolves to on the current thread.
+#[inline]
+fn with_pinning_mutator<F, R>(f: F) -> R
+where
+    F: FnOnce(&Mutator) -> R,
+{
+    let rebound = REBOUND.with(|r| r.get());
+    if rebound.is_null() {
+        MUTATOR.with(|mutator| f(mutator))
+    } else {
+        // The mutator outlives the call to `with_rebound` that set it.
+        f(unsafe { &*rebound })
+    }
+}
+
+thread_local! {
+    /// The global epoch in which the garbage being destroyed on the current thread was reclaimed,
+    /// if the current thread is collecting.
+    static RECLAIM_EPOCH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Runs `f`, which destroys garbage reclaimed in `epoch`.
+fn reclaiming_in<F: FnOnce() -> R, R>(epoch: usize, f: F) -> R {
+    let previous = RECLAIM_EPOCH.with(|e| e.replace(Some(epoch)));
+    defer! { RECLAIM_EPOCH.with(|e| e.set(previous)) }
+    #[cfg(feature = "alloc_trim")]
+    {
+        // Collections nested in destructors count towards the outermost one.
+        if previous.is_none() {
+            let freed = trim::start();
+            let result = f();
+            trim::finish(freed);
+            return result;
+        }
+    }
+    f()
+}
+
+/// Returns the global epoch in which the garbage being destroyed on the current thread was
+/// reclaimed, or `None` if it isn't destroyed by a collection.
+pub fn reclaim_epoch() -> Option<usize> {
+    RECLAIM_EPOCH.with(|e| e.get())
+}
+
+/// Executes `f` with the mutator of the current thread.
+pub fn with_mutator<F, R>(f: F) -> R
+where
+    F: FnOnce(&Mutator) -> R,
+{
+    MUTATOR.with(|mutator| f(mutator))
+}
+
 /// Pin the current thread.
+///
+/// The thread is pinned in the default collector, unless [`with_collector`] redirected it into
+/// another one.
+///
+/// [`with_collector`]: fn.with_collector.html
+#[cfg_attr(feature = "profiler", track_caller)]
 pub fn pin<F, R>(f: F) -> R
 where
     F: FnOnce(&Scope) -> R,
 {
-    MUTATOR.with(|mutator| mutator.pin(f))
+    #[cfg(feature = "profiler")]
+    let site = ::std::panic::Location::caller();
+
+    #[cfg(feature = "testkit")]
+    ::testkit::yield_point();
+
+    with_pinning_mutator(|mutator| {
+        #[cfg(feature = "profiler")]
+        mutator.set_pin_site(site);
+
+        mutator.pin(f)
+    })
+}
+
+/// Pins the current thread for a short read-only function `f`, eliding the pinning in a hardware
+/// transaction if possible.
+///
+/// With the `htm` feature enabled on x86-64 CPUs that support restricted transactional memory, `f`
+/// is first run in a hardware transaction instead of announcing the pinning, which saves a fence.
+/// If the transaction aborts, e.g. because the global epoch advanced or `f` made a system call, or
+/// elision isn't available, `f` is run under a regular pinning.
+///
+/// `f` may therefore be called more than once, but the effects of all calls but the last are
+/// rolled back. It should only read shared memory and return quickly.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic};
+/// use std::sync::atomic::Ordering::Acquire;
+///
+/// let a = Atomic::new(7);
+/// let value = epoch::pin_elided(|scope| unsafe { *a.load(Acquire, scope).deref() });
+/// assert_eq!(value, 7);
+/// # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(Acquire, scope)) });
+/// ```
+#[cfg(feature = "unstable")]
+pub fn pin_elided<F, R>(f: F) -> R
+where
+    F: Fn(&Scope) -> R,
+{
+    MUTATOR.with(|mutator| mutator.pin_elided(f))
 }
 
 /// Check if the current thread is pinned.
 pub fn is_pinned() -> bool {
-    MUTATOR.with(|mutator| mutator.is_pinned())
+    with_pinning_mutator(|mutator| mutator.is_pinned())
+}
+
+/// Deferred execution of an arbitrary function `f`, without a [`Scope`] at hand.
+///
+/// This is useful for retiring objects from contexts that don't have access to a scope, e.g. from
+/// a destructor. The function is pushed directly into the global garbage queue, marked with an
+/// epoch one step later than the current one: the caller isn't pinned, so the removal of the
+/// object is not guaranteed to be ordered before the current epoch is read.
+///
+/// Pushing into the global queue still requires pinning. The current thread is pinned if possible,
+/// and otherwise (e.g. during thread exit) a temporary mutator is registered for the purpose.
+///
+/// # Safety
+///
+/// The same rules as for [`Scope::defer`] apply.
+///
+/// [`Scope`]: struct.Scope.html
+/// [`Scope::defer`]: struct.Scope.html#method.defer
+pub unsafe fn defer_unpinned<F: FnOnce() + Send + 'static>(f: F) {
+    if cfg!(feature = "leak_only") {
+        return mem::forget(f);
+    }
+
+    let garbage = Garbage::new(f);
+
+    #[cfg(feature = "unstable")]
+    let garbage = match domain::defer_adopted(garbage) {
+        Ok(()) => return,
+        Err(g) => g,
+    };
+
+    defer_garbage_unpinned_in(&REALM, garbage);
+}
+
+/// Pushes `garbage` directly into the global queue of `realm`, like [`defer_unpinned`] does for
+/// the default realm.
+///
+/// The mutator of the current thread is pinned if it belongs to `realm`, and otherwise a temporary
+/// mutator is registered in `realm` for the purpose.
+///
+/// # Safety
+///
+/// The same rules as for [`Scope::defer`] apply.
+///
+/// [`defer_unpinned`]: fn.defer_unpinned.html
+/// [`Scope::defer`]: struct.Scope.html#method.defer
+pub unsafe fn defer_garbage_unpinned_in(realm: &Arc<Realm>, garbage: Garbage) {
+    let mut bag = Bag::with_garbage(garbage);
+
+    if !cfg!(feature = "release_acquire") {
+        atomic::fence(SeqCst);
+    }
+    let epoch = seal_epoch(realm).wrapping_add(2);
+    if !Arc::ptr_eq(realm, &REALM) {
+        let mutator = Mutator::temporary_in(realm.clone());
+        return mutator.pin(|scope| push_bag_at(&mut bag, epoch, scope));
+    }
+    let pushed = MUTATOR.try_with(|mutator| {
+        mutator.pin(|scope| push_bag_at(&mut bag, epoch, scope))
+    });
+    if pushed.is_err() {
+        Mutator::temporary().pin(|scope| push_bag_at(&mut bag, epoch, scope));
+    }
 }
 
 /// Returns a [`Scope`] without pinning any mutator.
@@ -99,23 +1175,117 @@ pub fn is_pinned() -> bool {
 /// Just like with the safe epoch::pin function, unprotected use of atomics is enclosed within a
 /// scope so that pointers created within it don't leak out or get mixed with pointers from other
 /// scopes.
+///
+/// # Safety
+///
+/// No other mutator may be accessing the atomics and objects used within the scope at the same
+/// time.
 pub unsafe fn unprotected<F, R>(f: F) -> R
 where
     F: FnOnce(&Scope) -> R,
 {
-    let mut bag = Bag::new();
-    let result = unprotected_with_bag(&mut bag, f);
-    result
+    unprotected_with_bag(&mut None, f)
+}
+
+/// Returns a `'static` [`Scope`] without pinning any mutator.
+///
+/// This is like [`unprotected`], except that the scope isn't confined to a closure, which is
+/// convenient e.g. in `Drop` implementations that traverse and free a whole data structure.
+/// Garbage deferred through the scope is destroyed right away instead of being put into a bag,
+/// and flushing it does nothing.
+///
+/// # Safety
+///
+/// The same rules as for [`unprotected`] apply, for as long as the scope or any pointer loaded
+/// through it is in use. In addition, nothing deferred through the scope may be accessed by
+/// anybody afterwards, since it is destroyed right away.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::sync::atomic::Ordering::Relaxed;
+///
+/// struct Node {
+///     value: u32,
+///     next: Atomic<Node>,
+/// }
+///
+/// struct Stack {
+///     head: Atomic<Node>,
+/// }
+///
+/// impl Drop for Stack {
+///     fn drop(&mut self) {
+///         // We have exclusive access to the stack, so there's no need to pin.
+///         unsafe {
+///             let scope = epoch::unprotected_scope();
+///             let mut node = self.head.load(Relaxed, scope);
+///             while !node.is_null() {
+///                 let next = node.deref().next.load(Relaxed, scope);
+///                 scope.defer_drop(node);
+///                 node = next;
+///             }
+///         }
+///     }
+/// }
+///
+/// // Nobody else has access to the stack while it's being built either.
+/// let stack = Stack { head: Atomic::null() };
+/// unsafe {
+///     let scope = epoch::unprotected_scope();
+///     for value in 0..3 {
+///         let next = Atomic::from_ptr(stack.head.load(Relaxed, scope));
+///         stack.head.store(Owned::new(Node { value, next }).into_ptr(scope), Relaxed);
+///     }
+/// }
+/// drop(stack);
+/// ```
+///
+/// [`Scope`]: struct.Scope.html
+/// [`unprotected`]: fn.unprotected.html
+pub unsafe fn unprotected_scope() -> &'static Scope {
+    unprotected_static()
 }
 
 
 #[cfg(test)]
 mod tests {
     use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
     use std::sync::atomic::Ordering::Relaxed;
 
+    use garbage::{Garbage, MAX_OBJECTS};
+    #[cfg(not(feature = "leak_only"))]
+    use testkit::Drops;
     use super::*;
 
+    #[test]
+    fn unprotected_scope_destroys_immediately() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        unsafe {
+            let scope = unprotected_scope();
+            let c = count.clone();
+            scope.defer(move || {
+                c.fetch_add(1, Relaxed);
+            });
+            assert_eq!(count.load(Relaxed), 1);
+
+            let mut batch = scope.defer_batch();
+            for _ in 0..MAX_OBJECTS + 1 {
+                let c = count.clone();
+                batch.defer(move || {
+                    c.fetch_add(1, Relaxed);
+                });
+            }
+            drop(batch);
+            assert_eq!(count.load(Relaxed), MAX_OBJECTS + 2);
+            scope.flush();
+        }
+    }
+
     #[test]
     fn pin_reentrant() {
         assert!(!is_pinned());
@@ -134,9 +1304,9 @@ mod tests {
             .map(|_| {
                 thread::spawn(|| for _ in 0..500_000 {
                     pin(|scope| {
-                        let before = EPOCH.load(Relaxed);
-                        EPOCH.try_advance(&REGISTRIES, scope);
-                        let after = EPOCH.load(Relaxed);
+                        let before = REALM.epoch.load(Relaxed);
+                        REALM.epoch.try_advance(&REALM.registries, scope);
+                        let after = REALM.epoch.load(Relaxed);
 
                         assert!(after.wrapping_sub(before) <= 2);
                     });
@@ -148,4 +1318,204 @@ mod tests {
             t.join().unwrap();
         }
     }
+
+    #[test]
+    fn collect_round_robin() {
+        let destroyed = (0..2).map(|_| Arc::new(AtomicUsize::new(0))).collect::<Vec<_>>();
+        let garbages = (0..2).map(|_| Queue::new()).collect::<Vec<_>>();
+
+        unsafe {
+            unprotected(|scope| {
+                // The first queue is flooded with bags, while the second one holds just one.
+                for (i, count) in [COLLECT_BUDGET + 100, 1].iter().enumerate() {
+                    for _ in 0..*count {
+                        let d = destroyed[i].clone();
+                        let mut bag = Bag::new();
+                        assert!(bag.try_push(Garbage::new(move || {
+                            d.fetch_add(1, Relaxed);
+                        })).is_ok());
+                        garbages[i].push((0, bag), scope);
+                    }
+                }
+
+                collect_shards(&garbages, &Queue::new(), 0, 100, COLLECT_BUDGET, scope);
+            });
+        }
+
+        assert_eq!(destroyed[0].load(Relaxed), COLLECT_BUDGET - 1);
+        assert_eq!(destroyed[1].load(Relaxed), 1);
+    }
+
+    #[test]
+    fn collect_slices_bags() {
+        let destroyed = Arc::new(AtomicUsize::new(0));
+        let garbages = vec![Queue::new()];
+        let partial = Queue::new();
+
+        unsafe {
+            unprotected(|scope| {
+                let mut bag = Bag::new();
+                while !bag.is_full() {
+                    let d = destroyed.clone();
+                    assert!(bag.try_push(Garbage::new(move || {
+                        d.fetch_add(1, Relaxed);
+                    })).is_ok());
+                }
+                garbages[0].push((0, bag), scope);
+
+                // The budget runs out in the middle of the bag, and the rest is set aside.
+                let budget = MAX_OBJECTS / 2;
+                collect_shards(&garbages, &partial, 0, 100, budget, scope);
+                assert_eq!(destroyed.load(Relaxed), budget);
+
+                // The next collection continues with the rest.
+                assert_eq!(collect_queue(&partial, &partial, 100, budget, scope), 0);
+                assert_eq!(destroyed.load(Relaxed), MAX_OBJECTS);
+                assert!(partial.try_pop_if(|_| true, scope).is_none());
+            });
+        }
+    }
+
+    #[test]
+    fn oldest_age_of_shards() {
+        let garbages = (0..3).map(|_| Queue::new()).collect::<Vec<_>>();
+
+        unsafe {
+            unprotected(|scope| {
+                assert_eq!(oldest_age(&garbages, 10, scope), None);
+
+                garbages[1].push((6, Bag::new()), scope);
+                garbages[1].push((2, Bag::new()), scope);
+                garbages[2].push((4, Bag::new()), scope);
+                assert_eq!(oldest_age(&garbages, 10, scope), Some(3));
+
+                // The global epoch may have wrapped around since the bag was sealed.
+                garbages[0].push((usize::MAX - 1, Bag::new()), scope);
+                assert_eq!(oldest_age(&garbages, 10, scope), Some(6));
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn defer_unpinned_runs() {
+        let drops = Drops::new();
+
+        let value = drops.wrap(());
+        unsafe { defer_unpinned(move || drop(value)) }
+
+        ::assert_reclaimed!(drops, 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn large_garbage_skips_bag() {
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        pin(|scope| {
+            let r = ran.clone();
+            unsafe {
+                scope.defer_sized(move || {
+                    r.fetch_add(1, Relaxed);
+                }, usize::MAX);
+            }
+        });
+
+        // The local bag is never flushed, yet the large garbage gets destroyed.
+        for _ in 0..100_000 {
+            if ran.load(Relaxed) == 1 {
+                break;
+            }
+            pin(collect);
+        }
+        assert_eq!(ran.load(Relaxed), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn held_bag_leaked_if_set_aside_fails() {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::ptr;
+        use std::sync::atomic::AtomicBool;
+
+        use {Collector, CollectorConfig, Owned};
+
+        /// An allocator that fails while `failing` is set.
+        struct Failing {
+            failing: AtomicBool,
+            failures: AtomicUsize,
+        }
+
+        unsafe impl GlobalAlloc for Failing {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                if self.failing.load(Relaxed) {
+                    self.failures.fetch_add(1, Relaxed);
+                    return ptr::null_mut();
+                }
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        static NODES: Failing = Failing {
+            failing: AtomicBool::new(false),
+            failures: AtomicUsize::new(0),
+        };
+
+        let collector = Collector::with_allocator(CollectorConfig::default(), &NODES);
+        let handle = collector.register();
+        let drops = Drops::new();
+
+        handle.pin(|scope| unsafe { scope.defer_drop(Owned::new(drops.wrap(())).into_ptr(scope)) });
+        let sealed = handle.pin(|scope| {
+            scope.flush();
+            scope.seal()
+        });
+
+        // Setting the bag aside for the sealed scope fails, which doesn't abort.
+        set_alloc_failure_policy(AllocFailurePolicy::Error);
+        NODES.failing.store(true, Relaxed);
+        for _ in 0..16 {
+            handle.pin(collect);
+        }
+        NODES.failing.store(false, Relaxed);
+        set_alloc_failure_policy(AllocFailurePolicy::Abort);
+
+        assert!(NODES.failures.load(Relaxed) > 0);
+        drop(sealed);
+        for _ in 0..16 {
+            handle.pin(|scope| scope.flush());
+        }
+        assert_eq!(drops.count(), 0);
+    }
+
+    #[test]
+    fn advance_hooks_see_each_advance() {
+        static ADVANCES: ::std::sync::Mutex<Vec<(usize, usize)>> =
+            ::std::sync::Mutex::new(Vec::new());
+
+        ::epoch::on_epoch_advance(|old, new| ADVANCES.lock().unwrap().push((old, new)));
+        let start = REALM.epoch.load(Relaxed);
+        for _ in 0..10 {
+            pin(collect);
+        }
+
+        // Other tests may be pinned concurrently, so the epoch doesn't necessarily advance.
+        let mut advances = ADVANCES.lock().unwrap().clone();
+        if REALM.epoch.load(Relaxed) != start {
+            assert!(advances.iter().any(|&(old, _)| old == start));
+        }
+        for &(old, new) in advances.iter() {
+            assert_eq!(new, old.wrapping_add(2));
+        }
+
+        // Each advance is reported once.
+        let len = advances.len();
+        advances.sort();
+        advances.dedup();
+        assert_eq!(advances.len(), len);
+    }
 }
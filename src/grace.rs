@@ -0,0 +1,219 @@
+//! Awaiting grace periods
+//!
+//! Some cleanup can't be expressed as a deferred function, e.g. unmapping a region of memory that
+//! readers may still be looking into, after which the task doing it has more work to do. Such a
+//! task needs to wait until every mutator that was pinned when the region got unlinked has
+//! unpinned, i.e. until a grace period has elapsed. Blocking the thread for that would stall an
+//! executor, so [`synchronize_async`] returns a future instead.
+//!
+//! The future registers its waker with the epoch of the collector, which wakes it whenever the
+//! epoch advances or a mutator lagging behind unpins, and it tries to advance the epoch itself
+//! when polled.
+//!
+//! [`synchronize_async`]: fn.synchronize_async.html
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use global::{self, Realm};
+use mutator::Mutator;
+use primitive::atomic::Ordering::SeqCst;
+
+/// A future that resolves once a grace period has elapsed.
+///
+/// Returned by [`synchronize_async`] and [`Collector::synchronize_async`].
+///
+/// [`synchronize_async`]: fn.synchronize_async.html
+/// [`Collector::synchronize_async`]: struct.Collector.html#method.synchronize_async
+#[must_use = "futures do nothing unless polled"]
+pub struct GracePeriod {
+    realm: Arc<Realm>,
+    /// The epoch in which the future was created.
+    start: usize,
+    /// The mutator the epoch is advanced with, registered on the first poll.
+    mutator: Option<Mutator<'static>>,
+}
+
+impl GracePeriod {
+    /// Returns a future that resolves once every mutator of `realm` pinned now has unpinned.
+    pub(crate) fn new(realm: Arc<Realm>) -> Self {
+        let start = realm.epoch.load(SeqCst);
+        GracePeriod {
+            realm,
+            start,
+            mutator: None,
+        }
+    }
+
+    /// Returns `true` if the grace period has elapsed as of `epoch`.
+    ///
+    /// Mutators pinned when the future was created are pinned in the start epoch or the one
+    /// before it, and the epoch can advance twice more only once all of them have unpinned.
+    fn elapsed_in(&self, epoch: usize) -> bool {
+        let advanced = epoch.wrapping_sub(self.start);
+        (4..=usize::MAX / 2).contains(&advanced)
+    }
+}
+
+impl Future for GracePeriod {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        // Mutators don't announce their pinning, so there's nothing to wait for.
+        if cfg!(feature = "leak_only") {
+            return Poll::Ready(());
+        }
+        let mut epoch = this.realm.epoch.load(SeqCst);
+        let mut registered = false;
+        loop {
+            if this.elapsed_in(epoch) {
+                return Poll::Ready(());
+            }
+            let realm = &this.realm;
+            let mutator = this.mutator.get_or_insert_with(|| Mutator::temporary_in(realm.clone()));
+            let next = mutator.pin(|scope| {
+                let realm = scope.realm();
+                realm.epoch.try_advance(&realm.registries, scope)
+            });
+            if next == epoch {
+                if registered {
+                    return Poll::Pending;
+                }
+                // Some mutator lags behind. Registering before trying once more makes sure that
+                // we're woken when it unpins.
+                this.realm.epoch.register_waker(cx.waker());
+                registered = true;
+            }
+            epoch = next;
+        }
+    }
+}
+
+impl fmt::Debug for GracePeriod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GracePeriod")
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+/// Returns a future that resolves once every mutator of the global garbage collector pinned now
+/// has unpinned.
+///
+/// The future doesn't block: it is woken whenever the epoch advances or a lagging mutator unpins,
+/// and tries to advance the epoch when polled. It never resolves if it's awaited while the current
+/// thread is pinned.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::future::Future;
+/// use std::pin::pin;
+/// use std::task::{Context, Poll, Waker};
+///
+/// let mut grace = pin!(epoch::synchronize_async());
+/// let mut cx = Context::from_waker(Waker::noop());
+/// // No other thread is pinned, so the grace period elapses right away.
+/// assert_eq!(grace.as_mut().poll(&mut cx), Poll::Ready(()));
+/// ```
+pub fn synchronize_async() -> GracePeriod {
+    GracePeriod::new(global::REALM.clone())
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::sync::mpsc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+
+    use Collector;
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, SeqCst);
+        }
+    }
+
+    #[test]
+    fn waits_for_pinned_mutators() {
+        let collector = Collector::new();
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (unpin_tx, unpin_rx) = mpsc::channel::<()>();
+
+        let reader = {
+            let handle = collector.register();
+            thread::spawn(move || {
+                handle.pin(|_| {
+                    pinned_tx.send(()).unwrap();
+                    unpin_rx.recv().unwrap();
+                })
+            })
+        };
+        pinned_rx.recv().unwrap();
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut grace = pin!(collector.synchronize_async());
+        assert_eq!(grace.as_mut().poll(&mut cx), Poll::Pending);
+        assert!(!flag.0.load(SeqCst));
+
+        unpin_tx.send(()).unwrap();
+        reader.join().unwrap();
+        assert!(flag.0.load(SeqCst));
+        assert_eq!(grace.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn repinning_wakes_waiters() {
+        let collector = Collector::new();
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (repin_tx, repin_rx) = mpsc::channel::<()>();
+
+        let reader = {
+            let handle = collector.register();
+            thread::spawn(move || {
+                handle.pin(|scope| {
+                    pinned_tx.send(()).unwrap();
+                    repin_rx.recv().unwrap();
+                    unsafe { scope.repin() }
+                    pinned_tx.send(()).unwrap();
+                    repin_rx.recv().unwrap();
+                })
+            })
+        };
+        pinned_rx.recv().unwrap();
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        // The future may be polled on any thread.
+        let mut grace = Box::pin(collector.synchronize_async());
+        assert_eq!(grace.as_mut().poll(&mut cx), Poll::Pending);
+
+        repin_tx.send(()).unwrap();
+        pinned_rx.recv().unwrap();
+        assert!(flag.0.load(SeqCst));
+        let grace = thread::spawn(move || {
+            let mut cx = Context::from_waker(Waker::noop());
+            while grace.as_mut().poll(&mut cx).is_pending() {
+                thread::yield_now();
+            }
+        });
+        repin_tx.send(()).unwrap();
+        reader.join().unwrap();
+        grace.join().unwrap();
+    }
+}
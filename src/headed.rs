@@ -0,0 +1,183 @@
+//! Objects with an inline header
+//!
+//! Retiring an object with [`Scope::defer_drop`] records everything needed to destroy it later in
+//! the bag: its address, its size, and a function that drops it. Objects allocated with
+//! [`Owned::new_with_header`] instead carry that bookkeeping in a [`Headed`] header placed in front
+//! of the value, written once at allocation. Retiring such an object with [`Scope::defer_headed`]
+//! records nothing but the pointer.
+//!
+//! The header also remembers the global epoch at which the object was allocated and the name of
+//! its type, so that long-lived or leaked objects can be told apart.
+//!
+//! [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+//! [`Scope::defer_headed`]: struct.Scope.html#method.defer_headed
+//! [`Owned::new_with_header`]: struct.Owned.html#method.new_with_header
+//! [`Headed`]: struct.Headed.html
+
+use std::any;
+use std::fmt;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering::Relaxed;
+
+use atomic::Owned;
+use epoch_safe::EpochSafe;
+use global::REALM;
+
+/// Bookkeeping in front of a [`Headed`] value.
+///
+/// [`Headed`]: struct.Headed.html
+#[repr(C)]
+pub struct Header {
+    /// The global epoch at the time of allocation.
+    birth_epoch: usize,
+    /// The size of the allocation.
+    size: usize,
+    /// Drops the `Headed` object the header belongs to and frees its allocation.
+    destroy: unsafe fn(*mut Header),
+    /// Returns the name of the type of the value.
+    type_name: fn() -> &'static str,
+}
+
+impl Header {
+    /// Returns the size of the allocation.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Destroys the object `header` belongs to.
+    ///
+    /// # Safety
+    ///
+    /// `header` must be the header of a `Headed` object allocated with `Owned::new_with_header`,
+    /// which must not be used afterwards.
+    pub unsafe fn destroy(header: *mut Header) {
+        ((*header).destroy)(header)
+    }
+}
+
+/// A value with a header that holds the bookkeeping for retiring it.
+///
+/// Created with [`Owned::new_with_header`], and retired with [`Scope::defer_headed`]. It
+/// dereferences to the value.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::from_owned(Owned::new_with_header(String::from("old")));
+///
+/// epoch::pin(|scope| {
+///     let new = Owned::new_with_header(String::from("new"));
+///     let old = a.swap(new.into_ptr(scope), SeqCst, scope);
+///     unsafe {
+///         assert_eq!(**old.deref(), "old");
+///         assert_eq!(old.deref().type_name(), "alloc::string::String");
+///         scope.defer_headed(old);
+///     }
+/// });
+/// # epoch::pin(|scope| unsafe { scope.defer_headed(a.load(SeqCst, scope)) });
+/// ```
+///
+/// [`Owned::new_with_header`]: struct.Owned.html#method.new_with_header
+/// [`Scope::defer_headed`]: struct.Scope.html#method.defer_headed
+#[repr(C)]
+pub struct Headed<T> {
+    header: Header,
+    value: T,
+}
+
+impl<T> Headed<T> {
+    /// Returns the global epoch at which the object was allocated.
+    pub fn birth_epoch(&self) -> usize {
+        self.header.birth_epoch
+    }
+
+    /// Returns the size of the allocation, including the header.
+    pub fn size(&self) -> usize {
+        self.header.size
+    }
+
+    /// Returns the name of the type of the value.
+    pub fn type_name(&self) -> &'static str {
+        (self.header.type_name)()
+    }
+
+    /// Returns a pointer to the header.
+    pub(crate) fn header(ptr: *const Self) -> *mut Header {
+        ptr as *mut Header
+    }
+}
+
+impl<T> Deref for Headed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Headed<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Headed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Headed")
+            .field("birth_epoch", &self.header.birth_epoch)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+impl<T: Send + EpochSafe + 'static> Owned<Headed<T>> {
+    /// Allocates `value` on the heap behind a header, and returns a new owned pointer to it.
+    ///
+    /// The object can be retired with [`Scope::defer_headed`], which doesn't need to store
+    /// anything but the pointer. See [`Headed`] for an example.
+    ///
+    /// [`Scope::defer_headed`]: struct.Scope.html#method.defer_headed
+    /// [`Headed`]: struct.Headed.html
+    pub fn new_with_header(value: T) -> Self {
+        unsafe fn destroy<T>(header: *mut Header) {
+            drop(Box::from_raw(header as *mut Headed<T>));
+        }
+
+        Owned::new(Headed {
+            header: Header {
+                birth_epoch: REALM.epoch.load(Relaxed),
+                size: mem::size_of::<Headed<T>>(),
+                destroy: destroy::<T>,
+                type_name: any::type_name::<T>,
+            },
+            value,
+        })
+    }
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use {pin, Owned};
+    use testkit::{DropCounter, Drops};
+    use super::*;
+
+    #[test]
+    fn destroyed_through_header() {
+        let drops = Drops::new();
+        let epoch = REALM.epoch.load(Relaxed);
+
+        pin(|scope| unsafe {
+            let p = Owned::new_with_header(drops.wrap(())).into_ptr(scope);
+            assert!(p.deref().birth_epoch() >= epoch);
+            assert_eq!(p.deref().size(), mem::size_of::<Headed<DropCounter<()>>>());
+            assert!(p.deref().type_name().ends_with("DropCounter<()>"));
+            scope.defer_headed(p);
+        });
+
+        ::assert_reclaimed!(drops, 1);
+    }
+}
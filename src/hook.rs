@@ -0,0 +1,190 @@
+//! Reclamation hooks
+//!
+//! Memory pools want to take back the nodes of their data structures instead of returning them to
+//! the allocator. A hook registered for a type with [`register_reclaim_hook`] receives every object
+//! of that type retired with [`Scope::defer_drop`] once it has expired, in place of dropping and
+//! deallocating it.
+//!
+//! The other global hooks and handlers of the crate, which hold a single callback each, are kept
+//! in a [`Slot`].
+//!
+//! [`register_reclaim_hook`]: fn.register_reclaim_hook.html
+//! [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+//! [`Slot`]: struct.Slot.html
+
+use std::any::{Any, TypeId};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+/// A global hook that can be set or replaced at any time.
+///
+/// Hooks are rarely set but looked up on hot paths, so a flag tells whether there is one before the
+/// lock is taken. The hook is cloned out of the lock rather than called under it, since hooks may
+/// well pin, collect garbage, or set another hook themselves.
+pub struct Slot<H: ?Sized> {
+    hook: Mutex<Option<Arc<H>>>,
+    is_set: AtomicBool,
+    /// The number of times a hook has been set.
+    version: AtomicUsize,
+}
+
+impl<H: ?Sized> Slot<H> {
+    /// Returns an empty slot.
+    pub const fn new() -> Self {
+        Slot {
+            hook: Mutex::new(None),
+            is_set: AtomicBool::new(false),
+            version: AtomicUsize::new(0),
+        }
+    }
+
+    /// Replaces the hook in the slot with `hook`.
+    pub fn set(&self, hook: Option<Arc<H>>) {
+        let mut slot = self.hook.lock().unwrap_or_else(|e| e.into_inner());
+        self.is_set.store(hook.is_some(), Release);
+        self.version.fetch_add(1, Release);
+        *slot = hook;
+    }
+
+    /// Returns `true` if the slot holds a hook.
+    #[inline]
+    pub fn is_set(&self) -> bool {
+        self.is_set.load(Relaxed)
+    }
+
+    /// Returns a number that changes whenever the hook is set, so that a copy of the hook can be
+    /// cached until then.
+    #[inline]
+    pub fn version(&self) -> usize {
+        self.version.load(Acquire)
+    }
+
+    /// Returns the hook in the slot, if any.
+    #[inline]
+    pub fn get(&self) -> Option<Arc<H>> {
+        if self.is_set.load(Acquire) {
+            self.load()
+        } else {
+            None
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn load(&self) -> Option<Arc<H>> {
+        self.hook.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Registered hooks, each of which is an `fn(Box<T>)` keyed by `T`'s type id.
+static RECLAIM_HOOKS: Mutex<Vec<(TypeId, Box<dyn Any + Send>)>> = Mutex::new(Vec::new());
+
+/// Whether `RECLAIM_HOOKS` is nonempty.
+static HAS_RECLAIM_HOOKS: AtomicBool = AtomicBool::new(false);
+
+/// Registers `hook` to receive objects of type `T` when they are reclaimed.
+///
+/// Objects retired with [`Scope::defer_drop`] after the hook has been registered are passed to it
+/// instead of being dropped. The hook runs on whichever thread collects the object, so it must not
+/// block. It replaces any hook previously registered for `T`.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic};
+/// use std::sync::Mutex;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// struct Node {
+///     value: u64,
+/// }
+///
+/// static POOL: Mutex<Vec<Box<Node>>> = Mutex::new(Vec::new());
+///
+/// epoch::register_reclaim_hook(|node: Box<Node>| POOL.lock().unwrap().push(node));
+///
+/// let a = Atomic::new(Node { value: 7 });
+/// epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+/// ```
+///
+/// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+pub fn register_reclaim_hook<T: 'static>(hook: fn(Box<T>)) {
+    let mut hooks = RECLAIM_HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+    let id = TypeId::of::<T>();
+
+    hooks.retain(|&(i, _)| i != id);
+    hooks.push((id, Box::new(hook)));
+    HAS_RECLAIM_HOOKS.store(true, Release);
+}
+
+/// Returns the hook registered for `T`, if any.
+#[inline]
+pub fn reclaim_hook<T: 'static>() -> Option<fn(Box<T>)> {
+    if !HAS_RECLAIM_HOOKS.load(Relaxed) {
+        return None;
+    }
+
+    let hooks = RECLAIM_HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+    hooks
+        .iter()
+        .find(|&&(i, _)| i == TypeId::of::<T>())
+        .and_then(|(_, h)| h.downcast_ref::<fn(Box<T>)>())
+        .cloned()
+}
+
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use {Atomic, pin};
+    use global;
+    use super::*;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    #[allow(clippy::vec_box)]
+    static POOLED: Mutex<Vec<Box<Node>>> = Mutex::new(Vec::new());
+
+    struct Node(usize);
+
+    impl Drop for Node {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn hook_receives_objects() {
+        register_reclaim_hook(|node: Box<Node>| POOLED.lock().unwrap().push(node));
+
+        for i in 0..10 {
+            let a = Atomic::new(Node(i));
+            pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+        }
+        global::drain();
+
+        let pooled = POOLED.lock().unwrap();
+        assert_eq!(pooled.len(), 10);
+        assert_eq!(pooled.iter().map(|n| n.0).sum::<usize>(), 45);
+        assert_eq!(DROPPED.load(SeqCst), 0);
+    }
+
+    #[test]
+    fn slot_is_replaced() {
+        let slot = Slot::<dyn Fn() -> usize + Send + Sync>::new();
+        assert!(slot.get().is_none());
+
+        let version = slot.version();
+        slot.set(Some(Arc::new(|| 1)));
+        assert_ne!(slot.version(), version);
+        assert_eq!(slot.get().map(|h| h()), Some(1));
+
+        let version = slot.version();
+        slot.set(None);
+        assert_ne!(slot.version(), version);
+        assert!(!slot.is_set());
+        assert!(slot.get().is_none());
+    }
+}
@@ -0,0 +1,48 @@
+//! Hardware transactional memory
+//!
+//! Pinning announces the epoch of the current thread, which takes a full fence. For a short
+//! read-only lookup that fence may be the most expensive part. On CPUs with restricted
+//! transactional memory (Intel TSX), the announcement can be elided instead: the lookup runs in a
+//! hardware transaction that reads the global epoch. Advancing the epoch writes to it, which aborts
+//! the transaction, so no garbage can expire while the lookup is running. If the transaction
+//! aborts for any reason, the lookup is run again under a regular pinning.
+//!
+//! Elision is only attempted with the `htm` feature enabled on x86-64, and only if the CPU supports
+//! it, which is detected at runtime.
+
+/// Runs `f` in a hardware transaction, and returns `None` if it couldn't be started or aborted.
+///
+/// All effects on memory of an aborted transaction are rolled back, so it's as if `f` had never
+/// been called.
+#[cfg(all(feature = "htm", target_arch = "x86_64"))]
+#[inline]
+pub fn elide<F: FnOnce() -> R, R>(f: F) -> Option<R> {
+    use std::arch::asm;
+
+    /// The status of a transaction that has started.
+    const STARTED: u32 = !0;
+
+    if !is_x86_feature_detected!("rtm") {
+        return None;
+    }
+
+    unsafe {
+        // On abort, execution resumes right after `xbegin` with the abort status in `eax`.
+        let status: u32;
+        asm!("mov eax, -1", "xbegin 2f", "2:", out("eax") status, options(nostack));
+        if status != STARTED {
+            return None;
+        }
+
+        let result = f();
+        asm!("xend", options(nostack));
+        Some(result)
+    }
+}
+
+/// Runs `f` in a hardware transaction, and returns `None` if it couldn't be started or aborted.
+#[cfg(not(all(feature = "htm", target_arch = "x86_64")))]
+#[inline]
+pub fn elide<F: FnOnce() -> R, R>(_f: F) -> Option<R> {
+    None
+}
@@ -0,0 +1,404 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{self, AcqRel, Acquire, Relaxed, Release};
+
+use atomic::Ptr;
+use raw::CompareAndSetOrdering;
+use mutator::Scope;
+
+/// Types whose values consist of initialized bytes only.
+///
+/// Values of such types can be copied into a machine word byte by byte, and compared bitwise.
+///
+/// # Safety
+///
+/// The type must be `Copy` and must not contain any padding or otherwise uninitialized bytes. For
+/// example, a fieldless `#[repr(u8)]` enum qualifies, but `(u8, u16)` doesn't.
+pub unsafe trait Plain: Copy {}
+
+macro_rules! impl_plain {
+    ($($t:ty)*) => {
+        $(unsafe impl Plain for $t {})*
+    };
+}
+
+impl_plain!(() bool char u8 u16 u32 u64 usize i8 i16 i32 i64 isize f32 f64);
+
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+/// Returns `ord` strengthened so that the loaded value may be dereferenced.
+#[inline]
+fn acquire(ord: Ordering) -> Ordering {
+    match ord {
+        Relaxed => Acquire,
+        Release => AcqRel,
+        ord => ord,
+    }
+}
+
+/// Returns `ord` strengthened so that the stored value is published.
+#[inline]
+fn release(ord: Ordering) -> Ordering {
+    match ord {
+        Relaxed => Release,
+        Acquire => AcqRel,
+        ord => ord,
+    }
+}
+
+/// Returns the bytes of `value`.
+#[inline]
+fn bytes<T: Plain>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+/// An atomic cell holding a value of a [`Plain`] type.
+///
+/// Values that fit into a machine word (e.g. small enums or indices) are stored directly in an
+/// `AtomicUsize`, so reading and writing them never allocates and never produces garbage. Larger
+/// values are boxed on the heap instead, and replaced boxes are reclaimed by the epoch GC.
+///
+/// Either way, the value is read and written as a whole, and [`compare_and_set`] compares values
+/// bitwise.
+///
+/// [`Plain`]: trait.Plain.html
+/// [`compare_and_set`]: struct.AtomicInline.html#method.compare_and_set
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AtomicInline};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = AtomicInline::new(7u32);
+/// assert!(AtomicInline::<u32>::is_inline());
+///
+/// epoch::pin(|scope| {
+///     assert_eq!(a.swap(8, SeqCst, scope), 7);
+///     assert_eq!(a.compare_and_set(7, 9, SeqCst, scope), Err(8));
+///     assert_eq!(a.load(SeqCst, scope), 8);
+/// });
+/// ```
+pub struct AtomicInline<T: Plain> {
+    /// Either the bytes of the value, or a pointer to the boxed value.
+    data: AtomicUsize,
+    _marker: PhantomData<Box<T>>,
+}
+
+unsafe impl<T: Plain + Send + Sync> Send for AtomicInline<T> {}
+unsafe impl<T: Plain + Send + Sync> Sync for AtomicInline<T> {}
+
+impl<T: Plain> AtomicInline<T> {
+    /// Returns `true` if values of type `T` are stored inline, without heap allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::AtomicInline;
+    ///
+    /// assert!(AtomicInline::<u8>::is_inline());
+    /// assert!(!AtomicInline::<[u64; 4]>::is_inline());
+    /// ```
+    #[inline]
+    pub fn is_inline() -> bool {
+        mem::size_of::<T>() <= mem::size_of::<usize>()
+    }
+
+    /// Encodes `value` into a word, allocating it on the heap if it isn't stored inline.
+    fn encode(value: T) -> usize {
+        if Self::is_inline() {
+            let mut data = 0usize;
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    &value as *const T as *const u8,
+                    &mut data as *mut usize as *mut u8,
+                    mem::size_of::<T>(),
+                );
+            }
+            data
+        } else {
+            Box::into_raw(Box::new(value)) as usize
+        }
+    }
+
+    /// Decodes the value out of the word `data`.
+    ///
+    /// If the value is not stored inline, `data` must point to a live box.
+    unsafe fn decode(data: usize) -> T {
+        if Self::is_inline() {
+            ptr::read_unaligned(&data as *const usize as *const T)
+        } else {
+            *(data as *const T)
+        }
+    }
+
+    /// Defers deallocation of the box `data` points to, if the value is not stored inline.
+    unsafe fn retire(data: usize, scope: &Scope) {
+        if !Self::is_inline() {
+            scope.defer_free(Ptr::from_raw(data as *const T));
+        }
+    }
+
+    /// Returns a new atomic cell holding `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::AtomicInline;
+    ///
+    /// let a = AtomicInline::new(1234u64);
+    /// ```
+    pub fn new(value: T) -> Self {
+        AtomicInline {
+            data: AtomicUsize::new(Self::encode(value)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Loads the value.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation. If the value is stored on the heap, the ordering is strengthened to at least
+    /// `Acquire`.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, AtomicInline};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicInline::new(1234u64);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope), 1234));
+    /// ```
+    pub fn load(&self, ord: Ordering, _: &Scope) -> T {
+        if Self::is_inline() {
+            unsafe { Self::decode(self.data.load(ord)) }
+        } else {
+            unsafe { Self::decode(self.data.load(acquire(ord))) }
+        }
+    }
+
+    /// Stores `value` into the atomic cell.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation. If the value is stored on the heap, the ordering is strengthened to at least
+    /// `Release`.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, AtomicInline};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicInline::new(1234u64);
+    /// epoch::pin(|scope| a.store(5678, SeqCst, scope));
+    /// ```
+    pub fn store(&self, value: T, ord: Ordering, scope: &Scope) {
+        if Self::is_inline() {
+            self.data.store(Self::encode(value), ord);
+        } else {
+            self.swap(value, ord, scope);
+        }
+    }
+
+    /// Stores `value` into the atomic cell, returning the previous value.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation. If the value is stored on the heap, the ordering is strengthened to at least
+    /// `AcqRel`.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, AtomicInline};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicInline::new(1234u64);
+    /// epoch::pin(|scope| assert_eq!(a.swap(5678, SeqCst, scope), 1234));
+    /// ```
+    pub fn swap(&self, value: T, ord: Ordering, scope: &Scope) -> T {
+        if Self::is_inline() {
+            unsafe { Self::decode(self.data.swap(Self::encode(value), ord)) }
+        } else {
+            let previous = self.data.swap(Self::encode(value), acquire(release(ord)));
+            unsafe {
+                let value = Self::decode(previous);
+                Self::retire(previous, scope);
+                value
+            }
+        }
+    }
+
+    /// Stores `new` into the atomic cell if the current value is bitwise equal to `current`.
+    ///
+    /// The return value is a result indicating whether the new value was written. On failure the
+    /// actual current value is returned.
+    ///
+    /// This method takes a [`CompareAndSetOrdering`] argument which describes the memory
+    /// ordering of this operation. If the value is stored on the heap, the orderings are
+    /// strengthened as for [`load`] and [`store`].
+    ///
+    /// [`CompareAndSetOrdering`]: trait.CompareAndSetOrdering.html
+    /// [`load`]: struct.AtomicInline.html#method.load
+    /// [`store`]: struct.AtomicInline.html#method.store
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, AtomicInline};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicInline::new(1234u64);
+    ///
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(a.compare_and_set(1234, 5678, SeqCst, scope), Ok(()));
+    ///     assert_eq!(a.compare_and_set(1234, 0, SeqCst, scope), Err(5678));
+    /// });
+    /// ```
+    pub fn compare_and_set<O>(&self, current: T, new: T, ord: O, scope: &Scope) -> Result<(), T>
+    where
+        O: CompareAndSetOrdering,
+    {
+        if Self::is_inline() {
+            let current = Self::encode(current);
+            return self.data
+                .compare_exchange(current, Self::encode(new), ord.success(), ord.failure())
+                .map(|_| ())
+                .map_err(|previous| unsafe { Self::decode(previous) });
+        }
+
+        // The current box can't be compared with `current` directly, so keep reloading it until
+        // either its value differs or the box is replaced.
+        let success = acquire(release(ord.success()));
+        let failure = acquire(ord.failure());
+        let new = Self::encode(new);
+        let mut previous = self.data.load(failure);
+
+        loop {
+            let value = unsafe { Self::decode(previous) };
+            if bytes(&value) != bytes(&current) {
+                unsafe { drop(Box::from_raw(new as *mut T)) };
+                return Err(value);
+            }
+
+            match self.data.compare_exchange(previous, new, success, failure) {
+                Ok(_) => {
+                    unsafe { Self::retire(previous, scope) };
+                    return Ok(());
+                }
+                Err(p) => previous = p,
+            }
+        }
+    }
+
+    /// Consumes the atomic cell and returns the value it holds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::AtomicInline;
+    ///
+    /// let a = AtomicInline::new([1u64, 2, 3, 4]);
+    /// assert_eq!(a.into_inner(), [1, 2, 3, 4]);
+    /// ```
+    pub fn into_inner(self) -> T {
+        let value = unsafe { Self::decode(self.data.load(Relaxed)) };
+        // `Drop` deallocates the box, if there is one.
+        drop(self);
+        value
+    }
+}
+
+impl<T: Plain> Drop for AtomicInline<T> {
+    fn drop(&mut self) {
+        if !Self::is_inline() {
+            unsafe { drop(Box::from_raw(*self.data.get_mut() as *mut T)) };
+        }
+    }
+}
+
+impl<T: Plain + fmt::Debug> fmt::Debug for AtomicInline<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only the owner may read the value without a scope, so don't print it.
+        f.debug_struct("AtomicInline")
+            .field("inline", &Self::is_inline())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+    use std::thread;
+
+    use pin;
+    use super::{AtomicInline, Plain};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(u8)]
+    enum State {
+        Idle,
+        Busy,
+    }
+
+    unsafe impl Plain for State {}
+
+    #[test]
+    fn inline_enum() {
+        assert!(AtomicInline::<State>::is_inline());
+
+        let a = AtomicInline::new(State::Idle);
+        pin(|scope| {
+            assert_eq!(a.compare_and_set(State::Idle, State::Busy, Relaxed, scope), Ok(()));
+            let res = a.compare_and_set(State::Idle, State::Busy, Relaxed, scope);
+            assert_eq!(res, Err(State::Busy));
+            assert_eq!(a.load(Relaxed, scope), State::Busy);
+        });
+    }
+
+    #[test]
+    fn heap_concurrent_increments() {
+        const THREADS: u64 = 4;
+        const STEPS: u64 = 10_000;
+
+        let a = Arc::new(AtomicInline::new([0u64; 3]));
+
+        let threads = (0..THREADS)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || for _ in 0..STEPS {
+                    pin(|scope| {
+                        let mut current = a.load(SeqCst, scope);
+                        loop {
+                            let new = [current[0] + 1, current[1] + 2, current[2] + 3];
+                            match a.compare_and_set(current, new, SeqCst, scope) {
+                                Ok(()) => break,
+                                Err(c) => current = c,
+                            }
+                        }
+                    });
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let n = THREADS * STEPS;
+        let a = Arc::try_unwrap(a).unwrap();
+        assert_eq!(a.into_inner(), [n, 2 * n, 3 * n]);
+    }
+}
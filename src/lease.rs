@@ -0,0 +1,171 @@
+//! Lease-style pinning
+//!
+//! A thread that stays pinned for a long time, e.g. while slowly iterating over a data structure,
+//! holds back the global epoch and therefore all garbage collection. A [`Lease`] is a pinned scope
+//! that gets renewed at regular checkpoints: once the lease term is over, the next checkpoint
+//! unpins and repins the thread, so it stops holding back the epoch.
+//!
+//! Pointers loaded before a checkpoint can't be used after it, unless they are protected with
+//! [`Scope::protect`], which requires the `unstable` feature.
+//!
+//! [`Lease`]: struct.Lease.html
+//! [`Scope::protect`]: struct.Scope.html#method.protect
+
+use std::time::Duration;
+
+use mutator::{Mutator, Scope};
+use sys::{self, Timestamp};
+
+/// When a lease gets renewed.
+#[derive(Clone, Copy, Debug)]
+enum Term {
+    /// After the given time since the last renewal.
+    Time(Duration),
+    /// After the given number of checkpoints since the last renewal.
+    Checkpoints(usize),
+}
+
+/// A pinned scope that gets periodically renewed at checkpoints.
+///
+/// Leases are created by [`pin_for`] and [`pin_for_checkpoints`]. Since [`checkpoint`] takes
+/// `&mut self`, no pointer loaded through the [`scope`] of the lease may be held across it.
+///
+/// If the thread was already pinned when the lease was created, the lease is never renewed.
+///
+/// [`pin_for`]: fn.pin_for.html
+/// [`pin_for_checkpoints`]: fn.pin_for_checkpoints.html
+/// [`checkpoint`]: struct.Lease.html#method.checkpoint
+/// [`scope`]: struct.Lease.html#method.scope
+pub struct Lease<'a> {
+    mutator: &'a Mutator<'a>,
+    scope: &'a Scope,
+    /// Whether the lease is nested in another pinning, and thus can't be renewed.
+    nested: bool,
+    term: Term,
+    /// When the lease was last renewed.
+    renewed_at: Timestamp,
+    /// Number of checkpoints since the lease was last renewed.
+    checkpoints: usize,
+}
+
+impl<'a> Lease<'a> {
+    /// Returns the scope of the lease.
+    pub fn scope(&self) -> &Scope {
+        self.scope
+    }
+
+    /// Renews the lease if its term is over, and returns `true` if it was renewed.
+    pub fn checkpoint(&mut self) -> bool {
+        self.checkpoints += 1;
+
+        let over = match self.term {
+            Term::Time(duration) => self.renewed_at.elapsed() >= duration,
+            Term::Checkpoints(count) => self.checkpoints >= count,
+        };
+
+        over && self.renew()
+    }
+
+    /// Renews the lease right away, and returns `true` if it was renewed.
+    pub fn renew(&mut self) -> bool {
+        if self.nested {
+            return false;
+        }
+
+        self.mutator.repin();
+        self.renewed_at = sys::now();
+        self.checkpoints = 0;
+        true
+    }
+}
+
+/// Pins the mutator for a lease, and executes `f` with it.
+fn lease<F, R>(mutator: &Mutator, term: Term, f: F) -> R
+where
+    F: FnOnce(&mut Lease) -> R,
+{
+    let nested = mutator.is_pinned();
+    mutator.pin(|scope| {
+        f(&mut Lease {
+            mutator,
+            scope,
+            nested,
+            term,
+            renewed_at: sys::now(),
+            checkpoints: 0,
+        })
+    })
+}
+
+/// Pins the current thread for a lease that gets renewed at the first checkpoint after
+/// `duration` elapses, and executes `f` with it.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+/// use std::time::Duration;
+///
+/// let items = (0..100).map(Atomic::new).collect::<Vec<_>>();
+///
+/// let sum = epoch::pin_for(Duration::from_millis(1), |lease| {
+///     let mut sum = 0;
+///     for item in &items {
+///         sum += unsafe { *item.load(SeqCst, lease.scope()).deref() };
+///         lease.checkpoint();
+///     }
+///     sum
+/// });
+/// assert_eq!(sum, 4950);
+/// # epoch::pin(|scope| for item in &items {
+/// #     unsafe { scope.defer_drop(item.load(SeqCst, scope)) }
+/// # });
+/// ```
+pub fn pin_for<F, R>(duration: Duration, f: F) -> R
+where
+    F: FnOnce(&mut Lease) -> R,
+{
+    ::global::with_mutator(|mutator| lease(mutator, Term::Time(duration), f))
+}
+
+/// Pins the current thread for a lease that gets renewed at every `count`-th checkpoint, and
+/// executes `f` with it.
+pub fn pin_for_checkpoints<F, R>(count: usize, f: F) -> R
+where
+    F: FnOnce(&mut Lease) -> R,
+{
+    ::global::with_mutator(|mutator| lease(mutator, Term::Checkpoints(count), f))
+}
+
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use global::REALM;
+    use pin;
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn renewal_lets_epoch_advance() {
+        pin_for_checkpoints(1, |lease| {
+            let start = REALM.epoch.load(Relaxed);
+            for _ in 0..100_000 {
+                REALM.epoch.try_advance(&REALM.registries, lease.scope());
+                assert!(lease.checkpoint());
+                if REALM.epoch.load(Relaxed).wrapping_sub(start) > 4 {
+                    break;
+                }
+            }
+            assert!(REALM.epoch.load(Relaxed).wrapping_sub(start) > 4);
+        });
+    }
+
+    #[test]
+    fn nested_lease_not_renewed() {
+        pin(|_| pin_for_checkpoints(1, |lease| assert!(!lease.checkpoint())));
+    }
+}
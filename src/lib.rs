@@ -21,30 +21,191 @@
 //! There is a global shared instance of garbage queue, which can deallocate ([`defer_free`]) or
 //! drop ([`defer_drop`]) objects, or even run arbitrary destruction procedures ([`defer`]).
 //!
+//! # Leak-only mode
+//!
+//! Short-lived tools may rather leak garbage than pay for reclaiming it. With the `leak_only`
+//! feature, deferred objects and functions are simply forgotten: they are never destroyed or
+//! called, and pinning never collects garbage. The API stays the same, so the same data structures
+//! serve both long-running services and one-shot tools. Pinning doesn't even announce the mutator,
+//! and the epoch never advances, so grace periods, e.g. [`synchronize_async`], elapse right away
+//! and can't be relied on to wait for pinned mutators.
+//!
+//! # Release-acquire mode
+//!
+//! By default, pinning is ordered with a `SeqCst` fence or compare-and-swap. With the
+//! `release_acquire` feature, pinning, sealing garbage, and advancing the epoch use no `SeqCst`
+//! operations at all. Instead, each of them reads the global epoch with an `AcqRel`
+//! read-modify-write, and the total order of those takes the place of the fences.
+//! This is sound under the release-acquire fragment of the C++ memory model, but every pin then
+//! writes to the shared cache line of the epoch, so it's a trade-off to be measured on the workload
+//! at hand, not a free speed-up. The default stays the conservative `SeqCst` design.
+//!
+//! # Platform support
+//!
+//! The epoch GC is built on lock-free pointer-sized atomic operations, including compare-and-swap.
+//! Targets that don't provide them (e.g. some microcontrollers without atomic read-modify-write
+//! instructions) are rejected at compile time instead of silently falling back to locks.
+//!
 //! [`Atomic`]: struct.Atomic.html
 //! [`Ptr`]: struct.Ptr.html
 //! [`pin`]: fn.pin.html
 //! [`defer_free`]: fn.defer_free.html
 //! [`defer_drop`]: fn.defer_drop.html
 //! [`defer`]: fn.defer.html
+//! [`synchronize_async`]: fn.synchronize_async.html
+
+#![cfg_attr(feature = "nightly", feature(auto_traits, negative_impls))]
 
-#![cfg_attr(feature = "nightly", feature(const_fn))]
+#[cfg(not(target_has_atomic = "ptr"))]
+compile_error!(
+    "crossbeam-epoch requires lock-free pointer-sized atomics with compare-and-swap, which this \
+     target doesn't support"
+);
 
 #[macro_use(defer)]
 extern crate scopeguard;
-#[macro_use]
+#[cfg_attr(not(loom), macro_use)]
 extern crate lazy_static;
 extern crate arrayvec;
 extern crate boxfnonce;
 extern crate crossbeam_utils;
+#[cfg(loom)]
+extern crate loom;
 
+pub mod core;
+pub mod compat;
+pub mod prelude;
+mod primitive;
+mod raw;
 mod atomic;
+mod allocator;
+mod any;
+mod build;
+mod inline;
+mod links;
+mod seq;
+mod snapshot;
+mod scope_ref;
+mod sealed;
 mod mutator;
 mod garbage;
+mod headed;
 mod epoch;
+mod grace;
 mod global;
-mod sync;
+mod collector;
+pub mod sync;
+mod debug;
+mod tag;
+mod ticket;
+mod cancel;
+mod lease;
+mod pinned;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod protect;
+mod epoch_safe;
+#[macro_use]
+mod project;
+mod scoped;
+mod registration;
+#[cfg(feature = "unstable")]
+mod executor;
+#[cfg(feature = "unstable")]
+mod domain;
+mod hook;
+mod pause;
+mod prefetch;
+mod pressure;
+mod random;
+mod unwind;
+mod cooperate;
+mod misuse;
+mod sys;
+#[cfg(feature = "destroy_budget")]
+mod budget;
+#[cfg(feature = "alloc_trim")]
+mod trim;
+#[cfg(feature = "spill")]
+mod spill;
+#[cfg(feature = "reclaim_hook")]
+mod reclaim;
+#[cfg(feature = "watchdog")]
+mod watchdog;
+#[cfg(feature = "unstable")]
+mod htm;
+#[cfg(feature = "profiler")]
+mod profiler;
+#[cfg(any(test, feature = "testkit"))]
+#[cfg_attr(not(feature = "testkit"), allow(dead_code))]
+pub mod testkit;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+#[cfg(test)]
+mod litmus;
+#[cfg(feature = "shm")]
+pub mod shm;
 
-pub use self::atomic::{Atomic, CompareAndSetOrdering, Owned, Ptr};
-pub use self::global::{pin, is_pinned, unprotected};
-pub use self::mutator::Scope;
+pub use self::raw::{CompareAndSetOrdering, HIGH_TAG_BITS, Pointable};
+pub use self::atomic::{Atomic, DerefError, Owned, Ptr, TagPolicy, TransferError};
+pub use self::allocator::Allocated;
+pub use self::any::{AnyPtr, AtomicAny};
+pub use self::build::Builder;
+pub use self::headed::Headed;
+pub use self::inline::{AtomicInline, Plain};
+pub use self::links::AtomicLinks;
+pub use self::seq::{AtomicSeq, SeqReader};
+pub use self::snapshot::Snapshot;
+pub use self::scope_ref::{ScopeBound, ScopeRef};
+pub use self::sealed::SealedScope;
+pub use self::debug::register_reachability_check;
+pub use self::hook::register_reclaim_hook;
+pub use self::unwind::set_collection_panic_handler;
+pub use self::cooperate::set_collection_yield;
+pub use self::misuse::{Misuse, MisuseCheck, set_misuse_handler};
+pub use self::epoch::on_epoch_advance;
+pub use self::grace::{GracePeriod, synchronize_async};
+pub use self::pause::{PauseHistogram, collect_pauses, reset_collect_pauses,
+                      set_track_collect_pauses};
+pub use self::pressure::{PressureWatcher, relieve_memory_pressure, watch_memory_pressure};
+pub use self::random::RandomSource;
+pub use self::tag::{GarbageTag, TagStats, tagged_garbage};
+pub use self::ticket::RetireTicket;
+pub use self::cancel::{CancelToken, CompactionStats, compaction_stats};
+pub use self::lease::{Lease, pin_for, pin_for_checkpoints};
+pub use self::pinned::PinnedScope;
+#[cfg(feature = "unstable")]
+pub use self::protect::{MAX_PROTECTED, Protected};
+pub use self::epoch_safe::{AssertEpochSafe, EpochSafe};
+pub use self::project::EpochNode;
+pub use self::scoped::{ThreadScope, scope};
+#[cfg(feature = "unstable")]
+pub use self::executor::{Spawn, Task, executor, set_executor};
+#[cfg(feature = "unstable")]
+pub use self::domain::{DynCollector, RAW_DOMAIN_VERSION, RawDomain, adopt_domain, adopted_domain,
+                       raw_domain};
+pub use self::registration::{MutatorInfo, TooManyMutators, max_mutators, register,
+                             registered_mutators, set_max_mutators};
+pub use self::global::{pin, is_pinned, unprotected, unprotected_scope, defer_unpinned,
+                       memory_usage, oldest_garbage_age, large_garbage_threshold,
+                       set_large_garbage_threshold,
+                       AllocError, AllocFailurePolicy, alloc_failure_policy,
+                       set_alloc_failure_policy, MaintenanceStatus, Stats, stats};
+#[cfg(feature = "unstable")]
+pub use self::global::pin_elided;
+pub use self::mutator::{AsScope, DeferBatch, DestroyToken, Scope, bag_overflows};
+pub use self::garbage::{INLINE_CLOSURE_SIZE, spilled_closures};
+pub use self::collector::{Collector, CollectorConfig, FlushPolicy, GarbageClass, LocalHandle,
+                          MAX_BAG_CAPACITY, MAX_GARBAGE_CLASSES, ReadScope, ReaderHandle,
+                          ScopedCollector, ScopedHandle, with_collector};
+#[cfg(feature = "profiler")]
+pub use self::profiler::{HotSlot, PinSite, hot_slots, reset_hot_slots, reset_pin_sites,
+                         top_pin_sites};
+#[cfg(feature = "destroy_budget")]
+pub use self::budget::{SlowDestroy, destroy_budget, set_destroy_budget, set_slow_destroy_handler};
+#[cfg(feature = "alloc_trim")]
+pub use self::trim::{set_trim_hook, set_trim_threshold, trim_threshold};
+#[cfg(feature = "spill")]
+pub use self::spill::register_spill_type;
+#[cfg(feature = "watchdog")]
+pub use self::watchdog::{Stall, StalledMutator, Watchdog, log_stall, set_pin_backtraces,
+                         stalled_mutators, watch_stalls};
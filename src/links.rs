@@ -0,0 +1,168 @@
+//! Fixed arrays of links embedded in nodes
+//!
+//! Nodes of some data structures hold a variable number of links, e.g. the tower of a skiplist
+//! node. Keeping them in a `Vec<Atomic<T>>` costs a second allocation per node, and a second
+//! object to retire. [`AtomicLinks`] is a fixed array of links stored inline in the node, with
+//! helpers to load and update several of them at once. Since the links are part of the node, the
+//! whole node is retired with a single [`Scope::defer_drop`].
+//!
+//! [`AtomicLinks`]: struct.AtomicLinks.html
+//! [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+
+use std::array;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+
+use atomic::{Atomic, Ptr};
+use raw::CompareAndSetOrdering;
+use mutator::Scope;
+
+/// A fixed array of `N` atomic pointers, meant to be embedded in a node.
+///
+/// The links dereference to a slice of [`Atomic`]s, so they can be accessed individually as well.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AtomicLinks, Owned, Ptr};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// struct Node {
+///     key: u32,
+///     height: usize,
+///     next: AtomicLinks<Node, 4>,
+/// }
+///
+/// let head = Node { key: 0, height: 4, next: AtomicLinks::null() };
+///
+/// epoch::pin(|scope| {
+///     // Link a node of height 2 right after the head.
+///     let succs = head.next.load_all(SeqCst, scope);
+///     let node = Owned::new(Node { key: 1, height: 2, next: AtomicLinks::from_ptrs(succs) });
+///     let node = node.into_ptr(scope);
+///     assert!(head.next.compare_and_set_all(&succs[..2], &[node; 2], SeqCst, scope).is_ok());
+///
+///     assert_eq!(head.next.load_all(SeqCst, scope).iter().filter(|p| !p.is_null()).count(), 2);
+///     assert_eq!(unsafe { head.next[1].load(SeqCst, scope).deref() }.key, 1);
+///
+///     // Unlink the node, and retire it as a whole.
+///     let next = unsafe { node.deref() }.next.load_all(SeqCst, scope);
+///     assert!(head.next.compare_and_set_all(&[node; 2], &next[..2], SeqCst, scope).is_ok());
+///     unsafe { scope.defer_drop(node) }
+/// });
+/// ```
+///
+/// [`Atomic`]: struct.Atomic.html
+pub struct AtomicLinks<T, const N: usize> {
+    links: [Atomic<T>; N],
+}
+
+impl<T, const N: usize> AtomicLinks<T, N> {
+    /// Returns links that are all null.
+    pub fn null() -> Self {
+        AtomicLinks { links: array::from_fn(|_| Atomic::null()) }
+    }
+
+    /// Returns links pointing to `ptrs`.
+    pub fn from_ptrs(ptrs: [Ptr<T>; N]) -> Self {
+        AtomicLinks { links: ptrs.map(Atomic::from_ptr) }
+    }
+
+    /// Loads all links.
+    ///
+    /// Each link is loaded separately, so the returned pointers don't form a snapshot.
+    pub fn load_all<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> [Ptr<'scope, T>; N] {
+        array::from_fn(|i| self.links[i].load(ord, scope))
+    }
+
+    /// Stores `ptrs` into the first `ptrs.len()` links, starting with the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more than `N` pointers.
+    pub fn store_all(&self, ptrs: &[Ptr<T>], ord: Ordering) {
+        assert!(ptrs.len() <= N, "more pointers than links");
+        for (link, &ptr) in self.links.iter().zip(ptrs) {
+            link.store(ptr, ord);
+        }
+    }
+
+    /// Stores `new[i]` into link `i` if its current value is `current[i]`, for each `i` in turn,
+    /// starting with the first link.
+    ///
+    /// The links are updated one by one, stopping at the first one that doesn't hold the expected
+    /// value. The error holds the index of that link and its actual current value, and the links
+    /// before it have been updated. This matches how a skiplist node is linked in bottom-up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `current` and `new` have different lengths, or are longer than `N`.
+    pub fn compare_and_set_all<'scope, O>(
+        &self,
+        current: &[Ptr<T>],
+        new: &[Ptr<T>],
+        ord: O,
+        scope: &'scope Scope,
+    ) -> Result<(), (usize, Ptr<'scope, T>)>
+    where
+        O: CompareAndSetOrdering,
+    {
+        assert_eq!(current.len(), new.len(), "different numbers of current and new pointers");
+        assert!(new.len() <= N, "more pointers than links");
+
+        let ord = (ord.success(), ord.failure());
+        for (i, link) in self.links.iter().enumerate().take(new.len()) {
+            link.compare_and_set(current[i], new[i], ord, scope).map_err(|actual| (i, actual))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Default for AtomicLinks<T, N> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T, const N: usize> Deref for AtomicLinks<T, N> {
+    type Target = [Atomic<T>];
+
+    fn deref(&self) -> &[Atomic<T>] {
+        &self.links
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use {pin, Owned};
+    use super::*;
+
+    #[test]
+    fn compare_and_set_stops_at_mismatch() {
+        let links = AtomicLinks::<u32, 3>::null();
+
+        pin(|scope| unsafe {
+            let a = Owned::new(1).into_ptr(scope);
+            let b = Owned::new(2).into_ptr(scope);
+            links[1].store(b, SeqCst);
+
+            let null = Ptr::null();
+            let result = links.compare_and_set_all(&[null; 3], &[a; 3], SeqCst, scope);
+            let (i, actual) = result.unwrap_err();
+            assert_eq!((i, actual.as_raw()), (1, b.as_raw()));
+
+            let loaded = links.load_all(SeqCst, scope);
+            assert_eq!(loaded[0].as_raw(), a.as_raw());
+            assert_eq!(loaded[1].as_raw(), b.as_raw());
+            assert!(loaded[2].is_null());
+
+            links.store_all(&[null, null], SeqCst);
+            assert!(links.load_all(SeqCst, scope).iter().all(|p| p.is_null()));
+
+            scope.defer_drop(a);
+            scope.defer_drop(b);
+        });
+    }
+}
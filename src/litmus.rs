@@ -0,0 +1,190 @@
+//! Litmus tests of the memory ordering contract
+//!
+//! These tests hammer the few ordering properties that the safety of epoch GC rests on, so that
+//! changes to the fences in pinning, deferral, and epoch advancement have an executable safety
+//! net. On x86 most of them can only fail because of compiler reorderings; they are meant to be
+//! run on weakly ordered targets such as AArch64 as well, preferably in release mode:
+//!
+//! ```text
+//! cargo test --release litmus
+//! ```
+//!
+//! Each test uses its own collector, so that other tests pinning the global one don't hide bugs.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+use std::thread;
+
+use {Atomic, Collector, LocalHandle, Owned, Ptr};
+
+/// Number of rounds each reader performs.
+const ROUNDS: usize = 100_000;
+
+/// An object that records when it's destroyed.
+struct Canary {
+    destroyed: Arc<AtomicBool>,
+}
+
+impl Canary {
+    fn new() -> Self {
+        Canary { destroyed: Arc::new(AtomicBool::new(false)) }
+    }
+}
+
+impl Drop for Canary {
+    fn drop(&mut self) {
+        self.destroyed.store(true, Relaxed);
+    }
+}
+
+/// Runs `writer` in a loop on another thread while `reader` runs `ROUNDS` times, each with a
+/// handle of its own registered in `collector`.
+fn race<R, W>(collector: &Collector, reader: R, writer: W)
+where
+    R: Fn(&LocalHandle),
+    W: Fn(&LocalHandle) + Sync,
+{
+    let done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            let handle = collector.register();
+            while !done.load(Relaxed) {
+                writer(&handle);
+            }
+        });
+
+        // Stop the writer even if the reader panics, or the test would hang instead of failing.
+        defer! { done.store(true, Relaxed) }
+
+        let handle = collector.register();
+        for _ in 0..ROUNDS {
+            reader(&handle);
+        }
+    });
+}
+
+/// An object loaded by a pinned mutator is not destroyed before the mutator gets unpinned.
+#[test]
+fn not_destroyed_while_pinned() {
+    let collector = Collector::new();
+    let slot = Atomic::new(Canary::new());
+
+    race(
+        &collector,
+        |handle| {
+            handle.pin(|scope| {
+                let destroyed = unsafe { slot.load(Acquire, scope).deref() }.destroyed.clone();
+                for _ in 0..16 {
+                    assert!(!destroyed.load(Relaxed), "destroyed while pinned");
+                }
+                scope.flush();
+                assert!(!destroyed.load(Relaxed), "destroyed while pinned");
+            });
+        },
+        |handle| {
+            handle.pin(|scope| unsafe {
+                let old = slot.swap(Owned::new(Canary::new()).into_ptr(scope), AcqRel, scope);
+                scope.defer_drop(old);
+                scope.flush();
+            });
+        },
+    );
+
+    let handle = collector.register();
+    handle.pin(|scope| unsafe { scope.defer_drop(slot.load(Relaxed, scope)) });
+}
+
+/// The contents of an object published with a release store are visible through an acquire load.
+#[test]
+fn message_passing() {
+    let collector = Collector::new();
+    let slot = Atomic::new([0usize; 8]);
+    let next = AtomicUsize::new(1);
+
+    race(
+        &collector,
+        |handle| {
+            handle.pin(|scope| {
+                let values = unsafe { slot.load(Acquire, scope).deref() };
+                assert!(values.iter().all(|&v| v == values[0]), "torn object {:?}", values);
+            });
+        },
+        |handle| {
+            let value = next.fetch_add(1, Relaxed);
+            handle.pin(|scope| unsafe {
+                let new = Owned::new([value; 8]).into_ptr(scope);
+                scope.defer_drop(slot.swap(new, Release, scope));
+            });
+        },
+    );
+
+    collector.register().pin(|scope| unsafe {
+        scope.defer_drop(slot.swap(Ptr::null(), Relaxed, scope))
+    });
+}
+
+/// The global epoch advances at most once while a mutator is pinned.
+///
+/// The epoch the mutator is pinned at may already be behind, since the global epoch can advance
+/// between its load and the announcement of the pinning. But once the pinning is announced, every
+/// advancement that starts afterwards sees it, so only one already in flight can still succeed.
+#[test]
+#[cfg(not(feature = "leak_only"))]
+fn pinned_bounds_epoch() {
+    let collector = Collector::new();
+
+    race(
+        &collector,
+        |handle| {
+            handle.pin(|scope| {
+                assert!(unsafe { (*scope.local_epoch()).get_state() }.0);
+                let start = scope.realm().epoch.load(SeqCst);
+                for _ in 0..16 {
+                    let global = scope.realm().epoch.load(SeqCst);
+                    assert!(
+                        global.wrapping_sub(start) <= 2,
+                        "epoch advanced from {} to {} while pinned",
+                        start,
+                        global
+                    );
+                }
+            });
+        },
+        |handle| handle.pin(|scope| scope.flush()),
+    );
+}
+
+/// A deferred function doesn't run while a mutator that was pinned when it was deferred is still
+/// pinned.
+#[test]
+fn deferred_after_unpin() {
+    let collector = Collector::new();
+    let deferred = AtomicUsize::new(0);
+    let executed = Arc::new(AtomicUsize::new(0));
+
+    race(
+        &collector,
+        |handle| {
+            handle.pin(|scope| {
+                // Everything deferred from now on must wait for this pinning to end.
+                let before = deferred.load(SeqCst);
+                scope.flush();
+                let after = executed.load(SeqCst);
+                assert!(after <= before, "deferred function ran while pinned");
+            });
+        },
+        |handle| {
+            let executed = executed.clone();
+            handle.pin(|scope| unsafe {
+                // Count the function as deferred before it can possibly run.
+                let round = deferred.fetch_add(1, SeqCst) + 1;
+                scope.defer(move || {
+                    executed.fetch_max(round, SeqCst);
+                });
+                scope.flush();
+            });
+        },
+    );
+}
@@ -0,0 +1,217 @@
+//! Reporting misuse before panicking
+//!
+//! Misaligned pointers, retiring objects that are still reachable, using stale pointers, and
+//! other misuse detected at runtime make the offending call panic. In a build with
+//! `panic = "abort"` that takes down the whole process, and all that's left is the panic message,
+//! if the environment keeps stderr at all. A handler set with [`set_misuse_handler`] is called
+//! with a structured description of the misuse right before the panic, so that embedded or server
+//! environments can log it, or flush their own diagnostics first.
+//!
+//! [`set_misuse_handler`]: fn.set_misuse_handler.html
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+
+use collector::Collector;
+use global::Realm;
+use hook::Slot;
+use raw;
+
+/// The check that detected a [`Misuse`].
+///
+/// [`Misuse`]: struct.Misuse.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MisuseCheck {
+    /// A pointer isn't aligned enough to hold a tag.
+    UnalignedPointer,
+    /// An atomic pointer holds an implausible address, e.g. after a stray write.
+    CorruptedPointer,
+    /// A tag doesn't fit into the unused bits of a pointer, or an address overlaps them.
+    TagOverflow,
+    /// An object was deferred for destruction while still reachable, according to the check
+    /// registered with `register_reachability_check`.
+    ReachableObject,
+    /// An object loaded from an `AtomicAny` was downcast to the wrong type.
+    WrongType,
+    /// A static object was deferred for destruction.
+    StaticObject,
+    /// An object was deferred for destruction twice.
+    DoubleRetire,
+    /// A pointer was used with the scope of a later pinning than the one it was loaded in.
+    StalePointer,
+    /// Retirements staged with `Scope::stage_destroy` were neither committed nor rolled back.
+    UnresolvedStaged,
+    /// Registering a mutator exceeded the limit set with `set_max_mutators`.
+    TooManyMutators,
+    /// A collector was created with an invalid configuration.
+    InvalidConfig,
+    /// Destroying a piece of garbage exceeded the budget set with `set_destroy_budget`.
+    SlowDestroy,
+    /// A mutator was unpinned on another thread than the one it was pinned on, e.g. through the
+    /// token of an adopted domain.
+    UnpinnedOnOtherThread,
+}
+
+/// A description of misuse detected at runtime, passed to the handler set with
+/// [`set_misuse_handler`].
+///
+/// [`set_misuse_handler`]: fn.set_misuse_handler.html
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Misuse {
+    /// The check that failed.
+    pub check: MisuseCheck,
+    /// The message the call panics with.
+    pub message: String,
+    /// The thread that made the call.
+    pub thread: ThreadId,
+    /// The name of that thread, if it has one.
+    pub thread_name: Option<String>,
+    /// The collector the misuse happened in, if known.
+    pub collector: Option<Collector>,
+}
+
+impl fmt::Display for Misuse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?} check failed on ", self.message, self.check)?;
+        match self.thread_name {
+            Some(ref name) => write!(f, "thread '{}'", name)?,
+            None => write!(f, "unnamed thread {:?}", self.thread)?,
+        }
+        f.write_str(")")
+    }
+}
+
+/// The handler set with `set_misuse_handler`, if any.
+static HANDLER: Slot<dyn Fn(&Misuse) + Send + Sync> = Slot::new();
+
+thread_local! {
+    /// Whether the handler is running on the current thread, so that misuse within the handler
+    /// itself doesn't recurse.
+    static IN_HANDLER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Sets `handler` to be called with a description of every misuse detected at runtime, right
+/// before the offending call panics.
+///
+/// The handler runs on the thread that made the call, and it replaces any handler set before.
+/// Misuse detected while the handler runs on the same thread isn't reported to it again.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, MisuseCheck, Ptr};
+/// use std::panic;
+/// use std::sync::Mutex;
+///
+/// static LAST: Mutex<Option<MisuseCheck>> = Mutex::new(None);
+///
+/// epoch::set_misuse_handler(|misuse| {
+///     eprintln!("epoch misuse: {}", misuse);
+///     *LAST.lock().unwrap() = Some(misuse.check);
+/// });
+///
+/// let result = panic::catch_unwind(|| Ptr::from_raw(3 as *const u64));
+/// assert!(result.is_err());
+/// assert_eq!(*LAST.lock().unwrap(), Some(MisuseCheck::UnalignedPointer));
+/// ```
+pub fn set_misuse_handler<F>(handler: F)
+where
+    F: Fn(&Misuse) + Send + Sync + 'static,
+{
+    HANDLER.set(Some(Arc::new(handler)));
+}
+
+/// Reports misuse detected by `check` in the collector of `realm`, if known, and panics with
+/// `message`.
+#[cold]
+#[inline(never)]
+pub fn report(check: MisuseCheck, realm: Option<&Arc<Realm>>, message: fmt::Arguments) -> ! {
+    let message = message.to_string();
+    if HANDLER.is_set() && !IN_HANDLER.with(|h| h.replace(true)) {
+        let _reset = ::scopeguard::guard((), |_| IN_HANDLER.with(|h| h.set(false)));
+        let thread = thread::current();
+        let misuse = Misuse {
+            check,
+            message: message.clone(),
+            thread: thread.id(),
+            thread_name: thread.name().map(String::from),
+            collector: realm.map(|realm| Collector::from_realm(realm.clone())),
+        };
+
+        if let Some(handler) = HANDLER.get() {
+            handler(&misuse);
+        }
+    }
+    panic!("{}", message)
+}
+
+/// Reports a tagged pointer that failed a check of the `raw` module, and panics.
+pub fn report_raw(failure: raw::Failure, message: fmt::Arguments) -> ! {
+    let check = match failure {
+        raw::Failure::UnalignedPointer => MisuseCheck::UnalignedPointer,
+        raw::Failure::CorruptedPointer => MisuseCheck::CorruptedPointer,
+        raw::Failure::TagOverflow => MisuseCheck::TagOverflow,
+    };
+    report(check, None, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use testkit::{self, Recorder};
+    use {Collector, CollectorConfig};
+    use super::*;
+
+    /// Misuse reported to the handler.
+    static REPORTED: Recorder<Misuse> = Recorder::new();
+
+    /// Sets a handler recording the reported misuse, shared by all tests.
+    fn record_misuse() {
+        REPORTED.set_once(|| set_misuse_handler(|misuse| REPORTED.record(misuse.clone())));
+    }
+
+    #[test]
+    fn reports_invalid_config() {
+        record_misuse();
+        let config = CollectorConfig {
+            collect_budget: 0,
+            ..Default::default()
+        };
+
+        let name = "reports_invalid_config";
+        let result = testkit::on_thread(name, move || {
+            panic::catch_unwind(|| Collector::with_config(config)).is_err()
+        });
+        assert!(result);
+
+        let reported = REPORTED.on_thread(name);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].check, MisuseCheck::InvalidConfig);
+        assert_eq!(reported[0].message, "collection budget must not be zero");
+    }
+
+    #[test]
+    fn reports_collector() {
+        record_misuse();
+        let collector = Collector::new();
+        let realm = collector.register().pin(|scope| scope.realm_arc().clone());
+
+        let name = "reports_collector";
+        let result = testkit::on_thread(name, move || {
+            panic::catch_unwind(panic::AssertUnwindSafe(move || {
+                report(MisuseCheck::StalePointer, Some(&realm), format_args!("reports_collector"))
+            }))
+            .is_err()
+        });
+        assert!(result);
+
+        let reported = REPORTED.on_thread(name);
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].collector, Some(collector));
+    }
+}
@@ -15,30 +15,178 @@
 //! When a mutator is pinned, a `Scope` is returned as a witness that the mutator is pinned.  Scopes
 //! are necessary for performing atomic operations, and for freeing/dropping locations.
 
-use std::cell::{Cell, UnsafeCell};
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering::{Relaxed, Release, SeqCst};
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::VecDeque;
+use std::alloc::{self, Layout};
+use std::fmt;
+#[cfg(feature = "unstable")]
+use std::future::Future;
+use std::mem;
+use std::panic::Location;
+use std::ptr;
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
+use primitive::atomic::{AtomicBool, AtomicUsize};
+use primitive::atomic::Ordering::{Acquire, Relaxed, Release};
+use primitive::thread_local;
 
 use atomic::Ptr;
+use raw::Pointable;
 use sync::list::Node;
-use garbage::{Garbage, Bag};
-use global;
+use garbage::{Garbage, Bag, MAX_CLASSES};
+use headed::Headed;
+use debug;
+use misuse::{self, MisuseCheck};
+use sys;
+#[cfg(feature = "unstable")]
+use domain;
+use epoch_safe::EpochSafe;
+use epoch::Epoch;
+use global::{self, AllocError, Realm};
+use tag::GarbageTag;
+use ticket::RetireTicket;
+use cancel::CancelToken;
+use collector::FlushPolicy;
+#[cfg(feature = "unstable")]
+use executor;
+use hook;
+#[cfg(feature = "reclaim_hook")]
+use reclaim;
+#[cfg(feature = "unstable")]
+use htm;
+#[cfg(feature = "unstable")]
+use protect::{self, Protected};
+use registration;
+use scope_ref::ScopeBound;
+#[cfg(feature = "profiler")]
+use profiler;
+#[cfg(feature = "watchdog")]
+use watchdog::Pinning;
 
+/// Number of pinnings after which a mutator will collect some global garbage, by default.
+pub const PINS_BETWEEN_COLLECT: usize = 128;
 
-/// Number of pinnings after which a mutator will collect some global garbage.
-const PINS_BETWEEN_COLLECT: usize = 128;
+thread_local! {
+    /// Number of times a local bag of the current thread overflowed.
+    static BAG_OVERFLOWS: Cell<usize> = const { Cell::new(0) };
+
+    /// Functions deferred on the current thread that must also be executed on it.
+    static LOCAL_DEFERRED: RefCell<LocalDeferred> = RefCell::new(LocalDeferred::default());
+
+    /// Retirements staged through unprotected scopes on the current thread that haven't been
+    /// committed or rolled back yet.
+    static STAGED: RefCell<Vec<Staged>> = const { RefCell::new(Vec::new()) };
+
+    /// Number of calls to `Scope::inhibit_collection` running on the current thread.
+    static INHIBITED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns `true` if the current thread shouldn't take on collection work on its own accord.
+fn collection_inhibited() -> bool {
+    INHIBITED.try_with(|i| i.get() > 0).unwrap_or(false)
+}
+
+/// Runs `f` with pinnings and exits of mutators on the current thread not collecting garbage.
+pub fn inhibiting_collection<F: FnOnce() -> R, R>(f: F) -> R {
+    INHIBITED.with(|i| i.set(i.get() + 1));
+    defer!(INHIBITED.with(|i| i.set(i.get() - 1)));
+    f()
+}
+
+/// An object retirement staged with `Scope::stage_destroy`.
+struct Staged {
+    object: *mut u8,
+    retire: unsafe fn(&Scope, *mut u8),
+}
+
+// Only objects of `Send` types are staged.
+unsafe impl Send for Staged {}
+
+/// Retires the staged object `object` of type `T` in `scope`.
+unsafe fn retire_staged<T: Send + EpochSafe + 'static>(scope: &Scope, object: *mut u8) {
+    scope.defer_drop(Ptr::from_raw(object as *const T));
+}
+
+/// A function deferred on the local thread, with its realm and epoch.
+type LocalFn = (Arc<Realm>, usize, Box<dyn FnOnce()>);
+
+/// A queue of functions that must be executed on the thread that deferred them, each marked with
+/// the realm and the epoch it was deferred in.
+#[derive(Default)]
+struct LocalDeferred {
+    queue: VecDeque<LocalFn>,
+}
+
+impl Drop for LocalDeferred {
+    fn drop(&mut self) {
+        // The thread is exiting before the remaining functions could be safely executed. Dropping
+        // them would destroy the captured state too early, so leak them instead.
+        for (_, _, f) in self.queue.drain(..) {
+            mem::forget(f);
+        }
+    }
+}
+
+/// Executes the functions deferred on the current thread whose grace period has elapsed.
+fn run_local_deferred() {
+    loop {
+        // The function is executed outside the borrow so that it may defer more functions.
+        let f = LOCAL_DEFERRED.try_with(|local| {
+            let mut local = local.borrow_mut();
+            match local.queue.front() {
+                Some((realm, e, _)) if global::is_expired(*e, realm.epoch.load(Acquire)) => {
+                    local.queue.pop_front()
+                }
+                _ => None,
+            }
+        });
+
+        match f {
+            Ok(Some((_, _, f))) => f(),
+            _ => break,
+        }
+    }
+}
 
 
 /// Entity that changes shared locations.
 pub struct Mutator<'scope> {
     /// The local garbage objects that will be later freed.
-    bag: UnsafeCell<Bag>,
+    ///
+    /// The bag is allocated on the first deferral, so that threads that only read don't pay for it.
+    bag: UnsafeCell<Option<Box<Bag>>>,
     /// This mutator's entry in the local epoch list.
     local_epoch: &'scope Node<LocalEpoch>,
+    /// Registration number of the mutator, unless it is temporary.
+    registration: Option<usize>,
+    /// The realm the mutator is registered in.
+    realm: Arc<Realm>,
     /// Whether the mutator is currently pinned.
     is_pinned: Cell<bool>,
     /// Total number of pinnings performed.
     pin_count: Cell<usize>,
+    /// Number of pinnings currently in progress, counting nested ones.
+    depth: Cell<usize>,
+    /// The thread the mutator was pinned on by `pin_raw`, if it must be unpinned on it as well.
+    pinned_on: Cell<Option<ThreadId>>,
+    /// Whether the mutator only reads, in which case it doesn't even collect garbage, since that
+    /// produces garbage of its own.
+    reader: bool,
+    /// Call site of the pinning that is about to happen.
+    #[cfg(feature = "profiler")]
+    pin_site: Cell<Option<profiler::Site>>,
+    /// Generation of the current or last pinning, which loaded pointers are stamped with.
+    #[cfg(feature = "stale_ptr_check")]
+    generation: Cell<debug::Generation>,
+    /// The hook called with the memory freed by collections while the mutator is pinned.
+    #[cfg(feature = "reclaim_hook")]
+    reclaim_hook: reclaim::Slot,
+    /// Restores the hook that was called before `pin_raw`, while pinned by it.
+    #[cfg(feature = "reclaim_hook")]
+    reclaim_installed: Cell<Option<reclaim::Installed>>,
+    /// Retirements staged through scopes of the mutator that haven't been committed or rolled
+    /// back yet.
+    staged: RefCell<Vec<Staged>>,
 }
 
 /// An entry in the linked list of the registered mutators.
@@ -47,6 +195,9 @@ pub struct LocalEpoch {
     /// The least significant bit is set if the mutator is currently pinned. The rest of the bits
     /// encode the current epoch.
     state: AtomicUsize,
+    /// The pinnings of the mutator, as far as the watchdog can tell.
+    #[cfg(feature = "watchdog")]
+    pinning: Pinning,
 }
 
 /// A witness that the current mutator is pinned.
@@ -61,26 +212,303 @@ pub struct LocalEpoch {
 /// [`Atomic`]: struct.Atomic.html
 #[derive(Debug)]
 pub struct Scope {
-    bag: *mut Bag, // !Send + !Sync
+    /// The local bag, or null if deferred garbage is destroyed right away.
+    bag: *mut Option<Box<Bag>>, // !Send + !Sync
+    /// The realm the mutator is registered in, or null for the default realm.
+    realm: *const Arc<Realm>,
+    /// The entry of the pinned mutator, or null if the scope is not pinned.
+    local_epoch: *const LocalEpoch,
+    /// Generation of the pinning, or null if the scope is unprotected.
+    #[cfg(feature = "stale_ptr_check")]
+    generation: *const Cell<debug::Generation>,
+    /// Retirements staged through the scope, or null if they are staged on the current thread.
+    staged: *const RefCell<Vec<Staged>>,
+}
+
+/// Types that provide access to a [`Scope`].
+///
+/// Data structures can take `&impl AsScope<'scope>` instead of a plain `&'scope Scope`, so that
+/// their methods may be called with pinned scopes as well as with scopes from [`unprotected`]
+/// (e.g. in a destructor) or any wrapper types that hold on to a scope.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, AsScope, Atomic};
+/// use std::sync::atomic::Ordering::Relaxed;
+///
+/// fn get<'scope, S: AsScope<'scope>>(a: &Atomic<i32>, s: &S) -> Option<&'scope i32> {
+///     unsafe { a.load(Relaxed, s.as_scope()).as_ref() }
+/// }
+///
+/// let a = Atomic::new(7);
+/// epoch::pin(|scope| assert_eq!(get(&a, &scope), Some(&7)));
+/// unsafe {
+///     epoch::unprotected(|scope| {
+///         assert_eq!(get(&a, &scope), Some(&7));
+///         drop(Box::from_raw(a.load(Relaxed, scope).as_raw() as *mut i32));
+///     });
+/// }
+/// ```
+///
+/// [`Scope`]: struct.Scope.html
+/// [`unprotected`]: fn.unprotected.html
+pub trait AsScope<'scope> {
+    /// Returns the scope.
+    fn as_scope(&self) -> &'scope Scope;
+}
+
+impl<'scope> AsScope<'scope> for &'scope Scope {
+    #[inline]
+    fn as_scope(&self) -> &'scope Scope {
+        self
+    }
+}
+
+/// A token that tells whether an object deferred for destruction has been destroyed yet.
+///
+/// Tokens are returned by [`Scope::defer_drop_with_token`] and [`Scope::defer_drop_after`], and
+/// passed to [`Scope::defer_drop_after`] to make sure a child object is destroyed no earlier than
+/// its parent.
+///
+/// [`Scope::defer_drop_with_token`]: struct.Scope.html#method.defer_drop_with_token
+/// [`Scope::defer_drop_after`]: struct.Scope.html#method.defer_drop_after
+#[derive(Clone, Debug)]
+pub struct DestroyToken {
+    destroyed: Arc<AtomicBool>,
+}
+
+impl DestroyToken {
+    /// Returns a new token for an object that has or hasn't been `destroyed` yet.
+    fn new(destroyed: bool) -> Self {
+        DestroyToken { destroyed: Arc::new(AtomicBool::new(destroyed)) }
+    }
+
+    /// Returns `true` if the object has been destroyed.
+    pub fn is_destroyed(&self) -> bool {
+        self.destroyed.load(Acquire)
+    }
+
+    /// Marks the object as destroyed.
+    fn set_destroyed(&self) {
+        self.destroyed.store(true, Release);
+    }
+}
+
+/// A batch of deferred destructions and functions, which are pushed into the local bag all at once.
+///
+/// Created with [`Scope::defer_batch`]. The batch is pushed when it's dropped.
+///
+/// [`Scope::defer_batch`]: struct.Scope.html#method.defer_batch
+pub struct DeferBatch<'scope> {
+    scope: &'scope Scope,
+    garbage: Vec<Garbage>,
+    /// The large garbage threshold at the time the batch was created.
+    large: usize,
+}
+
+impl<'scope> DeferBatch<'scope> {
+    /// Adds deferred deallocation of heap-allocated object `ptr` to the batch.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`Scope::defer_free`] apply.
+    ///
+    /// [`Scope::defer_free`]: struct.Scope.html#method.defer_free
+    pub unsafe fn defer_free<T, const HIGH_TAG: bool>(&mut self, ptr: Ptr<T, HIGH_TAG>) {
+        ptr.check(self.scope);
+        let garbage = Garbage::new_free(ptr.as_raw() as *mut T, 1);
+        self.push_sized(garbage, mem::size_of::<T>());
+    }
+
+    /// Adds deferred destruction and deallocation of heap-allocated object `ptr` to the batch.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`Scope::defer_drop`] apply.
+    ///
+    /// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+    #[track_caller]
+    pub unsafe fn defer_drop<T, const HIGH_TAG: bool>(&mut self, ptr: Ptr<T, HIGH_TAG>)
+    where
+        T: Send + EpochSafe + 'static,
+    {
+        ptr.check(self.scope);
+        debug::check_unreachable(ptr.as_raw());
+        let garbage = match hook::reclaim_hook::<T>() {
+            Some(hook) => Garbage::new_reclaim(ptr.as_raw() as *mut T, hook),
+            None => Garbage::new_drop_at(ptr.as_raw() as *mut T, Location::caller()),
+        };
+        self.push_sized(garbage, mem::size_of::<T>());
+    }
+
+    /// Adds deferred execution of function `f` to the batch.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`Scope::defer`] apply.
+    ///
+    /// [`Scope::defer`]: struct.Scope.html#method.defer
+    pub unsafe fn defer<F: FnOnce() + Send + 'static>(&mut self, f: F) {
+        self.push(Garbage::new(f));
+    }
+
+    /// Returns the number of objects and functions in the batch, counting each of the coalesced
+    /// objects.
+    pub fn len(&self) -> usize {
+        self.garbage.iter().map(Garbage::count).sum()
+    }
+
+    /// Returns `true` if the batch is empty.
+    pub fn is_empty(&self) -> bool {
+        self.garbage.is_empty()
+    }
+
+    /// Adds `garbage` holding on to `size` bytes of memory, unless it's large enough to be
+    /// destroyed as soon as possible.
+    unsafe fn push_sized(&mut self, garbage: Garbage, size: usize) {
+        if size >= self.large {
+            self.scope.defer_garbage_sized(garbage, size);
+        } else {
+            #[cfg(feature = "stats")]
+            self.scope.realm().total_deferred_bytes.fetch_add(size, Relaxed);
+
+            self.push(garbage);
+        }
+    }
+
+    /// Adds `garbage` to the batch, coalescing it with the last object if possible.
+    fn push(&mut self, garbage: Garbage) {
+        let garbage = match self.garbage.last_mut() {
+            Some(last) => match last.try_coalesce(garbage) {
+                Ok(()) => return,
+                Err(g) => g,
+            },
+            None => garbage,
+        };
+        if self.garbage.capacity() == 0 {
+            self.garbage.reserve(self.scope.realm().config.bag_capacity);
+        }
+        self.garbage.push(garbage);
+    }
+}
+
+impl<'scope> Drop for DeferBatch<'scope> {
+    fn drop(&mut self) {
+        unsafe { self.scope.defer_garbage_batch(mem::take(&mut self.garbage)) }
+    }
+}
+
+impl<'scope> fmt::Debug for DeferBatch<'scope> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DeferBatch").field("len", &self.len()).finish()
+    }
 }
 
+/// Destroys `garbage` if `parent` has been destroyed, and otherwise defers trying again in `realm`.
+unsafe fn drop_after(
+    garbage: Garbage,
+    parent: DestroyToken,
+    token: DestroyToken,
+    realm: Arc<Realm>,
+) {
+    if parent.is_destroyed() {
+        // Children waiting for the token mustn't wait forever if the destructor panics.
+        defer!(token.set_destroyed());
+        drop(garbage);
+    } else {
+        let r = realm.clone();
+        let retry = Garbage::new(move || drop_after(garbage, parent, token, r));
+        global::defer_garbage_unpinned_in(&realm, retry);
+    }
+}
 
 impl<'scope> Mutator<'scope> {
+    /// Registers a new mutator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with `set_max_mutators` would be exceeded.
     pub fn new() -> Self {
+        Self::with_realm(global::REALM.clone())
+    }
+
+    /// Registers a new mutator in `realm`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with `set_max_mutators` would be exceeded.
+    pub fn with_realm(realm: Arc<Realm>) -> Self {
+        match registration::enter() {
+            Ok(key) => {
+                // Stagger the collections of threads registered at the same time.
+                let offset = realm.config.random.below(realm.config.pins_between_collect);
+                let mutator = Self::with_registration(Some(key), realm);
+                mutator.pin_count.set(offset);
+                mutator
+            }
+            Err(err) => misuse::report(
+                MisuseCheck::TooManyMutators,
+                Some(&realm),
+                format_args!("{}", err),
+            ),
+        }
+    }
+
+    /// Registers a new mutator in `realm` that never produces garbage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limit set with `set_max_mutators` would be exceeded.
+    pub fn reader_in(realm: Arc<Realm>) -> Self {
+        let mut mutator = Self::with_realm(realm);
+        mutator.reader = true;
+        mutator
+    }
+
+    /// Registers a temporary mutator, e.g. for use during thread exit.
+    ///
+    /// Temporary mutators don't count towards the limit set with `set_max_mutators`.
+    pub fn temporary() -> Self {
+        Self::temporary_in(global::REALM.clone())
+    }
+
+    /// Registers a temporary mutator in `realm`.
+    pub fn temporary_in(realm: Arc<Realm>) -> Self {
+        Self::with_registration(None, realm)
+    }
+
+    fn with_registration(registration: Option<usize>, realm: Arc<Realm>) -> Self {
+        #[cfg(feature = "testkit")]
+        let _quiet = ::testkit::Quiet::new();
+
         Mutator {
-            bag: UnsafeCell::new(Bag::new()),
+            bag: UnsafeCell::new(None),
             local_epoch: unsafe {
                 // Since we dereference no pointers in this block and create no garbages, it is safe
-                // to use `unprotected_with_bag` with an invalid bag.
-                let mut bag = ::std::mem::zeroed::<Bag>();
-                unprotected_with_bag(&mut bag, |scope| {
-                    &*global::REGISTRIES
+                // to use `unprotected_with_bag` with a temporary bag.
+                unprotected_with_bag(&mut None, |scope| {
+                    &*realm.registries
                         .insert_head(LocalEpoch::new(), scope)
                         .as_raw()
                 })
             },
+            registration,
+            realm,
             is_pinned: Cell::new(false),
             pin_count: Cell::new(0),
+            depth: Cell::new(0),
+            pinned_on: Cell::new(None),
+            reader: false,
+            #[cfg(feature = "profiler")]
+            pin_site: Cell::new(None),
+            #[cfg(feature = "stale_ptr_check")]
+            generation: Cell::new(debug::Generation::new_mutator()),
+            #[cfg(feature = "reclaim_hook")]
+            reclaim_hook: RefCell::new(None),
+            #[cfg(feature = "reclaim_hook")]
+            reclaim_installed: Cell::new(None),
+            staged: RefCell::new(Vec::new()),
         }
     }
 
@@ -107,9 +535,13 @@ impl<'scope> Mutator<'scope> {
         F: FnOnce(&Scope) -> R,
     {
         let local_epoch = self.local_epoch.get();
-        let scope = &Scope { bag: self.bag.get() };
+        let scope = &self.scope();
 
         let was_pinned = self.is_pinned.get();
+        self.depth.set(self.depth.get() + 1);
+        defer!(self.depth.set(self.depth.get() - 1));
+        #[cfg(feature = "reclaim_hook")]
+        let _reclaim_hook = (!was_pinned).then(|| reclaim::install(&self.reclaim_hook));
         if !was_pinned {
             // Increment the pin counter.
             let count = self.pin_count.get();
@@ -117,62 +549,323 @@ impl<'scope> Mutator<'scope> {
 
             // Pin the mutator.
             self.is_pinned.set(true);
-            local_epoch.set_pinned();
+            local_epoch.set_pinned(&self.realm.epoch);
+
+            #[cfg(feature = "watchdog")]
+            local_epoch.pinning().pinned(self.registration);
+
+            #[cfg(feature = "stale_ptr_check")]
+            self.generation.set(self.generation.get().next());
 
             // If the counter progressed enough, try advancing the epoch and collecting garbage.
-            if count % PINS_BETWEEN_COLLECT == 0 {
+            if !self.reader &&
+                !cfg!(feature = "leak_only") &&
+                count.is_multiple_of(self.realm.config.pins_between_collect) &&
+                !collection_inhibited()
+            {
                 global::collect(scope);
+                run_local_deferred();
             }
         }
 
+        // Pin the current thread in the adopted domain as well.
+        #[cfg(feature = "unstable")]
+        let mut adopted = if was_pinned { None } else { domain::pin_adopted() };
+
+        // Measure this pinned section if it's due for sampling.
+        #[cfg(feature = "profiler")]
+        let mut sample = match self.pin_site.take() {
+            Some(site) if !was_pinned &&
+                self.pin_count.get().is_multiple_of(profiler::SAMPLE_INTERVAL) => {
+                Some(profiler::Sample::start(site))
+            }
+            _ => None,
+        };
+
         // This will unpin the mutator even if `f` panics.
         defer! {
             if !was_pinned {
                 // Unpin the mutator.
+                let (_, pinned) = local_epoch.get_state();
                 local_epoch.set_unpinned();
                 self.is_pinned.set(false);
+                self.realm.epoch.unpinned(pinned);
+
+                #[cfg(feature = "unstable")]
+                domain::unpin_adopted(adopted.take());
+
+                // Roll back retirements that were left staged.
+                let unresolved = !mem::take(&mut *self.staged.borrow_mut()).is_empty();
+                let checked = cfg!(any(debug_assertions, feature = "strict"));
+                if checked && unresolved && !::std::thread::panicking() {
+                    misuse::report(
+                        MisuseCheck::UnresolvedStaged,
+                        Some(&self.realm),
+                        format_args!("staged retirements were neither committed nor rolled back"),
+                    );
+                }
+
+                #[cfg(feature = "profiler")]
+                {
+                    if let Some(sample) = sample.take() {
+                        sample.finish();
+                    }
+                }
             }
         }
 
         f(scope)
     }
 
+    /// Executes a short read-only function `f` with a scope, eliding the pinning in a hardware
+    /// transaction if possible, and otherwise pinning the mutator.
+    ///
+    /// `f` may be called more than once, but the effects of all calls but the last are rolled
+    /// back.
+    #[cfg(feature = "unstable")]
+    pub fn pin_elided<F, R>(&self, f: F) -> R
+    where
+        F: Fn(&Scope) -> R,
+    {
+        if !self.is_pinned.get() {
+            let scope = &Scope {
+                bag: self.bag.get(),
+                realm: &self.realm,
+                // Repinning would abort the transaction anyway.
+                local_epoch: ptr::null(),
+                #[cfg(feature = "stale_ptr_check")]
+                generation: &self.generation,
+                staged: &self.staged,
+            };
+            let elided = htm::elide(|| {
+                // Advancing the epoch writes to it, which aborts the transaction.
+                self.realm.epoch.load(Relaxed);
+
+                #[cfg(feature = "stale_ptr_check")]
+                self.generation.set(self.generation.get().next());
+
+                f(scope)
+            });
+            if let Some(result) = elided {
+                return result;
+            }
+        }
+
+        self.pin(f)
+    }
+
+    /// Returns a scope of the mutator, which is only a witness of pinning while the mutator is
+    /// pinned.
+    pub fn scope(&self) -> Scope {
+        Scope {
+            bag: self.bag.get(),
+            realm: &self.realm,
+            local_epoch: self.local_epoch.get(),
+            #[cfg(feature = "stale_ptr_check")]
+            generation: &self.generation,
+            staged: &self.staged,
+        }
+    }
+
+    /// Pins the mutator until a matching call to `unpin_raw`, returning whether it was pinned
+    /// already.
+    ///
+    /// This is for pinning without a closure, e.g. by other domains that adopted this one.
+    pub fn pin_raw(&self) -> bool {
+        let was_pinned = self.is_pinned.get();
+        self.depth.set(self.depth.get() + 1);
+        if !was_pinned {
+            self.is_pinned.set(true);
+            self.local_epoch.get().set_pinned(&self.realm.epoch);
+
+            #[cfg(feature = "watchdog")]
+            self.local_epoch.get().pinning().pinned(self.registration);
+
+            #[cfg(feature = "stale_ptr_check")]
+            self.generation.set(self.generation.get().next());
+
+            // Temporary mutators, e.g. that of a pinned scope, may be unpinned anywhere.
+            let checked = cfg!(any(debug_assertions, feature = "strict"));
+            if checked && self.registration.is_some() {
+                self.pinned_on.set(Some(thread::current().id()));
+            }
+
+            // A temporary mutator, e.g. that of a pinned scope, has no hook and may leave the
+            // thread, so it only keeps collections from being reported to another one.
+            #[cfg(feature = "reclaim_hook")]
+            {
+                let slot = match self.registration {
+                    Some(_) => &self.reclaim_hook as *const _,
+                    None => ptr::null(),
+                };
+                self.reclaim_installed.set(Some(reclaim::install(slot)));
+            }
+        }
+        was_pinned
+    }
+
+    /// Undoes a call to `pin_raw` that returned `was_pinned`.
+    pub fn unpin_raw(&self, was_pinned: bool) {
+        self.depth.set(self.depth.get() - 1);
+        if !was_pinned {
+            let (_, pinned) = self.local_epoch.get().get_state();
+            self.local_epoch.get().set_unpinned();
+            self.is_pinned.set(false);
+            self.realm.epoch.unpinned(pinned);
+
+            // Roll back retirements that were left staged.
+            self.staged.borrow_mut().clear();
+
+            #[cfg(feature = "reclaim_hook")]
+            drop(self.reclaim_installed.take());
+
+            if let Some(pinned_on) = self.pinned_on.take() {
+                if pinned_on != thread::current().id() && !thread::panicking() {
+                    misuse::report(
+                        MisuseCheck::UnpinnedOnOtherThread,
+                        Some(&self.realm),
+                        format_args!("a mutator was unpinned on another thread than it was pinned"),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Unpins the mutator and immediately pins it again, in the current epoch.
+    ///
+    /// Must be called only while the mutator is pinned, and only with no scope in use other than
+    /// that of the outermost pinning.
+    pub fn repin(&self) {
+        let local_epoch = self.local_epoch.get();
+        let (_, pinned) = local_epoch.get_state();
+        local_epoch.set_unpinned();
+        local_epoch.set_pinned(&self.realm.epoch);
+        self.realm.epoch.unpinned(pinned);
+
+        #[cfg(feature = "watchdog")]
+        local_epoch.pinning().repinned();
+
+        #[cfg(feature = "stale_ptr_check")]
+        self.generation.set(self.generation.get().next());
+    }
+
+    /// Records `site` as the call site of the next pinning, for the pinned-section profiler.
+    #[cfg(feature = "profiler")]
+    pub fn set_pin_site(&self, site: profiler::Site) {
+        self.pin_site.set(Some(site));
+    }
+
     /// Returns `true` if the current mutator is pinned.
     pub fn is_pinned(&'scope self) -> bool {
         self.is_pinned.get()
     }
+
+    /// Returns the number of pinnings of the mutator currently in progress, counting nested ones.
+    pub fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    /// Sets the hook called with the memory freed by collections while the mutator is pinned.
+    #[cfg(feature = "reclaim_hook")]
+    pub fn set_reclaim_hook(&self, hook: Option<reclaim::Hook>) {
+        *self.reclaim_hook.borrow_mut() = hook;
+    }
 }
 
 impl<'scope> Drop for Mutator<'scope> {
     fn drop(&mut self) {
         // Now that the mutator is exiting, we must move the local bag into the global garbage
-        // queue. Also, let's try advancing the epoch and help free some garbage.
+        // queue. Also, let's try advancing the epoch and help free some garbage, unless the mutator
+        // is a reader.
+        if !self.reader && !cfg!(feature = "leak_only") {
+            self.pin(|scope| {
+                // Spare some cycles on garbage collection.
+                if !collection_inhibited() {
+                    global::collect(scope);
+                }
 
-        self.pin(|scope| {
-            // Spare some cycles on garbage collection.
-            global::collect(scope);
+                // Push the local bag into the global garbage queue.
+                if let Some(bag) = scope.local_bag() {
+                    if !bag.is_empty() {
+                        global::push_bag(bag, scope);
+                    }
+                }
+            });
+        }
 
-            // Unregister the mutator by marking this entry as deleted.
-            self.local_epoch.delete(scope);
+        if self.bag.get_mut().is_some() {
+            self.realm.local_bags.fetch_sub(1, Relaxed);
+        }
 
-            // Push the local bag into the global garbage queue.
-            unsafe {
-                global::push_bag(&mut *self.bag.get(), scope);
-            }
-        });
+        // Unregister the mutator by marking this entry as deleted. This must come last: once the
+        // entry is deleted, other mutators no longer wait for this one, and may unlink and free the
+        // entry at any time.
+        unsafe {
+            unprotected_with_bag(&mut None, |scope| {
+                self.realm.registries.delete(self.local_epoch, scope)
+            })
+        }
+
+        if let Some(key) = self.registration {
+            registration::leave(key);
+        }
     }
 }
 
 /// Returns a [`Scope`] without pinning any mutator, with arbitrary bag.
+///
+/// The bag is allocated on the first deferral if `bag` is `None`.
+///
+/// # Safety
+///
+/// The same rules as for [`unprotected`] apply.
+///
+/// [`Scope`]: struct.Scope.html
+/// [`unprotected`]: fn.unprotected.html
 #[inline]
-pub unsafe fn unprotected_with_bag<F, R>(bag: &mut Bag, f: F) -> R
+pub unsafe fn unprotected_with_bag<F, R>(bag: &mut Option<Box<Bag>>, f: F) -> R
 where
     F: FnOnce(&Scope) -> R,
 {
-    let scope = &Scope { bag: bag };
+    let scope = &Scope {
+        bag,
+        // Not `&*global::REALM`: the realm itself is built with unprotected scopes.
+        realm: ptr::null(),
+        local_epoch: ptr::null(),
+        #[cfg(feature = "stale_ptr_check")]
+        generation: ptr::null(),
+        staged: ptr::null(),
+    };
     f(scope)
 }
 
+/// The scope returned by `unprotected_scope`.
+struct StaticScope(Scope);
+
+unsafe impl Sync for StaticScope {}
+
+static UNPROTECTED: StaticScope = StaticScope(Scope {
+    bag: ptr::null_mut(),
+    realm: ptr::null(),
+    local_epoch: ptr::null(),
+    #[cfg(feature = "stale_ptr_check")]
+    generation: ptr::null(),
+    staged: ptr::null(),
+});
+
+/// Returns a [`Scope`] without pinning any mutator, which destroys deferred garbage right away.
+///
+/// # Safety
+///
+/// The same rules as for [`unprotected_scope`] apply.
+///
+/// [`Scope`]: struct.Scope.html
+/// [`unprotected_scope`]: fn.unprotected_scope.html
+#[inline]
+pub unsafe fn unprotected_static() -> &'static Scope {
+    &UNPROTECTED.0
+}
+
 impl LocalEpoch {
     // FIXME(stjepang): Registries are stored in a linked list because linked lists are fairly easy
     // to implement in a lock-free manner. However, traversal is rather slow due to cache misses and
@@ -194,48 +887,212 @@ impl LocalEpoch {
     ///
     /// Must not be called if the mutator is already pinned!
     #[inline]
-    pub fn set_pinned(&self) {
-        let epoch = global::EPOCH.load(Relaxed);
-        let state = epoch | 1;
+    pub fn set_pinned(&self, epoch: &Epoch) {
+        // Nothing is ever reclaimed, so there is no one to announce the epoch to.
+        if cfg!(feature = "leak_only") {
+            return;
+        }
+
+        let state = epoch.load(Relaxed) | 1;
 
         // Now we must store `state` into `self.state`. It's important that any succeeding loads
         // don't get reordered with this store. In order words, this mutator's epoch must be fully
         // announced to other mutators. Only then it becomes safe to load from the shared memory.
-        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-            // On x86 architectures we have a choice:
-            // 1. `atomic::fence(SeqCst)`, which compiles to a `mfence` instruction.
-            // 2. `compare_and_swap(_, _, SeqCst)`, which compiles to a `lock cmpxchg` instruction.
-            //
-            // Both instructions have the effect of a full barrier, but the second one seems to be
-            // faster in this particular case.
-            let result = self.state.compare_and_swap(0, state, SeqCst);
-            debug_assert_eq!(0, result, "LocalEpoch::set_pinned()'s CAS should succeed.");
+        if cfg!(feature = "release_acquire") {
+            // See the ordering section of the `epoch` module for why this suffices.
+            self.state.store(state, Release);
+            epoch.sync();
         } else {
-            self.state.store(state, Relaxed);
-            ::std::sync::atomic::fence(SeqCst);
+            sys::announce(&self.state, state);
         }
     }
 
     /// Marks the mutator as unpinned.
     #[inline]
     pub fn set_unpinned(&self) {
+        if cfg!(feature = "leak_only") {
+            return;
+        }
+
         // Clear the last bit.
         // We don't need to preserve the epoch, so just store the number zero.
         self.state.store(0, Release);
     }
+
+    /// Returns the pinnings of the mutator, as far as the watchdog can tell.
+    #[cfg(feature = "watchdog")]
+    #[inline]
+    pub fn pinning(&self) -> &Pinning {
+        &self.pinning
+    }
 }
 
 impl Scope {
+    /// Returns the realm the scope belongs to.
+    #[inline]
+    pub(crate) fn realm(&self) -> &Realm {
+        self.realm_arc()
+    }
+
+    /// Returns the shared handle to the realm the scope belongs to.
+    #[inline]
+    pub(crate) fn realm_arc(&self) -> &Arc<Realm> {
+        if self.realm.is_null() {
+            &global::REALM
+        } else {
+            unsafe { &*self.realm }
+        }
+    }
+
+    /// Returns the local bag, allocating it if necessary.
+    #[allow(clippy::mut_from_ref)]
     unsafe fn get_bag(&self) -> &mut Bag {
-        &mut *self.bag
+        (*self.bag).get_or_insert_with(|| {
+            // Count the bags of mutators, which are freed when the mutator is dropped.
+            if !self.realm.is_null() {
+                self.realm().local_bags.fetch_add(1, Relaxed);
+            }
+            Box::new(Bag::new())
+        })
+    }
+
+    /// Returns the local bag if it has been allocated.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) fn local_bag(&self) -> Option<&mut Bag> {
+        unsafe { self.bag.as_mut().and_then(|bag| bag.as_deref_mut()) }
+    }
+
+    /// Returns the entry of the pinned mutator, or null if the scope is not pinned.
+    #[inline]
+    pub(crate) fn local_epoch(&self) -> *const LocalEpoch {
+        self.local_epoch
+    }
+
+    /// Returns `true` if deferred garbage is destroyed right away instead of being put into a bag.
+    #[inline]
+    fn destroys_immediately(&self) -> bool {
+        self.bag.is_null()
+    }
+
+    /// Returns the generation of the pinning, or the default one if the scope is unprotected.
+    #[cfg(feature = "stale_ptr_check")]
+    pub(crate) fn generation(&self) -> debug::Generation {
+        unsafe { self.generation.as_ref().map(Cell::get).unwrap_or_default() }
+    }
+
+    unsafe fn defer_garbage(&self, garbage: Garbage) {
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        if self.destroys_immediately() {
+            return drop(garbage);
+        }
+        if cfg!(feature = "leak_only") {
+            return mem::forget(garbage);
+        }
+
+        #[cfg(feature = "unstable")]
+        let garbage = match domain::defer_adopted(garbage) {
+            Ok(()) => return,
+            Err(g) => g,
+        };
+
+        self.push_garbage(garbage)
+    }
+
+    /// Defers all of `batch`, in order, moving as much of it into the local bag at once as fits.
+    unsafe fn defer_garbage_batch(&self, batch: Vec<Garbage>) {
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        if batch.is_empty() || self.destroys_immediately() {
+            return drop(batch);
+        }
+        if cfg!(feature = "leak_only") {
+            return batch.into_iter().for_each(mem::forget);
+        }
+
+        #[cfg(feature = "unstable")]
+        let batch = match domain::defer_batch_adopted(batch) {
+            Ok(()) => return,
+            Err(b) => b,
+        };
+
+        // Only the first object may coalesce with the bag, as the rest were coalesced in the batch.
+        let mut batch = batch.into_iter();
+        self.push_garbage(batch.next().unwrap());
+
+        let bag = self.get_bag();
+        let capacity = self.realm().config.bag_capacity;
+        while batch.len() > 0 {
+            if bag.len() >= capacity {
+                let _ = BAG_OVERFLOWS.try_with(|c| c.set(c.get().wrapping_add(1)));
+                global::push_bag(bag, self);
+            }
+            if bag.is_empty() {
+                bag.start(self.realm().epoch.load(Relaxed));
+            }
+            let room = capacity - bag.len();
+            bag.extend(batch.by_ref().take(room));
+        }
     }
 
-    unsafe fn defer_garbage(&self, mut garbage: Garbage) {
+    /// Pushes `garbage` into the local bag, never handing it to an adopted domain.
+    pub(crate) unsafe fn push_garbage(&self, mut garbage: Garbage) {
+        if self.destroys_immediately() {
+            return drop(garbage);
+        }
+        if cfg!(feature = "leak_only") {
+            return mem::forget(garbage);
+        }
+
         let bag = self.get_bag();
+        let capacity = self.realm().config.bag_capacity;
 
-        while let Err(g) = bag.try_push(garbage) {
+        garbage = match bag.try_coalesce(garbage) {
+            Ok(()) => return,
+            Err(g) => g,
+        };
+        loop {
+            if bag.len() < capacity {
+                if bag.is_empty() {
+                    bag.start(self.realm().epoch.load(Relaxed));
+                }
+                match bag.try_push(garbage) {
+                    Ok(()) => return,
+                    Err(g) => garbage = g,
+                }
+            }
+            let _ = BAG_OVERFLOWS.try_with(|c| c.set(c.get().wrapping_add(1)));
             global::push_bag(bag, self);
-            garbage = g;
+        }
+    }
+
+    /// Defers `garbage` holding on to `size` bytes of memory.
+    unsafe fn defer_garbage_sized(&self, garbage: Garbage, size: usize) {
+        if self.destroys_immediately() {
+            return drop(garbage);
+        }
+        if cfg!(feature = "leak_only") {
+            return mem::forget(garbage);
+        }
+
+        #[cfg(feature = "stats")]
+        self.realm().total_deferred_bytes.fetch_add(size, Relaxed);
+
+        #[cfg(feature = "unstable")]
+        let garbage = match domain::defer_adopted(garbage) {
+            Ok(()) => return,
+            Err(g) => g,
+        };
+
+        if size >= global::large_garbage_threshold() {
+            #[cfg(feature = "testkit")]
+            ::testkit::yield_point();
+
+            global::push_large(garbage, self);
+        } else {
+            self.defer_garbage(garbage);
         }
     }
 
@@ -244,48 +1101,1400 @@ impl Scope {
     /// This function inserts the object into a mutator-local [`Bag`]. When the bag becomes full,
     /// the bag is flushed into the globally shared queue of bags.
     ///
-    /// If the object is unusually large, it is wise to follow up with a call to [`flush`] so that
-    /// it doesn't get stuck waiting in the local bag for a long time.
+    /// If `T` is at least [`large_garbage_threshold`] bytes large, the object bypasses the bag and
+    /// is deallocated as soon as possible instead.
+    ///
+    /// # Safety
+    ///
+    /// The object must not be reachable by other mutators anymore, and it must not be deferred
+    /// more than once.
     ///
     /// [`Bag`]: struct.Bag.html
-    /// [`flush`]: fn.flush.html
-    pub unsafe fn defer_free<T>(&self, ptr: Ptr<T>) {
-        self.defer_garbage(Garbage::new_free(ptr.as_raw() as *mut T, 1))
+    /// [`large_garbage_threshold`]: fn.large_garbage_threshold.html
+    pub unsafe fn defer_free<T, const HIGH_TAG: bool>(&self, ptr: Ptr<T, HIGH_TAG>) {
+        ptr.check(self);
+        let garbage = Garbage::new_free(ptr.as_raw() as *mut T, 1);
+        self.defer_garbage_sized(garbage, mem::size_of::<T>())
     }
 
     /// Deferred destruction and deallocation of heap-allocated object `ptr`.
-    // FIXME(jeehoonkang): `T: 'static` may be too restrictive.
-    pub unsafe fn defer_drop<T: Send + 'static>(&self, ptr: Ptr<T>) {
-        self.defer_garbage(Garbage::new_drop(ptr.as_raw() as *mut T, 1))
-    }
+    ///
+    /// In debug builds the object is first passed to the reachability check registered for `T`
+    /// with [`register_reachability_check`], if any.
+    ///
+    /// As with [`defer_free`], objects of at least [`large_garbage_threshold`] bytes are destroyed
+    /// as soon as possible.
+    ///
+    /// The destructor is run by whichever thread collects the object, so `T` must be
+    /// [`EpochSafe`]. If a hook is registered for `T` with [`register_reclaim_hook`], the object is
+    /// passed to the hook instead.
+    ///
+    /// # Safety
+    ///
+    /// The object must not be reachable by other mutators anymore, and it must not be deferred
+    /// more than once.
+    ///
+    /// [`register_reachability_check`]: fn.register_reachability_check.html
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    /// [`large_garbage_threshold`]: fn.large_garbage_threshold.html
+    /// [`EpochSafe`]: trait.EpochSafe.html
+    /// [`register_reclaim_hook`]: fn.register_reclaim_hook.html
+    // FIXME(jeehoonkang): `T: 'static` may be too restrictive.
+    #[track_caller]
+    pub unsafe fn defer_drop<T, const HIGH_TAG: bool>(&self, ptr: Ptr<T, HIGH_TAG>)
+    where
+        T: Send + EpochSafe + 'static,
+    {
+        ptr.check(self);
+        debug::check_unreachable(ptr.as_raw());
+        let garbage = match hook::reclaim_hook::<T>() {
+            Some(hook) => Garbage::new_reclaim(ptr.as_raw() as *mut T, hook),
+            None => Garbage::new_drop_at(ptr.as_raw() as *mut T, Location::caller()),
+        };
+        self.defer_garbage_sized(garbage, mem::size_of::<T>())
+    }
+
+    /// Deferred destruction and deallocation of object `ptr` allocated with a header.
+    ///
+    /// Unlike [`defer_drop`], this stores nothing but the pointer, since the header tells how to
+    /// destroy the object. Reclaim hooks and reachability checks don't apply to such objects.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated with [`Owned::new_with_header`], it must not be
+    /// reachable by other mutators anymore, and it must not be deferred more than once.
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`Owned::new_with_header`]: struct.Owned.html#method.new_with_header
+    pub unsafe fn defer_headed<T, const HIGH_TAG: bool>(&self, ptr: Ptr<Headed<T>, HIGH_TAG>)
+    where
+        T: Send + EpochSafe + 'static,
+    {
+        ptr.check(self);
+        let header = Headed::header(ptr.as_raw());
+        self.defer_garbage_sized(Garbage::new_headed(header), (*header).size())
+    }
+
+    /// Deferred destruction and deallocation of object `ptr`, as if by dropping
+    /// `ptr.into_owned()`.
+    ///
+    /// Unlike [`defer_drop`], this also accepts dynamically sized objects such as arrays created
+    /// with [`Owned::init`]. Reclaim hooks and reachability checks don't apply to such objects.
+    ///
+    /// # Safety
+    ///
+    /// The object must not be reachable by other mutators anymore, and it must not be deferred
+    /// more than once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::mem::MaybeUninit;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<[MaybeUninit<u64>]>::init(16);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let new = Owned::<[MaybeUninit<u64>]>::init(32).into_ptr(scope);
+    ///     let old = a.swap(new, SeqCst, scope);
+    ///     unsafe { scope.defer_destroy(old) }
+    /// });
+    /// # epoch::pin(|scope| unsafe { scope.defer_destroy(a.swap(Ptr::null(), SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`Owned::init`]: struct.Owned.html#method.init
+    pub unsafe fn defer_destroy<T, const HIGH_TAG: bool>(&self, ptr: Ptr<T, HIGH_TAG>)
+    where
+        T: ?Sized + Pointable + Send + EpochSafe + 'static,
+    {
+        ptr.check(self);
+        let size = mem::size_of_val(ptr.deref());
+        let garbage = Garbage::new_pointable::<T>(ptr.address() as *mut (), size);
+        self.defer_garbage_sized(garbage, size)
+    }
+
+    /// Deferred destruction and deallocation of boxed object `ptr`, as if by dropping
+    /// `Box::from_raw(ptr)`.
+    ///
+    /// This is meant for objects that are shared through raw pointers rather than [`Atomic`],
+    /// such as boxed slices and trait objects.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `Box::into_raw`. The same rules as for
+    /// [`defer_destroy`] apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch as epoch;
+    ///
+    /// let slice: Box<[String]> = vec![String::from("a"), String::from("b")].into_boxed_slice();
+    /// let ptr = Box::into_raw(slice);
+    ///
+    /// epoch::pin(|scope| unsafe { scope.defer_destroy_box(ptr) });
+    /// ```
+    ///
+    /// [`Atomic`]: struct.Atomic.html
+    /// [`defer_destroy`]: struct.Scope.html#method.defer_destroy
+    pub unsafe fn defer_destroy_box<T>(&self, ptr: *mut T)
+    where
+        T: ?Sized + Send + EpochSafe + 'static,
+    {
+        let object = Box::from_raw(ptr);
+        let size = mem::size_of_val(&*object);
+        self.defer_garbage_sized(Garbage::new(move || drop(object)), size)
+    }
+
+    /// Deferred deallocation of the memory at `ptr`, which was allocated with `layout` by the
+    /// global allocator.
+    ///
+    /// No destructors are run.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated with `layout` through `std::alloc::alloc` or a similar
+    /// function. The same rules as for [`defer_free`] apply.
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    pub unsafe fn defer_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let object = ptr as usize;
+        let garbage = Garbage::new(move || alloc::dealloc(object as *mut u8, layout));
+        self.defer_garbage_sized(garbage, layout.size())
+    }
+
+    /// Deferred destruction and deallocation of heap-allocated object `ptr`, like [`defer_drop`],
+    /// returning a token that tells when the object has been destroyed.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer_drop`] apply.
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    pub unsafe fn defer_drop_with_token<T>(&self, ptr: Ptr<T>) -> DestroyToken
+    where
+        T: Send + EpochSafe + 'static,
+    {
+        self.defer_drop_after(ptr, &DestroyToken::new(true))
+    }
+
+    /// Deferred destruction and deallocation of heap-allocated object `ptr`, which is guaranteed
+    /// to happen no earlier than the destruction of the object `parent` belongs to.
+    ///
+    /// This is useful when the destructor of a child node reads data of its parent node, as the
+    /// order in which deferred objects are destroyed is otherwise unspecified. The returned token
+    /// can in turn be used as the parent of other objects.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer_drop`] apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let parent = Atomic::new(1);
+    /// let child = Atomic::new(2);
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let token = scope.defer_drop_with_token(parent.load(SeqCst, scope));
+    ///     scope.defer_drop_after(child.load(SeqCst, scope), &token);
+    /// });
+    /// ```
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    pub unsafe fn defer_drop_after<T: Send + EpochSafe + 'static>(
+        &self,
+        ptr: Ptr<T>,
+        parent: &DestroyToken,
+    ) -> DestroyToken {
+        ptr.check(self);
+        debug::check_unreachable(ptr.as_raw());
+
+        let object = match hook::reclaim_hook::<T>() {
+            Some(hook) => Garbage::new_reclaim(ptr.as_raw() as *mut T, hook),
+            None => Garbage::new_drop(ptr.as_raw() as *mut T, 1),
+        };
+        let parent = parent.clone();
+        let token = DestroyToken::new(false);
+        let t = token.clone();
+        let realm = self.realm_arc().clone();
+        let garbage = Garbage::new(move || drop_after(object, parent, t, realm));
+        self.defer_garbage_sized(garbage, mem::size_of::<T>());
+        token
+    }
+
+    /// Returns an empty batch of deferred destructions and functions.
+    ///
+    /// Deferring many objects at once, e.g. all nodes removed by a bulk operation, through a batch
+    /// pays the bookkeeping of deferral once for the whole batch instead of once per object. The
+    /// batch is pushed into the local bag when it's dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let nodes = (0..100).map(Atomic::new).collect::<Vec<_>>();
+    ///
+    /// epoch::pin(|scope| {
+    ///     let mut batch = scope.defer_batch();
+    ///     for node in &nodes {
+    ///         let p = node.swap(Ptr::null(), SeqCst, scope);
+    ///         unsafe { batch.defer_drop(p) }
+    ///     }
+    ///     assert_eq!(batch.len(), 100);
+    /// });
+    /// ```
+    pub fn defer_batch(&self) -> DeferBatch<'_> {
+        DeferBatch {
+            scope: self,
+            garbage: Vec::new(),
+            large: global::large_garbage_threshold(),
+        }
+    }
 
     /// Deferred execution of an arbitrary function `f`.
+    ///
+    /// # Safety
+    ///
+    /// The function may run on any thread at any time after all currently pinned mutators get
+    /// unpinned, so it must not rely on anything that is unsafe to do at that point.
     pub unsafe fn defer<F: FnOnce() + Send + 'static>(&self, f: F) {
         self.defer_garbage(Garbage::new(f))
     }
 
+    /// Deferred execution of function `f`, which is passed the global epoch in which its grace
+    /// period elapsed.
+    ///
+    /// Epochs are the numbers passed to hooks registered with [`on_epoch_advance`]. The epoch is
+    /// at least two advancements past the one `f` was deferred in, so every mutator pinned back
+    /// then has unpinned since. This lets external systems, e.g. a write-ahead log being
+    /// truncated, align their own cleanup without keeping epochs of their own. If the function
+    /// isn't run by a collection, e.g. because the scope is unprotected, it is passed the
+    /// earliest epoch in which its grace period could have elapsed.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch as epoch;
+    /// use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    /// use std::sync::Arc;
+    ///
+    /// let truncated = Arc::new(AtomicUsize::new(0));
+    /// epoch::pin(|scope| {
+    ///     let truncated = truncated.clone();
+    ///     unsafe { scope.defer_with_epoch(move |epoch| truncated.store(epoch, SeqCst)) }
+    /// });
+    /// ```
+    ///
+    /// [`on_epoch_advance`]: fn.on_epoch_advance.html
+    /// [`defer`]: struct.Scope.html#method.defer
+    pub unsafe fn defer_with_epoch<F: FnOnce(usize) + Send + 'static>(&self, f: F) {
+        let epoch = self.realm().epoch.load(Relaxed);
+        if self.destroys_immediately() {
+            return f(epoch);
+        }
+        // The garbage can't expire before the epoch advances twice past the one of its bag.
+        let earliest = epoch.wrapping_add(4);
+        self.defer(move || f(global::reclaim_epoch().unwrap_or(earliest)))
+    }
+
+    /// Deferred execution of an arbitrary function `f`, which may borrow non-`'static` data.
+    ///
+    /// The function is never handed to an adopted domain. It is executed at the latest when the
+    /// realm of the scope is torn down, which for the default realm means it may never be.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply. In addition, everything `f` borrows must outlive its
+    /// execution, which usually means it must outlive the [`Collector`] the scope belongs to and
+    /// all of its handles. Prefer [`ScopedHandle::defer`], which checks this at compile time.
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    /// [`Collector`]: struct.Collector.html
+    /// [`ScopedHandle::defer`]: struct.ScopedHandle.html#method.defer
+    pub unsafe fn defer_unchecked<F: FnOnce() + Send>(&self, f: F) {
+        #[cfg(feature = "testkit")]
+        ::testkit::yield_point();
+
+        self.push_garbage(Garbage::new_unchecked(f))
+    }
+
+    /// Deferred execution of an asynchronous function `f`.
+    ///
+    /// Once it is safe to do so, `f` is called and the future it returns is spawned onto the
+    /// executor set with [`set_executor`], so that it runs outside the collecting thread. The
+    /// executor is looked up right away.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply to `f` and the future it returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no executor is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Spawn, Task};
+    /// use std::future;
+    /// use std::sync::Arc;
+    ///
+    /// struct Detach;
+    ///
+    /// impl Spawn for Detach {
+    ///     fn spawn(&self, task: Task) {
+    ///         // A real executor would poll the task to completion.
+    ///         drop(task);
+    ///     }
+    /// }
+    ///
+    /// epoch::set_executor(Some(Arc::new(Detach)));
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     scope.defer_async(|| {
+    ///         println!("releasing the lease");
+    ///         future::ready(())
+    ///     });
+    /// });
+    /// ```
+    ///
+    /// [`set_executor`]: fn.set_executor.html
+    /// [`defer`]: struct.Scope.html#method.defer
+    #[cfg(feature = "unstable")]
+    pub unsafe fn defer_async<F, T>(&self, f: F)
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let executor = executor::executor().expect("no executor set for asynchronous deferral");
+        self.defer(move || executor.spawn(Box::pin(f())))
+    }
+
+    /// Protects the object `ptr` points to from destruction until the returned pointer is
+    /// dropped, even after the current mutator gets unpinned.
+    ///
+    /// This lets iterators unpin between items while keeping only their current node safe. At
+    /// most [`MAX_PROTECTED`] pointers can be protected at the same time across all threads; if
+    /// they're all taken, `None` is returned.
+    ///
+    /// Only objects deferred with [`defer_free`] or [`defer_drop`] are kept alive; functions passed
+    /// to [`defer`] are executed regardless.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(7);
+    ///
+    /// let p = epoch::pin(|scope| scope.protect(a.load(SeqCst, scope)).unwrap());
+    /// // The thread is no longer pinned, but the object is still safe to read.
+    /// assert_eq!(unsafe { *p.deref() }, 7);
+    /// # drop(p);
+    /// # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`MAX_PROTECTED`]: constant.MAX_PROTECTED.html
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`defer`]: struct.Scope.html#method.defer
+    #[cfg(feature = "unstable")]
+    pub fn protect<T>(&self, ptr: Ptr<T>) -> Option<Protected<T>> {
+        ptr.check(self);
+        protect::protect(ptr)
+    }
+
+    /// Retires object `ptr` without destroying it, and returns a ticket that tells when its grace
+    /// period has elapsed.
+    ///
+    /// This is for protocols that reuse the memory of unlinked objects in place rather than free
+    /// it, e.g. slots of an announcement array. Once the ticket reports the grace period elapsed,
+    /// no mutator can still be reading the object through a pointer loaded before it was retired.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let slot = Atomic::new(7);
+    ///
+    /// let ticket = epoch::pin(|scope| scope.retire(slot.load(SeqCst, scope)));
+    /// ticket.on_reclaim(|| println!("the slot may be reused"));
+    /// # epoch::pin(|scope| unsafe { scope.defer_drop(slot.load(SeqCst, scope)) });
+    /// ```
+    pub fn retire<T>(&self, ptr: Ptr<T>) -> RetireTicket {
+        ptr.check(self);
+        let ticket = RetireTicket::new();
+        let t = ticket.clone();
+        unsafe { self.defer(move || t.elapse()) }
+        ticket
+    }
+
+    /// Deferred execution of an arbitrary function `f`, attributed to `tag`.
+    ///
+    /// This is like [`defer`], except that the function is counted as pending garbage of `tag`
+    /// until it's executed.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    pub unsafe fn defer_tagged<F: FnOnce() + Send + 'static>(&self, tag: &GarbageTag, f: F) {
+        let reclaimed = tag.deferred();
+        self.defer(move || {
+            f();
+            reclaimed();
+        })
+    }
+
+    /// Deferred execution of an arbitrary function `f` that can be cancelled with the returned
+    /// token until it is executed.
+    ///
+    /// Cancelled functions are dropped without being executed, and compacted away when the local
+    /// bag is sealed.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch as epoch;
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let token = scope.defer_cancellable(|| panic!("the retirement was rolled back"));
+    ///     assert!(token.cancel());
+    ///     scope.flush();
+    /// });
+    /// ```
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    pub unsafe fn defer_cancellable<F>(&self, f: F) -> CancelToken
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let token = CancelToken::new();
+        self.defer_garbage(Garbage::new_cancellable(f, token.state()));
+        token
+    }
+
+    /// Stages the destruction of heap-allocated object `ptr`, to be submitted with [`commit`] or
+    /// discarded with [`rollback`].
+    ///
+    /// Lock-free operations often unlink several objects, but only logically complete at a final
+    /// compare-and-set. Staging their retirements as they are unlinked, and then committing them
+    /// on success or rolling them back before a retry, avoids both leaking and retiring twice.
+    ///
+    /// Retirements are staged per mutator, or per thread for unprotected scopes. Those still
+    /// staged when the mutator is unpinned are rolled back, and in debug builds this panics.
+    ///
+    /// # Safety
+    ///
+    /// If the retirement is committed, the same rules as for [`defer_drop`] apply.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1);
+    /// epoch::pin(|scope| unsafe {
+    ///     let old = a.load(SeqCst, scope);
+    ///     scope.stage_destroy(old);
+    ///
+    ///     match a.compare_and_set(old, Ptr::null(), SeqCst, scope) {
+    ///         Ok(()) => scope.commit(),
+    ///         Err(_) => scope.rollback(),
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`commit`]: struct.Scope.html#method.commit
+    /// [`rollback`]: struct.Scope.html#method.rollback
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    pub unsafe fn stage_destroy<T, const HIGH_TAG: bool>(&self, ptr: Ptr<T, HIGH_TAG>)
+    where
+        T: Send + EpochSafe + 'static,
+    {
+        ptr.check(self);
+        let staged = Staged {
+            object: ptr.as_raw() as *mut u8,
+            retire: retire_staged::<T>,
+        };
+        self.with_staged(|s| s.push(staged));
+    }
+
+    /// Submits all retirements staged with [`stage_destroy`] for destruction.
+    ///
+    /// [`stage_destroy`]: struct.Scope.html#method.stage_destroy
+    pub fn commit(&self) {
+        for staged in self.with_staged(mem::take) {
+            unsafe { (staged.retire)(self, staged.object) }
+        }
+    }
+
+    /// Discards all retirements staged with [`stage_destroy`], leaving the objects alive.
+    ///
+    /// [`stage_destroy`]: struct.Scope.html#method.stage_destroy
+    pub fn rollback(&self) {
+        self.with_staged(|s| s.clear());
+    }
+
+    /// Calls `f` with the retirements staged through the scope.
+    fn with_staged<F: FnOnce(&mut Vec<Staged>) -> R, R>(&self, f: F) -> R {
+        if self.staged.is_null() {
+            STAGED.with(|s| f(&mut s.borrow_mut()))
+        } else {
+            f(&mut unsafe { &*self.staged }.borrow_mut())
+        }
+    }
+
+    /// Deferred execution of an arbitrary function `f`, returning an error if the garbage
+    /// collector fails to allocate memory.
+    ///
+    /// This is like [`defer`], except that if the local bag is full and can't be pushed into the
+    /// global queue, what happens depends on the [`AllocFailurePolicy`]. Unless the policy is to
+    /// abort, an [`AllocError`] is returned and `f` is dropped without being executed.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    /// [`AllocFailurePolicy`]: enum.AllocFailurePolicy.html
+    /// [`AllocError`]: struct.AllocError.html
+    pub unsafe fn try_defer<F>(&self, f: F) -> Result<(), AllocError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let garbage = Garbage::new(f);
+        if self.destroys_immediately() {
+            drop(garbage);
+            return Ok(());
+        }
+
+        #[cfg(feature = "unstable")]
+        let garbage = match domain::defer_adopted(garbage) {
+            Ok(()) => return Ok(()),
+            Err(g) => g,
+        };
+
+        let bag = self.get_bag();
+        if bag.is_full() {
+            let _ = BAG_OVERFLOWS.try_with(|c| c.set(c.get().wrapping_add(1)));
+            global::try_push_bag(bag, self)?;
+        }
+        if bag.is_empty() {
+            bag.start(self.realm().epoch.load(Relaxed));
+        }
+
+        if bag.try_push(garbage).is_err() {
+            unreachable!("the bag must have room for garbage after being pushed");
+        }
+        Ok(())
+    }
+
+    /// Deferred execution of function `f` that releases `size` bytes of memory.
+    ///
+    /// This is like [`defer`], except that if `size` is at least [`large_garbage_threshold`], the
+    /// function bypasses the bag and is executed as soon as possible. It is meant for objects that
+    /// own large buffers indirectly, e.g. a node holding a big `Vec`.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    /// [`large_garbage_threshold`]: fn.large_garbage_threshold.html
+    pub unsafe fn defer_sized<F: FnOnce() + Send + 'static>(&self, f: F, size: usize) {
+        self.defer_garbage_sized(Garbage::new(f), size)
+    }
+
+    /// Deferred execution of function `f` that releases `size` bytes of garbage of `class`.
+    ///
+    /// This is like [`defer_sized`], except that the function counts towards the thresholds of the
+    /// class in the collector's [`CollectorConfig`]. Once the garbage of the class in the local bag
+    /// reaches one of them, the bag is flushed according to the policy of the class. Garbage that
+    /// bypasses the bag for its size doesn't count.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `class` is not less than [`MAX_GARBAGE_CLASSES`].
+    ///
+    /// [`defer_sized`]: struct.Scope.html#method.defer_sized
+    /// [`defer`]: struct.Scope.html#method.defer
+    /// [`CollectorConfig`]: struct.CollectorConfig.html#structfield.classes
+    /// [`MAX_GARBAGE_CLASSES`]: constant.MAX_GARBAGE_CLASSES.html
+    pub unsafe fn defer_in_class<F>(&self, class: usize, size: usize, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        assert!(class < MAX_CLASSES, "garbage class {} out of range", class);
+        self.defer_garbage_sized(Garbage::new(f), size);
+        if size >= global::large_garbage_threshold() {
+            return;
+        }
+
+        let (count, bytes) = match self.local_bag() {
+            Some(bag) => bag.add_to_class(class, size),
+            None => return,
+        };
+
+        let thresholds = self.realm().config.classes[class];
+        if count >= thresholds.max_count || bytes >= thresholds.max_bytes {
+            match thresholds.policy {
+                FlushPolicy::Seal => {
+                    if let Some(bag) = self.local_bag() {
+                        global::push_bag(bag, self);
+                    }
+                }
+                FlushPolicy::Collect => self.flush(),
+            }
+        }
+    }
+
+    /// Deferred execution of function `f` on the current thread.
+    ///
+    /// Unlike [`defer`], the function is guaranteed to be executed on the thread that deferred it,
+    /// so it may touch thread-local state and doesn't have to be `Send`. It is executed at one of
+    /// the thread's later pinnings or flushes, once all mutators pinned at the time of deferral
+    /// have been unpinned.
+    ///
+    /// If the thread exits before that happens, the function is leaked: it's neither executed nor
+    /// dropped.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`defer`] apply.
+    ///
+    /// [`defer`]: struct.Scope.html#method.defer
+    pub unsafe fn defer_local<F: FnOnce() + 'static>(&self, f: F) {
+        if cfg!(feature = "leak_only") {
+            return mem::forget(f);
+        }
+
+        let realm = self.realm_arc();
+        let epoch = global::seal_epoch(realm);
+
+        let _ = LOCAL_DEFERRED.try_with(|local| {
+            local.borrow_mut().queue.push_back((realm.clone(), epoch, Box::new(f)))
+        });
+    }
+
     /// Flushes all garbage in the thread-local storage into the global garbage queue, attempts to
     /// advance the epoch, and collects some garbage.
     ///
     /// Even though flushing can be explicitly called, it is also automatically triggered when the
     /// thread-local storage fills up or when we pin the current thread a specific number of times.
-    ///
-    /// It is wise to flush the bag just after passing a very large object to [`defer_free`] or
-    /// [`defer_drop`], so that it isn't sitting in the local bag for a long time.
-    ///
-    /// [`defer_free`]: fn.defer_free.html [`defer_drop`]: fn.defer_drop.html
     pub fn flush(&self) {
-        unsafe {
-            let bag = self.get_bag();
+        if self.destroys_immediately() {
+            return;
+        }
+
+        if let Some(bag) = self.local_bag() {
             if !bag.is_empty() {
                 global::push_bag(bag, self);
             }
         }
 
         global::collect(self);
+        run_local_deferred();
+
+        #[cfg(feature = "unstable")]
+        domain::flush_adopted();
     }
+
+    /// Unpins the current mutator and immediately pins it again, in the current epoch.
+    ///
+    /// A thread that stays pinned for a long time holds back the global epoch, so no garbage can
+    /// be destroyed in the meantime. Long-running loops can instead repin every now and then to
+    /// let the epoch advance, and call [`flush`] to get their own garbage collected too.
+    ///
+    /// Does nothing if the scope is not pinned, e.g. if it was returned by [`unprotected`].
+    ///
+    /// # Safety
+    ///
+    /// Pointers loaded before the call must not be dereferenced after it, and neither must
+    /// references obtained from them. This includes pointers loaded through the scopes of the
+    /// enclosing pinnings, if the mutator was already pinned when this scope was created.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(0);
+    ///
+    /// epoch::pin(|scope| for i in 1..1000 {
+    ///     let new = epoch::Owned::new(i).into_ptr(scope);
+    ///     let old = a.swap(new, SeqCst, scope);
+    ///     unsafe {
+    ///         scope.defer_drop(old);
+    ///         if i % 100 == 0 {
+    ///             // `old` and `new` are not used anymore.
+    ///             scope.repin();
+    ///             scope.flush();
+    ///         }
+    ///     }
+    /// });
+    /// # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`flush`]: struct.Scope.html#method.flush
+    /// [`unprotected`]: fn.unprotected.html
+    pub unsafe fn repin(&self) {
+        if self.local_epoch.is_null() {
+            return;
+        }
+
+        let local_epoch = &*self.local_epoch;
+        let (_, pinned) = local_epoch.get_state();
+        local_epoch.set_unpinned();
+        local_epoch.set_pinned(&self.realm().epoch);
+        self.realm().epoch.unpinned(pinned);
+
+        #[cfg(feature = "watchdog")]
+        local_epoch.pinning().repinned();
+
+        #[cfg(feature = "stale_ptr_check")]
+        (*self.generation).set((*self.generation).get().next());
+    }
+
+    /// Flushes the local bag like [`flush`], returning an error if the garbage collector fails to
+    /// allocate memory.
+    ///
+    /// If the bag can't be pushed into the global queue and the [`AllocFailurePolicy`] is not to
+    /// abort, the garbage is kept in the local bag and an [`AllocError`] is returned.
+    ///
+    /// [`flush`]: struct.Scope.html#method.flush
+    /// [`AllocFailurePolicy`]: enum.AllocFailurePolicy.html
+    /// [`AllocError`]: struct.AllocError.html
+    pub fn try_flush(&self) -> Result<(), AllocError> {
+        if self.destroys_immediately() {
+            return Ok(());
+        }
+
+        if let Some(bag) = self.local_bag() {
+            if !bag.is_empty() {
+                global::try_push_bag(bag, self)?;
+            }
+        }
+
+        global::collect(self);
+        run_local_deferred();
+
+        #[cfg(feature = "unstable")]
+        domain::flush_adopted();
+        Ok(())
+    }
+
+    /// Executes `f` without letting the current thread take on collection work.
+    ///
+    /// Every so often pinning advances the epoch and destroys some garbage, which adds latency to
+    /// whichever pinning happens to do it. While `f` runs, no pinning on the current thread does
+    /// so, in any collector, and normal behavior is restored once `f` returns or panics. Garbage is
+    /// still deferred as usual, and explicit calls to [`flush`] still collect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(0);
+    ///
+    /// epoch::pin(|scope| {
+    ///     scope.inhibit_collection(|| {
+    ///         // A latency-critical span: none of these pinnings collect garbage.
+    ///         for _ in 0..1000 {
+    ///             epoch::pin(|scope| assert!(!a.load(SeqCst, scope).is_null()));
+    ///         }
+    ///     })
+    /// });
+    /// # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`flush`]: struct.Scope.html#method.flush
+    pub fn inhibit_collection<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        inhibiting_collection(f)
+    }
+
+    /// Calls `f` with the scope and returns its result bound to the scope, as a [`ScopeBound`].
+    ///
+    /// The scope handed to the closure of [`pin`] may have any lifetime, so a closure written
+    /// inside it that takes a `&Scope` and returns something borrowing it needs its lifetimes
+    /// spelled out, which closures don't support. `run` hands the scope over with the lifetime it
+    /// already has, so the closure's result can simply borrow it, and keeps the scope at hand
+    /// along with the result.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Scope, ScopeBound};
+    /// use std::sync::atomic::Ordering::Acquire;
+    ///
+    /// let slots = (1..4).map(Atomic::new).collect::<Vec<_>>();
+    ///
+    /// // Looks up the values of the slots, given some scope.
+    /// fn values<'scope>(
+    ///     slots: &'scope [Atomic<i32>],
+    ///     scope: &'scope Scope,
+    /// ) -> impl Iterator<Item = &'scope i32> {
+    ///     slots.iter().filter_map(move |a| unsafe { a.load(Acquire, scope).as_ref() })
+    /// }
+    ///
+    /// epoch::pin(|scope| {
+    ///     let values = scope.run(|scope| values(&slots, scope));
+    ///     assert!(std::ptr::eq(ScopeBound::scope(&values), scope));
+    ///     assert_eq!(values.sum::<i32>(), 6);
+    ///
+    ///     let max = scope.run(|scope| {
+    ///         slots.iter().filter_map(|a| unsafe { a.load(Acquire, scope).as_ref() }).max()
+    ///     });
+    ///     assert_eq!(*max, Some(&3));
+    /// });
+    /// # epoch::pin(|scope| unsafe {
+    /// #     for a in &slots {
+    /// #         scope.defer_drop(a.load(Acquire, scope));
+    /// #     }
+    /// # });
+    /// ```
+    ///
+    /// [`pin`]: fn.pin.html
+    /// [`ScopeBound`]: struct.ScopeBound.html
+    #[inline]
+    pub fn run<'scope, R, F>(&'scope self, f: F) -> ScopeBound<'scope, R>
+    where
+        R: 'scope,
+        F: FnOnce(&'scope Scope) -> R,
+    {
+        ScopeBound::new(self, f(self))
+    }
+}
+
+/// Returns the number of times a local bag of the current thread overflowed.
+///
+/// A bag overflows when garbage is deferred while it's full, in which case the whole bag is
+/// flushed into the global garbage queue. Frequent overflows suggest that garbage is produced in
+/// bursts, which may be worth batching differently.
+pub fn bag_overflows() -> usize {
+    BAG_OVERFLOWS.with(|c| c.get())
 }
 
 
 #[cfg(test)]
-mod tests {}
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::thread;
+    use primitive::atomic::Ordering::SeqCst;
+
+    use garbage::{INLINE_CLOSURE_SIZE, MAX_OBJECTS, spilled_closures};
+    use {pin, unprotected, Owned};
+    use testkit::{DropCounter, Drops};
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn defer_batch_destroys_all() {
+        let drops = Drops::new();
+        let count = MAX_OBJECTS * 3 + 1;
+        pin(|scope| {
+            let mut batch = scope.defer_batch();
+            for _ in 0..count {
+                unsafe { batch.defer_drop(Owned::new(drops.wrap(())).into_ptr(scope)) }
+            }
+            assert_eq!(batch.len(), count);
+        });
+
+        ::assert_reclaimed!(drops, count);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn defer_destroy_variants() {
+        use std::mem::MaybeUninit;
+
+        let drops = Drops::new();
+
+        pin(|scope| unsafe {
+            let array = Owned::<[MaybeUninit<DropCounter<()>>]>::init(4).into_ptr(scope);
+            for slot in array.deref() {
+                ptr::write(slot.as_ptr() as *mut DropCounter<()>, drops.wrap(()));
+            }
+            // Elements of the array are not dropped, since they might be uninitialized.
+            scope.defer_destroy(array);
+            scope.defer_destroy(Owned::new(drops.wrap(())).into_ptr(scope));
+
+            let slice = (0..3).map(|_| drops.wrap(())).collect::<Vec<_>>().into_boxed_slice();
+            scope.defer_destroy_box(Box::into_raw(slice));
+
+            let layout = Layout::new::<[u64; 8]>();
+            scope.defer_dealloc(alloc::alloc(layout), layout);
+        });
+
+        ::assert_reclaimed!(drops, 4);
+        assert_eq!(drops.count(), 4);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn count_bag_overflows() {
+        thread::spawn(|| {
+            assert_eq!(bag_overflows(), 0);
+            pin(|scope| for _ in 0..MAX_OBJECTS + 1 {
+                unsafe { scope.defer(|| ()) }
+            });
+            assert!(bag_overflows() >= 1);
+        }).join()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn large_closures_spill() {
+        const WORDS: usize = INLINE_CLOSURE_SIZE / mem::size_of::<usize>();
+
+        thread::spawn(|| {
+            let sum = Arc::new(AtomicUsize::new(0));
+            unsafe {
+                ::unprotected(|scope| {
+                    let (s, small) = (sum.clone(), [1; WORDS - 1]);
+                    scope.defer(move || {
+                        s.fetch_add(small.iter().sum(), SeqCst);
+                    });
+                    assert_eq!(spilled_closures(), 0);
+
+                    let (s, large) = (sum.clone(), [1; WORDS]);
+                    scope.defer(move || {
+                        s.fetch_add(large.iter().sum(), SeqCst);
+                    });
+                    assert_eq!(spilled_closures(), 1);
+                });
+            }
+            assert_eq!(sum.load(SeqCst), WORDS * 2 - 1);
+        }).join()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn bag_allocated_lazily() {
+        let mut bag = None;
+        unsafe {
+            unprotected_with_bag(&mut bag, |scope| {
+                assert!(scope.local_bag().is_none());
+                scope.defer(|| ());
+                assert!(scope.local_bag().is_some());
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    fn unpinning_on_other_thread_panics() {
+        let mutator = Mutator::with_realm(Arc::new(Realm::new()));
+        let was_pinned = mutator.pin_raw();
+        let panicked = thread::spawn(move || {
+            let unpin = ::std::panic::AssertUnwindSafe(|| mutator.unpin_raw(was_pinned));
+            ::std::panic::catch_unwind(unpin).is_err()
+        }).join()
+            .unwrap();
+        assert!(panicked);
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "garbage_backtrace", feature = "leak_only")))]
+    fn coalesce_same_call_site() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, SeqCst);
+            }
+        }
+
+        let mut bag = None;
+        unsafe {
+            unprotected_with_bag(&mut bag, |scope| {
+                // As many as coalesce into a single entry.
+                for _ in 0..MAX_OBJECTS {
+                    scope.defer_drop(Owned::new(Counted).into_ptr(scope));
+                }
+                scope.defer_drop(Owned::new(Counted).into_ptr(scope));
+                scope.defer_drop(Owned::new(0u64).into_ptr(scope));
+
+                let bag = scope.local_bag().unwrap();
+                assert_eq!(bag.len(), 3);
+                assert_eq!(bag.deferred(), MAX_OBJECTS + 2);
+            });
+        }
+        assert_eq!(DROPPED.load(SeqCst), 0);
+        drop(bag);
+        assert_eq!(DROPPED.load(SeqCst), MAX_OBJECTS + 1);
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "garbage_backtrace", feature = "leak_only")))]
+    fn coalesce_in_batch() {
+        let mut bag = None;
+        unsafe {
+            unprotected_with_bag(&mut bag, |scope| {
+                {
+                    let mut batch = scope.defer_batch();
+                    for _ in 0..10 {
+                        batch.defer_drop(Owned::new(0u64).into_ptr(scope));
+                    }
+                    batch.defer_free(Owned::new(0u64).into_ptr(scope));
+                    assert_eq!(batch.len(), 11);
+                }
+
+                let bag = scope.local_bag().unwrap();
+                assert_eq!(bag.len(), 2);
+                assert_eq!(bag.deferred(), 11);
+            });
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn defer_with_epoch_after_grace_period() {
+        let drops = Drops::new();
+        let reclaimed = Arc::new(AtomicUsize::new(usize::MAX));
+        let deferred = pin(|scope| unsafe {
+            let r = reclaimed.clone();
+            let d = drops.wrap(());
+            scope.defer_with_epoch(move |epoch| {
+                r.store(epoch, SeqCst);
+                drop(d);
+            });
+            scope.realm().epoch.load(Relaxed)
+        });
+
+        ::assert_reclaimed!(drops, 1);
+        let elapsed = reclaimed.load(SeqCst).wrapping_sub(deferred);
+        assert!((4..usize::MAX / 2).contains(&elapsed), "elapsed {}", elapsed);
+
+        unsafe { unprotected(|scope| scope.defer_with_epoch(|epoch| assert_eq!(epoch % 2, 0))) }
+    }
+
+    #[test]
+    fn try_defer_overflows_bag() {
+        thread::spawn(|| {
+            pin(|scope| for _ in 0..MAX_OBJECTS + 1 {
+                assert_eq!(unsafe { scope.try_defer(|| ()) }, Ok(()));
+            });
+            assert_eq!(bag_overflows(), 1);
+            pin(|scope| assert_eq!(scope.try_flush(), Ok(())));
+        }).join()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn drop_after_parent() {
+        struct Parent(Arc<AtomicBool>);
+        struct Child(Arc<AtomicBool>);
+
+        impl Drop for Parent {
+            fn drop(&mut self) {
+                self.0.store(true, SeqCst);
+            }
+        }
+
+        impl Drop for Child {
+            fn drop(&mut self) {
+                assert!(self.0.load(SeqCst), "child destroyed before its parent");
+            }
+        }
+
+        let drops = Drops::new();
+        let parent_dropped = Arc::new(AtomicBool::new(false));
+        let parent = Owned::new(Parent(parent_dropped.clone()));
+        let child = Owned::new(drops.wrap(Child(parent_dropped.clone())));
+
+        pin(|scope| unsafe {
+            // The parent is deferred while the child is already waiting in the bag.
+            let token = DestroyToken::new(false);
+            let child_token = scope.defer_drop_after(child.into_ptr(scope), &token);
+            scope.flush();
+            let parent = parent.into_ptr(scope).as_raw() as usize;
+            let t = token.clone();
+            scope.defer(move || {
+                drop(Box::from_raw(parent as *mut Parent));
+                t.set_destroyed();
+            });
+            assert!(!child_token.is_destroyed());
+        });
+
+        ::assert_reclaimed!(drops, 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn drop_after_panicking_parent() {
+        use Collector;
+
+        struct Parent;
+
+        impl Drop for Parent {
+            fn drop(&mut self) {
+                panic!("parent destructor panicked");
+            }
+        }
+
+        let drops = Drops::new();
+        let handle = Collector::new().register();
+        handle.pin(|scope| unsafe {
+            let token = scope.defer_drop_with_token(Owned::new(Parent).into_ptr(scope));
+            scope.defer_drop_after(Owned::new(drops.wrap(())).into_ptr(scope), &token);
+        });
+
+        ::assert_reclaimed!(drops, 1, in: handle);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn drop_after_waits_in_own_realm() {
+        use Collector;
+
+        let drops = Drops::new();
+        let collector = Collector::new();
+        let handle = collector.register();
+        let token = DestroyToken::new(false);
+        handle.pin(|scope| unsafe {
+            scope.defer_drop_after(Owned::new(drops.wrap(())).into_ptr(scope), &token);
+            scope.flush();
+        });
+
+        // The child waits for its parent in the queue of the collector, not of the default realm.
+        assert_eq!(collector.try_drain(), 1);
+        token.set_destroyed();
+        ::assert_reclaimed!(drops, 1, in: handle);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn cancelled_compacted_on_seal() {
+        let drops = Drops::new();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let before = ::compaction_stats();
+
+        let tokens = pin(|scope| unsafe {
+            let tokens = (0..4)
+                .map(|_| {
+                    let r = ran.clone();
+                    let d = drops.wrap(());
+                    scope.defer_cancellable(move || {
+                        r.fetch_add(1, SeqCst);
+                        drop(d);
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for t in &tokens[..3] {
+                assert!(t.cancel());
+            }
+            scope.flush();
+            tokens
+        });
+        assert!(::compaction_stats().compacted >= before.compacted + 3);
+
+        // The cancelled functions are dropped without being called.
+        ::assert_reclaimed!(drops, 4);
+        assert_eq!(ran.load(SeqCst), 1);
+        assert!(!tokens[3].cancel());
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn staged_retirements() {
+        struct Flag(Arc<AtomicBool>);
+
+        impl Drop for Flag {
+            fn drop(&mut self) {
+                self.0.store(true, Relaxed);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let p = Owned::new(Flag(dropped.clone())).leak();
+
+        pin(|scope| unsafe {
+            scope.stage_destroy(p);
+            scope.rollback();
+            scope.commit();
+        });
+        assert!(!dropped.load(Relaxed));
+
+        // Garbage deferred into a temporary bag is destroyed along with the bag.
+        unsafe {
+            unprotected_with_bag(&mut None, |scope| {
+                scope.stage_destroy(p);
+                scope.commit();
+            });
+        }
+        assert!(dropped.load(Relaxed));
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "neither committed nor rolled back")]
+    fn staged_retirements_must_be_resolved() {
+        let p = Owned::new(1).leak();
+        pin(|scope| unsafe { scope.stage_destroy(p) });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn defer_local_runs_on_same_thread() {
+        thread::spawn(|| {
+            let id = thread::current().id();
+            let ran = Rc::new(Cell::new(false));
+
+            let drops = Drops::new();
+            let r = ran.clone();
+            let d = drops.wrap(());
+            pin(|scope| unsafe {
+                scope.defer_local(move || {
+                    assert_eq!(thread::current().id(), id);
+                    r.set(true);
+                    drop(d);
+                })
+            });
+
+            ::assert_reclaimed!(drops, 1);
+            assert!(ran.get());
+        }).join()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "leak_only")]
+    fn leak_only_forgets_garbage() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicUsize;
+
+        use Collector;
+
+        let collector = Collector::new();
+        let handle = collector.register();
+        let dropped = Arc::new(AtomicUsize::new(0));
+        handle.pin(|scope| unsafe {
+            for _ in 0..MAX_OBJECTS * 4 {
+                let d = dropped.clone();
+                scope.defer(move || {
+                    d.fetch_add(1, Relaxed);
+                });
+            }
+        });
+        for _ in 0..16 {
+            handle.pin(|scope| scope.flush());
+        }
+        drop(handle);
+        drop(collector);
+        assert_eq!(dropped.load(Relaxed), 0);
+        assert_eq!(Arc::strong_count(&dropped), MAX_OBJECTS * 4 + 1);
+    }
+
+    #[test]
+    #[cfg(feature = "release_acquire")]
+    fn release_acquire_protects_loaded_objects() {
+        use std::sync::atomic::Ordering::{AcqRel, Acquire};
+
+        use {Atomic, Collector};
+
+        /// Poisons its value on drop, so that use after destruction is caught.
+        struct Canary(AtomicUsize);
+
+        impl Drop for Canary {
+            fn drop(&mut self) {
+                self.0.store(0, Relaxed);
+            }
+        }
+
+        const LIVE: usize = 0x5eed;
+
+        let collector = Collector::new();
+        let slot = Arc::new(Atomic::new(Canary(AtomicUsize::new(LIVE))));
+
+        let readers = (0..3)
+            .map(|_| {
+                let handle = collector.register();
+                let slot = slot.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        handle.pin(|scope| {
+                            let canary = unsafe { slot.load(Acquire, scope).deref() };
+                            for _ in 0..4 {
+                                assert_eq!(canary.0.load(Relaxed), LIVE);
+                            }
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let handle = collector.register();
+        for _ in 0..10_000 {
+            handle.pin(|scope| unsafe {
+                let new = Owned::new(Canary(AtomicUsize::new(LIVE)));
+                let old = slot.swap(new.into_ptr(scope), AcqRel, scope);
+                scope.defer_drop(old);
+            });
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        handle.pin(|scope| unsafe { scope.defer_drop(slot.load(Relaxed, scope)) });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn repin_lets_garbage_be_destroyed() {
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        pin(|scope| unsafe {
+            let d = dropped.clone();
+            scope.defer(move || d.store(true, Relaxed));
+
+            // Without repinning, the epoch can't advance far enough while we're pinned.
+            for _ in 0..100_000 {
+                if dropped.load(Relaxed) {
+                    break;
+                }
+                scope.repin();
+                scope.flush();
+            }
+        });
+        assert!(dropped.load(Relaxed));
+    }
+}
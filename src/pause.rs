@@ -0,0 +1,239 @@
+//! Collection pause histogram
+//!
+//! A single collection destroys a bounded amount of garbage, but how long that takes depends on
+//! the destructors involved. The duration of every collection is recorded into a histogram with
+//! fixed, logarithmically sized buckets, in the style of HdrHistogram: each power of two of
+//! nanoseconds is split into four buckets, so any recorded duration is known to within 25%.
+//!
+//! Timing a collection reads the clock twice, so pauses are only recorded after
+//! [`set_track_collect_pauses`] turns tracking on.
+//!
+//! A snapshot of the histogram is returned by [`collect_pauses`].
+//!
+//! [`set_track_collect_pauses`]: fn.set_track_collect_pauses.html
+//! [`collect_pauses`]: fn.collect_pauses.html
+
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+
+use sys::{self, Timestamp};
+
+/// Number of buckets each power of two is split into, as a power of two.
+const SUB_BITS: u32 = 2;
+
+/// Number of buckets, enough for any duration in nanoseconds that fits into a `u64`.
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) << SUB_BITS;
+
+/// Number of collections that took a duration in each bucket.
+static COUNTS: [AtomicUsize; BUCKETS] = [const { AtomicUsize::new(0) }; BUCKETS];
+
+/// Whether collections are timed.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Returns the index of the bucket `nanos` falls into.
+fn index(nanos: u64) -> usize {
+    let sub = 1 << SUB_BITS;
+    if nanos < sub {
+        return nanos as usize;
+    }
+
+    let msb = 63 - nanos.leading_zeros();
+    let shift = msb - SUB_BITS;
+    (((shift + 1) << SUB_BITS) as u64 + ((nanos >> shift) & (sub - 1))) as usize
+}
+
+/// Returns the smallest number of nanoseconds in bucket `index`.
+fn lower_bound(index: usize) -> u64 {
+    let sub = 1 << SUB_BITS;
+    if index < sub {
+        return index as u64;
+    }
+
+    let shift = (index >> SUB_BITS) - 1;
+    ((sub + (index & (sub - 1))) as u64) << shift
+}
+
+/// Returns the largest number of nanoseconds in bucket `index`.
+fn upper_bound(index: usize) -> u64 {
+    if index + 1 < BUCKETS {
+        lower_bound(index + 1) - 1
+    } else {
+        u64::MAX
+    }
+}
+
+/// Sets whether the duration of every collection is recorded into the histogram.
+///
+/// Tracking is off by default. Turning it off keeps the collections recorded so far.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_track_collect_pauses(true);
+/// epoch::pin(|scope| scope.flush());
+/// epoch::set_track_collect_pauses(false);
+/// ```
+pub fn set_track_collect_pauses(enabled: bool) {
+    TRACKING.store(enabled, Relaxed);
+}
+
+/// Returns the time a collection starts at, if collections are timed.
+#[inline]
+pub fn start() -> Option<Timestamp> {
+    if TRACKING.load(Relaxed) {
+        Some(sys::now())
+    } else {
+        None
+    }
+}
+
+/// Records a collection that started at `started`, if it was timed.
+#[inline]
+pub fn finish(started: Option<Timestamp>) {
+    if let Some(started) = started {
+        record(started.elapsed());
+    }
+}
+
+/// Records a collection that took `duration`.
+#[inline]
+fn record(duration: Duration) {
+    let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+    COUNTS[index(nanos)].fetch_add(1, Relaxed);
+}
+
+/// A snapshot of the histogram of collection pauses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PauseHistogram {
+    counts: Vec<usize>,
+}
+
+impl PauseHistogram {
+    /// Returns the number of recorded collections.
+    pub fn count(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns an upper bound on the duration that a fraction `q` of collections didn't exceed, or
+    /// `None` if none were recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between 0 and 1.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        assert!((0.0..=1.0).contains(&q), "quantile is not between 0 and 1");
+
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((q * count as f64).ceil() as usize).max(1);
+        let mut seen = 0;
+        for (i, &c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return Some(Duration::from_nanos(upper_bound(i)));
+            }
+        }
+        unreachable!("rank is at most the total count")
+    }
+
+    /// Returns an upper bound on the longest collection, or `None` if none were recorded.
+    pub fn max(&self) -> Option<Duration> {
+        self.quantile(1.0)
+    }
+
+    /// Returns the non-empty buckets in ascending order, each as the shortest and longest duration
+    /// it holds, and the number of collections in it.
+    pub fn buckets(&self) -> Vec<(Duration, Duration, usize)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c > 0)
+            .map(|(i, &c)| {
+                (Duration::from_nanos(lower_bound(i)), Duration::from_nanos(upper_bound(i)), c)
+            })
+            .collect()
+    }
+}
+
+/// Returns a snapshot of the histogram of how long collections took.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_track_collect_pauses(true);
+/// epoch::pin(|scope| scope.flush());
+///
+/// let pauses = epoch::collect_pauses();
+/// if let Some(p99) = pauses.quantile(0.99) {
+///     println!("99% of {} collections took at most {:?}", pauses.count(), p99);
+/// }
+/// ```
+pub fn collect_pauses() -> PauseHistogram {
+    PauseHistogram {
+        counts: COUNTS.iter().map(|c| c.load(Relaxed)).collect(),
+    }
+}
+
+/// Clears the histogram of collection pauses.
+pub fn reset_collect_pauses() {
+    for c in COUNTS.iter() {
+        c.store(0, Relaxed);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_all_durations() {
+        assert_eq!(lower_bound(0), 0);
+        for i in 0..BUCKETS {
+            assert_eq!(index(lower_bound(i)), i);
+            assert_eq!(index(upper_bound(i)), i);
+            if i + 1 < BUCKETS {
+                assert_eq!(upper_bound(i) + 1, lower_bound(i + 1));
+            }
+        }
+        assert_eq!(upper_bound(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn quantiles() {
+        let mut counts = vec![0; BUCKETS];
+        for &nanos in &[1_000, 1_000, 1_000, 2_000_000] {
+            counts[index(nanos)] += 1;
+        }
+        let pauses = PauseHistogram { counts };
+
+        assert_eq!(pauses.count(), 4);
+        let p50 = pauses.quantile(0.5).unwrap();
+        assert!(p50 >= Duration::from_nanos(1_000) && p50 < Duration::from_nanos(1_250));
+        let max = pauses.max().unwrap();
+        assert!(max >= Duration::from_millis(2) && max < Duration::from_micros(2_500));
+        assert_eq!(pauses.buckets().len(), 2);
+    }
+
+    #[test]
+    fn tracking() {
+        let count = || collect_pauses().count();
+
+        let before = count();
+        finish(start());
+        assert_eq!(count(), before);
+
+        set_track_collect_pauses(true);
+        finish(start());
+        set_track_collect_pauses(false);
+        assert!(count() > before);
+    }
+}
@@ -0,0 +1,152 @@
+//! Pinned scopes that aren't tied to a thread
+//!
+//! Pinning goes through the mutator of the current thread, and the scope it hands out must not
+//! leave the thread. An async task may however move to another thread whenever it awaits, so it
+//! can't hold on to such a scope across an `.await`. A [`PinnedScope`] instead owns a mutator of
+//! its own, registered without any thread-local state, and stays pinned for as long as it lives,
+//! on whichever thread that happens to be.
+//!
+//! [`PinnedScope`]: struct.PinnedScope.html
+
+use std::fmt;
+use std::sync::Arc;
+
+use global::Realm;
+use mutator::{AsScope, Mutator, Scope};
+
+/// A pinned scope that owns its mutator, so that it can be sent to other threads.
+///
+/// Pinned scopes are created by [`Collector::pin_owned`], and unpinned when dropped. While
+/// pinned, the scope holds back the epoch of its collector just like any other pinning does, so
+/// it shouldn't be kept for long.
+///
+/// Garbage deferred through the scope goes into the local bag of its own mutator, which is handed
+/// to the collector once the scope is dropped. Retirements staged with [`Scope::stage_destroy`]
+/// belong to the scope too, so they may be committed on another thread, but functions deferred
+/// with [`Scope::defer_local`] still belong to the current thread.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{Atomic, Collector};
+/// use std::sync::atomic::Ordering::SeqCst;
+/// use std::thread;
+///
+/// let collector = Collector::new();
+/// let a = Atomic::new(7);
+///
+/// let pinned = collector.pin_owned();
+/// let p = a.load(SeqCst, pinned.scope()).as_raw() as usize;
+///
+/// // The object is not destroyed as long as `pinned` lives, even on another thread.
+/// thread::spawn(move || {
+///     assert_eq!(unsafe { *(p as *const i32) }, 7);
+///     drop(pinned);
+/// }).join().unwrap();
+/// # collector.register().pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+/// ```
+///
+/// [`Collector::pin_owned`]: struct.Collector.html#method.pin_owned
+/// [`Scope::defer_local`]: struct.Scope.html#method.defer_local
+/// [`Scope::stage_destroy`]: struct.Scope.html#method.stage_destroy
+pub struct PinnedScope {
+    /// The scope, which points into `mutator`.
+    scope: Scope,
+    /// The mutator, boxed so that the scope keeps pointing to it when moved.
+    mutator: Box<Mutator<'static>>,
+}
+
+// The scope only points into the mutator, which moves along with it, and the mutator doesn't
+// depend on the thread it was registered on.
+unsafe impl Send for PinnedScope {}
+
+impl PinnedScope {
+    /// Registers a new mutator in `realm`, and pins it.
+    pub(crate) fn new(realm: Arc<Realm>) -> Self {
+        let mutator = Box::new(Mutator::temporary_in(realm));
+        mutator.pin_raw();
+        PinnedScope {
+            scope: mutator.scope(),
+            mutator,
+        }
+    }
+
+    /// Returns the scope.
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+}
+
+impl Drop for PinnedScope {
+    fn drop(&mut self) {
+        self.mutator.unpin_raw(false);
+    }
+}
+
+impl<'a> AsScope<'a> for &'a PinnedScope {
+    #[inline]
+    fn as_scope(&self) -> &'a Scope {
+        self.scope()
+    }
+}
+
+impl fmt::Debug for PinnedScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PinnedScope").finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread;
+    use std::time::Duration;
+
+    use {Atomic, Collector, Owned, Ptr};
+    use testkit::{self, Drops};
+
+    #[test]
+    fn holds_back_epoch_on_other_thread() {
+        let collector = Collector::new();
+        let drops = Drops::new();
+        let a = Atomic::new(0);
+
+        let pinned = collector.pin_owned();
+        let pinned = thread::spawn(move || {
+            // Garbage deferred through the scope is handed over when the scope is dropped.
+            unsafe { pinned.scope().defer_drop(Owned::new(1).into_ptr(pinned.scope())) };
+            pinned
+        }).join()
+            .unwrap();
+
+        let handle = collector.register();
+        handle.pin(|scope| unsafe {
+            let value = drops.wrap(());
+            scope.defer_drop(a.swap(Ptr::null(), SeqCst, scope));
+            scope.defer(move || drop(value));
+        });
+        let timeout = Duration::from_millis(100);
+        assert_eq!(testkit::wait_for_drops_in(&handle, &drops, 1, timeout), 0);
+
+        thread::spawn(move || drop(pinned)).join().unwrap();
+        ::assert_reclaimed!(drops, 1, in: handle);
+    }
+
+    #[test]
+    fn stages_on_other_thread() {
+        let collector = Collector::new();
+        let drops = Drops::new();
+        let a = Atomic::new(drops.wrap(()));
+
+        let pinned = collector.pin_owned();
+        unsafe { pinned.scope().stage_destroy(a.load(SeqCst, pinned.scope())) };
+        thread::spawn(move || {
+            // The retirement staged on the other thread is committed along with the scope.
+            pinned.scope().commit();
+        }).join()
+            .unwrap();
+
+        let handle = collector.register();
+        ::assert_reclaimed!(drops, 1, in: handle);
+    }
+}
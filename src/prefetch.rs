@@ -0,0 +1,58 @@
+//! Prefetch hints
+//!
+//! Traversing a linked data structure stalls on every node, since the address of the next node is
+//! only known once the current one is loaded. Prefetching the next node while the current one is
+//! still being looked at overlaps those stalls.
+//!
+//! The hints compile to a single prefetch instruction on x86 and AArch64, and to nothing elsewhere.
+//! Prefetching never faults, so any address may be passed, even a dangling or null one.
+
+/// Hints that the cache line holding `address` is about to be read.
+#[inline(always)]
+pub fn read(address: usize) {
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse"))]
+    unsafe {
+        use std::arch::x86_64::{_MM_HINT_T0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_T0>(address as *const i8);
+    }
+    #[cfg(all(target_arch = "x86", target_feature = "sse"))]
+    unsafe {
+        use std::arch::x86::{_MM_HINT_T0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_T0>(address as *const i8);
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        use std::arch::asm;
+        asm!("prfm pldl1keep, [{}]", in(reg) address, options(nostack, readonly, preserves_flags));
+    }
+    #[cfg(not(any(
+        all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+        target_arch = "aarch64"
+    )))]
+    let _ = address;
+}
+
+/// Hints that the cache line holding `address` is about to be written.
+#[inline(always)]
+pub fn write(address: usize) {
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse"))]
+    unsafe {
+        use std::arch::x86_64::{_MM_HINT_ET0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_ET0>(address as *const i8);
+    }
+    #[cfg(all(target_arch = "x86", target_feature = "sse"))]
+    unsafe {
+        use std::arch::x86::{_MM_HINT_ET0, _mm_prefetch};
+        _mm_prefetch::<_MM_HINT_ET0>(address as *const i8);
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        use std::arch::asm;
+        asm!("prfm pstl1keep, [{}]", in(reg) address, options(nostack, readonly, preserves_flags));
+    }
+    #[cfg(not(any(
+        all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+        target_arch = "aarch64"
+    )))]
+    let _ = address;
+}
@@ -0,0 +1,29 @@
+//! The types and traits of the semver-stable core, for glob imports
+//!
+//! `use crossbeam_epoch::prelude::*;` brings the pointer types, the scope, and the traits whose
+//! methods they are used with into scope. Everything here is part of the contract described in
+//! [`core`], so a crate can re-export the prelude, or build its own guard types on [`ScopeRef`]
+//! and [`AsScope`], without tying its API to the parts of this crate that may still change.
+//!
+//! # Examples
+//!
+//! ```
+//! use crossbeam_epoch::prelude::*;
+//! use crossbeam_epoch as epoch;
+//! use std::sync::atomic::Ordering::SeqCst;
+//!
+//! fn first<'scope, S: AsScope<'scope>>(a: &Atomic<u32>, s: S) -> Option<ScopeRef<'scope, u32>> {
+//!     let scope = s.as_scope();
+//!     unsafe { a.load(SeqCst, scope).as_ref() }.map(|v| ScopeRef::new(scope, v))
+//! }
+//!
+//! let a = Atomic::new(3);
+//! epoch::pin(|scope| assert_eq!(*first(&a, scope).unwrap(), 3));
+//! # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+//! ```
+//!
+//! [`core`]: ../core/index.html
+//! [`ScopeRef`]: ../struct.ScopeRef.html
+//! [`AsScope`]: ../trait.AsScope.html
+
+pub use core::{AsScope, Atomic, CompareAndSetOrdering, Owned, Ptr, Scope, ScopeRef};
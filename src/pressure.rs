@@ -0,0 +1,149 @@
+//! Memory pressure
+//!
+//! Garbage that has expired but hasn't been collected yet is memory the process could give back.
+//! Normally it is destroyed a little at a time, as threads pin and flush. When the system is
+//! running low on memory, that's the worst time to sit on it.
+//!
+//! [`relieve_memory_pressure`] flushes the local garbage of the current thread and destroys all
+//! garbage that can be destroyed right away. It can be called from any low-memory notification,
+//! e.g. a cgroup event or a callback of the allocator. Where the platform reports memory pressure,
+//! e.g. through the pressure stall information of Linux, [`watch_memory_pressure`] starts a thread
+//! that watches it and calls it whenever memory pressure crosses a threshold.
+//!
+//! [`relieve_memory_pressure`]: fn.relieve_memory_pressure.html
+//! [`watch_memory_pressure`]: fn.watch_memory_pressure.html
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use global::{self, pin};
+use sys;
+
+/// Flushes the local garbage of the current thread, and destroys all garbage that can be destroyed
+/// right away.
+///
+/// Garbage of other threads that hasn't been flushed yet is left alone, and so is garbage that
+/// pinned threads may still be reading.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// // E.g. from a handler of low-memory notifications:
+/// epoch::relieve_memory_pressure();
+/// ```
+pub fn relieve_memory_pressure() {
+    pin(|scope| scope.flush());
+
+    // Garbage expires once the epoch has advanced twice, and each pinning tries to advance it once.
+    for _ in 0..3 {
+        pin(global::reclaim);
+    }
+}
+
+/// A thread watching memory pressure, created by [`watch_memory_pressure`].
+///
+/// The thread is stopped when the watcher is dropped.
+///
+/// [`watch_memory_pressure`]: fn.watch_memory_pressure.html
+#[derive(Debug)]
+pub struct PressureWatcher {
+    stop: Arc<AtomicBool>,
+    events: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PressureWatcher {
+    /// Returns the number of times memory pressure was relieved so far.
+    pub fn events(&self) -> usize {
+        self.events.load(Relaxed)
+    }
+}
+
+impl Drop for PressureWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts a thread that checks memory pressure every `interval`, and calls
+/// [`relieve_memory_pressure`] whenever some task was stalled on memory for more than `threshold`
+/// percent of the last ten seconds.
+///
+/// On Linux, pressure is read from the pressure stall information of the kernel
+/// (`/proc/pressure/memory`), which requires Linux 4.20 or later. An error is returned if it isn't
+/// available, and always on platforms that don't report memory pressure.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::time::Duration;
+///
+/// if let Ok(watcher) = epoch::watch_memory_pressure(10.0, Duration::from_secs(1)) {
+///     // Memory pressure is relieved until `watcher` is dropped.
+///     drop(watcher);
+/// }
+/// ```
+///
+/// [`relieve_memory_pressure`]: fn.relieve_memory_pressure.html
+pub fn watch_memory_pressure(threshold: f64, interval: Duration) -> io::Result<PressureWatcher> {
+    sys::memory_pressure()?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let events = Arc::new(AtomicUsize::new(0));
+    let (s, e) = (stop.clone(), events.clone());
+
+    let thread = thread::Builder::new()
+        .name("epoch-pressure".to_string())
+        .spawn(move || {
+            while !s.load(Relaxed) {
+                if sys::memory_pressure().is_ok_and(|avg10| avg10 > threshold) {
+                    relieve_memory_pressure();
+                    e.fetch_add(1, Relaxed);
+                }
+                thread::park_timeout(interval);
+            }
+        })?;
+
+    Ok(PressureWatcher {
+        stop,
+        events,
+        thread: Some(thread),
+    })
+}
+
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use super::*;
+
+    #[test]
+    fn relieve_destroys_garbage() {
+        let destroyed = Arc::new(AtomicBool::new(false));
+        let d = destroyed.clone();
+        pin(|scope| unsafe { scope.defer(move || d.store(true, SeqCst)) });
+
+        // Other tests may keep the epoch from advancing for a while.
+        for _ in 0..100_000 {
+            relieve_memory_pressure();
+            if destroyed.load(SeqCst) {
+                break;
+            }
+        }
+        assert!(destroyed.load(SeqCst));
+    }
+}
@@ -0,0 +1,67 @@
+//! Synchronization primitives of the epoch protocol
+//!
+//! The atomics, fences, and thread-locals the protocol is built on are taken from here rather
+//! than from `std`, so that building with `RUSTFLAGS="--cfg loom"` swaps them for the ones of
+//! [`loom`], which explores every interleaving of the threads of a model. Downstream crates can
+//! then model-check how their own data structures interact with pinning and reclamation:
+//!
+//! ```ignore
+//! loom::model(|| {
+//!     let mut config = CollectorConfig::default();
+//!     config.random = RandomSource::new(|| 0);
+//!     let collector = Collector::with_config(config);
+//!     let c = collector.clone();
+//!     let t = loom::thread::spawn(move || c.register().pin(|scope| { /* ... */ }));
+//!     collector.register().pin(|scope| { /* ... */ });
+//!     t.join().unwrap();
+//! });
+//! ```
+//!
+//! Models must be deterministic, so the collector needs a [`RandomSource`] that doesn't depend on
+//! earlier runs, and there is a single global garbage queue. Every pinning may collect garbage and
+//! advance the epoch, so models quickly get expensive to explore exhaustively; bounding the number
+//! of preemptions with `LOOM_MAX_PREEMPTIONS` helps. Bags of garbage are large, so threads of a
+//! model may need bigger stacks than `loom` gives them by default. `tests/loom.rs` has models of
+//! the collector itself.
+//!
+//! Statics holding configuration or hooks keep using `std` atomics, since those of `loom` can't
+//! be created in a constant, and they aren't part of the protocol anyway.
+//!
+//! [`loom`]: https://docs.rs/loom
+//! [`RandomSource`]: struct.RandomSource.html
+
+#[cfg(loom)]
+pub use loom::sync::atomic;
+
+#[cfg(not(loom))]
+pub use std::sync::atomic;
+#[cfg(not(loom))]
+pub use std::thread_local;
+
+/// Declares thread-locals of `loom`, taking the same declarations as `std::thread_local!`.
+///
+/// The macro of `loom` doesn't accept `const` initializers, which only spare the lazy
+/// initialization, so they are evaluated lazily like any other.
+#[cfg(loom)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __loom_thread_local {
+    () => {};
+    (
+        $(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = const { $init:expr }
+        $(; $($rest:tt)*)?
+    ) => {
+        ::loom::thread_local!($(#[$attr])* $vis static $name: $t = $init);
+        $($crate::__loom_thread_local!($($rest)*);)?
+    };
+    (
+        $(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr
+        $(; $($rest:tt)*)?
+    ) => {
+        ::loom::thread_local!($(#[$attr])* $vis static $name: $t = $init);
+        $($crate::__loom_thread_local!($($rest)*);)?
+    };
+}
+
+#[cfg(loom)]
+pub use __loom_thread_local as thread_local;
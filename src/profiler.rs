@@ -0,0 +1,378 @@
+//! Pinned-section profiler
+//!
+//! Keeping a mutator pinned for a long time blocks epoch advancement and therefore memory
+//! reclamation. With the `profiler` feature enabled, the duration of every `SAMPLE_INTERVAL`-th
+//! pinned section is measured and attributed to the call site of [`pin`], which makes it possible
+//! to find the code paths that hold the epoch the longest.
+//!
+//! Only the outermost pinning of a mutator is measured, since nested pinnings are noops.
+//!
+//! Contention on a single [`Atomic`], e.g. the head of a queue, is just as hard to find in a large
+//! data structure. Every `SLOT_SAMPLE_INTERVAL`-th load, store, swap, or compare-and-set on a
+//! thread is therefore counted against the address of the `Atomic` it accessed, and [`hot_slots`]
+//! reports the most frequently accessed ones. Each thread buffers its samples and adds them to a
+//! shared table in batches. Only a bounded number of addresses is tracked; when the table is full,
+//! the less accessed half of them makes room for new ones.
+//!
+//! [`pin`]: fn.pin.html
+//! [`Atomic`]: struct.Atomic.html
+//! [`hot_slots`]: fn.hot_slots.html
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::panic::Location;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use sys::{self, Timestamp};
+
+/// Every `SAMPLE_INTERVAL`-th pinning is measured.
+pub const SAMPLE_INTERVAL: usize = 16;
+
+/// Call site of a pinning.
+pub type Site = &'static Location<'static>;
+
+/// Aggregated measurements of pinned sections started at one call site.
+#[derive(Clone, Debug)]
+pub struct PinSite {
+    /// The call site of `pin`.
+    pub location: Site,
+    /// Number of measured pinned sections.
+    pub samples: u64,
+    /// Total duration of the measured pinned sections.
+    pub total: Duration,
+    /// Duration of the longest measured pinned section.
+    pub max: Duration,
+}
+
+/// Measurements of all call sites seen so far.
+static SITES: Mutex<Vec<PinSite>> = Mutex::new(Vec::new());
+
+/// Every `SLOT_SAMPLE_INTERVAL`-th access to an `Atomic` on a thread is recorded.
+pub const SLOT_SAMPLE_INTERVAL: usize = 64;
+
+/// Maximum number of `Atomic`s whose accesses are tracked at once.
+const MAX_SLOTS: usize = 1024;
+
+/// Number of sampled accesses a thread buffers before adding them to the table.
+const BUFFER_LEN: usize = 64;
+
+/// An access to an `Atomic`.
+#[derive(Clone, Copy, Debug)]
+pub enum Access {
+    /// A load.
+    Load,
+    /// A store.
+    Store,
+    /// A swap.
+    Swap,
+    /// A compare-and-set, and whether it succeeded.
+    CompareAndSet(bool),
+}
+
+/// Sampled accesses to one `Atomic`.
+#[derive(Clone, Debug)]
+pub struct HotSlot {
+    /// The address of the `Atomic`.
+    pub address: usize,
+    /// Number of sampled loads.
+    pub loads: u64,
+    /// Number of sampled stores.
+    pub stores: u64,
+    /// Number of sampled swaps.
+    pub swaps: u64,
+    /// Number of sampled compare-and-set operations.
+    pub compare_and_sets: u64,
+    /// Number of sampled compare-and-set operations that failed.
+    pub failed_compare_and_sets: u64,
+}
+
+impl HotSlot {
+    /// Returns the number of sampled accesses.
+    pub fn accesses(&self) -> u64 {
+        self.loads + self.stores + self.swaps + self.compare_and_sets
+    }
+}
+
+/// Sampled accesses of the tracked `Atomic`s, keyed by address.
+static SLOTS: Mutex<BTreeMap<usize, HotSlot>> = Mutex::new(BTreeMap::new());
+
+/// Accesses to `Atomic`s on a thread.
+struct Buffer {
+    /// Number of accesses so far.
+    accesses: Cell<usize>,
+    /// Sampled accesses not added to the table yet.
+    samples: RefCell<Vec<(usize, Access)>>,
+}
+
+impl Buffer {
+    /// Counts `access` to the `Atomic` at `address`, and buffers it if it's due for sampling.
+    #[inline]
+    fn count(&self, address: usize, access: Access) {
+        let count = self.accesses.get().wrapping_add(1);
+        self.accesses.set(count);
+        if count.is_multiple_of(SLOT_SAMPLE_INTERVAL) {
+            self.push(address, access);
+        }
+    }
+
+    /// Buffers a sampled `access` to the `Atomic` at `address`.
+    #[cold]
+    fn push(&self, address: usize, access: Access) {
+        let mut samples = self.samples.borrow_mut();
+        samples.push((address, access));
+        if samples.len() >= BUFFER_LEN {
+            record_slots(&mut samples);
+        }
+    }
+
+    /// Adds the buffered samples to the table.
+    fn flush(&self) {
+        record_slots(&mut self.samples.borrow_mut());
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+thread_local! {
+    static BUFFER: Buffer = const {
+        Buffer {
+            accesses: Cell::new(0),
+            samples: RefCell::new(Vec::new()),
+        }
+    };
+}
+
+/// A pinned section that is being measured.
+pub struct Sample {
+    site: Site,
+    start: Timestamp,
+}
+
+impl Sample {
+    /// Starts measuring a pinned section that was entered at `site`.
+    #[inline]
+    pub fn start(site: Site) -> Self {
+        Sample {
+            site,
+            start: sys::now(),
+        }
+    }
+
+    /// Stops measuring and records the duration of the pinned section.
+    pub fn finish(self) {
+        let elapsed = self.start.elapsed();
+        let mut sites = SITES.lock().unwrap_or_else(|e| e.into_inner());
+
+        match sites.iter_mut().find(|s| s.location == self.site) {
+            Some(s) => {
+                s.samples += 1;
+                s.total += elapsed;
+                if elapsed > s.max {
+                    s.max = elapsed;
+                }
+            }
+            None => sites.push(PinSite {
+                location: self.site,
+                samples: 1,
+                total: elapsed,
+                max: elapsed,
+            }),
+        }
+    }
+}
+
+/// Counts `access` to the `Atomic` at `address`, if it's due for sampling.
+#[inline]
+pub fn sample_slot(address: usize, access: Access) {
+    let _ = BUFFER.try_with(|b| b.count(address, access));
+}
+
+/// Adds the sampled accesses in `samples` to the table, leaving `samples` empty.
+fn record_slots(samples: &mut Vec<(usize, Access)>) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    for (address, access) in samples.drain(..) {
+        record(&mut slots, address, access);
+    }
+}
+
+/// Adds a sampled `access` to the `Atomic` at `address` to `slots`.
+fn record(slots: &mut BTreeMap<usize, HotSlot>, address: usize, access: Access) {
+    if slots.len() >= MAX_SLOTS && !slots.contains_key(&address) {
+        evict(slots);
+    }
+
+    let slot = slots.entry(address).or_insert(HotSlot {
+        address,
+        loads: 0,
+        stores: 0,
+        swaps: 0,
+        compare_and_sets: 0,
+        failed_compare_and_sets: 0,
+    });
+    match access {
+        Access::Load => slot.loads += 1,
+        Access::Store => slot.stores += 1,
+        Access::Swap => slot.swaps += 1,
+        Access::CompareAndSet(success) => {
+            slot.compare_and_sets += 1;
+            if !success {
+                slot.failed_compare_and_sets += 1;
+            }
+        }
+    }
+}
+
+/// Discards at least the less accessed half of the full table `slots`.
+///
+/// This takes time linear in the size of the table, but leaves room for as many new slots.
+#[cold]
+fn evict(slots: &mut BTreeMap<usize, HotSlot>) {
+    let mut accesses = slots.values().map(HotSlot::accesses).collect::<Vec<_>>();
+    let (_, &mut median, _) = accesses.select_nth_unstable(slots.len() / 2);
+    slots.retain(|_, s| s.accesses() > median);
+}
+
+/// Returns up to `n` [`Atomic`]s that were accessed the most, along with their sampled accesses.
+///
+/// Only about one in `SLOT_SAMPLE_INTERVAL` accesses is sampled. The slots are sorted by the
+/// number of sampled accesses, most first. A high share of failed compare-and-set operations
+/// indicates contention.
+///
+/// The accesses sampled on the current thread are all included, but other threads only hand
+/// theirs over every `BUFFER_LEN` samples, and when they exit.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let head = Atomic::new(0);
+/// for _ in 0..10_000 {
+///     epoch::pin(|scope| head.load(SeqCst, scope).is_null());
+/// }
+///
+/// let address = &head as *const _ as usize;
+/// let hottest = epoch::hot_slots(10);
+/// assert!(hottest.iter().any(|s| s.address == address));
+/// # epoch::pin(|scope| unsafe { scope.defer_drop(head.load(SeqCst, scope)) });
+/// ```
+///
+/// [`Atomic`]: struct.Atomic.html
+pub fn hot_slots(n: usize) -> Vec<HotSlot> {
+    let _ = BUFFER.try_with(Buffer::flush);
+    let slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut slots = slots.values().cloned().collect::<Vec<_>>();
+    slots.sort_by_key(|s| Reverse(s.accesses()));
+    slots.truncate(n);
+    slots
+}
+
+/// Discards all sampled accesses to `Atomic`s collected so far.
+pub fn reset_hot_slots() {
+    let _ = BUFFER.try_with(|b| b.samples.borrow_mut().clear());
+    SLOTS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Returns up to `n` call sites of [`pin`] that kept mutators pinned the longest in total.
+///
+/// The sites are sorted by the total duration of their measured pinned sections, longest first.
+///
+/// [`pin`]: fn.pin.html
+pub fn top_pin_sites(n: usize) -> Vec<PinSite> {
+    let mut sites = SITES.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    sites.sort_by_key(|s| Reverse(s.total));
+    sites.truncate(n);
+    sites
+}
+
+/// Discards all measurements collected so far.
+pub fn reset_pin_sites() {
+    SITES.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    use {pin, Atomic};
+    use super::*;
+
+    #[test]
+    fn report_slow_site() {
+        thread::spawn(|| for _ in 0..SAMPLE_INTERVAL * 4 {
+            pin(|_| thread::sleep(Duration::from_millis(1)));
+        }).join()
+            .unwrap();
+
+        let sites = top_pin_sites(usize::MAX);
+        let site = sites
+            .iter()
+            .find(|s| s.location.file() == file!() && s.max >= Duration::from_millis(1))
+            .expect("the slow site should be reported");
+        assert!(site.samples >= 4);
+        assert!(site.total >= site.max);
+    }
+
+    #[test]
+    fn report_hot_slot() {
+        let a = Atomic::new(0);
+        let address = &a as *const _ as usize;
+
+        pin(|scope| {
+            let p = a.load(Relaxed, scope);
+            for _ in 0..SLOT_SAMPLE_INTERVAL * 100 {
+                a.load(Relaxed, scope);
+            }
+            for _ in 0..SLOT_SAMPLE_INTERVAL * 100 {
+                let _ = a.compare_and_set(p.with_tag(1), p, Relaxed, scope);
+            }
+            for _ in 0..SLOT_SAMPLE_INTERVAL * 100 {
+                a.store(p, Relaxed);
+            }
+            for _ in 0..SLOT_SAMPLE_INTERVAL * 100 {
+                a.swap(p, Relaxed, scope);
+            }
+        });
+        pin(|scope| unsafe { scope.defer_drop(a.load(Relaxed, scope)) });
+
+        let slots = hot_slots(usize::MAX);
+        let slot = slots
+            .iter()
+            .find(|s| s.address == address)
+            .expect("the hot slot should be reported");
+        assert!(slot.loads >= 90);
+        assert!(slot.compare_and_sets >= 90);
+        assert_eq!(slot.failed_compare_and_sets, slot.compare_and_sets);
+        assert!(slot.stores >= 90);
+        assert!(slot.swaps >= 90);
+    }
+
+    #[test]
+    fn evict_cold_slots() {
+        let mut slots = BTreeMap::new();
+        for address in 0..MAX_SLOTS {
+            for _ in 0..address % 4 + 1 {
+                record(&mut slots, address, Access::Load);
+            }
+        }
+        assert_eq!(slots.len(), MAX_SLOTS);
+
+        record(&mut slots, MAX_SLOTS, Access::Load);
+        assert!(slots.len() <= MAX_SLOTS / 2 + 1);
+        assert!(slots.contains_key(&MAX_SLOTS));
+        assert!(slots.values().filter(|s| s.address != MAX_SLOTS).all(|s| s.loads > 2));
+    }
+}
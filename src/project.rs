@@ -0,0 +1,130 @@
+//! Projections
+//!
+//! Traversing a linked data structure dereferences a [`Ptr`] at every step, and each dereference
+//! is an `unsafe` block whose justification is the same every time: the node was loaded under the
+//! current scope, and nodes are only ever destroyed through the garbage collector. Implementing
+//! [`EpochNode`] for the node type states that justification once, after which pointers to nodes
+//! can be converted into references with [`Ptr::get`], and fields projected out of them with
+//! [`project!`].
+//!
+//! The references are shared and bound to the scope, so no `&mut` to a node can be obtained this
+//! way, and no reference outlives the pinning it was loaded in.
+//!
+//! [`Ptr`]: struct.Ptr.html
+//! [`Ptr::get`]: struct.Ptr.html#method.get
+//! [`EpochNode`]: trait.EpochNode.html
+//! [`project!`]: macro.project.html
+
+/// Types whose objects can be safely read through any pointer to them.
+///
+/// # Safety
+///
+/// Implementing the trait asserts the following about every [`Ptr`] to the type that exists in the
+/// program, including pointers inside [`Atomic`]s:
+///
+/// - It is null, or points to an object that was allocated with [`Owned`].
+/// - The object is destroyed only after it can no longer be loaded, and only through the garbage
+///   collector, e.g. with [`Scope::defer_drop`], or once no other thread can access it.
+/// - The object is not mutated through a `&mut` while it can be loaded.
+/// - It was published with `Release` (or stronger) ordering and loaded with `Acquire` (or
+///   stronger) ordering, so that initialization of the object happens before reads through it.
+///
+/// Since [`Ptr::from_raw`] can be called on any pointer, this can only be upheld for types private
+/// to a crate, like the nodes of a data structure.
+///
+/// [`Atomic`]: struct.Atomic.html
+/// [`Owned`]: struct.Owned.html
+/// [`Ptr`]: struct.Ptr.html
+/// [`Ptr::from_raw`]: struct.Ptr.html#method.from_raw
+/// [`Scope::defer_drop`]: struct.Scope.html#method.defer_drop
+pub unsafe trait EpochNode {}
+
+/// Projects a field out of the object a [`Ptr`] to an [`EpochNode`] points to.
+///
+/// `project!(ptr => field)` returns `None` if `ptr` is null, or else a reference to `field` of the
+/// object, bound to the scope `ptr` was loaded in. Nested fields can be projected with
+/// `project!(ptr => field.inner)`.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use(project)]
+/// extern crate crossbeam_epoch as epoch;
+///
+/// use epoch::{Atomic, EpochNode, Owned};
+/// use std::sync::atomic::Ordering::{Acquire, Release};
+///
+/// struct Node {
+///     value: u32,
+///     next: Atomic<Node>,
+/// }
+///
+/// unsafe impl EpochNode for Node {}
+///
+/// # fn main() {
+/// let head = Atomic::null();
+/// epoch::pin(|scope| {
+///     for value in 0..3 {
+///         let next = Atomic::from_ptr(head.load(Acquire, scope));
+///         head.store_owned(Owned::new(Node { value, next }), Release);
+///     }
+///
+///     let mut sum = 0;
+///     let mut node = head.load(Acquire, scope);
+///     while let Some(&value) = project!(node => value) {
+///         sum += value;
+///         node = project!(node => next).unwrap().load(Acquire, scope);
+///     }
+///     assert_eq!(sum, 3);
+///     # let mut node = head.load(Acquire, scope);
+///     # while let Some(next) = project!(node => next) {
+///     #     let n = next.load(Acquire, scope);
+///     #     unsafe { scope.defer_drop(node) }
+///     #     node = n;
+///     # }
+/// });
+/// # }
+/// ```
+///
+/// [`Ptr`]: struct.Ptr.html
+/// [`EpochNode`]: trait.EpochNode.html
+#[macro_export]
+macro_rules! project {
+    ($ptr:expr => $($field:tt).+) => {
+        $ptr.get().map(|node| &node.$($field).+)
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::{Acquire, Release};
+
+    use {pin, Atomic, Ptr};
+    use super::EpochNode;
+
+    struct Pair {
+        inner: (u8, u16),
+        next: Atomic<Pair>,
+    }
+
+    unsafe impl EpochNode for Pair {}
+
+    #[test]
+    fn projects_nested_fields() {
+        let a = Atomic::new(Pair {
+            inner: (1, 2),
+            next: Atomic::null(),
+        });
+
+        pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert_eq!(project!(p => inner.1), Some(&2));
+            assert!(project!(p => next).unwrap().load(Acquire, scope).is_null());
+            assert_eq!(project!(Ptr::<Pair>::null() => inner.0), None);
+
+            a.store(Ptr::null(), Release);
+            scope.defer_drop(p);
+        });
+    }
+}
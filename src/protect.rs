@@ -0,0 +1,187 @@
+//! Protected pointers
+//!
+//! Epoch-based reclamation protects everything a pinned thread may have loaded, but only while the
+//! thread stays pinned. Iterators that want to unpin between items can instead protect just the
+//! node they're at, in the style of hazard pointers.
+//!
+//! Protected addresses are published in a global, bounded set of slots. When a bag of garbage is
+//! collected, any object in it that is still protected is not destroyed, but deferred once more.
+
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, Release, SeqCst};
+
+use atomic::Ptr;
+use mutator::Scope;
+
+/// Maximum number of pointers that can be protected at the same time, across all threads.
+pub const MAX_PROTECTED: usize = 64;
+
+/// Slots holding the protected addresses, or zero if unused.
+static SLOTS: [AtomicUsize; MAX_PROTECTED] = [const { AtomicUsize::new(0) }; MAX_PROTECTED];
+
+/// Number of slots in use.
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+/// A pointer whose object is protected from destruction until the pointer is dropped, even if
+/// the thread gets unpinned in the meantime.
+///
+/// Protected pointers are created by [`Scope::protect`].
+///
+/// [`Scope::protect`]: struct.Scope.html#method.protect
+#[derive(Debug)]
+pub struct Protected<T> {
+    /// Index of the slot publishing the address, or `None` for null pointers.
+    slot: Option<usize>,
+    raw: *const T,
+    tag: usize,
+    _marker: PhantomData<*const T>, // !Send + !Sync
+}
+
+impl<T> Protected<T> {
+    /// Returns the raw pointer to the object.
+    pub fn as_raw(&self) -> *const T {
+        self.raw
+    }
+
+    /// Returns the tag the pointer was protected with.
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    /// Returns `true` if the pointer is null.
+    pub fn is_null(&self) -> bool {
+        self.raw.is_null()
+    }
+
+    /// Dereferences the pointer.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must not be null, and the object must have been alive when it was protected.
+    pub unsafe fn deref(&self) -> &T {
+        &*self.raw
+    }
+
+    /// Converts the pointer to a reference, or returns `None` if it is null.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been alive when it was protected.
+    pub unsafe fn as_ref(&self) -> Option<&T> {
+        self.raw.as_ref()
+    }
+
+    /// Returns the pointer as a [`Ptr`] in `scope`, e.g. to load the next node from it.
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub fn ptr<'scope>(&self, _: &'scope Scope) -> Ptr<'scope, T> {
+        Ptr::from_raw(self.raw).with_tag(self.tag)
+    }
+}
+
+impl<T> Drop for Protected<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            SLOTS[slot].store(0, Release);
+            IN_USE.fetch_sub(1, Release);
+        }
+    }
+}
+
+/// Protects the object `ptr` points to, or returns `None` if all slots are in use.
+pub fn protect<T>(ptr: Ptr<T>) -> Option<Protected<T>> {
+    let raw = ptr.as_raw();
+    let mut protected = Protected {
+        slot: None,
+        raw,
+        tag: ptr.tag(),
+        _marker: PhantomData,
+    };
+
+    if !raw.is_null() {
+        IN_USE.fetch_add(1, SeqCst);
+        let slot = SLOTS
+            .iter()
+            .position(|s| s.compare_exchange(0, raw as usize, SeqCst, Relaxed).is_ok());
+
+        match slot {
+            Some(slot) => protected.slot = Some(slot),
+            None => {
+                IN_USE.fetch_sub(1, Release);
+                return None;
+            }
+        }
+    }
+    Some(protected)
+}
+
+/// Returns `true` if any object might be protected.
+#[inline]
+pub fn any_protected() -> bool {
+    IN_USE.load(SeqCst) != 0
+}
+
+/// Returns `true` if the object at `object` is protected.
+pub fn is_protected(object: *const u8) -> bool {
+    !object.is_null() && SLOTS.iter().any(|s| s.load(SeqCst) == object as usize)
+}
+
+
+#[cfg(all(test, feature = "unstable"))]
+mod tests {
+    use {pin, Atomic, Collector, Owned};
+    use testkit::Drops;
+    use super::*;
+
+    #[test]
+    fn protected_survives_unpin() {
+        let drops = Drops::new();
+        let a = Atomic::from_owned(Owned::new(drops.wrap(7)));
+
+        let protected = pin(|scope| unsafe {
+            let p = a.swap(Ptr::null(), SeqCst, scope);
+            let protected = scope.protect(p).unwrap();
+            scope.defer_drop(p);
+            protected
+        });
+
+        for _ in 0..1000 {
+            pin(|scope| scope.flush());
+        }
+        assert_eq!(drops.count(), 0);
+        assert_eq!(unsafe { **protected.deref() }, 7);
+
+        drop(protected);
+        ::assert_reclaimed!(drops, 1);
+    }
+
+    #[test]
+    fn protected_outlives_collector() {
+        let drops = Drops::new();
+        let collector = Collector::new();
+        let handle = collector.register();
+
+        let protected = handle.pin(|scope| unsafe {
+            let p = Owned::new(drops.wrap(7)).into_ptr(scope);
+            let protected = scope.protect(p).unwrap();
+            scope.defer_drop(p);
+            protected
+        });
+        drop(handle);
+        drop(collector);
+
+        // The object is leaked rather than destroyed while it's still protected.
+        assert_eq!(drops.count(), 0);
+        assert_eq!(unsafe { **protected.deref() }, 7);
+    }
+
+    #[test]
+    fn protect_null() {
+        pin(|scope| {
+            let p = scope.protect(Ptr::<u64>::null()).unwrap();
+            assert!(p.is_null());
+            assert!(!is_protected(p.as_raw() as *const u8));
+        });
+    }
+}
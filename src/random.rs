@@ -0,0 +1,147 @@
+//! Randomness for heuristics
+//!
+//! Some heuristics of the collector work better with a little randomness, e.g. staggering when
+//! the threads registered with a collector collect garbage, so that threads started together
+//! don't all collect at the same pinnings. The numbers come from the [`RandomSource`] in the
+//! [`CollectorConfig`], which by default is a PCG generator per thread. Supplying a seeded source
+//! instead makes the heuristics reproducible, e.g. to replay a failing test.
+//!
+//! [`RandomSource`]: struct.RandomSource.html
+//! [`CollectorConfig`]: struct.CollectorConfig.html
+
+use std::cell::Cell;
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// The multiplier of the PCG generators.
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/// The seed of the PCG generator of the next thread.
+static NEXT_SEED: AtomicU64 = AtomicU64::new(0x853c_49e6_748f_ea9b);
+
+thread_local! {
+    /// The state of the PCG generator of the current thread, or zero until it's seeded.
+    static STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Returns the next 32 random bits of the PCG generator with state `state` (PCG-XSH-RR).
+fn pcg32(state: &mut u64) -> u32 {
+    let old = *state;
+    *state = old.wrapping_mul(MULTIPLIER).wrapping_add(1442695040888963407);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    xorshifted.rotate_right((old >> 59) as u32)
+}
+
+/// Returns the next random number of the PCG generator of the current thread.
+fn thread_pcg() -> u64 {
+    STATE
+        .try_with(|s| {
+            let mut state = s.get();
+            if state == 0 {
+                // Give every thread its own stream, in the order the threads ask for one.
+                state = NEXT_SEED.fetch_add(0x9e37_79b9_7f4a_7c15, Relaxed) | 1;
+            }
+            let high = pcg32(&mut state) as u64;
+            let low = pcg32(&mut state) as u64;
+            s.set(state);
+            (high << 32) | low
+        })
+        .unwrap_or(0)
+}
+
+/// A source of random numbers for the heuristics of a collector.
+///
+/// The numbers only steer heuristics, so they don't need to be of cryptographic quality, but they
+/// must be cheap to produce, since they may be asked for on every registration or collection.
+///
+/// # Examples
+///
+/// A deterministic source, so that runs can be replayed:
+///
+/// ```
+/// use crossbeam_epoch::{Collector, CollectorConfig, RandomSource};
+/// use std::sync::atomic::AtomicU64;
+/// use std::sync::atomic::Ordering::Relaxed;
+///
+/// fn splitmix() -> u64 {
+///     static STATE: AtomicU64 = AtomicU64::new(42);
+///     let mut z = STATE.fetch_add(0x9e3779b97f4a7c15, Relaxed).wrapping_add(0x9e3779b97f4a7c15);
+///     z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+///     z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+///     z ^ (z >> 31)
+/// }
+///
+/// let mut config = CollectorConfig::default();
+/// config.random = RandomSource::new(splitmix);
+/// let collector = Collector::with_config(config);
+/// ```
+#[derive(Clone, Copy)]
+pub struct RandomSource {
+    next: fn() -> u64,
+}
+
+impl RandomSource {
+    /// Returns a source that calls `next` for every random number.
+    pub const fn new(next: fn() -> u64) -> Self {
+        RandomSource { next }
+    }
+
+    /// Returns the default source, a PCG generator per thread.
+    ///
+    /// The generators of threads are seeded in the order the threads first ask for a number.
+    pub const fn thread_pcg() -> Self {
+        RandomSource::new(thread_pcg)
+    }
+
+    /// Returns the next random number.
+    #[inline]
+    pub fn next_u64(&self) -> u64 {
+        (self.next)()
+    }
+
+    /// Returns a random number below `n`, which must not be zero.
+    #[inline]
+    pub(crate) fn below(&self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+impl Default for RandomSource {
+    fn default() -> Self {
+        RandomSource::thread_pcg()
+    }
+}
+
+impl PartialEq for RandomSource {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::fn_addr_eq(self.next, other.next)
+    }
+}
+
+impl Eq for RandomSource {}
+
+impl fmt::Debug for RandomSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RandomSource").field(&(self.next as *const ())).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn threads_get_distinct_streams() {
+        let source = RandomSource::default();
+        let here = (0..4).map(|_| source.next_u64()).collect::<Vec<_>>();
+        let there = thread::spawn(move || (0..4).map(|_| source.next_u64()).collect::<Vec<_>>())
+            .join()
+            .unwrap();
+        assert_ne!(here, there);
+        assert_ne!(here[0], here[1]);
+    }
+}
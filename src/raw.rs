@@ -0,0 +1,457 @@
+//! The pointer and tag layer
+//!
+//! Everything about tagged pointers that doesn't involve pinning lives here: the [`Pointable`]
+//! trait with its allocation of sized types and arrays, the layouts of low and high tags, the
+//! arithmetic packing a tag and an address into a word, and the checks of those words. None of it
+//! depends on mutators, their registration, or the global epoch, so it can be reused where the
+//! collector can't run, e.g. in a kernel module, and it is tested on its own below. [`Atomic`],
+//! [`Owned`], and [`Ptr`] build on top of it, and add the scopes that make loads safe.
+//!
+//! It uses no other part of the crate either: pointers that fail the checks are reported through
+//! a [`Report`] callback passed in by the caller, which the rest of the crate routes to the misuse
+//! handler. `tests/raw.rs` builds the module on its own to keep it that way.
+//!
+//! [`Pointable`]: trait.Pointable.html
+//! [`Atomic`]: ../struct.Atomic.html
+//! [`Owned`]: ../struct.Owned.html
+//! [`Ptr`]: ../struct.Ptr.html
+//! [`Report`]: type.Report.html
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::Ordering;
+
+/// A check of a tagged pointer that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// A pointer isn't aligned enough to hold a tag.
+    UnalignedPointer,
+    /// A tagged pointer holds an implausible address.
+    CorruptedPointer,
+    /// A tag doesn't fit into the unused bits of a pointer, or an address overlaps them.
+    TagOverflow,
+}
+
+/// Reports a failed check with a message, and panics.
+pub type Report = fn(Failure, fmt::Arguments) -> !;
+
+/// Given ordering for the success case in a compare-exchange operation, returns the strongest
+/// appropriate ordering for the failure case.
+#[inline]
+fn strongest_failure_ordering(ord: Ordering) -> Ordering {
+    use self::Ordering::*;
+    match ord {
+        Relaxed | Release => Relaxed,
+        Acquire | AcqRel => Acquire,
+        _ => SeqCst,
+    }
+}
+
+/// Memory orderings for compare-and-set operations.
+///
+/// A compare-and-set operation can have different memory orderings depending on whether it
+/// succeeds or fails. This trait generalizes different ways of specifying memory orderings.
+///
+/// The two ways of specifying orderings for compare-and-set are:
+///
+/// 1. Just one `Ordering` for the success case. In case of failure, the strongest appropriate
+///    ordering is chosen.
+/// 2. A pair of `Ordering`s. The first one is for the success case, while the second one is
+///    for the failure case.
+pub trait CompareAndSetOrdering {
+    /// The ordering of the operation when it succeeds.
+    fn success(&self) -> Ordering;
+
+    /// The ordering of the operation when it fails.
+    ///
+    /// The failure ordering can't be `Release` or `AcqRel` and must be equivalent or weaker than
+    /// the success ordering.
+    ///
+    /// The current value that the `compare_and_set` methods of [`Atomic`] return on failure is
+    /// acquired even with a `Relaxed` failure ordering. So is the one returned on failure by the
+    /// methods built on compare-and-set, like `swap_if_tag`, `compare_and_set_tag` and
+    /// `fetch_update`.
+    ///
+    /// [`Atomic`]: struct.Atomic.html
+    fn failure(&self) -> Ordering;
+}
+
+impl CompareAndSetOrdering for Ordering {
+    #[inline]
+    fn success(&self) -> Ordering {
+        *self
+    }
+
+    #[inline]
+    fn failure(&self) -> Ordering {
+        strongest_failure_ordering(*self)
+    }
+}
+
+impl CompareAndSetOrdering for (Ordering, Ordering) {
+    #[inline]
+    fn success(&self) -> Ordering {
+        self.0
+    }
+
+    #[inline]
+    fn failure(&self) -> Ordering {
+        self.1
+    }
+}
+
+/// Types that [`Atomic`], [`Owned`], and [`Ptr`] can point to.
+///
+/// The trait abstracts how objects are allocated, initialized, accessed, and freed, so that
+/// pointers may refer to dynamically sized types too. It is implemented for all sized types, whose
+/// objects are allocated like a `Box<T>`, and for `[MaybeUninit<T>]`, whose elements are allocated
+/// together with the length. A variable-length array, e.g. a bucket of a hash table, thus takes a
+/// single allocation and a single indirection, instead of two with `Atomic<Box<[T]>>`.
+///
+/// # Safety
+///
+/// `init` must return a pointer aligned to `ALIGN` that `deref` and `deref_mut` turn into
+/// references to a valid object, until `drop` is called with it.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::mem::MaybeUninit;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let mut bucket = Owned::<[MaybeUninit<u32>]>::init(4);
+/// for (i, slot) in bucket.iter_mut().enumerate() {
+///     *slot = MaybeUninit::new(i as u32);
+/// }
+/// let a = Atomic::from_owned(bucket);
+///
+/// epoch::pin(|scope| {
+///     let bucket = unsafe { a.load(SeqCst, scope).deref() };
+///     assert_eq!(bucket.len(), 4);
+///     assert_eq!(unsafe { bucket[3].assume_init() }, 3);
+/// });
+/// # unsafe { drop(a.into_owned()) }
+/// ```
+///
+/// [`Atomic`]: struct.Atomic.html
+/// [`Owned`]: struct.Owned.html
+/// [`Ptr`]: struct.Ptr.html
+pub unsafe trait Pointable {
+    /// The alignment of pointers to objects.
+    const ALIGN: usize;
+
+    /// The argument to initialize an object with.
+    type Init;
+
+    /// Allocates an object initialized with `init`, and returns a pointer to it.
+    ///
+    /// # Safety
+    ///
+    /// The returned pointer must be freed with `drop`.
+    unsafe fn init(init: Self::Init) -> *mut ();
+
+    /// Dereferences the pointer to an object.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `init` and not dropped yet, and the object must not be
+    /// mutably borrowed for `'a`.
+    unsafe fn deref<'a>(ptr: *mut ()) -> &'a Self;
+
+    /// Mutably dereferences the pointer to an object.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `init` and not dropped yet, and the object must not be
+    /// borrowed at all for `'a`.
+    unsafe fn deref_mut<'a>(ptr: *mut ()) -> &'a mut Self;
+
+    /// Drops the object and frees its memory.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `init` and not dropped yet, and the object must not be
+    /// borrowed.
+    unsafe fn drop(ptr: *mut ());
+}
+
+unsafe impl<T> Pointable for T {
+    const ALIGN: usize = mem::align_of::<T>();
+
+    type Init = T;
+
+    unsafe fn init(init: T) -> *mut () {
+        Box::into_raw(Box::new(init)) as *mut ()
+    }
+
+    unsafe fn deref<'a>(ptr: *mut ()) -> &'a T {
+        &*(ptr as *const T)
+    }
+
+    unsafe fn deref_mut<'a>(ptr: *mut ()) -> &'a mut T {
+        &mut *(ptr as *mut T)
+    }
+
+    unsafe fn drop(ptr: *mut ()) {
+        drop(Box::from_raw(ptr as *mut T));
+    }
+}
+
+/// The allocation behind a pointer to `[MaybeUninit<T>]`: the length, followed by the elements.
+#[repr(C)]
+struct Array<T> {
+    len: usize,
+    elements: [MaybeUninit<T>; 0],
+}
+
+impl<T> Array<T> {
+    /// Returns the layout of an array of `len` elements.
+    fn layout(len: usize) -> Layout {
+        let elements = Layout::array::<MaybeUninit<T>>(len).expect("array too large");
+        let (layout, _) = Layout::new::<Self>().extend(elements).expect("array too large");
+        layout.pad_to_align()
+    }
+}
+
+unsafe impl<T> Pointable for [MaybeUninit<T>] {
+    const ALIGN: usize = mem::align_of::<Array<T>>();
+
+    /// The length of the array.
+    type Init = usize;
+
+    unsafe fn init(len: usize) -> *mut () {
+        let layout = Array::<T>::layout(len);
+        let array = alloc::alloc(layout) as *mut Array<T>;
+        if array.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        ptr::addr_of_mut!((*array).len).write(len);
+        array as *mut ()
+    }
+
+    unsafe fn deref<'a>(ptr: *mut ()) -> &'a Self {
+        let array = ptr as *mut Array<T>;
+        slice::from_raw_parts(ptr::addr_of!((*array).elements) as *const _, (*array).len)
+    }
+
+    unsafe fn deref_mut<'a>(ptr: *mut ()) -> &'a mut Self {
+        let array = ptr as *mut Array<T>;
+        slice::from_raw_parts_mut(ptr::addr_of_mut!((*array).elements) as *mut _, (*array).len)
+    }
+
+    unsafe fn drop(ptr: *mut ()) {
+        let array = ptr as *mut Array<T>;
+        alloc::dealloc(ptr as *mut u8, Array::<T>::layout((*array).len));
+    }
+}
+
+/// Panics if the pointer is not properly unaligned.
+#[inline]
+pub fn ensure_aligned<T>(raw: *const T, report: Report) {
+    if raw as usize & low_bits::<T>() != 0 {
+        report(Failure::UnalignedPointer, format_args!("unaligned pointer"));
+    }
+}
+
+/// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
+#[inline]
+pub fn low_bits<T: ?Sized + Pointable>() -> usize {
+    (1 << T::ALIGN.trailing_zeros()) - 1
+}
+
+/// Returns `true` if `addr` looks like an address a heap allocation could live at.
+#[inline]
+pub fn is_plausible_address(addr: usize) -> bool {
+    // The first page is never mapped.
+    if addr < 4096 {
+        return false;
+    }
+
+    // On x86-64 the upper 17 bits of a valid address are all zeros or all ones.
+    #[cfg(all(target_arch = "x86_64", target_pointer_width = "64"))]
+    {
+        let high = (addr as u64) >> 47;
+        if high != 0 && high != (1 << 17) - 1 {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Number of tag bits available above the address when tags are stored in the high bits of a
+/// pointer, or zero if the target has no unused high bits.
+///
+/// On x86-64 and AArch64 user-space addresses fit into the lower 48 bits. High tags are stored in
+/// bits 48 to 55, which leaves the top byte alone: AArch64 top-byte ignore (and memory tagging on
+/// top of it) and Intel LAM_U57 use it for their own metadata, so pointers tagged by the allocator
+/// or the hardware keep their tag. On other targets, high-tag pointers fall back to the low bits.
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), target_pointer_width = "64"))]
+pub const HIGH_TAG_BITS: u32 = 8;
+
+/// Number of tag bits available above the address when tags are stored in the high bits of a
+/// pointer, or zero if the target has no unused high bits.
+#[cfg(not(all(any(target_arch = "x86_64", target_arch = "aarch64"), target_pointer_width = "64")))]
+pub const HIGH_TAG_BITS: u32 = 0;
+
+/// Position of the lowest high tag bit.
+pub const HIGH_TAG_SHIFT: u32 = if HIGH_TAG_BITS > 0 { 48 } else { 0 };
+
+/// Returns the bitmask of tag values and their position within a tagged pointer to `T`.
+#[inline]
+pub fn tag_layout<T: ?Sized + Pointable, const HIGH_TAG: bool>() -> (usize, u32) {
+    if HIGH_TAG && HIGH_TAG_BITS > 0 {
+        ((1 << HIGH_TAG_BITS) - 1, HIGH_TAG_SHIFT)
+    } else {
+        (low_bits::<T>(), 0)
+    }
+}
+
+/// Returns a bitmask containing the bits of a tagged pointer to `T` that hold the tag.
+#[inline]
+pub fn tag_mask<T: ?Sized + Pointable, const HIGH_TAG: bool>() -> usize {
+    let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+    mask << shift
+}
+
+/// Returns the tag of the tagged pointer `data`.
+#[inline]
+pub fn data_tag<T: ?Sized + Pointable, const HIGH_TAG: bool>(data: usize) -> usize {
+    let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+    (data >> shift) & mask
+}
+
+/// Returns the address of the tagged pointer `data`.
+#[inline]
+pub fn data_address<T: ?Sized + Pointable, const HIGH_TAG: bool>(data: usize) -> usize {
+    data & !tag_mask::<T, HIGH_TAG>()
+}
+
+/// Reports the tagged pointer `data` read from or written into `slot` if it does not decode to a
+/// plausible pointer to `T`. Returns `data` unchanged.
+///
+/// This check is performed only in debug builds or with the `strict` feature. It catches stray
+/// writes and broken tag arithmetic at the moment of corruption rather than when the bogus pointer
+/// is finally dereferenced.
+#[inline]
+pub fn validate<T: ?Sized + Pointable, const HIGH_TAG: bool>(
+    slot: *const (),
+    data: usize,
+    report: Report,
+) -> usize {
+    if cfg!(any(debug_assertions, feature = "strict")) {
+        let raw = data_address::<T, HIGH_TAG>(data);
+
+        // Pointers to zero-sized objects are dangling, i.e. equal to their alignment.
+        if raw != 0 && raw != T::ALIGN && !is_plausible_address(raw) {
+            report(
+                Failure::CorruptedPointer,
+                format_args!(
+                    "corrupted atomic pointer {:#x} (tag {}) in slot {:p}",
+                    data,
+                    data_tag::<T, HIGH_TAG>(data),
+                    slot
+                ),
+            );
+        }
+    }
+    data
+}
+
+/// Given a tagged pointer `data`, returns the same pointer, but tagged with `tag`.  `tag` is
+/// truncated to be fit into the unused bits of the pointer to `T`.
+#[inline]
+pub fn data_with_tag<T: ?Sized + Pointable, const HIGH_TAG: bool>(
+    data: usize,
+    tag: usize,
+) -> usize {
+    let (mask, shift) = tag_layout::<T, HIGH_TAG>();
+    (data & !(mask << shift)) | ((tag & mask) << shift)
+}
+
+/// Reports `tag` with the `strict` feature if it doesn't fit into the unused bits of the pointer to
+/// `T`. Returns `tag` unchanged.
+#[inline]
+pub fn check_tag<T: ?Sized + Pointable, const HIGH_TAG: bool>(tag: usize, report: Report) -> usize {
+    if cfg!(feature = "strict") {
+        let mask = tag_layout::<T, HIGH_TAG>().0;
+        if tag & !mask != 0 {
+            report(
+                Failure::TagOverflow,
+                format_args!("tag {:#x} doesn't fit into the tag bits {:#x}", tag, mask),
+            );
+        }
+    }
+    tag
+}
+
+/// Given a tagged pointer `data` using either tag layout, returns the same pointer using the
+/// layout `TO`, with the tag truncated to fit.
+///
+/// Reports the pointer if the address uses bits that hold the tag in layout `TO`.
+#[inline]
+pub fn data_retagged<T: ?Sized + Pointable, const FROM: bool, const TO: bool>(
+    data: usize,
+    report: Report,
+) -> usize {
+    let raw = data_address::<T, FROM>(data);
+    if raw & tag_mask::<T, TO>() != 0 {
+        report(Failure::TagOverflow, format_args!("address overlaps the tag bits"));
+    }
+    data_with_tag::<T, TO>(raw, data_tag::<T, FROM>(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(_: Failure, message: fmt::Arguments) -> ! {
+        panic!("{}", message)
+    }
+
+    #[test]
+    fn low_tags() {
+        let data = data_with_tag::<u64, false>(0x1000, 5);
+        assert_eq!(data, 0x1005);
+        assert_eq!(data_tag::<u64, false>(data), 5);
+        assert_eq!(data_address::<u64, false>(data), 0x1000);
+        // Tags are truncated to the three unused bits.
+        assert_eq!(data_tag::<u64, false>(data_with_tag::<u64, false>(0x1000, 9)), 1);
+        assert_eq!(low_bits::<u8>(), 0);
+    }
+
+    #[test]
+    fn high_tags() {
+        let data = data_with_tag::<u8, true>(0x1000, 200);
+        if HIGH_TAG_BITS > 0 {
+            assert_eq!(data_tag::<u8, true>(data), 200);
+            assert_eq!(data_retagged::<u8, true, false>(data, report), 0x1000);
+        }
+        assert_eq!(data_address::<u8, true>(data), 0x1000);
+    }
+
+    #[test]
+    fn arrays() {
+        unsafe {
+            let array = <[MaybeUninit<u16>] as Pointable>::init(3);
+            assert_eq!(array as usize & low_bits::<[MaybeUninit<u16>]>(), 0);
+            for (i, slot) in <[MaybeUninit<u16>]>::deref_mut(array).iter_mut().enumerate() {
+                *slot = MaybeUninit::new(i as u16);
+            }
+            let elements = <[MaybeUninit<u16>]>::deref(array);
+            assert_eq!(elements.len(), 3);
+            assert_eq!(elements[2].assume_init(), 2);
+            <[MaybeUninit<u16>] as Pointable>::drop(array);
+        }
+    }
+
+    #[test]
+    fn implausible_addresses() {
+        assert!(!is_plausible_address(8));
+        assert!(is_plausible_address(0x7f00_0000_1000));
+    }
+}
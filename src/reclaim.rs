@@ -0,0 +1,201 @@
+//! Reclamation accounting
+//!
+//! Garbage is destroyed by whichever thread happens to collect it, long after it was retired, so
+//! an application that keeps its own books on memory, e.g. reconciling them with the statistics
+//! of the allocator or charging subsystems for their usage, can't tell from the outside when and
+//! where the memory it handed over is actually freed.
+//!
+//! With the `reclaim_hook` feature, a hook set with [`LocalHandle::set_reclaim_hook`] is called
+//! with the number of bytes and the number of objects freed after each slice of a collection that
+//! runs on the thread while the handle is pinned. Only memory whose size is known is counted as
+//! bytes, i.e. objects retired with `Scope::defer_drop` and the like, but not whatever deferred
+//! functions free, although these count as objects all the same.
+//!
+//! [`LocalHandle::set_reclaim_hook`]: struct.LocalHandle.html#method.set_reclaim_hook
+
+use std::cell::{Cell, RefCell};
+use std::ptr;
+use std::sync::Arc;
+
+/// A reclaim hook, which is called with the number of bytes and the number of objects freed.
+pub type Hook = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// The slot of the hook of a mutator, which may be empty.
+pub type Slot = RefCell<Option<Hook>>;
+
+thread_local! {
+    /// The slot of the hook of the mutator pinned on the current thread, or null if there is none.
+    static CURRENT: Cell<*const Slot> = const { Cell::new(ptr::null()) };
+
+    /// The number of bytes and objects freed in the collection slice running on the current
+    /// thread so far.
+    static FREED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// Makes the hook in `slot` the one called on the current thread, or no hook at all if `slot` is
+/// null, until the returned guard is dropped.
+///
+/// `slot` must outlive the guard.
+pub fn install(slot: *const Slot) -> Installed {
+    let (current, previous) = CURRENT
+        .try_with(|c| (c as *const _, c.replace(slot)))
+        .unwrap_or((ptr::null(), ptr::null()));
+    Installed { current, previous }
+}
+
+/// Restores the hook that was called on the current thread before a mutator got pinned.
+pub struct Installed {
+    /// The slot of the thread the guard was created on.
+    current: *const Cell<*const Slot>,
+    previous: *const Slot,
+}
+
+// The guard only restores the hook of the thread it was created on.
+unsafe impl Send for Installed {}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        // A pinned scope may be dropped on another thread, which has hooks of its own.
+        let _ = CURRENT.try_with(|c| {
+            if ptr::eq(c, self.current) {
+                c.set(self.previous);
+            }
+        });
+    }
+}
+
+/// Counts `bytes` and `objects` as freed on the current thread.
+#[inline]
+pub fn freed(bytes: usize, objects: usize) {
+    let _ = FREED.try_with(|f| {
+        let (b, o) = f.get();
+        f.set((b.wrapping_add(bytes), o.wrapping_add(objects)));
+    });
+}
+
+/// Starts a collection slice, returning the counts of the one it's nested in.
+#[inline]
+pub fn start() -> (usize, usize) {
+    FREED.try_with(|f| f.replace((0, 0))).unwrap_or((0, 0))
+}
+
+/// Finishes the collection slice that started with `outer` as the counts of the one it's nested
+/// in, and hands what the slice freed to the hook.
+///
+/// Garbage freed by nested slices is only counted towards those.
+#[inline]
+pub fn finish(outer: (usize, usize)) {
+    let (bytes, objects) = FREED.try_with(|f| f.replace(outer)).unwrap_or((0, 0));
+    if objects > 0 {
+        report(bytes, objects);
+    }
+}
+
+/// Hands `bytes` and `objects` to the hook called on the current thread, if any.
+fn report(bytes: usize, objects: usize) {
+    let slot = CURRENT.try_with(Cell::get).unwrap_or(ptr::null());
+    if slot.is_null() {
+        return;
+    }
+    // The hook may replace itself, which borrows the slot mutably.
+    let hook = unsafe { (*slot).borrow().clone() };
+    if let Some(hook) = hook {
+        hook(bytes, objects);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use {Atomic, Collector, LocalHandle};
+    use global::{self, Realm};
+    use mutator::{Mutator, Scope};
+    use testkit::Drops;
+
+    /// Retires eight objects of 512 bytes and a closure through `scope`, counting their drops in
+    /// `drops`.
+    unsafe fn retire(scope: &Scope, drops: &Drops) {
+        for _ in 0..8 {
+            let a = Atomic::new(drops.wrap([0u8; 512]));
+            scope.defer_drop(a.load(Relaxed, scope));
+        }
+        let d = drops.wrap(());
+        scope.defer(move || drop(d));
+    }
+
+    /// Retires eight objects of 512 bytes and a closure through `handle`, and collects them.
+    fn retire_and_collect(handle: &LocalHandle) {
+        let drops = Drops::new();
+        handle.pin(|scope| unsafe { retire(scope, &drops) });
+        ::assert_reclaimed!(drops, 9, in: handle);
+    }
+
+    #[test]
+    fn reports_frees_to_the_pinned_handle() {
+        let collector = Collector::new();
+        let handle = collector.register();
+
+        let freed = Arc::new(Mutex::new(Vec::new()));
+        let f = freed.clone();
+        handle.set_reclaim_hook(move |bytes, objects| f.lock().unwrap().push((bytes, objects)));
+        retire_and_collect(&handle);
+
+        let reports = freed.lock().unwrap().len();
+        let (bytes, objects) = freed.lock().unwrap().iter().fold((0, 0), |(b, o), &(bytes, n)| {
+            assert!(n > 0);
+            (b + bytes, o + n)
+        });
+        // The collector's own garbage is counted as well.
+        assert!(bytes >= 8 * 512);
+        assert!(objects >= 9);
+
+        handle.clear_reclaim_hook();
+        retire_and_collect(&handle);
+        assert_eq!(freed.lock().unwrap().len(), reports);
+    }
+
+    /// Returns a hook that adds up what it's handed in `freed`.
+    fn recorder(freed: &Arc<Mutex<(usize, usize)>>) -> super::Hook {
+        let freed = freed.clone();
+        Arc::new(move |bytes, objects| {
+            let mut f = freed.lock().unwrap();
+            *f = (f.0 + bytes, f.1 + objects);
+        })
+    }
+
+    #[test]
+    fn reports_frees_by_reclaim() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let freed = Arc::new(Mutex::new((0, 0)));
+        let hook = recorder(&freed);
+        handle.set_reclaim_hook(move |bytes, objects| hook(bytes, objects));
+
+        let drops = Drops::new();
+        handle.pin(|scope| unsafe {
+            retire(scope, &drops);
+            scope.flush();
+        });
+        ::assert_reclaimed!(drops, 9, by: || handle.pin(global::reclaim));
+        assert!(freed.lock().unwrap().0 >= 8 * 512);
+    }
+
+    #[test]
+    fn reports_frees_while_pinned_raw() {
+        let mutator = Mutator::with_realm(Arc::new(Realm::new()));
+        let freed = Arc::new(Mutex::new((0, 0)));
+        mutator.set_reclaim_hook(Some(recorder(&freed)));
+
+        let drops = Drops::new();
+        mutator.pin(|scope| unsafe { retire(scope, &drops) });
+        let was_pinned = mutator.pin_raw();
+        ::assert_reclaimed!(drops, 9, by: || {
+            mutator.scope().flush();
+            mutator.repin();
+        });
+        mutator.unpin_raw(was_pinned);
+        assert!(freed.lock().unwrap().0 >= 8 * 512);
+    }
+}
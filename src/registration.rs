@@ -0,0 +1,240 @@
+//! Limits on registered mutators
+//!
+//! Every registered mutator holds back advancement of the global epoch while pinned, and has to be
+//! checked on each attempt to advance it. A registration leak, e.g. a thread pool that keeps
+//! spawning threads instead of reusing them, therefore slowly degrades garbage collection without
+//! any visible error. Embedders that know how many threads they run can set a limit with
+//! [`set_max_mutators`], so that such a leak is detected as soon as it exceeds the limit.
+//!
+//! Alongside the lock-free list of registries, each registered mutator is recorded here together
+//! with its thread, so that the error can tell who is registered. Registration is rare, so a lock
+//! is fine.
+//!
+//! [`set_max_mutators`]: fn.set_max_mutators.html
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+use global;
+use sys;
+
+/// The maximum number of registered mutators, or `usize::MAX` if there is no limit.
+static MAX_MUTATORS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The registered mutators, keyed by registration number.
+static MUTATORS: Mutex<BTreeMap<usize, MutatorInfo>> = Mutex::new(BTreeMap::new());
+
+/// Notified whenever a mutator is unregistered.
+static LEFT: Condvar = Condvar::new();
+
+/// Registration number of the next mutator.
+static NEXT_KEY: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// A registration made by `register` for the thread's mutator, which is about to be created.
+    static RESERVED: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// A registered mutator.
+#[derive(Clone, Debug)]
+pub struct MutatorInfo {
+    /// The thread the mutator was registered on.
+    pub thread: ThreadId,
+    /// The name of that thread, if it has one.
+    pub name: Option<String>,
+    /// When the mutator was registered.
+    pub registered_at: Instant,
+}
+
+impl fmt::Display for MutatorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "thread '{}' ({:?})", name, self.thread)?,
+            None => write!(f, "unnamed thread ({:?})", self.thread)?,
+        }
+        let ago = Instant::from(sys::now()).saturating_duration_since(self.registered_at);
+        write!(f, ", registered {:.1?} ago", ago)
+    }
+}
+
+/// The error returned when registering a mutator would exceed the limit set with
+/// [`set_max_mutators`].
+///
+/// [`set_max_mutators`]: fn.set_max_mutators.html
+#[derive(Clone, Debug)]
+pub struct TooManyMutators {
+    limit: usize,
+    mutators: Vec<MutatorInfo>,
+}
+
+impl TooManyMutators {
+    /// Returns the limit that would have been exceeded.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the mutators that were registered at the time of the failed registration.
+    pub fn mutators(&self) -> &[MutatorInfo] {
+        &self.mutators
+    }
+}
+
+impl fmt::Display for TooManyMutators {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot register more than {} mutators, currently registered:",
+            self.limit
+        )?;
+        for mutator in &self.mutators {
+            write!(f, "\n    {}", mutator)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for TooManyMutators {}
+
+/// Returns the maximum number of registered mutators, or `None` if there is no limit.
+pub fn max_mutators() -> Option<usize> {
+    match MAX_MUTATORS.load(Relaxed) {
+        usize::MAX => None,
+        limit => Some(limit),
+    }
+}
+
+/// Sets the maximum number of registered mutators, or removes the limit with `None`.
+///
+/// There is no limit by default. Once the limit is reached, [`register`] returns an error, and
+/// threads registering implicitly on their first pinning panic with the same diagnostics. Mutators
+/// registered before the limit was lowered stay registered.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_max_mutators(Some(64));
+/// assert_eq!(epoch::max_mutators(), Some(64));
+/// ```
+///
+/// [`register`]: fn.register.html
+pub fn set_max_mutators(limit: Option<usize>) {
+    MAX_MUTATORS.store(limit.unwrap_or(usize::MAX), Relaxed);
+}
+
+/// Returns the currently registered mutators.
+pub fn registered_mutators() -> Vec<MutatorInfo> {
+    lock().values().cloned().collect()
+}
+
+/// Returns the mutator with registration number `key`, if it's still registered.
+#[cfg(feature = "watchdog")]
+pub(crate) fn mutator_info(key: usize) -> Option<MutatorInfo> {
+    lock().get(&key).cloned()
+}
+
+/// Registers the current thread, unless it is registered already.
+///
+/// Threads are registered implicitly on their first pinning, which panics if the limit set with
+/// [`set_max_mutators`] would be exceeded. Registering explicitly, e.g. at the start of a worker
+/// thread, reports that as an error instead.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_max_mutators(Some(8));
+///
+/// if let Err(err) = epoch::register() {
+///     panic!("leaked a thread: {}", err);
+/// }
+/// ```
+///
+/// [`set_max_mutators`]: fn.set_max_mutators.html
+pub fn register() -> Result<(), TooManyMutators> {
+    let current = thread::current().id();
+    if lock().values().any(|m| m.thread == current) {
+        return Ok(());
+    }
+
+    let key = reserve(MAX_MUTATORS.load(Relaxed))?;
+    RESERVED.with(|r| r.set(Some(key)));
+    global::with_mutator(|_| ());
+    Ok(())
+}
+
+/// Registers a new mutator on the current thread, and returns its registration number.
+///
+/// If `register` has made a registration for the thread's mutator, that one is taken instead.
+pub(crate) fn enter() -> Result<usize, TooManyMutators> {
+    match RESERVED.try_with(Cell::take) {
+        Ok(Some(key)) => Ok(key),
+        _ => reserve(MAX_MUTATORS.load(Relaxed)),
+    }
+}
+
+/// Unregisters the mutator with registration number `key`.
+pub(crate) fn leave(key: usize) {
+    lock().remove(&key);
+    LEFT.notify_all();
+}
+
+/// Blocks until none of `threads` has a registered mutator.
+pub(crate) fn wait_for_threads(threads: &[ThreadId]) {
+    let mut mutators = lock();
+    while mutators.values().any(|m| threads.contains(&m.thread)) {
+        mutators = LEFT.wait(mutators).unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+/// Registers a new mutator on the current thread, unless there are `limit` mutators already.
+fn reserve(limit: usize) -> Result<usize, TooManyMutators> {
+    let mut mutators = lock();
+    if mutators.len() >= limit {
+        return Err(TooManyMutators {
+            limit,
+            mutators: mutators.values().cloned().collect(),
+        });
+    }
+
+    let thread = thread::current();
+    let key = NEXT_KEY.fetch_add(1, Relaxed);
+    mutators.insert(key, MutatorInfo {
+        thread: thread.id(),
+        name: thread.name().map(String::from),
+        registered_at: sys::now().into(),
+    });
+    Ok(key)
+}
+
+fn lock() -> ::std::sync::MutexGuard<'static, BTreeMap<usize, MutatorInfo>> {
+    MUTATORS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_lists_mutators() {
+        register().unwrap();
+        let current = thread::current().id();
+
+        let err = reserve(0).unwrap_err();
+        assert_eq!(err.limit(), 0);
+        assert!(err.mutators().iter().any(|m| m.thread == current));
+        assert!(err.to_string().starts_with("cannot register more than 0 mutators"));
+
+        let key = reserve(usize::MAX).unwrap();
+        leave(key);
+    }
+}
@@ -0,0 +1,349 @@
+//! References bound to a pinning
+//!
+//! A container built on this crate usually hands out references to its elements that are only
+//! valid while the caller is pinned, e.g. `get(&self, key, scope) -> Option<&'scope V>`. Returning
+//! a plain reference loses the scope, so the caller has to keep passing it around alongside, and
+//! a container that wants its own guard type ends up exposing [`Scope`] in its API anyway.
+//!
+//! A [`ScopeRef`] keeps the two together. It dereferences to the element, provides the scope
+//! through [`AsScope`], and can be narrowed down to a part of the element with [`ScopeRef::map`],
+//! like the guards of `std::cell::Ref` or `std::sync::MutexGuard`. A container can wrap it in a
+//! newtype of its own and later swap the reclamation scheme underneath without changing its API.
+//!
+//! Iterators and cursors over a container hold on to more than a reference. A [`ScopeBound`]
+//! keeps any such value together with its scope, so that a function can return it without
+//! spelling out how its type borrows the scope, and the caller can still reach the scope.
+//! [`Scope::run`] binds whatever a closure returns to the scope this way.
+//!
+//! [`Scope`]: struct.Scope.html
+//! [`Scope::run`]: struct.Scope.html#method.run
+//! [`ScopeRef`]: struct.ScopeRef.html
+//! [`ScopeBound`]: struct.ScopeBound.html
+//! [`AsScope`]: trait.AsScope.html
+//! [`ScopeRef::map`]: struct.ScopeRef.html#method.map
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use mutator::{AsScope, Scope};
+
+/// A reference that is valid for as long as the scope it was obtained in.
+///
+/// The functions of this type are associated functions rather than methods, so that they don't
+/// shadow methods of `T`, just like those of `std::cell::Ref`.
+///
+/// # Examples
+///
+/// A map exposing its own guard type:
+///
+/// ```
+/// use crossbeam_epoch::prelude::*;
+/// use crossbeam_epoch as epoch;
+/// use std::sync::atomic::Ordering::Acquire;
+///
+/// struct Entry {
+///     value: String,
+/// }
+///
+/// /// A value of the map, valid while the map's caller is pinned.
+/// pub struct ValueRef<'scope>(ScopeRef<'scope, str>);
+///
+/// fn get<'scope>(slot: &Atomic<Entry>, scope: &'scope Scope) -> Option<ValueRef<'scope>> {
+///     let entry = unsafe { slot.load(Acquire, scope).as_ref()? };
+///     let entry = ScopeRef::new(scope, entry);
+///     Some(ValueRef(ScopeRef::map(entry, |e| &e.value[..])))
+/// }
+///
+/// let slot = Atomic::new(Entry { value: "one".to_string() });
+/// epoch::pin(|scope| {
+///     let value = get(&slot, scope).unwrap();
+///     assert_eq!(&*value.0, "one");
+///     // The scope stays at hand, e.g. to look up more entries.
+///     assert!(get(&slot, ScopeRef::scope(&value.0)).is_some());
+/// });
+/// # epoch::pin(|scope| unsafe { scope.defer_drop(slot.load(Acquire, scope)) });
+/// ```
+pub struct ScopeRef<'scope, T: 'scope + ?Sized> {
+    scope: &'scope Scope,
+    value: &'scope T,
+}
+
+impl<'scope, T: ?Sized> ScopeRef<'scope, T> {
+    /// Returns a reference to `value` bound to `scope`, which it was obtained in.
+    #[inline]
+    pub fn new(scope: &'scope Scope, value: &'scope T) -> Self {
+        ScopeRef { scope, value }
+    }
+
+    /// Returns the scope the reference was obtained in.
+    #[inline]
+    pub fn scope(this: &Self) -> &'scope Scope {
+        this.scope
+    }
+
+    /// Returns the reference itself, with the lifetime of the scope.
+    #[inline]
+    pub fn get(this: &Self) -> &'scope T {
+        this.value
+    }
+
+    /// Returns a reference to a part of the referenced value, bound to the same scope.
+    #[inline]
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> ScopeRef<'scope, U>
+    where
+        F: FnOnce(&'scope T) -> &'scope U,
+    {
+        ScopeRef::new(this.scope, f(this.value))
+    }
+
+    /// Returns a reference to a part of the referenced value, bound to the same scope, if `f`
+    /// finds one.
+    #[inline]
+    pub fn filter_map<U: ?Sized, F>(this: Self, f: F) -> Option<ScopeRef<'scope, U>>
+    where
+        F: FnOnce(&'scope T) -> Option<&'scope U>,
+    {
+        f(this.value).map(|value| ScopeRef::new(this.scope, value))
+    }
+}
+
+impl<'scope, T: ?Sized> Clone for ScopeRef<'scope, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'scope, T: ?Sized> Copy for ScopeRef<'scope, T> {}
+
+impl<'scope, T: ?Sized> Deref for ScopeRef<'scope, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'scope, T: ?Sized> AsScope<'scope> for ScopeRef<'scope, T> {
+    #[inline]
+    fn as_scope(&self) -> &'scope Scope {
+        self.scope
+    }
+}
+
+impl<'scope, T: ?Sized + fmt::Debug> fmt::Debug for ScopeRef<'scope, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.value, f)
+    }
+}
+
+impl<'scope, T: ?Sized + fmt::Display> fmt::Display for ScopeRef<'scope, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.value, f)
+    }
+}
+
+/// A value that is valid for as long as the scope it was obtained in, e.g. an iterator or a cursor
+/// over a container.
+///
+/// The value is reached through `Deref` and `DerefMut`, and a bound iterator is an iterator itself.
+/// Like those of [`ScopeRef`], the functions of this type are associated functions rather than
+/// methods.
+///
+/// # Examples
+///
+/// A stack returning an iterator over its elements:
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned, Scope, ScopeBound};
+/// use std::sync::atomic::Ordering::{Acquire, Release};
+///
+/// struct Node {
+///     value: u32,
+///     next: Atomic<Node>,
+/// }
+///
+/// struct Stack {
+///     head: Atomic<Node>,
+/// }
+///
+/// impl Stack {
+///     fn iter<'scope>(
+///         &'scope self,
+///         scope: &'scope Scope,
+///     ) -> ScopeBound<'scope, impl Iterator<Item = &'scope u32>> {
+///         let mut node = self.head.load(Acquire, scope);
+///         let values = std::iter::from_fn(move || {
+///             let n = unsafe { node.as_ref()? };
+///             node = n.next.load(Acquire, scope);
+///             Some(&n.value)
+///         });
+///         ScopeBound::new(scope, values)
+///     }
+/// }
+///
+/// let stack = Stack { head: Atomic::null() };
+/// epoch::pin(|scope| {
+///     for value in 1..4 {
+///         let next = stack.head.load(Acquire, scope);
+///         let node = Owned::new(Node { value, next: Atomic::from_ptr(next) });
+///         stack.head.store_owned(node, Release);
+///     }
+///
+///     let mut values = stack.iter(scope);
+///     assert_eq!(values.next(), Some(&3));
+///     // The scope stays at hand, e.g. to start over.
+///     let again = stack.iter(ScopeBound::scope(&values));
+///     assert_eq!(values.chain(again).count(), 5);
+/// });
+/// # epoch::pin(|scope| unsafe {
+/// #     let mut node = stack.head.load(Acquire, scope);
+/// #     while let Some(n) = node.as_ref() {
+/// #         let next = n.next.load(Acquire, scope);
+/// #         scope.defer_drop(node);
+/// #         node = next;
+/// #     }
+/// # });
+/// ```
+///
+/// [`ScopeRef`]: struct.ScopeRef.html
+pub struct ScopeBound<'scope, T> {
+    scope: &'scope Scope,
+    value: T,
+}
+
+impl<'scope, T> ScopeBound<'scope, T> {
+    /// Returns `value` bound to `scope`, which it was obtained in.
+    #[inline]
+    pub fn new(scope: &'scope Scope, value: T) -> Self {
+        ScopeBound { scope, value }
+    }
+
+    /// Returns the scope the value was obtained in.
+    #[inline]
+    pub fn scope(this: &Self) -> &'scope Scope {
+        this.scope
+    }
+
+    /// Returns the value itself.
+    #[inline]
+    pub fn into_inner(this: Self) -> T {
+        this.value
+    }
+
+    /// Returns the value returned by `f` for the bound value and its scope, bound to the same
+    /// scope.
+    #[inline]
+    pub fn map<U, F>(this: Self, f: F) -> ScopeBound<'scope, U>
+    where
+        F: FnOnce(T, &'scope Scope) -> U,
+    {
+        ScopeBound::new(this.scope, f(this.value, this.scope))
+    }
+}
+
+impl<'scope, T: Clone> Clone for ScopeBound<'scope, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        ScopeBound::new(self.scope, self.value.clone())
+    }
+}
+
+impl<'scope, T> Deref for ScopeBound<'scope, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<'scope, T> DerefMut for ScopeBound<'scope, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<'scope, T: Iterator> Iterator for ScopeBound<'scope, T> {
+    type Item = T::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<T::Item> {
+        self.value.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.value.size_hint()
+    }
+}
+
+impl<'scope, T: DoubleEndedIterator> DoubleEndedIterator for ScopeBound<'scope, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<T::Item> {
+        self.value.next_back()
+    }
+}
+
+impl<'scope, T: ExactSizeIterator> ExactSizeIterator for ScopeBound<'scope, T> {}
+
+impl<'scope, T> AsScope<'scope> for ScopeBound<'scope, T> {
+    #[inline]
+    fn as_scope(&self) -> &'scope Scope {
+        self.scope
+    }
+}
+
+impl<'scope, T: fmt::Debug> fmt::Debug for ScopeBound<'scope, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.value, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::{Acquire, Relaxed};
+
+    use {pin, unprotected, Atomic};
+    use super::*;
+
+    /// Returns the values of `slots`.
+    fn values<'scope>(
+        slots: &'scope [Atomic<u32>],
+        scope: &'scope Scope,
+    ) -> impl Iterator<Item = &'scope u32> {
+        slots.iter().filter_map(move |a| unsafe { a.load(Acquire, scope).as_ref() })
+    }
+
+    #[test]
+    fn closures_return_bound_values() {
+        let slots = (0..4).map(Atomic::new).collect::<Vec<_>>();
+
+        pin(|scope| {
+            // Without `run`, the closure's result couldn't borrow its argument.
+            let evens = ScopeBound::map(scope.run(|scope| values(&slots, scope)), |values, _| {
+                values.filter(|&&v| v % 2 == 0)
+            });
+            assert!(ptr_eq(ScopeBound::scope(&evens), scope));
+            assert_eq!(evens.collect::<Vec<_>>(), [&0, &2]);
+
+            let mut values = scope.run(|scope| values(&slots, scope));
+            assert_eq!(values.next(), Some(&0));
+            assert_eq!(ScopeBound::into_inner(values).count(), 3);
+        });
+
+        unsafe {
+            unprotected(|scope| {
+                for a in &slots {
+                    drop(a.load(Relaxed, scope).into_owned());
+                }
+            })
+        }
+    }
+
+    fn ptr_eq(a: &Scope, b: &Scope) -> bool {
+        ::std::ptr::eq(a, b)
+    }
+}
@@ -0,0 +1,169 @@
+//! Scoped threads
+//!
+//! Tests and batch jobs often spawn a few threads that work on a data structure, and then want to
+//! make sure that all the garbage they produced has been destroyed, e.g. to check for leaks or to
+//! release memory before the next phase. [`scope`] packages that lifecycle: it spawns scoped
+//! threads, and before returning, joins them and drains all garbage deferred in the meantime.
+//!
+//! Joining a thread doesn't wait for the destructors of its thread-locals, so a joined thread may
+//! still be registered, with its mutator holding on to a bag of garbage. [`scope`] therefore also
+//! waits until the mutators of all threads it spawned are unregistered.
+//!
+//! [`scope`]: fn.scope.html
+
+use std::fmt;
+use std::sync::Mutex;
+use std::thread::{self, ScopedJoinHandle, ThreadId};
+
+use global::{self, pin};
+use registration;
+
+/// A scope for spawning threads whose garbage is drained at its end.
+///
+/// Created by [`scope`].
+///
+/// [`scope`]: fn.scope.html
+pub struct ThreadScope<'scope, 'env: 'scope> {
+    inner: &'scope thread::Scope<'scope, 'env>,
+    /// The threads spawned in the scope.
+    threads: Mutex<Vec<ThreadId>>,
+}
+
+impl<'scope, 'env> ThreadScope<'scope, 'env> {
+    /// Spawns a scoped thread running `f`.
+    ///
+    /// The thread registers with the garbage collector on its first pinning, as usual. When `f`
+    /// returns, the local garbage of the thread is flushed into the global queue, and the scope
+    /// waits for its mutator to be unregistered before draining garbage.
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let handle = self.inner.spawn(move || {
+            let result = f();
+            pin(|scope| scope.flush());
+            result
+        });
+        lock(&self.threads).push(handle.thread().id());
+        handle
+    }
+}
+
+impl<'scope, 'env> fmt::Debug for ThreadScope<'scope, 'env> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadScope").finish()
+    }
+}
+
+/// Creates a scope for spawning threads, and executes `f` with it.
+///
+/// Once `f` returns, all threads spawned in the scope are joined and their mutators unregistered,
+/// and then all garbage deferred so far, on any thread, is destroyed before returning. Draining
+/// waits for the global epoch to advance, so it doesn't return while another thread stays pinned.
+///
+/// A spawned thread that leaks a mutator, e.g. a forgotten [`LocalHandle`], makes this function
+/// wait forever.
+///
+/// # Panics
+///
+/// Panics if any of the threads that weren't joined manually panicked.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::new(0);
+///
+/// epoch::scope(|s| {
+///     for i in 1..=4 {
+///         let a = &a;
+///         s.spawn(move || epoch::pin(|scope| unsafe {
+///             let old = a.swap(Owned::new(i).into_ptr(scope), SeqCst, scope);
+///             scope.defer_drop(old);
+///         }));
+///     }
+/// });
+/// // All replaced values have been dropped by now.
+/// # epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+/// ```
+///
+/// [`LocalHandle`]: struct.LocalHandle.html
+pub fn scope<'env, F, R>(f: F) -> R
+where
+    F: for<'scope> FnOnce(&ThreadScope<'scope, 'env>) -> R,
+{
+    let (result, threads) = thread::scope(|inner| {
+        let scope = ThreadScope {
+            inner,
+            threads: Mutex::new(Vec::new()),
+        };
+        let result = f(&scope);
+        (result, scope.threads.into_inner().unwrap_or_else(|e| e.into_inner()))
+    });
+
+    // The mutators of the threads push their bags into the global queue when unregistering.
+    registration::wait_for_threads(&threads);
+
+    global::drain();
+    result
+}
+
+fn lock(threads: &Mutex<Vec<ThreadId>>) -> ::std::sync::MutexGuard<'_, Vec<ThreadId>> {
+    threads.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports, dead_code))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use registration::registered_mutators;
+    use Owned;
+    use super::*;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn garbage_drained_on_exit() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        scope(|s| {
+            for _ in 0..4 {
+                let dropped = dropped.clone();
+                s.spawn(move || for _ in 0..100 {
+                    pin(|scope| unsafe {
+                        let p = Owned::new(Counted(dropped.clone())).into_ptr(scope);
+                        scope.defer_drop(p);
+                    });
+                });
+            }
+        });
+
+        assert_eq!(dropped.load(SeqCst), 400);
+    }
+
+    #[test]
+    fn mutators_unregistered_on_exit() {
+        let threads = scope(|s| {
+            (0..4)
+                .map(|_| s.spawn(|| pin(|_| ())).thread().id())
+                .collect::<Vec<_>>()
+        });
+
+        for mutator in registered_mutators() {
+            assert!(!threads.contains(&mutator.thread));
+        }
+    }
+}
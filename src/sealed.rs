@@ -0,0 +1,153 @@
+//! Sealed scopes for long-lived readers
+//!
+//! A pinned scope keeps the global epoch from advancing by more than one step, so a reader that
+//! stays pinned for a long time, e.g. while iterating over a large structure or serving a range
+//! scan, blocks reclamation of everything retired in the meantime.
+//!
+//! A [`SealedScope`] instead records the epoch of the scope it was sealed from, and lets that
+//! scope be unpinned. It only holds on to objects retired in that epoch or earlier, and the global
+//! epoch keeps advancing past it, so objects retired later are reclaimed as usual. This suits
+//! multi-version structures, where a reader of an old version only follows links to objects that
+//! were superseded, i.e. retired, by the time the version was read.
+//!
+//! [`SealedScope`]: struct.SealedScope.html
+
+use std::fmt;
+use std::sync::Arc;
+
+use atomic::{Atomic, Ptr};
+use raw::Pointable;
+use global::Realm;
+use mutator::Scope;
+use primitive::atomic::Ordering;
+
+/// A read-only scope that holds on to objects retired up to the epoch it was sealed in.
+///
+/// Created by [`Scope::seal`]. The scope isn't pinned, so it doesn't keep the global epoch from
+/// advancing. The garbage it holds on to is destroyed once the last sealed scope of the same or a
+/// later epoch is dropped.
+///
+/// [`Scope::seal`]: struct.Scope.html#method.seal
+pub struct SealedScope {
+    realm: Arc<Realm>,
+    epoch: usize,
+}
+
+impl SealedScope {
+    /// Returns the epoch the scope was sealed in.
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// Loads a [`Ptr`] from `atomic`, which may be dereferenced for as long as the sealed scope
+    /// lives.
+    ///
+    /// # Safety
+    ///
+    /// Objects retired after the scope was sealed aren't held on to, and may be destroyed at any
+    /// time. The caller must make sure that the object loaded was either retired by the time the
+    /// scope was sealed, or is kept alive by other means.
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub unsafe fn load<'s, T, const HIGH_TAG: bool>(
+        &'s self,
+        atomic: &'s Atomic<T, HIGH_TAG>,
+        ord: Ordering,
+    ) -> Ptr<'s, T, HIGH_TAG>
+    where
+        T: ?Sized + Pointable,
+    {
+        Ptr::from_data(atomic.data().load(ord))
+    }
+}
+
+impl Drop for SealedScope {
+    fn drop(&mut self) {
+        self.realm.unseal(self.epoch);
+    }
+}
+
+impl fmt::Debug for SealedScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SealedScope")
+            .field("epoch", &self.epoch)
+            .finish()
+    }
+}
+
+impl Scope {
+    /// Seals the scope, returning a [`SealedScope`] that holds on to the objects retired up to
+    /// the epoch the scope is pinned in even after it gets unpinned.
+    ///
+    /// Objects loaded in the scope may be dereferenced through the sealed scope as long as they
+    /// were retired before sealing; see [`SealedScope::load`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let version = Atomic::new(1);
+    ///
+    /// // A writer replaces the version a reader is looking at.
+    /// let (sealed, old) = epoch::pin(|scope| unsafe {
+    ///     let old = version.swap(Owned::new(2).into_ptr(scope), SeqCst, scope);
+    ///     scope.defer_drop(old);
+    ///     (scope.seal(), old.as_raw())
+    /// });
+    ///
+    /// // The epoch advances, but the old version is kept alive for the reader.
+    /// for _ in 0..16 {
+    ///     epoch::pin(|scope| scope.flush());
+    /// }
+    /// assert_eq!(unsafe { *old }, 1);
+    /// drop(sealed);
+    /// # epoch::pin(|scope| unsafe { scope.defer_drop(version.load(SeqCst, scope)) });
+    /// ```
+    ///
+    /// [`SealedScope`]: struct.SealedScope.html
+    /// [`SealedScope::load`]: struct.SealedScope.html#method.load
+    pub fn seal(&self) -> SealedScope {
+        let realm = self.realm_arc().clone();
+        let local = self.local_epoch();
+        let epoch = if local.is_null() {
+            realm.epoch.load(Ordering::Relaxed)
+        } else {
+            unsafe { (*local).get_state().1 }
+        };
+        realm.seal(epoch);
+        SealedScope { realm, epoch }
+    }
+}
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use {Collector, Owned};
+    use testkit::Drops;
+
+    #[test]
+    fn holds_only_earlier_garbage() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let drops = Drops::new();
+
+        handle.pin(|scope| unsafe { scope.defer_drop(Owned::new(drops.wrap(())).into_ptr(scope)) });
+        let sealed = handle.pin(|scope| {
+            scope.flush();
+            scope.seal()
+        });
+
+        // The epoch keeps advancing, and garbage retired later doesn't wait for the sealed scope.
+        for _ in 0..16 {
+            handle.pin(|scope| scope.flush());
+        }
+        assert!(collector.stats().epoch.wrapping_sub(sealed.epoch()) > 4);
+        handle.pin(|scope| unsafe { scope.defer_drop(Owned::new(drops.wrap(())).into_ptr(scope)) });
+        ::assert_reclaimed!(drops, 1, in: handle);
+        assert_eq!(drops.count(), 1);
+
+        drop(sealed);
+        ::assert_reclaimed!(drops, 2, in: handle);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+
+use atomic::{Atomic, Owned};
+use epoch_safe::EpochSafe;
+use global::{pin, unprotected};
+
+/// An epoch-protected pointer to a read-mostly value, paired with a sequence counter.
+///
+/// This is meant for extremely hot slots that rarely change, e.g. a pointer to the current
+/// configuration. Every store bumps the counter, so a [`SeqReader`] can keep a copy of the value
+/// and validate it with a single load of the counter. Only when the value has changed does the
+/// reader pin the current thread to fetch the new one, which saves the fence that comes with
+/// pinning in the common case.
+///
+/// [`SeqReader`]: struct.SeqReader.html
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::AtomicSeq;
+///
+/// let config = AtomicSeq::new(String::from("fast"));
+/// let mut reader = config.reader();
+/// assert_eq!(reader.get(), "fast");
+///
+/// config.store(String::from("safe"));
+/// assert_eq!(reader.get(), "safe");
+/// ```
+pub struct AtomicSeq<T> {
+    /// Number of stores so far.
+    seq: AtomicUsize,
+    value: Atomic<T>,
+}
+
+impl<T: Send + Sync + EpochSafe + 'static> AtomicSeq<T> {
+    /// Returns a new slot holding `value`.
+    pub fn new(value: T) -> Self {
+        AtomicSeq {
+            seq: AtomicUsize::new(0),
+            value: Atomic::new(value),
+        }
+    }
+
+    /// Returns the number of stores into the slot so far.
+    pub fn seq(&self) -> usize {
+        self.seq.load(Acquire)
+    }
+
+    /// Replaces the value with `value`, and defers destruction of the previous one.
+    pub fn store(&self, value: T) {
+        pin(|scope| unsafe {
+            let old = self.value.swap(Owned::new(value).into_ptr(scope), SeqCst, scope);
+            // Readers that see the new count must see the new value.
+            self.seq.fetch_add(1, Release);
+            scope.defer_drop(old);
+        })
+    }
+
+    /// Calls `f` with the current value, pinning the current thread.
+    pub fn with<F: FnOnce(&T) -> R, R>(&self, f: F) -> R {
+        pin(|scope| f(unsafe { self.value.load(Acquire, scope).deref() }))
+    }
+
+    /// Returns a reader holding a copy of the current value.
+    pub fn reader(&self) -> SeqReader<'_, T>
+    where
+        T: Clone,
+    {
+        let seq = self.seq();
+        SeqReader {
+            slot: self,
+            seq,
+            value: self.with(T::clone),
+        }
+    }
+}
+
+impl<T> Drop for AtomicSeq<T> {
+    fn drop(&mut self) {
+        unsafe {
+            unprotected(|scope| {
+                drop(Box::from_raw(self.value.load(Relaxed, scope).as_raw() as *mut T));
+            });
+        }
+    }
+}
+
+impl<T> fmt::Debug for AtomicSeq<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AtomicSeq")
+            .field("seq", &self.seq.load(Relaxed))
+            .finish()
+    }
+}
+
+/// A copy of the value of an [`AtomicSeq`], refreshed whenever it changes.
+///
+/// Created by [`AtomicSeq::reader`].
+///
+/// [`AtomicSeq`]: struct.AtomicSeq.html
+/// [`AtomicSeq::reader`]: struct.AtomicSeq.html#method.reader
+pub struct SeqReader<'a, T: 'a> {
+    slot: &'a AtomicSeq<T>,
+    /// The count of stores the copy is at least as recent as.
+    seq: usize,
+    value: T,
+}
+
+impl<'a, T: Clone + Send + Sync + EpochSafe + 'static> SeqReader<'a, T> {
+    /// Returns the current value.
+    ///
+    /// If the value hasn't changed since the last call, this is just a load of the counter.
+    pub fn get(&mut self) -> &T {
+        let seq = self.slot.seq();
+        if seq != self.seq {
+            self.value = self.slot.with(T::clone);
+            self.seq = seq;
+        }
+        &self.value
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for SeqReader<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SeqReader")
+            .field("seq", &self.seq)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn reader_sees_latest_store() {
+        let slot = Arc::new(AtomicSeq::new(0usize));
+
+        let readers = (0..4)
+            .map(|_| {
+                let slot = slot.clone();
+                thread::spawn(move || {
+                    let mut reader = slot.reader();
+                    let mut last = 0;
+                    while last < 1000 {
+                        let value = *reader.get();
+                        assert!(value >= last);
+                        last = value;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..=1000 {
+            slot.store(i);
+        }
+        for r in readers {
+            r.join().unwrap();
+        }
+        assert_eq!(slot.seq(), 1000);
+    }
+}
@@ -0,0 +1,555 @@
+//! Shared-memory mode (experimental)
+//!
+//! Multi-process programs, e.g. databases keeping an index in shared memory, can't use the global
+//! collector: its epoch and registries live in the memory of one process, and a shared-memory
+//! segment is generally mapped at a different address in each process. This module provides a
+//! separate, much simpler collector whose whole state lives in a [`Segment`] of memory shared by
+//! all processes.
+//!
+//! The segment begins with a header holding the epoch and one slot per attached process, where the
+//! process announces the epoch it is pinned in. Pointers stored in the segment are
+//! [`AtomicOffset`]s, i.e. offsets from the base of the segment, which each process resolves
+//! against its own mapping.
+//!
+//! Garbage can't be represented by closures, as they only make sense in the process that created
+//! them. Instead, retired objects are identified by their offsets, and each [`Process`] keeps its
+//! own retired offsets and hands them to a deallocation function once they have expired. The crate
+//! doesn't map or allocate shared memory itself; both are left to the caller.
+//!
+//! Limitations:
+//!
+//! - At most [`MAX_PROCESSES`] processes can be attached to a segment at the same time.
+//! - A process that crashes while pinned stops the epoch of the segment from advancing, until its
+//!   slot is released with [`Segment::release`].
+//! - Objects retired by a process that is still pending when the process detaches are leaked.
+//!
+//! This module is only available with the `shm` feature enabled.
+//!
+//! [`Segment`]: struct.Segment.html
+//! [`AtomicOffset`]: struct.AtomicOffset.html
+//! [`Process`]: struct.Process.html
+//! [`MAX_PROCESSES`]: constant.MAX_PROCESSES.html
+//! [`Segment::release`]: struct.Segment.html#method.release
+
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::atomic::Ordering::{self, Acquire, Relaxed, Release, SeqCst};
+
+use epoch::blocks_advance;
+use global::is_expired;
+
+/// The maximum number of processes attached to a segment at the same time.
+pub const MAX_PROCESSES: usize = 64;
+
+/// Marks an initialized segment header.
+const MAGIC: usize = 0x4550_4f43;
+
+/// Number of pinnings after which a process collects its garbage.
+const PINS_BETWEEN_COLLECT: usize = 128;
+
+/// Slot state of a process that is attached but not pinned.
+///
+/// Pinned processes store their epoch with the lowest bit set, and free slots hold zero.
+const ATTACHED: usize = 2;
+
+/// The header at the beginning of a segment.
+#[repr(C)]
+struct Header {
+    magic: AtomicUsize,
+    epoch: AtomicUsize,
+    slots: [AtomicUsize; MAX_PROCESSES],
+}
+
+/// An error in setting up a segment or attaching to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShmError {
+    /// The segment is too small or misaligned for the header.
+    InvalidSegment,
+    /// The segment hasn't been initialized with [`Segment::create`].
+    ///
+    /// [`Segment::create`]: struct.Segment.html#method.create
+    NotInitialized,
+    /// All process slots are taken.
+    TooManyProcesses,
+}
+
+impl fmt::Display for ShmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ShmError::InvalidSegment => "shared-memory segment is too small or misaligned",
+            ShmError::NotInitialized => "shared-memory segment is not initialized",
+            ShmError::TooManyProcesses => "too many processes attached to shared-memory segment",
+        })
+    }
+}
+
+impl Error for ShmError {}
+
+/// A region of memory shared between processes, as mapped into the current process.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::shm::{AtomicOffset, Segment};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// // A real program would map shared memory instead.
+/// let mut memory = vec![0usize; 1024];
+/// let len = memory.len() * std::mem::size_of::<usize>();
+/// let segment = unsafe { Segment::create(memory.as_mut_ptr() as *mut u8, len).unwrap() };
+///
+/// // Carve a slot and an object out of the segment.
+/// let root = Segment::data_offset();
+/// let object = root + std::mem::size_of::<usize>();
+/// unsafe { *(segment.base().add(object) as *mut u64) = 7 };
+/// let slot = unsafe { &*(segment.base().add(root) as *const AtomicOffset<u64>) };
+///
+/// let process = segment.attach(|_offset| { /* return the object to the allocator */ }).unwrap();
+/// process.pin(|scope| {
+///     slot.store(object, SeqCst);
+///     assert_eq!(unsafe { *slot.load(SeqCst, scope).deref() }, 7);
+///
+///     let old = slot.swap(0, SeqCst, scope);
+///     unsafe { scope.retire(old.offset()) };
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Segment {
+    base: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    /// Initializes the header of a segment at `base` spanning `len` bytes, and returns it.
+    ///
+    /// # Safety
+    ///
+    /// The memory must stay mapped and valid for reads and writes for as long as the segment is
+    /// used, and no other process may be using the segment yet.
+    pub unsafe fn create(base: *mut u8, len: usize) -> Result<Self, ShmError> {
+        let segment = Segment::new(base, len)?;
+        let header = segment.header();
+        header.epoch.store(0, Relaxed);
+        for slot in header.slots.iter() {
+            slot.store(0, Relaxed);
+        }
+        header.magic.store(MAGIC, Release);
+        Ok(segment)
+    }
+
+    /// Returns a segment at `base` spanning `len` bytes, that has been initialized by another
+    /// process.
+    ///
+    /// # Safety
+    ///
+    /// The memory must stay mapped and valid for reads and writes for as long as the segment is
+    /// used.
+    pub unsafe fn open(base: *mut u8, len: usize) -> Result<Self, ShmError> {
+        let segment = Segment::new(base, len)?;
+        if segment.header().magic.load(Acquire) != MAGIC {
+            return Err(ShmError::NotInitialized);
+        }
+        Ok(segment)
+    }
+
+    fn new(base: *mut u8, len: usize) -> Result<Self, ShmError> {
+        let aligned = (base as usize).is_multiple_of(mem::align_of::<Header>());
+        if len < mem::size_of::<Header>() || !aligned {
+            return Err(ShmError::InvalidSegment);
+        }
+        Ok(Segment { base, len })
+    }
+
+    /// Returns the offset of the first byte after the header, which is free for the caller to use.
+    pub fn data_offset() -> usize {
+        mem::size_of::<Header>()
+    }
+
+    /// Returns the address the segment is mapped at in the current process.
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// Returns the length of the segment in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the segment is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.base as *const Header) }
+    }
+
+    /// Attaches the current process to the segment.
+    ///
+    /// Retired offsets are passed to `free` once no process can be accessing them anymore.
+    pub fn attach<F: FnMut(usize)>(&self, free: F) -> Result<Process<'_, F>, ShmError> {
+        for (index, slot) in self.header().slots.iter().enumerate() {
+            if slot.compare_exchange(0, ATTACHED, SeqCst, Relaxed).is_ok() {
+                return Ok(Process {
+                    segment: self,
+                    slot: index,
+                    free: RefCell::new(free),
+                    garbage: RefCell::new(Vec::new()),
+                    is_pinned: Cell::new(false),
+                    pin_count: Cell::new(0),
+                });
+            }
+        }
+        Err(ShmError::TooManyProcesses)
+    }
+
+    /// Releases the slot of a process that has crashed.
+    ///
+    /// # Safety
+    ///
+    /// The process that attached with the slot must not be running anymore.
+    pub unsafe fn release(&self, slot: usize) {
+        self.header().slots[slot].store(0, Release);
+    }
+
+    /// Attempts to advance the epoch of the segment, and returns the current epoch.
+    fn try_advance(&self) -> usize {
+        let header = self.header();
+        let epoch = header.epoch.load(Relaxed);
+        atomic::fence(SeqCst);
+
+        for slot in header.slots.iter() {
+            let state = slot.load(Relaxed);
+            if blocks_advance((state & 1 == 1, state & !1), epoch) {
+                return epoch;
+            }
+        }
+        atomic::fence(Acquire);
+
+        let epoch_new = epoch.wrapping_add(2);
+        match header.epoch.compare_exchange(epoch, epoch_new, Release, Relaxed) {
+            Ok(_) => epoch_new,
+            Err(current) => current,
+        }
+    }
+}
+
+/// The current process, attached to a [`Segment`].
+///
+/// [`Segment`]: struct.Segment.html
+pub struct Process<'a, F: FnMut(usize)> {
+    segment: &'a Segment,
+    /// Index of the slot in the header.
+    slot: usize,
+    free: RefCell<F>,
+    /// Retired offsets, each with the epoch it was retired in.
+    garbage: RefCell<Vec<(usize, usize)>>,
+    is_pinned: Cell<bool>,
+    pin_count: Cell<usize>,
+}
+
+impl<'a, F: FnMut(usize)> Process<'a, F> {
+    /// Returns the index of the slot the process is attached with.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Pins the process, and executes `f` with a scope for accessing the segment.
+    ///
+    /// Pinning is reentrant.
+    pub fn pin<G, R>(&self, f: G) -> R
+    where
+        G: FnOnce(&ShmScope<'_, 'a, F>) -> R,
+    {
+        let scope = &ShmScope { process: self };
+        if self.is_pinned.get() {
+            return f(scope);
+        }
+
+        let header = self.segment.header();
+        let slot = &header.slots[self.slot];
+        slot.store(header.epoch.load(Relaxed) | 1, Relaxed);
+        atomic::fence(SeqCst);
+        self.is_pinned.set(true);
+
+        let count = self.pin_count.get();
+        self.pin_count.set(count.wrapping_add(1));
+        if count.is_multiple_of(PINS_BETWEEN_COLLECT) {
+            self.collect();
+        }
+
+        defer! {{
+            slot.store(ATTACHED, Release);
+            self.is_pinned.set(false);
+        }}
+        f(scope)
+    }
+
+    /// Tries advancing the epoch of the segment, and frees all retired offsets that have expired.
+    pub fn collect(&self) {
+        let epoch = self.segment.try_advance();
+        let expired = {
+            let mut garbage = self.garbage.borrow_mut();
+            let at = garbage
+                .iter()
+                .position(|&(e, _)| !is_expired(e, epoch))
+                .unwrap_or(garbage.len());
+            let pending = garbage.split_off(at);
+            mem::replace(&mut *garbage, pending)
+        };
+
+        let mut free = self.free.borrow_mut();
+        for (_, offset) in expired {
+            (*free)(offset);
+        }
+    }
+
+    /// Returns the number of retired offsets that haven't been freed yet.
+    pub fn pending(&self) -> usize {
+        self.garbage.borrow().len()
+    }
+}
+
+impl<'a, F: FnMut(usize)> Drop for Process<'a, F> {
+    fn drop(&mut self) {
+        self.collect();
+        self.segment.header().slots[self.slot].store(0, Release);
+    }
+}
+
+impl<'a, F: FnMut(usize)> fmt::Debug for Process<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Process")
+            .field("slot", &self.slot)
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+/// A witness that the current process is pinned in a segment.
+pub struct ShmScope<'p, 'a: 'p, F: FnMut(usize) + 'p> {
+    process: &'p Process<'a, F>,
+}
+
+impl<'p, 'a, F: FnMut(usize)> ShmScope<'p, 'a, F> {
+    /// Returns the segment.
+    pub fn segment(&self) -> &'a Segment {
+        self.process.segment
+    }
+
+    /// Retires the object at `offset`, which is freed once no process can be accessing it.
+    ///
+    /// # Safety
+    ///
+    /// The object must not be reachable from the segment anymore, and it must not be retired more
+    /// than once.
+    pub unsafe fn retire(&self, offset: usize) {
+        let epoch = self.segment().header().epoch.load(Relaxed);
+        self.process.garbage.borrow_mut().push((epoch, offset));
+    }
+}
+
+impl<'p, 'a, F: FnMut(usize)> fmt::Debug for ShmScope<'p, 'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShmScope").field("process", &self.process).finish()
+    }
+}
+
+/// An atomic offset of an object of type `T` within a segment, or zero for null.
+///
+/// Offsets are stored in the segment itself, so they are meaningful to all processes regardless
+/// of where they map the segment.
+#[repr(transparent)]
+pub struct AtomicOffset<T> {
+    offset: AtomicUsize,
+    _marker: PhantomData<*const T>,
+}
+
+unsafe impl<T: Send + Sync> Send for AtomicOffset<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicOffset<T> {}
+
+impl<T> AtomicOffset<T> {
+    /// Returns a new null offset.
+    pub const fn null() -> Self {
+        AtomicOffset {
+            offset: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Loads the offset, and resolves it within the segment of `scope`.
+    pub fn load<'s, F: FnMut(usize)>(
+        &self,
+        ord: Ordering,
+        scope: &'s ShmScope<'_, '_, F>,
+    ) -> OffsetPtr<'s, T> {
+        OffsetPtr::new(self.offset.load(ord), scope.segment())
+    }
+
+    /// Stores `offset`.
+    pub fn store(&self, offset: usize, ord: Ordering) {
+        self.offset.store(offset, ord);
+    }
+
+    /// Stores `offset`, and returns the previous one resolved within the segment of `scope`.
+    pub fn swap<'s, F: FnMut(usize)>(
+        &self,
+        offset: usize,
+        ord: Ordering,
+        scope: &'s ShmScope<'_, '_, F>,
+    ) -> OffsetPtr<'s, T> {
+        OffsetPtr::new(self.offset.swap(offset, ord), scope.segment())
+    }
+
+    /// Stores `new` if the current offset is `current`.
+    ///
+    /// The return value is a result indicating whether the new offset was stored. On failure the
+    /// current offset is returned.
+    pub fn compare_and_set<'s, F: FnMut(usize)>(
+        &self,
+        current: usize,
+        new: usize,
+        ord: Ordering,
+        scope: &'s ShmScope<'_, '_, F>,
+    ) -> Result<(), OffsetPtr<'s, T>> {
+        self.offset
+            .compare_exchange(current, new, ord, Relaxed)
+            .map(|_| ())
+            .map_err(|actual| OffsetPtr::new(actual, scope.segment()))
+    }
+}
+
+impl<T> fmt::Debug for AtomicOffset<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AtomicOffset")
+            .field("offset", &self.offset.load(SeqCst))
+            .finish()
+    }
+}
+
+impl<T> Default for AtomicOffset<T> {
+    fn default() -> Self {
+        AtomicOffset::null()
+    }
+}
+
+/// An offset loaded from an [`AtomicOffset`], resolved within the segment of a pinned scope.
+///
+/// [`AtomicOffset`]: struct.AtomicOffset.html
+pub struct OffsetPtr<'scope, T: 'scope> {
+    offset: usize,
+    segment: &'scope Segment,
+    _marker: PhantomData<&'scope T>,
+}
+
+impl<'scope, T> Clone for OffsetPtr<'scope, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'scope, T> Copy for OffsetPtr<'scope, T> {}
+
+impl<'scope, T> OffsetPtr<'scope, T> {
+    fn new(offset: usize, segment: &'scope Segment) -> Self {
+        OffsetPtr {
+            offset,
+            segment,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the offset.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns `true` if the offset is null.
+    pub fn is_null(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Returns the address of the object in the current process, or a null pointer.
+    pub fn as_raw(&self) -> *const T {
+        if self.is_null() {
+            ::std::ptr::null()
+        } else {
+            unsafe { self.segment.base.add(self.offset) as *const T }
+        }
+    }
+
+    /// Dereferences the pointer.
+    ///
+    /// # Safety
+    ///
+    /// The offset must be non-null and point to a valid object of type `T` within the segment.
+    pub unsafe fn deref(&self) -> &'scope T {
+        &*self.as_raw()
+    }
+
+    /// Converts the pointer to a reference, or returns `None` if it is null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null offset must point to a valid object of type `T` within the segment.
+    pub unsafe fn as_ref(&self) -> Option<&'scope T> {
+        self.as_raw().as_ref()
+    }
+}
+
+impl<'scope, T> fmt::Debug for OffsetPtr<'scope, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffsetPtr").field("offset", &self.offset).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn pinned_process_delays_free() {
+        let mut memory = vec![0usize; 256];
+        let len = memory.len() * mem::size_of::<usize>();
+        let segment = unsafe { Segment::create(memory.as_mut_ptr() as *mut u8, len).unwrap() };
+        let other = unsafe { Segment::open(segment.base(), segment.len()).unwrap() };
+
+        let freed = Rc::new(RefCell::new(Vec::new()));
+        let f = freed.clone();
+        let a = segment.attach(move |offset| f.borrow_mut().push(offset)).unwrap();
+        let b = other.attach(|_| ()).unwrap();
+        assert_ne!(a.slot(), b.slot());
+
+        b.pin(|_| {
+            a.pin(|scope| unsafe { scope.retire(64) });
+            for _ in 0..4 {
+                a.collect();
+            }
+            assert!(freed.borrow().is_empty());
+        });
+
+        for _ in 0..4 {
+            a.collect();
+        }
+        assert_eq!(*freed.borrow(), vec![64]);
+        assert_eq!(a.pending(), 0);
+    }
+
+    #[test]
+    fn rejects_uninitialized() {
+        let mut memory = vec![0usize; 256];
+        let len = memory.len() * mem::size_of::<usize>();
+        let err = unsafe { Segment::open(memory.as_mut_ptr() as *mut u8, len).unwrap_err() };
+        assert_eq!(err, ShmError::NotInitialized);
+    }
+}
@@ -0,0 +1,194 @@
+//! Consistent views of several atomic pointers
+//!
+//! Loading several atomic pointers one after another doesn't give a consistent view of them: by
+//! the time the last one is loaded, the first ones may have been changed already. Pinning keeps
+//! the loaded objects alive, but it doesn't tell whether they ever were linked together at the
+//! same time.
+//!
+//! [`Scope::snapshot`] reads optimistically instead: every pointer loaded through the
+//! [`Snapshot`] is recorded, and once the closure returns, all of them are loaded once more. If
+//! none has changed, there was a moment at which all of them held the loaded values, and the
+//! result of the closure is returned. Otherwise the closure is run again.
+//!
+//! [`Scope::snapshot`]: struct.Scope.html#method.snapshot
+//! [`Snapshot`]: struct.Snapshot.html
+
+use std::cell::RefCell;
+use std::fmt;
+
+use atomic::{Atomic, Ptr};
+use raw::Pointable;
+use mutator::Scope;
+use primitive::atomic::{self, AtomicUsize, Ordering};
+
+/// A record of the atomic pointers loaded while taking a snapshot.
+///
+/// Created by [`Scope::snapshot`].
+///
+/// [`Scope::snapshot`]: struct.Scope.html#method.snapshot
+pub struct Snapshot<'scope> {
+    scope: &'scope Scope,
+    /// The atomic pointers loaded so far, with the tagged pointers that were loaded.
+    loads: RefCell<Vec<(&'scope AtomicUsize, usize)>>,
+}
+
+impl<'scope> Snapshot<'scope> {
+    /// Returns the scope the snapshot is taken in.
+    pub fn scope(&self) -> &'scope Scope {
+        self.scope
+    }
+
+    /// Loads a [`Ptr`] from `atomic`, and records it so that it gets revalidated at the end of the
+    /// snapshot.
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub fn load<T, const HIGH_TAG: bool>(
+        &self,
+        atomic: &'scope Atomic<T, HIGH_TAG>,
+        ord: Ordering,
+    ) -> Ptr<'scope, T, HIGH_TAG>
+    where
+        T: ?Sized + Pointable,
+    {
+        let ptr = atomic.load(ord, self.scope);
+        self.loads.borrow_mut().push((atomic.data(), ptr.data()));
+        ptr
+    }
+
+    /// Returns the number of loads recorded so far.
+    pub fn len(&self) -> usize {
+        self.loads.borrow().len()
+    }
+
+    /// Returns `true` if nothing has been loaded yet.
+    pub fn is_empty(&self) -> bool {
+        self.loads.borrow().is_empty()
+    }
+
+    /// Returns `true` if every recorded atomic pointer still holds the tagged pointer that was
+    /// loaded from it.
+    fn validate(&self) -> bool {
+        // Keeps the loads of the snapshot from being reordered after the ones below.
+        atomic::fence(Ordering::Acquire);
+        self.loads
+            .borrow()
+            .iter()
+            .all(|&(slot, data)| slot.load(Ordering::Relaxed) == data)
+    }
+}
+
+impl<'scope> fmt::Debug for Snapshot<'scope> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("loads", &self.len())
+            .finish()
+    }
+}
+
+impl Scope {
+    /// Runs `f` until it observes a consistent view of every atomic pointer it loads, and returns
+    /// its result.
+    ///
+    /// Atomic pointers loaded through the [`Snapshot`] passed to `f` are loaded once more after
+    /// `f` returns. If any of them changed in the meantime, the result is dropped and `f` is run
+    /// again, so it shouldn't have side effects other than reading. The objects loaded stay
+    /// alive for as long as the scope is pinned, so `f` may safely dereference them even if they
+    /// get unlinked concurrently.
+    ///
+    /// Pointers are compared together with their tags. An atomic pointer that changes and then
+    /// changes back to the same address and tag in between goes unnoticed, which can't happen to
+    /// objects retired while the scope is pinned, but can to ones that were never shared. Using
+    /// the tag as a version counter makes such changes visible.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::{self as epoch, Atomic};
+    /// use std::sync::atomic::Ordering::Acquire;
+    ///
+    /// let first = Atomic::new(1);
+    /// let second = Atomic::new(2);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let sum = scope.snapshot(|s| {
+    ///         let a = s.load(&first, Acquire);
+    ///         let b = s.load(&second, Acquire);
+    ///         unsafe { a.deref() + b.deref() }
+    ///     });
+    ///     assert_eq!(sum, 3);
+    /// #   unsafe {
+    /// #       drop(first.load(Acquire, scope).into_owned());
+    /// #       drop(second.load(Acquire, scope).into_owned());
+    /// #   }
+    /// });
+    /// ```
+    ///
+    /// [`Snapshot`]: struct.Snapshot.html
+    pub fn snapshot<'scope, F, R>(&'scope self, mut f: F) -> R
+    where
+        F: FnMut(&Snapshot<'scope>) -> R,
+    {
+        let mut snapshot = Snapshot {
+            scope: self,
+            loads: RefCell::new(Vec::new()),
+        };
+        loop {
+            let result = f(&snapshot);
+            if snapshot.validate() {
+                return result;
+            }
+            drop(result);
+            snapshot.loads.get_mut().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+    use std::thread;
+
+    use {pin, Atomic, Owned};
+
+    #[test]
+    fn never_observes_torn_pair() {
+        const ROUNDS: usize = 10_000;
+
+        let pair = Arc::new((Atomic::new(0usize), Atomic::new(0usize)));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writer = {
+            let pair = pair.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                // `first` is always updated before `second`, so `second` never runs ahead.
+                for i in 1..=ROUNDS {
+                    for a in &[&pair.0, &pair.1] {
+                        pin(|scope| unsafe {
+                            let old = a.swap(Owned::new(i).into_ptr(scope), Release, scope);
+                            scope.defer_drop(old);
+                        });
+                    }
+                }
+                done.store(true, Relaxed);
+            })
+        };
+
+        while !done.load(Relaxed) {
+            pin(|scope| {
+                let (a, b) = scope.snapshot(|s| unsafe {
+                    (*s.load(&pair.0, Acquire).deref(), *s.load(&pair.1, Acquire).deref())
+                });
+                assert!(a == b || a == b + 1, "torn snapshot: {} and {}", a, b);
+            });
+        }
+        writer.join().unwrap();
+
+        pin(|scope| unsafe {
+            drop(pair.0.load(Relaxed, scope).into_owned());
+            drop(pair.1.load(Relaxed, scope).into_owned());
+        });
+    }
+}
@@ -0,0 +1,459 @@
+//! Spilling garbage to disk
+//!
+//! While a mutator stays pinned, the epoch can't advance, and garbage piles up until it unpins.
+//! Besides the retired objects themselves, every one of them takes up an entry in a bag. A pipeline
+//! that keeps retiring lots of small objects through a stall of several minutes may run out of
+//! memory for the bags alone.
+//!
+//! With the `spill` feature, a collector whose [`spill_threshold`] is set writes the garbage of
+//! bags sealed while more than that many objects are pending out to a file in the temporary
+//! directory, as records of the address of each object and the id of its destructor. Once the
+//! epoch has advanced past all spilled garbage, collections read it back into bags, a slice at a
+//! time, and destroy it as usual. The file has a random name and only the current user may read
+//! it, so that it doesn't give the layout of the heap away to other users.
+//!
+//! Closures can't be written out, so only objects of types registered with
+//! [`register_spill_type`] that are retired with `Scope::defer_drop` and the like get spilled. The
+//! rest of the garbage stays in memory, as does all of it if the file can't be written.
+//!
+//! [`spill_threshold`]: struct.CollectorConfig.html#structfield.spill_threshold
+//! [`register_spill_type`]: fn.register_spill_type.html
+
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+use garbage::{self, Bag, Destroy, Garbage, MAX_OBJECTS};
+use global::is_expired;
+
+/// The destructors of the registered types, whose ids are their indices.
+static DESTROYS: Mutex<Vec<Destroy>> = Mutex::new(Vec::new());
+
+/// The number of spill files created so far.
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of words of a record: the epoch the bag was pushed in, the epoch its first garbage
+/// was deferred in, the id of the destructor, the address of the object, and its length.
+const WORDS: usize = 5;
+
+/// The number of bytes of a record.
+const RECORD: usize = WORDS * 8;
+
+/// Registers `T` as a type whose retired objects may be spilled to disk.
+///
+/// Registering a type more than once has no effect.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Collector, CollectorConfig};
+///
+/// struct Record {
+///     payload: [u64; 4],
+/// }
+///
+/// epoch::register_spill_type::<Record>();
+///
+/// // Spill once more than a million objects are waiting for destruction.
+/// let mut config = CollectorConfig::default();
+/// config.spill_threshold = 1 << 20;
+/// let collector = Collector::with_config(config);
+/// ```
+pub fn register_spill_type<T: Send + 'static>() {
+    let destroy = garbage::drop_destroy::<T>();
+    let mut destroys = DESTROYS.lock().unwrap_or_else(|e| e.into_inner());
+    if !destroys.iter().any(|&d| d as usize == destroy as usize) {
+        destroys.push(destroy);
+    }
+}
+
+/// Returns a number that other processes can't predict.
+fn unguessable() -> u64 {
+    // The keys of `RandomState` are drawn from the operating system's random number generator.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.write_usize(FILES.fetch_add(1, Relaxed));
+    hasher.finish()
+}
+
+/// Returns the id of the destructor, the length, and the objects of `garbage`, if it consists of
+/// objects of a registered type.
+fn spillable<'a>(
+    destroys: &[Destroy],
+    garbage: &'a Garbage,
+) -> Option<(usize, usize, &'a [*mut u8])> {
+    let (destroy, size, objects) = garbage.destroy_parts()?;
+    let id = destroys.iter().position(|&d| d as usize == destroy as usize)?;
+    Some((id, size, objects))
+}
+
+/// The garbage of a realm spilled to disk, and the counts that decide when to spill.
+#[derive(Debug, Default)]
+pub struct Spill {
+    /// The number of objects sealed into bags of the realm so far.
+    sealed: AtomicUsize,
+    /// The number of objects destroyed from bags of the realm so far.
+    destroyed: AtomicUsize,
+    /// The number of records not read back yet.
+    records: AtomicUsize,
+    /// The file, created once garbage is spilled for the first time.
+    buffer: Mutex<Option<Buffer>>,
+}
+
+/// A file of records.
+#[derive(Debug)]
+struct Buffer {
+    file: File,
+    path: PathBuf,
+    /// The offset up to which records were written.
+    written: u64,
+    /// The offset up to which records were read back.
+    read: u64,
+    /// The newest epoch garbage was spilled in.
+    newest: usize,
+}
+
+impl Buffer {
+    /// Creates a new, empty file in the temporary directory.
+    ///
+    /// The records hold heap addresses, so the file is readable by the current user only, and its
+    /// name can't be guessed in advance.
+    fn create() -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        loop {
+            let name = format!("crossbeam-epoch-spill-{:016x}", unguessable());
+            let path = env::temp_dir().join(name);
+            match options.open(&path) {
+                Ok(file) => return Ok(Buffer::new(file, path)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn new(file: File, path: PathBuf) -> Self {
+        Buffer {
+            file,
+            path,
+            written: 0,
+            read: 0,
+            newest: 0,
+        }
+    }
+
+    /// Appends `bytes` to the records.
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.written))?;
+        self.file.write_all(bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Reads back the next `records` records.
+    fn read(&mut self, records: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; records * RECORD];
+        self.file.seek(SeekFrom::Start(self.read))?;
+        self.file.read_exact(&mut bytes)?;
+        self.read += bytes.len() as u64;
+        Ok(bytes)
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Spill {
+    /// Returns a new spill with nothing spilled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `count` objects as sealed into a bag.
+    #[inline]
+    pub fn sealed(&self, count: usize) {
+        self.sealed.fetch_add(count, Relaxed);
+    }
+
+    /// Counts `count` objects as destroyed.
+    #[inline]
+    pub fn destroyed(&self, count: usize) {
+        self.destroyed.fetch_add(count, Relaxed);
+    }
+
+    /// Returns the number of objects sealed into bags that haven't been destroyed yet.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        let pending = self.sealed.load(Relaxed).wrapping_sub(self.destroyed.load(Relaxed));
+        // Bags pushed without being sealed, e.g. set aside ones, are destroyed all the same.
+        cmp::max(pending as isize, 0) as usize
+    }
+
+    /// Returns the number of records not read back yet.
+    pub fn records(&self) -> usize {
+        self.records.load(Relaxed)
+    }
+
+    /// Writes the garbage of registered types in `bag`, which is being sealed in `epoch`, out to
+    /// the file and removes it from the bag.
+    pub fn spill(&self, bag: &mut Bag, epoch: usize) {
+        let destroys = DESTROYS.lock().unwrap_or_else(|e| e.into_inner());
+        if destroys.is_empty() {
+            return;
+        }
+        let started = bag.started().unwrap_or(epoch);
+
+        let mut bytes = Vec::new();
+        for (id, size, objects) in bag.iter().filter_map(|g| spillable(&destroys, g)) {
+            for &object in objects {
+                for word in [epoch, started, id, object as usize, size] {
+                    bytes.extend_from_slice(&(word as u64).to_le_bytes());
+                }
+            }
+        }
+        if bytes.is_empty() {
+            return;
+        }
+
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.is_none() {
+            match Buffer::create() {
+                Ok(b) => *buffer = Some(b),
+                Err(_) => return,
+            }
+        }
+        let buffer = buffer.as_mut().unwrap();
+        let was_empty = buffer.read == buffer.written;
+        if buffer.append(&bytes).is_err() {
+            return;
+        }
+        if was_empty || (epoch.wrapping_sub(buffer.newest) as isize) > 0 {
+            buffer.newest = epoch;
+        }
+
+        bag.forget_if(|g| spillable(&destroys, g).is_some());
+        let records = bytes.len() / RECORD;
+        self.records.fetch_add(records, Relaxed);
+        self.sealed(records);
+    }
+
+    /// Reads back up to `max` spilled objects into bags, each paired with the epoch it was pushed
+    /// in, once all spilled garbage has expired as of `epoch`, or right away if `epoch` is `None`.
+    pub fn replay(&self, epoch: Option<usize>, max: usize) -> Vec<(usize, Bag)> {
+        let mut bags = Vec::new();
+        if self.records() == 0 {
+            return bags;
+        }
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let buffer = match buffer.as_mut() {
+            Some(buffer) => buffer,
+            None => return bags,
+        };
+        if let Some(epoch) = epoch {
+            if !is_expired(buffer.newest, epoch) {
+                return bags;
+            }
+        }
+        let destroys = DESTROYS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut left = max;
+        while left > 0 && buffer.read < buffer.written {
+            let remaining = ((buffer.written - buffer.read) as usize) / RECORD;
+            let count = cmp::min(cmp::min(left, MAX_OBJECTS), remaining);
+            // If the file can't be read, the garbage is left for a later attempt.
+            let bytes = match buffer.read(count) {
+                Ok(bytes) => bytes,
+                Err(_) => break,
+            };
+            left -= count;
+            self.records.fetch_sub(count, Relaxed);
+
+            let mut current: Option<(usize, Bag)> = None;
+            for record in bytes.chunks(RECORD) {
+                let mut words = [0; WORDS];
+                for (word, bytes) in words.iter_mut().zip(record.chunks(8)) {
+                    let mut le = [0; 8];
+                    le.copy_from_slice(bytes);
+                    *word = u64::from_le_bytes(le) as usize;
+                }
+                let [pushed, started, id, object, size] = words;
+
+                // Garbage of different bags goes back into different bags.
+                let same = match current {
+                    Some((e, ref bag)) => e == pushed && bag.started() == Some(started),
+                    None => false,
+                };
+                if !same {
+                    bags.extend(current.take());
+                    let mut bag = Bag::new();
+                    bag.start(started);
+                    current = Some((pushed, bag));
+                }
+                let garbage =
+                    unsafe { Garbage::from_destroy_parts(destroys[id], object as *mut u8, size) };
+                let bag = &mut current.as_mut().unwrap().1;
+                if bag.try_push(garbage).is_err() {
+                    unreachable!("a bag holds as many objects as are read at once");
+                }
+            }
+            bags.extend(current);
+        }
+
+        if buffer.read == buffer.written {
+            // Start over, so that the file doesn't keep growing across stalls.
+            buffer.read = 0;
+            buffer.written = 0;
+            let _ = buffer.file.set_len(0);
+        }
+        bags
+    }
+}
+
+// Garbage tracked by its origin is never spilled.
+#[cfg(all(test, not(feature = "garbage_backtrace")))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use std::time::Duration;
+
+    use {Collector, CollectorConfig, MaintenanceStatus, Owned, large_garbage_threshold};
+    use super::*;
+
+    /// An object that counts its drops.
+    struct Spilled(Arc<AtomicUsize>);
+
+    impl Drop for Spilled {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn spills_during_stalls() {
+        register_spill_type::<Spilled>();
+
+        let config = CollectorConfig {
+            bag_capacity: 4,
+            spill_threshold: 0,
+            ..CollectorConfig::default()
+        };
+        let collector = Collector::with_config(config);
+        let handle = collector.register();
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let stall = collector.pin_owned();
+        for _ in 0..4 {
+            handle.pin(|scope| unsafe {
+                for _ in 0..8 {
+                    let object = Owned::new(Spilled(dropped.clone())).into_ptr(scope);
+                    scope.defer_drop(object);
+                }
+                scope.flush();
+            });
+        }
+        let records = handle.pin(|scope| scope.realm().spill.records());
+        assert!(records > 0, "nothing was spilled");
+        assert_eq!(dropped.load(SeqCst), 0);
+
+        drop(stall);
+        for _ in 0..16 {
+            handle.pin(|scope| scope.flush());
+        }
+        assert_eq!(handle.pin(|scope| scope.realm().spill.records()), 0);
+        assert_eq!(dropped.load(SeqCst), 32);
+    }
+
+    #[test]
+    fn maintenance_drains_spilled_garbage() {
+        register_spill_type::<Spilled>();
+
+        let config = CollectorConfig {
+            bag_capacity: 4,
+            spill_threshold: 0,
+            ..CollectorConfig::default()
+        };
+        let collector = Collector::with_config(config);
+        let handle = collector.register();
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let stall = collector.pin_owned();
+        for _ in 0..4 {
+            handle.pin(|scope| unsafe {
+                for _ in 0..8 {
+                    scope.defer_drop(Owned::new(Spilled(dropped.clone())).into_ptr(scope));
+                }
+                scope.flush();
+            });
+        }
+        assert!(handle.pin(|scope| scope.realm().spill.records()) > 0);
+        assert_eq!(
+            collector.poll_maintenance(Duration::from_secs(10)),
+            MaintenanceStatus::Blocked,
+        );
+
+        drop(stall);
+        let status = collector.poll_maintenance(Duration::from_secs(10));
+        assert_eq!(status, MaintenanceStatus::Done);
+        assert_eq!(handle.pin(|scope| scope.realm().spill.records()), 0);
+        assert_eq!(dropped.load(SeqCst), 32);
+    }
+
+    #[test]
+    fn counts_large_garbage() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let dropped = Arc::new(AtomicUsize::new(0));
+        // The garbage still pending, which mustn't have gone negative.
+        let pending = || {
+            handle.pin(|scope| {
+                let spill = &scope.realm().spill;
+                spill.sealed.load(SeqCst).wrapping_sub(spill.destroyed.load(SeqCst)) as isize
+            })
+        };
+
+        let stall = collector.pin_owned();
+        let before = pending();
+        let d = dropped.clone();
+        handle.pin(|scope| unsafe {
+            let size = large_garbage_threshold();
+            scope.defer_sized(move || drop(Spilled(d)), size);
+        });
+        assert_eq!(pending(), before + 1);
+
+        drop(stall);
+        while dropped.load(SeqCst) == 0 {
+            handle.pin(|scope| scope.flush());
+        }
+        assert!(pending() >= 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spill_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let a = Buffer::create().unwrap();
+        let b = Buffer::create().unwrap();
+        assert_ne!(a.path, b.path);
+        let mode = fs::metadata(&a.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,227 @@
+//! An announcement table for helping schemes.
+//!
+//! Wait-free algorithms make progress guarantees by having threads help each other: before
+//! starting an operation, a thread announces it in a slot of its own, and other threads that come
+//! across the announcement carry the operation out on its behalf. Exactly one of them then claims
+//! the announcement, i.e. removes it from the slot.
+//!
+//! Announcements are read by arbitrary threads at arbitrary times, so they can't simply be freed
+//! when claimed. `Announce` publishes them with release semantics and retires them through the
+//! garbage collector once they are replaced or claimed.
+
+use primitive::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use crossbeam_utils::cache_padded::CachePadded;
+
+use {Atomic, EpochSafe, Owned, Ptr, Scope, unprotected};
+
+/// A fixed-size table of announcement slots, one per participant of a helping scheme.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use crossbeam_epoch::sync::Announce;
+///
+/// let table = Announce::new(4);
+///
+/// epoch::pin(|scope| {
+///     // Participant 2 announces an operation...
+///     let request = table.announce(2, "push 7", scope);
+///
+///     // ...which another participant finds, carries out, and claims.
+///     for (index, pending) in table.iter(scope) {
+///         assert_eq!(index, 2);
+///         assert!(table.claim(index, pending, scope));
+///     }
+///
+///     // The announcement can be claimed only once.
+///     assert!(!table.claim(2, request, scope));
+///     assert!(table.get(2, scope).is_none());
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Announce<T> {
+    slots: Box<[CachePadded<Atomic<T>>]>,
+}
+
+impl<T: Send + EpochSafe + 'static> Announce<T> {
+    /// Returns a new table of `slots` empty slots.
+    pub fn new(slots: usize) -> Self {
+        Announce {
+            slots: (0..slots).map(|_| CachePadded::new(Atomic::null())).collect(),
+        }
+    }
+
+    /// Returns the number of slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns `true` if the table has no slots.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Publishes `request` in slot `index`, and returns a pointer to it.
+    ///
+    /// An announcement still in the slot is replaced and retired.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn announce<'scope>(
+        &self,
+        index: usize,
+        request: T,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T> {
+        let new = Owned::new(request).into_ptr(scope);
+        let old = self.slots[index].swap(new, AcqRel, scope);
+        if !old.is_null() {
+            unsafe { scope.defer_drop(old) }
+        }
+        new
+    }
+
+    /// Publishes `request` in slot `index` if the slot is empty, and returns a pointer to it.
+    ///
+    /// If the slot holds an announcement already, `request` is handed back instead. Unlike
+    /// [`announce`], this lets participants share the table without assigning slots up front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    ///
+    /// [`announce`]: struct.Announce.html#method.announce
+    pub fn try_announce<'scope>(
+        &self,
+        index: usize,
+        request: T,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, T> {
+        let new = Owned::new(request);
+        self.slots[index]
+            .compare_and_set_owned(Ptr::null(), new, AcqRel, scope)
+            .map_err(|(_, new)| *new.into_box())
+    }
+
+    /// Returns a pointer to the announcement in slot `index`, or a null pointer if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn load<'scope>(&self, index: usize, scope: &'scope Scope) -> Ptr<'scope, T> {
+        self.slots[index].load(Acquire, scope)
+    }
+
+    /// Returns the announcement in slot `index`, if there is one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn get<'a>(&'a self, index: usize, scope: &'a Scope) -> Option<&'a T> {
+        // Announcements are only destroyed through the garbage collector, after being removed.
+        unsafe { self.load(index, scope).as_ref() }
+    }
+
+    /// Removes the announcement `request` from slot `index` and retires it, if it's still there.
+    ///
+    /// Returns `true` if this call removed it. Of all threads claiming the same announcement, at
+    /// most one succeeds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn claim(&self, index: usize, request: Ptr<T>, scope: &Scope) -> bool {
+        if request.is_null() {
+            return false;
+        }
+        match self.slots[index].compare_and_set(request, Ptr::null(), AcqRel, scope) {
+            Ok(()) => {
+                unsafe { scope.defer_drop(request) }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns an iterator over the pending announcements and the indices of their slots.
+    pub fn iter<'scope>(
+        &'scope self,
+        scope: &'scope Scope,
+    ) -> impl Iterator<Item = (usize, Ptr<'scope, T>)> + 'scope {
+        (0..self.slots.len())
+            .map(move |index| (index, self.load(index, scope)))
+            .filter(|&(_, request)| !request.is_null())
+    }
+}
+
+impl<T> Drop for Announce<T> {
+    fn drop(&mut self) {
+        unsafe {
+            unprotected(|scope| {
+                for slot in self.slots.iter() {
+                    let request = slot.swap(Ptr::null(), Relaxed, scope);
+                    if !request.is_null() {
+                        drop(Box::from_raw(request.as_raw() as *mut T));
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use crossbeam_utils::scoped;
+
+    use pin;
+    use super::*;
+
+    #[test]
+    fn claimed_once() {
+        let table = Announce::new(4);
+        let helped = AtomicUsize::new(0);
+
+        for round in 0..100 {
+            pin(|scope| for index in 0..table.len() {
+                table.announce(index, round, scope);
+            });
+
+            scoped::scope(|s| for _ in 0..4 {
+                s.spawn(|| pin(|scope| for (index, request) in table.iter(scope) {
+                    if table.claim(index, request, scope) {
+                        helped.fetch_add(1, SeqCst);
+                    }
+                }));
+            });
+        }
+        assert_eq!(helped.load(SeqCst), 400);
+    }
+
+    #[test]
+    fn drops_announcements() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counted;
+
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, SeqCst);
+            }
+        }
+
+        {
+            let table = Announce::new(3);
+            pin(|scope| {
+                table.announce(0, Counted, scope);
+                table.announce(2, Counted, scope);
+            });
+        }
+        assert_eq!(DROPPED.load(SeqCst), 2);
+    }
+}
@@ -0,0 +1,733 @@
+//! A bounded multi-producer multi-consumer queue.
+//!
+//! The queue is a ring of slots, each stamped with the position it's next going to be written or
+//! read at, so that producers and consumers claim slots by moving the tail and the head forward
+//! without ever blocking each other. This is Dmitry Vyukov's bounded queue, as in
+//! `crossbeam-queue`.
+//!
+//! The ring itself never changes while elements flow through it, so epochs aren't needed for
+//! that. They come in when the ring is replaced: [`ArrayQueue::resize`] freezes the old ring,
+//! moves its elements over into a new one, and retires the old ring, which threads that loaded it
+//! just before may still be looking at. Threads that run into a frozen ring wait for the new one
+//! and carry on there.
+//!
+//! [`ArrayQueue::resize`]: struct.ArrayQueue.html#method.resize
+
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::hint;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::thread;
+
+use crossbeam_utils::cache_padded::CachePadded;
+
+use {Atomic, Owned, Scope, pin, unprotected};
+use super::WriterLock;
+
+/// Number of times a waiting thread spins before yielding.
+const SPINS: usize = 64;
+
+/// Spins for a while the first times a thread waits, then yields.
+fn snooze(step: &mut usize) {
+    if *step < SPINS {
+        hint::spin_loop();
+    } else {
+        thread::yield_now();
+    }
+    *step += 1;
+}
+
+/// A slot of a ring.
+struct Slot<T> {
+    /// The position the slot is next going to be written at, or that plus one if it holds an
+    /// element to be read at that position.
+    stamp: AtomicUsize,
+    /// The element, if the slot holds one.
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A ring of slots, with the positions of its head and tail.
+///
+/// A position consists of the index of a slot in its lowest bits, followed by two flags, followed
+/// by the lap the ring is in. Dropping a ring doesn't drop the elements it holds.
+struct Buffer<T> {
+    /// The position elements are popped at.
+    head: CachePadded<AtomicUsize>,
+    /// The position elements are pushed at.
+    tail: CachePadded<AtomicUsize>,
+    slots: Box<[Slot<T>]>,
+    /// The smallest power of two greater than the capacity, which is the flag that marks the
+    /// tail of a closed queue.
+    mark: usize,
+    /// The value that adds one lap to a position.
+    one_lap: usize,
+}
+
+unsafe impl<T: Send> Send for Buffer<T> {}
+unsafe impl<T: Send> Sync for Buffer<T> {}
+
+/// The outcome of an attempt to push an element into a ring.
+enum Push<T> {
+    /// The element was pushed, possibly displacing the oldest element.
+    Pushed(Option<T>),
+    Full(T),
+    Closed(T),
+    /// The ring is being replaced.
+    Frozen(T),
+}
+
+/// The outcome of an attempt to pop an element from a ring.
+enum Pop<T> {
+    Popped(T),
+    Empty,
+    /// The ring is being replaced.
+    Frozen,
+}
+
+impl<T> Buffer<T> {
+    /// Returns a new, empty ring of `cap` slots.
+    fn new(cap: usize) -> Self {
+        let mark = (cap + 1).next_power_of_two();
+        let slots = (0..cap)
+            .map(|i| Slot {
+                stamp: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Buffer {
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            slots,
+            mark,
+            one_lap: mark << 2,
+        }
+    }
+
+    #[inline]
+    fn cap(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The flag that marks the tail of a closed queue.
+    #[inline]
+    fn closed(&self) -> usize {
+        self.mark
+    }
+
+    /// The flag that marks the head and the tail of a ring that is being replaced.
+    #[inline]
+    fn frozen(&self) -> usize {
+        self.mark << 1
+    }
+
+    #[inline]
+    fn flags(&self) -> usize {
+        self.closed() | self.frozen()
+    }
+
+    /// Returns the slot at `pos`.
+    #[inline]
+    fn slot(&self, pos: usize) -> &Slot<T> {
+        &self.slots[pos & (self.mark - 1)]
+    }
+
+    /// Returns the position following `pos`.
+    #[inline]
+    fn next(&self, pos: usize) -> usize {
+        let index = pos & (self.mark - 1);
+        if index + 1 < self.cap() {
+            pos + 1
+        } else {
+            (pos & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    /// Returns the position of the `i`-th slot in the first lap, or the start of the second lap if
+    /// `i` is the capacity.
+    #[inline]
+    fn position(&self, i: usize) -> usize {
+        if i < self.cap() { i } else { self.one_lap }
+    }
+
+    /// Returns the number of elements between the unflagged positions `head` and `tail`.
+    fn count(&self, head: usize, tail: usize) -> usize {
+        let hix = head & (self.mark - 1);
+        let tix = tail & (self.mark - 1);
+        if hix < tix {
+            tix - hix
+        } else if hix > tix {
+            self.cap() - hix + tix
+        } else if tail == head {
+            0
+        } else {
+            self.cap()
+        }
+    }
+
+    /// Returns the number of elements in the ring.
+    fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load(SeqCst);
+            let head = self.head.load(SeqCst);
+            if self.tail.load(SeqCst) == tail {
+                return self.count(head & !self.flags(), tail & !self.flags());
+            }
+        }
+    }
+
+    /// Attempts to push `value`.
+    ///
+    /// If the ring looks full, `full` is called with the value, the tail, the position following
+    /// it, and the slot at the tail. It either hands the value back to try again or finishes the
+    /// push.
+    fn push_or_else<F>(&self, mut value: T, full: F) -> Push<T>
+    where
+        F: Fn(T, usize, usize, &Slot<T>) -> Result<T, Push<T>>,
+    {
+        let mut step = 0;
+        let mut tail = self.tail.load(Relaxed);
+        loop {
+            if tail & self.closed() != 0 {
+                return Push::Closed(value);
+            }
+            if tail & self.frozen() != 0 {
+                return Push::Frozen(value);
+            }
+
+            let new_tail = self.next(tail);
+            let slot = self.slot(tail);
+            let stamp = slot.stamp.load(Acquire);
+
+            if tail == stamp {
+                match self.tail.compare_exchange_weak(tail, new_tail, SeqCst, Relaxed) {
+                    Ok(_) => {
+                        unsafe { slot.value.get().write(MaybeUninit::new(value)) };
+                        slot.stamp.store(tail + 1, Release);
+                        return Push::Pushed(None);
+                    }
+                    Err(t) => {
+                        tail = t;
+                        hint::spin_loop();
+                    }
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // The slot still holds the element of the previous lap.
+                atomic::fence(SeqCst);
+                value = match full(value, tail, new_tail, slot) {
+                    Ok(value) => value,
+                    Err(done) => return done,
+                };
+                snooze(&mut step);
+                tail = self.tail.load(Relaxed);
+            } else {
+                // Another thread has claimed the slot but hasn't written it yet.
+                snooze(&mut step);
+                tail = self.tail.load(Relaxed);
+            }
+        }
+    }
+
+    /// Attempts to push `value`, failing if the ring is full.
+    fn push(&self, value: T) -> Push<T> {
+        self.push_or_else(value, |value, tail, _, _| {
+            let head = self.head.load(Relaxed);
+            if head.wrapping_add(self.one_lap) == tail {
+                Err(Push::Full(value))
+            } else {
+                Ok(value)
+            }
+        })
+    }
+
+    /// Attempts to push `value`, displacing the oldest element if the ring is full.
+    fn force_push(&self, value: T) -> Push<T> {
+        self.push_or_else(value, |value, tail, new_tail, slot| {
+            let head = tail.wrapping_sub(self.one_lap);
+            let new_head = new_tail.wrapping_sub(self.one_lap);
+
+            if self.head.compare_exchange_weak(head, new_head, SeqCst, Relaxed).is_ok() {
+                // A close may have flagged the tail in the meantime, which must be kept.
+                let flags = self.flags();
+                let _ = self.tail.fetch_update(SeqCst, Relaxed, |t| Some(new_tail | (t & flags)));
+                let old = unsafe { slot.value.get().replace(MaybeUninit::new(value)) };
+                let old = unsafe { old.assume_init() };
+                slot.stamp.store(tail + 1, Release);
+                Err(Push::Pushed(Some(old)))
+            } else {
+                Ok(value)
+            }
+        })
+    }
+
+    /// Attempts to pop an element.
+    fn pop(&self) -> Pop<T> {
+        let mut step = 0;
+        let mut head = self.head.load(Relaxed);
+        loop {
+            if head & self.frozen() != 0 {
+                return Pop::Frozen;
+            }
+
+            let slot = self.slot(head);
+            let stamp = slot.stamp.load(Acquire);
+
+            if head + 1 == stamp {
+                let new_head = self.next(head);
+                match self.head.compare_exchange_weak(head, new_head, SeqCst, Relaxed) {
+                    Ok(_) => {
+                        let value = unsafe { slot.value.get().read().assume_init() };
+                        slot.stamp.store(head.wrapping_add(self.one_lap), Release);
+                        return Pop::Popped(value);
+                    }
+                    Err(h) => {
+                        head = h;
+                        hint::spin_loop();
+                    }
+                }
+            } else if stamp == head {
+                atomic::fence(SeqCst);
+                let tail = self.tail.load(Relaxed);
+                if tail & !self.flags() == head {
+                    return Pop::Empty;
+                }
+                snooze(&mut step);
+                head = self.head.load(Relaxed);
+            } else {
+                // Another thread has claimed the slot but hasn't read it yet.
+                snooze(&mut step);
+                head = self.head.load(Relaxed);
+            }
+        }
+    }
+
+    /// Waits until no push or pop is halfway done with the frozen ring, and returns its unflagged
+    /// head and tail.
+    fn settle(&self) -> (usize, usize) {
+        let mut step = 0;
+        loop {
+            let head = self.head.load(SeqCst) & !self.flags();
+            let tail = self.tail.load(SeqCst) & !self.flags();
+            // A forced push may have moved the head but not yet the tail, and a pop of the slot at
+            // the tail from the previous lap may not have released it yet.
+            let full = head.wrapping_add(self.one_lap) == tail;
+            if full || self.slot(tail).stamp.load(Acquire) == tail {
+                return (head, tail);
+            }
+            snooze(&mut step);
+        }
+    }
+}
+
+/// The error returned by [`ArrayQueue::push`], holding the element that couldn't be pushed.
+///
+/// [`ArrayQueue::push`]: struct.ArrayQueue.html#method.push
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PushError<T> {
+    /// The queue is full.
+    Full(T),
+    /// The queue is closed.
+    Closed(T),
+}
+
+impl<T> PushError<T> {
+    /// Returns the element that couldn't be pushed.
+    pub fn into_inner(self) -> T {
+        match self {
+            PushError::Full(value) | PushError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PushError::Full(_) => f.write_str("Full(..)"),
+            PushError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PushError::Full(_) => f.write_str("pushing into a full queue"),
+            PushError::Closed(_) => f.write_str("pushing into a closed queue"),
+        }
+    }
+}
+
+impl<T> error::Error for PushError<T> {}
+
+/// A bounded multi-producer multi-consumer queue that can be resized and closed.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::sync::{ArrayQueue, PushError};
+///
+/// let q = ArrayQueue::new(2);
+/// assert_eq!(q.push('a'), Ok(()));
+/// assert_eq!(q.push('b'), Ok(()));
+/// assert_eq!(q.push('c'), Err(PushError::Full('c')));
+///
+/// // Grow the queue to make room.
+/// assert_eq!(q.resize(4), 4);
+/// assert_eq!(q.push('c'), Ok(()));
+///
+/// // A closed queue takes no more elements, but hands out the ones it holds.
+/// q.close();
+/// assert_eq!(q.push('d'), Err(PushError::Closed('d')));
+/// assert_eq!(q.pop(), Some('a'));
+/// assert_eq!(q.pop(), Some('b'));
+/// assert_eq!(q.pop(), Some('c'));
+/// assert_eq!(q.pop(), None);
+/// ```
+pub struct ArrayQueue<T> {
+    buffer: Atomic<Buffer<T>>,
+    /// Serializes resizes and closes.
+    lock: WriterLock,
+}
+
+unsafe impl<T: Send> Send for ArrayQueue<T> {}
+unsafe impl<T: Send> Sync for ArrayQueue<T> {}
+
+impl<T> ArrayQueue<T> {
+    /// Returns a new, empty queue with room for `cap` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero.
+    pub fn new(cap: usize) -> Self {
+        assert!(cap > 0, "capacity must be non-zero");
+        ArrayQueue {
+            buffer: Atomic::new(Buffer::new(cap)),
+            lock: WriterLock::new(),
+        }
+    }
+
+    /// Runs `f` on the current ring until it gets through without running into a resize.
+    fn with_buffer<F, R>(&self, scope: &Scope, mut f: F) -> R
+    where
+        F: FnMut(&Buffer<T>) -> Option<R>,
+    {
+        let mut buffer = self.buffer.load(Acquire, scope);
+        loop {
+            if let Some(r) = f(unsafe { buffer.deref() }) {
+                return r;
+            }
+            // Wait for the resize to install the new ring.
+            let mut step = 0;
+            loop {
+                let current = self.buffer.load(Acquire, scope);
+                if current.as_raw() != buffer.as_raw() {
+                    buffer = current;
+                    break;
+                }
+                snooze(&mut step);
+            }
+        }
+    }
+
+    /// Pushes `value` into the queue.
+    ///
+    /// Fails if the queue is full or closed, handing the element back.
+    pub fn push(&self, value: T) -> Result<(), PushError<T>> {
+        pin(|scope| {
+            let mut value = Some(value);
+            self.with_buffer(scope, |buffer| match buffer.push(value.take().unwrap()) {
+                Push::Pushed(_) => Some(Ok(())),
+                Push::Full(v) => Some(Err(PushError::Full(v))),
+                Push::Closed(v) => Some(Err(PushError::Closed(v))),
+                Push::Frozen(v) => {
+                    value = Some(v);
+                    None
+                }
+            })
+        })
+    }
+
+    /// Pushes `value` into the queue, displacing the oldest element if the queue is full.
+    ///
+    /// Returns the displaced element, if any, or fails with `value` if the queue is closed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crossbeam_epoch::sync::ArrayQueue;
+    ///
+    /// let q = ArrayQueue::new(2);
+    /// assert_eq!(q.force_push(1), Ok(None));
+    /// assert_eq!(q.force_push(2), Ok(None));
+    /// assert_eq!(q.force_push(3), Ok(Some(1)));
+    /// assert_eq!(q.pop(), Some(2));
+    /// ```
+    pub fn force_push(&self, value: T) -> Result<Option<T>, T> {
+        pin(|scope| {
+            let mut value = Some(value);
+            self.with_buffer(scope, |buffer| match buffer.force_push(value.take().unwrap()) {
+                Push::Pushed(old) => Some(Ok(old)),
+                Push::Closed(v) => Some(Err(v)),
+                Push::Full(_) => unreachable!("a forced push never finds the queue full"),
+                Push::Frozen(v) => {
+                    value = Some(v);
+                    None
+                }
+            })
+        })
+    }
+
+    /// Pops the oldest element from the queue, or returns `None` if the queue is empty.
+    pub fn pop(&self) -> Option<T> {
+        pin(|scope| {
+            self.with_buffer(scope, |buffer| match buffer.pop() {
+                Pop::Popped(value) => Some(Some(value)),
+                Pop::Empty => Some(None),
+                Pop::Frozen => None,
+            })
+        })
+    }
+
+    /// Returns the number of elements the queue has room for.
+    pub fn capacity(&self) -> usize {
+        pin(|scope| unsafe { self.buffer.load(Acquire, scope).deref() }.cap())
+    }
+
+    /// Returns the number of elements in the queue.
+    pub fn len(&self) -> usize {
+        pin(|scope| unsafe { self.buffer.load(Acquire, scope).deref() }.len())
+    }
+
+    /// Returns `true` if the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the queue is full.
+    pub fn is_full(&self) -> bool {
+        pin(|scope| {
+            let buffer = unsafe { self.buffer.load(Acquire, scope).deref() };
+            buffer.len() == buffer.cap()
+        })
+    }
+
+    /// Returns `true` if the queue is closed.
+    pub fn is_closed(&self) -> bool {
+        pin(|scope| {
+            let buffer = unsafe { self.buffer.load(Acquire, scope).deref() };
+            buffer.tail.load(SeqCst) & buffer.closed() != 0
+        })
+    }
+
+    /// Closes the queue, so that pushes fail from now on.
+    ///
+    /// Elements already in the queue can still be popped. Returns `false` if the queue was closed
+    /// already.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the current thread is pinned, like [`WriterLock::write`].
+    ///
+    /// [`WriterLock::write`]: struct.WriterLock.html#method.write
+    pub fn close(&self) -> bool {
+        // The lock keeps a resize from carrying over the flags of the old ring before the flag
+        // is set.
+        self.lock.write(|scope| {
+            let buffer = unsafe { self.buffer.load(Acquire, scope).deref() };
+            buffer.tail.fetch_or(buffer.closed(), SeqCst) & buffer.closed() == 0
+        })
+    }
+}
+
+impl<T: Send + 'static> ArrayQueue<T> {
+    /// Replaces the ring of the queue with one of room for `cap` elements, or as many as it holds
+    /// if there are more, and returns the new capacity.
+    ///
+    /// Pushes and pops wait while the elements are moved over. The old ring is retired, with its
+    /// size, once they are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero. In debug builds, also panics if the current thread is pinned, like
+    /// [`WriterLock::write`].
+    ///
+    /// [`WriterLock::write`]: struct.WriterLock.html#method.write
+    pub fn resize(&self, cap: usize) -> usize {
+        assert!(cap > 0, "capacity must be non-zero");
+        self.lock.write(|scope| {
+            let old = self.buffer.load(Acquire, scope);
+            let buffer = unsafe { old.deref() };
+
+            // Stop pushes before pops, so that pops keep draining a ring that is being filled.
+            let closed = buffer.tail.fetch_or(buffer.frozen(), SeqCst) & buffer.closed();
+            buffer.head.fetch_or(buffer.frozen(), SeqCst);
+            let (head, tail) = buffer.settle();
+
+            let len = buffer.count(head, tail);
+            let new = Buffer::new(cmp::max(cap, len));
+            let mut pos = head;
+            for (i, slot) in new.slots[..len].iter().enumerate() {
+                // Wait for pushes that have claimed their slots to write them.
+                let from = buffer.slot(pos);
+                let mut step = 0;
+                while from.stamp.load(Acquire) != pos + 1 {
+                    snooze(&mut step);
+                }
+                unsafe { slot.value.get().write(from.value.get().read()) };
+                slot.stamp.store(i + 1, Relaxed);
+                pos = buffer.next(pos);
+            }
+            let new_tail = new.position(len);
+            new.tail.store(new_tail | if closed != 0 { new.closed() } else { 0 }, Relaxed);
+
+            let new_cap = new.cap();
+            self.buffer.store_owned(Owned::new(new), Release);
+            unsafe {
+                let size = mem::size_of::<Buffer<T>>() + buffer.cap() * mem::size_of::<Slot<T>>();
+                let old = old.into_owned();
+                scope.defer_sized(move || drop(old), size);
+            }
+            new_cap
+        })
+    }
+}
+
+impl<T> Drop for ArrayQueue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            unprotected(|scope| {
+                let buffer = self.buffer.load(Relaxed, scope).into_owned();
+                let head = buffer.head.load(Relaxed) & !buffer.flags();
+                let tail = buffer.tail.load(Relaxed) & !buffer.flags();
+                let mut pos = head;
+                for _ in 0..buffer.count(head, tail) {
+                    ptr::drop_in_place((*buffer.slot(pos).value.get()).as_mut_ptr());
+                    pos = buffer.next(pos);
+                }
+            })
+        }
+    }
+}
+
+impl<T> fmt::Debug for ArrayQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("ArrayQueue { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use crossbeam_utils::scoped;
+
+    use super::*;
+
+    #[test]
+    fn force_push_displaces_oldest() {
+        let q = ArrayQueue::new(3);
+        for i in 0..3 {
+            assert_eq!(q.force_push(i), Ok(None));
+        }
+        assert!(q.is_full());
+        assert_eq!(q.force_push(3), Ok(Some(0)));
+        assert_eq!(q.force_push(4), Ok(Some(1)));
+        assert_eq!(q.len(), 3);
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+        assert_eq!(q.pop(), Some(4));
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn resize_keeps_elements() {
+        let q = ArrayQueue::new(4);
+        // Wrap around the ring first.
+        for i in 0..6 {
+            q.force_push(i).unwrap();
+        }
+        assert_eq!(q.resize(2), 4);
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.resize(8), 8);
+        for i in 6..11 {
+            q.push(i).unwrap();
+        }
+        assert_eq!(q.push(11), Err(PushError::Full(11)));
+        q.close();
+        assert_eq!(q.resize(16), 16);
+        assert!(q.is_closed());
+        assert_eq!(q.push(11), Err(PushError::Closed(11)));
+        for i in 3..11 {
+            assert_eq!(q.pop(), Some(i));
+        }
+        assert_eq!(q.pop(), None);
+    }
+
+    #[test]
+    fn drops_remaining_elements() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem;
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, SeqCst);
+            }
+        }
+
+        let q = ArrayQueue::new(4);
+        for _ in 0..6 {
+            drop(q.force_push(Elem));
+        }
+        assert_eq!(DROPS.load(SeqCst), 2);
+        drop(q.pop());
+        q.resize(8);
+        drop(q);
+        assert_eq!(DROPS.load(SeqCst), 6);
+    }
+
+    #[test]
+    fn mpmc_with_resizes() {
+        const THREADS: usize = 4;
+        const COUNT: usize = 2_000;
+
+        let q = ArrayQueue::new(4);
+        let popped = AtomicUsize::new(0);
+        let sum = AtomicUsize::new(0);
+
+        scoped::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for i in 0..COUNT {
+                        while q.push(i).is_err() {
+                            thread::yield_now();
+                        }
+                    }
+                });
+                s.spawn(|| {
+                    while popped.load(SeqCst) < THREADS * COUNT {
+                        if let Some(i) = q.pop() {
+                            popped.fetch_add(1, SeqCst);
+                            sum.fetch_add(i, SeqCst);
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                let mut cap = 1;
+                while popped.load(SeqCst) < THREADS * COUNT {
+                    q.resize(cap);
+                    cap = cap % 16 + 1;
+                }
+            });
+        });
+
+        assert_eq!(sum.load(SeqCst), THREADS * COUNT * (COUNT - 1) / 2);
+        assert!(q.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+//! Flat combining.
+//!
+//! Under heavy contention, a data structure guarded by a lock spends most of its time handing the
+//! lock from one thread to the next. With flat combining, threads publish their operations
+//! instead, and whichever thread gets hold of the lock applies all published operations in one
+//! pass, handing every result back to the thread that published the operation. The state stays
+//! in the cache of a single core for the whole pass, and the lock is acquired once per pass rather
+//! than once per operation.
+//!
+//! The delicate part is the publication records: the combiner reads them while their publishers
+//! wait, so they can't simply be freed by either side. `Combiner` publishes them in an
+//! [`Announce`] table, and the combiner retires each record when it claims it. Its publisher stays
+//! pinned until it has taken the result out, so the record outlives both of them.
+//!
+//! [`Announce`]: struct.Announce.html
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::hint;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
+
+use {AssertEpochSafe, Scope, pin};
+use super::Announce;
+
+/// Number of times a waiting thread spins before yielding.
+const SPINS: usize = 64;
+
+/// The slot of the announcement table the next thread starts looking for an empty slot at.
+static NEXT_HINT: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The slot of the announcement table the current thread starts looking for an empty slot at.
+    static HINT: usize = NEXT_HINT.fetch_add(1, Relaxed);
+}
+
+/// An operation on the state of a [`Combiner`].
+///
+/// [`Combiner`]: struct.Combiner.html
+pub trait Operation<T> {
+    /// The result of the operation.
+    type Output;
+
+    /// Applies the operation to `state`.
+    fn apply(self, state: &mut T) -> Self::Output;
+}
+
+/// A published operation, along with its result once it's applied.
+struct Record<Op, R> {
+    /// The operation, until the combiner that claimed the record takes it.
+    op: UnsafeCell<Option<Op>>,
+    /// The result, or the panic the operation raised, until the publisher takes it.
+    result: UnsafeCell<Option<thread::Result<R>>>,
+    /// Whether `result` has been set.
+    done: AtomicBool,
+}
+
+// The operation is only accessed by the single combiner that claimed the record, and the result
+// is handed over from it to the publisher through `done`.
+unsafe impl<Op: Send, R: Send> Send for Record<Op, R> {}
+unsafe impl<Op: Send, R: Send> Sync for Record<Op, R> {}
+
+/// A state shared by threads that apply operations to it through flat combining.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::sync::{Combiner, Operation};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// struct Push(u32);
+///
+/// impl Operation<Vec<u32>> for Push {
+///     type Output = usize;
+///
+///     fn apply(self, stack: &mut Vec<u32>) -> usize {
+///         stack.push(self.0);
+///         stack.len()
+///     }
+/// }
+///
+/// let combiner = Arc::new(Combiner::new(Vec::new(), 8));
+/// let threads = (0..4)
+///     .map(|i| {
+///         let combiner = combiner.clone();
+///         thread::spawn(move || combiner.execute(Push(i)))
+///     })
+///     .collect::<Vec<_>>();
+///
+/// let mut lens = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+/// lens.sort();
+/// assert_eq!(lens, [1, 2, 3, 4]);
+/// ```
+pub struct Combiner<T, Op: Operation<T>> {
+    state: Mutex<T>,
+    /// The published operations. The records are retired through the garbage collector, and
+    /// they are always empty by then, so their destructors can't block.
+    records: Announce<AssertEpochSafe<Record<Op, Op::Output>>>,
+}
+
+impl<T, Op> Combiner<T, Op>
+where
+    Op: Operation<T> + Send + 'static,
+    Op::Output: Send + 'static,
+{
+    /// Returns a combiner of `state`, with `slots` slots for publishing operations.
+    ///
+    /// The number of slots bounds how many operations a single pass applies. If all slots are
+    /// taken, a thread helps apply published operations until one becomes free.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `slots` is zero.
+    pub fn new(state: T, slots: usize) -> Self {
+        assert!(slots > 0, "a combiner needs at least one slot");
+        Combiner {
+            state: Mutex::new(state),
+            records: Announce::new(slots),
+        }
+    }
+
+    /// Applies `op` to the state, and returns its result.
+    ///
+    /// The operation is applied either by the current thread, if it gets to combine, or by
+    /// another thread combining in the meantime. If the operation panics, the panic is resumed on
+    /// the current thread.
+    ///
+    /// The current thread is pinned while waiting for the result.
+    pub fn execute(&self, op: Op) -> Op::Output {
+        pin(|scope| {
+            let record = self.publish(op, scope);
+            let mut spins = 0;
+            while !record.done.load(Acquire) {
+                if let Some(mut state) = self.try_lock() {
+                    self.combine(&mut state, scope);
+                } else if spins < SPINS {
+                    spins += 1;
+                    hint::spin_loop();
+                } else {
+                    thread::yield_now();
+                }
+            }
+
+            match unsafe { (*record.result.get()).take() } {
+                Some(Ok(output)) => output,
+                Some(Err(payload)) => panic::resume_unwind(payload),
+                None => unreachable!("a record is done only once its result is set"),
+            }
+        })
+    }
+
+    /// Returns the state, consuming the combiner.
+    pub fn into_inner(self) -> T {
+        self.state.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Publishes `op` in an empty slot, and returns its record.
+    fn publish<'scope>(&self, op: Op, scope: &'scope Scope) -> &'scope Record<Op, Op::Output> {
+        let mut record = AssertEpochSafe(Record {
+            op: UnsafeCell::new(Some(op)),
+            result: UnsafeCell::new(None),
+            done: AtomicBool::new(false),
+        });
+
+        let len = self.records.len();
+        let start = HINT.with(|h| *h);
+        loop {
+            for i in 0..len {
+                match self.records.try_announce((start + i) % len, record, scope) {
+                    // Announcements are only destroyed through the garbage collector.
+                    Ok(published) => return unsafe { &published.deref().0 },
+                    Err(r) => record = r,
+                }
+            }
+
+            // All slots are taken, so help get them applied.
+            match self.try_lock() {
+                Some(mut state) => self.combine(&mut state, scope),
+                None => thread::yield_now(),
+            }
+        }
+    }
+
+    /// Applies all published operations to `state`.
+    fn combine(&self, state: &mut T, scope: &Scope) {
+        for (index, request) in self.records.iter(scope) {
+            // The slot is only emptied by claims, which are made under the lock, so this can't
+            // fail. The record is retired, but its publisher is pinned until it takes the result.
+            if !self.records.claim(index, request, scope) {
+                continue;
+            }
+            let record = unsafe { &request.deref().0 };
+            let op = unsafe { (*record.op.get()).take() };
+            let op = op.expect("a claimed record holds an operation");
+            let result = panic::catch_unwind(AssertUnwindSafe(|| op.apply(state)));
+            unsafe { *record.result.get() = Some(result) };
+            record.done.store(true, Release);
+        }
+    }
+
+    /// Acquires the combiner lock without blocking, ignoring poisoning.
+    ///
+    /// Operations that panic are caught, so the state can't be left halfway through one.
+    fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.state.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+impl<T, Op: Operation<T>> fmt::Debug for Combiner<T, Op> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Combiner").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use crossbeam_utils::scoped;
+
+    use super::*;
+
+    struct Add(u64);
+
+    impl Operation<u64> for Add {
+        type Output = u64;
+
+        fn apply(self, sum: &mut u64) -> u64 {
+            assert!(self.0 != 0, "adding zero");
+            *sum += self.0;
+            *sum
+        }
+    }
+
+    #[test]
+    fn applies_every_operation_once() {
+        let combiner = Combiner::new(0, 4);
+        let seen = Mutex::new(Vec::new());
+
+        scoped::scope(|s| for _ in 0..8 {
+            s.spawn(|| for _ in 0..500 {
+                let sum = combiner.execute(Add(1));
+                seen.lock().unwrap().push(sum);
+            });
+        });
+
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort();
+        assert_eq!(seen, (1..=4000).collect::<Vec<_>>());
+        assert_eq!(combiner.into_inner(), 4000);
+    }
+
+    #[test]
+    fn resumes_panics() {
+        let combiner = Combiner::new(0, 2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| combiner.execute(Add(0))));
+        assert!(result.is_err());
+        assert_eq!(combiner.execute(Add(2)), 2);
+    }
+}
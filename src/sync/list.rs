@@ -3,7 +3,9 @@
 //! Michael.  High Performance Dynamic Lock-Free Hash Tables and List-Based Sets.  SPAA 2002.
 //! http://dl.acm.org/citation.cfm?id=564870.564881
 
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::mem;
+use primitive::atomic::AtomicUsize;
+use primitive::atomic::Ordering::{Acquire, Relaxed, Release};
 
 use {Atomic, Owned, Ptr, Scope, unprotected};
 use crossbeam_utils::cache_padded::CachePadded;
@@ -23,12 +25,20 @@ pub struct Node<T>(CachePadded<NodeInner<T>>);
 
 pub struct List<T> {
     head: Atomic<Node<T>>,
+
+    /// The number of entries marked as deleted that haven't been unlinked yet.
+    ///
+    /// It is incremented before an entry gets marked, so it is never lower than the actual number.
+    deleted: AtomicUsize,
 }
 
 pub struct Iter<'scope, T: 'scope> {
     /// The scope in which the iterator is operating.
     scope: &'scope Scope,
 
+    /// The list being iterated.
+    list: &'scope List<T>,
+
     /// Pointer from the predecessor to the current entry.
     pred: &'scope Atomic<Node<T>>,
 
@@ -46,7 +56,7 @@ impl<T> Node<T> {
     /// Returns the data in this entry.
     fn new(data: T) -> Self {
         Node(CachePadded::new(NodeInner {
-            data: data,
+            data,
             next: Atomic::null(),
         }))
     }
@@ -54,17 +64,43 @@ impl<T> Node<T> {
     pub fn get(&self) -> &T {
         &self.0.data
     }
-
-    /// Marks this entry as deleted.
-    pub fn delete<'scope>(&self, scope: &Scope) {
-        self.0.next.fetch_or(1, Release, scope);
-    }
 }
 
 impl<T> List<T> {
     /// Returns a new, empty linked list.
     pub fn new() -> Self {
-        List { head: Atomic::null() }
+        List {
+            head: Atomic::null(),
+            deleted: AtomicUsize::new(0),
+        }
+    }
+
+    /// Marks `node`, an entry of this list, as deleted.
+    ///
+    /// The entry is unlinked later on, by whoever iterates over it next.
+    pub fn delete(&self, node: &Node<T>, scope: &Scope) {
+        self.deleted.fetch_add(1, Relaxed);
+        node.0.next.fetch_or(1, Release, scope);
+    }
+
+    /// Returns the number of entries that are marked as deleted but haven't been unlinked yet.
+    pub fn deleted(&self) -> usize {
+        self.deleted.load(Relaxed)
+    }
+
+    /// Unlinks all entries that are marked as deleted by iterating over the whole list.
+    ///
+    /// Returns `false` if iteration was aborted because another thread is unlinking entries as
+    /// well, in which case some of them may still be linked.
+    pub fn compact(&self, scope: &Scope) -> bool {
+        let mut iter = self.iter(scope);
+        loop {
+            match iter.next() {
+                IterResult::Some(_) => {}
+                IterResult::None => return true,
+                IterResult::Abort => return false,
+            }
+        }
     }
 
     /// Inserts `data` into the list.
@@ -97,6 +133,23 @@ impl<T> List<T> {
         self.insert(&self.head, data, scope)
     }
 
+    /// Returns the number of entries in the list, including entries that are marked as deleted
+    /// but haven't been unlinked yet.
+    pub fn count(&self, scope: &Scope) -> usize {
+        let mut count = 0;
+        let mut curr = self.head.load(Acquire, scope);
+        while let Some(c) = unsafe { curr.as_ref() } {
+            curr = c.0.next.load(Acquire, scope).with_tag(0);
+            count += 1;
+        }
+        count
+    }
+
+    /// Returns the size of an entry of the list.
+    pub fn node_size() -> usize {
+        mem::size_of::<Node<T>>()
+    }
+
     /// Returns an iterator over all data.
     ///
     /// Every datum that is inserted at the moment this function is called and persists at least
@@ -109,7 +162,12 @@ impl<T> List<T> {
     pub fn iter<'scope>(&'scope self, scope: &'scope Scope) -> Iter<'scope, T> {
         let pred = &self.head;
         let curr = pred.load(Acquire, scope);
-        Iter { scope, pred, curr }
+        Iter {
+            scope,
+            list: self,
+            pred,
+            curr,
+        }
     }
 }
 
@@ -129,7 +187,7 @@ impl<T> Drop for List<T> {
 }
 
 impl<'scope, T> Iter<'scope, T> {
-    pub fn next(&mut self) -> IterResult<T> {
+    pub fn next(&mut self) -> IterResult<'scope, T> {
         while let Some(c) = unsafe { self.curr.as_ref() } {
             let succ = c.0.next.load(Acquire, self.scope);
 
@@ -144,6 +202,7 @@ impl<'scope, T> Iter<'scope, T> {
                     self.scope,
                 ) {
                     Ok(_) => {
+                        self.list.deleted.fetch_sub(1, Relaxed);
                         unsafe {
                             self.scope.defer_free(self.curr);
                         }
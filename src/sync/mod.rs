@@ -1,4 +1,15 @@
 //! Synchronization primitives.
 
-pub mod list;
-pub mod queue;
+mod announce;
+mod array_queue;
+mod combiner;
+pub(crate) mod list;
+pub(crate) mod queue;
+mod segment_list;
+mod writer_lock;
+
+pub use self::announce::Announce;
+pub use self::array_queue::{ArrayQueue, PushError};
+pub use self::combiner::{Combiner, Operation};
+pub use self::segment_list::{Iter, SEGMENT_LEN, SegmentList};
+pub use self::writer_lock::WriterLock;
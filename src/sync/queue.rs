@@ -5,10 +5,13 @@
 //! Michael and Scott.  Simple, Fast, and Practical Non-Blocking and Blocking Concurrent Queue
 //! Algorithms.  PODC 1996.  http://dl.acm.org/citation.cfm?id=248106
 
-use std::{mem, ptr};
-use std::sync::atomic::Ordering::{Relaxed, Acquire, Release};
+use std::alloc::{self, Layout};
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use primitive::atomic::Ordering::{Relaxed, Acquire, Release};
 
-use {Atomic, Owned, Ptr, Scope, pin, unprotected};
+use allocator::AllocRef;
+use {Atomic, Ptr, Scope, pin, unprotected};
 use crossbeam_utils::cache_padded::CachePadded;
 
 // The representation here is a singly-linked list, with a sentinel node at the front. In general
@@ -18,11 +21,14 @@ use crossbeam_utils::cache_padded::CachePadded;
 pub struct Queue<T> {
     head: CachePadded<Atomic<Node<T>>>,
     tail: CachePadded<Atomic<Node<T>>>,
+    /// The allocator of the nodes.
+    alloc: AllocRef,
 }
 
 #[derive(Debug)]
 struct Node<T> {
-    data: T,
+    /// The payload. Uninitialized in the sentinel node.
+    data: MaybeUninit<T>,
     next: Atomic<Node<T>>,
 }
 
@@ -33,22 +39,52 @@ unsafe impl<T: Send> Send for Queue<T> {}
 
 impl<T> Queue<T> {
     /// Create a new, empty queue.
+    #[allow(dead_code)]
     pub fn new() -> Queue<T> {
+        Self::new_in(AllocRef::global())
+    }
+
+    /// Create a new, empty queue whose nodes are allocated from `alloc`.
+    pub fn new_in(alloc: AllocRef) -> Queue<T> {
         let q = Queue {
             head: CachePadded::new(Atomic::null()),
             tail: CachePadded::new(Atomic::null()),
+            alloc,
         };
-        let sentinel = Owned::new(Node {
-            data: unsafe { mem::uninitialized() },
-            next: Atomic::null(),
-        });
+        let sentinel = match q.alloc_node(MaybeUninit::uninit()) {
+            Ok(sentinel) => sentinel,
+            Err(_) => alloc::handle_alloc_error(Layout::new::<Node<T>>()),
+        };
+        let sentinel = Ptr::from_raw(sentinel);
+        q.head.store(sentinel, Relaxed);
+        q.tail.store(sentinel, Relaxed);
+        q
+    }
+
+    /// Allocate a node holding `data`, or return `data` back if the allocation fails.
+    fn alloc_node(&self, data: MaybeUninit<T>) -> Result<*const Node<T>, MaybeUninit<T>> {
         unsafe {
-            unprotected(|scope| {
-                let sentinel = sentinel.into_ptr(scope);
-                q.head.store(sentinel, Relaxed);
-                q.tail.store(sentinel, Relaxed);
-                q
-            })
+            let node = self.alloc.alloc(Layout::new::<Node<T>>()) as *mut Node<T>;
+            if node.is_null() {
+                return Err(data);
+            }
+            node.write(Node {
+                data,
+                next: Atomic::null(),
+            });
+            Ok(node)
+        }
+    }
+
+    /// Free the unlinked node `node` once no thread may be reading it anymore. The data must have
+    /// been moved out of it already.
+    unsafe fn defer_free_node(&self, node: Ptr<Node<T>>, scope: &Scope) {
+        if self.alloc.is_global() {
+            scope.defer_free(node);
+        } else {
+            let alloc = self.alloc;
+            let node = node.as_raw() as usize;
+            scope.defer_unchecked(move || alloc.dealloc(node as *mut u8, Layout::new::<Node<T>>()));
         }
     }
 
@@ -77,12 +113,20 @@ impl<T> Queue<T> {
     }
 
     /// Add `t` to the back of the queue, possibly waking up threads blocked on `pop`.
+    #[allow(dead_code)]
     pub fn push(&self, t: T, scope: &Scope) {
-        let new = Owned::new(Node {
-            data: t,
-            next: Atomic::null(),
-        });
-        let new = Owned::into_ptr(new, scope);
+        if let Err(t) = self.try_push(t, scope) {
+            mem::forget(t);
+            alloc::handle_alloc_error(Layout::new::<Node<T>>());
+        }
+    }
+
+    /// Add `t` to the back of the queue, or return it back if allocating a node for it fails.
+    pub fn try_push(&self, t: T, scope: &Scope) -> Result<(), T> {
+        let new = match self.alloc_node(MaybeUninit::new(t)) {
+            Ok(new) => Ptr::from_raw(new),
+            Err(data) => return Err(unsafe { data.assume_init() }),
+        };
 
         loop {
             // We push onto the tail, so we'll start optimistically by looking there first.
@@ -93,6 +137,7 @@ impl<T> Queue<T> {
                 break;
             }
         }
+        Ok(())
     }
 
     #[inline(always)]
@@ -106,8 +151,8 @@ impl<T> Queue<T> {
                 self.head
                     .compare_and_set(head, next, Release, scope)
                     .map(|_| {
-                        scope.defer_free(head);
-                        Some(ptr::read(&n.data))
+                        self.defer_free_node(head, scope);
+                        Some(ptr::read(n.data.as_ptr()))
                     })
                     .map_err(|_| ())
             },
@@ -127,12 +172,12 @@ impl<T> Queue<T> {
         let h = unsafe { head.deref() };
         let next = h.next.load(Acquire, scope);
         match unsafe { next.as_ref() } {
-            Some(n) if condition(&n.data) => unsafe {
+            Some(n) if condition(unsafe { &*n.data.as_ptr() }) => unsafe {
                 self.head
                     .compare_and_set(head, next, Release, scope)
                     .map(|_| {
-                        scope.defer_free(head);
-                        Some(ptr::read(&n.data))
+                        self.defer_free_node(head, scope);
+                        Some(ptr::read(n.data.as_ptr()))
                     })
                     .map_err(|_| ())
             },
@@ -140,7 +185,47 @@ impl<T> Queue<T> {
         }
     }
 
+    /// Returns the number of items in the queue.
+    ///
+    /// Items may be concurrently pushed and popped, so the result is only a snapshot.
+    pub fn len(&self, scope: &Scope) -> usize {
+        let mut len = 0;
+        let mut node = self.head.load(Acquire, scope);
+        while let Some(n) = unsafe { node.as_ref() } {
+            node = n.next.load(Acquire, scope);
+            len += 1;
+        }
+        // Don't count the sentinel node.
+        len - 1
+    }
+
+    /// Returns the sum of `f` applied to all items in the queue.
+    ///
+    /// Items may be concurrently pushed and popped, so the result is only a snapshot. An item may
+    /// be concurrently dequeued while `f` is looking at it, but it won't be dropped before `f`
+    /// returns.
+    pub fn sum_by<F>(&self, f: F, scope: &Scope) -> usize
+    where
+        T: Sync,
+        F: Fn(&T) -> usize,
+    {
+        let mut sum = 0;
+        let head = self.head.load(Acquire, scope);
+        let mut node = unsafe { head.deref() }.next.load(Acquire, scope);
+        while let Some(n) = unsafe { node.as_ref() } {
+            sum += f(unsafe { &*n.data.as_ptr() });
+            node = n.next.load(Acquire, scope);
+        }
+        sum
+    }
+
+    /// Returns the size of a node of the queue, each of which holds one item.
+    pub fn node_size() -> usize {
+        mem::size_of::<Node<T>>()
+    }
+
     /// Check if this queue is empty.
+    #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         pin(|scope| {
             let head = self.head.load(Acquire, scope);
@@ -149,6 +234,21 @@ impl<T> Queue<T> {
         })
     }
 
+    /// Applies `f` to the item at the front of the queue, if any.
+    ///
+    /// The item may be concurrently dequeued while `f` is looking at it, but it won't be dropped
+    /// before `f` returns.
+    pub fn peek_with<F, R>(&self, f: F, scope: &Scope) -> Option<R>
+    where
+        T: Sync,
+        F: FnOnce(&T) -> R,
+    {
+        let head = self.head.load(Acquire, scope);
+        let h = unsafe { head.deref() };
+        let next = h.next.load(Acquire, scope);
+        unsafe { next.as_ref() }.map(|n| f(unsafe { &*n.data.as_ptr() }))
+    }
+
     /// Attempt to dequeue from the front.
     ///
     /// Returns `None` if the queue is observed to be empty.
@@ -181,11 +281,11 @@ impl<T> Drop for Queue<T> {
     fn drop(&mut self) {
         unsafe {
             unprotected(|scope| {
-                while let Some(_) = self.try_pop(scope) {}
+                while self.try_pop(scope).is_some() {}
 
                 // Destroy the remaining sentinel node.
-                let sentinel = self.head.load(Relaxed, scope).as_raw() as *mut Node<T>;
-                drop(Vec::from_raw_parts(sentinel, 0, 1));
+                let sentinel = self.head.load(Relaxed, scope).as_raw() as *mut u8;
+                self.alloc.dealloc(sentinel, Layout::new::<Node<T>>());
             })
         }
     }
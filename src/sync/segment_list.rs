@@ -0,0 +1,373 @@
+//! An append-only log of segments.
+//!
+//! Items are pushed to the back of the log and never move, so readers can iterate over the log
+//! while it grows, without locking. The log is stored as a linked list of fixed-size segments:
+//! pushing reserves an index with a single `fetch_add`, and appends a new segment only once every
+//! `SEGMENT_LEN` items. Old segments are dropped from the front by truncation, and destroyed
+//! through the garbage collector once no reader can be iterating over them anymore.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ptr;
+use primitive::atomic::{AtomicBool, AtomicUsize};
+use primitive::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use crossbeam_utils::cache_padded::CachePadded;
+
+use {Atomic, EpochSafe, Owned, Ptr, Scope, unprotected};
+
+/// The number of items in a segment.
+pub const SEGMENT_LEN: usize = 32;
+
+/// A slot for one item.
+struct Slot<T> {
+    /// Whether the item has been written.
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A segment of `SEGMENT_LEN` consecutive items.
+struct Segment<T> {
+    /// The index of the first item of the segment.
+    start: usize,
+    slots: [Slot<T>; SEGMENT_LEN],
+    /// The next segment, once it has been appended.
+    next: Atomic<Segment<T>>,
+}
+
+// Items are written only once, before they're marked ready, so they're never accessed mutably
+// while shared.
+unsafe impl<T: Send + Sync> Sync for Segment<T> {}
+
+// Dropping a segment drops nothing but its items, and the cells merely hold them.
+#[cfg(feature = "nightly")]
+unsafe impl<T: EpochSafe> EpochSafe for Segment<T> {}
+
+impl<T> Segment<T> {
+    /// Returns a new empty segment starting at index `start`.
+    fn new(start: usize) -> Self {
+        Segment {
+            start,
+            slots: ::std::array::from_fn(|_| Slot {
+                ready: AtomicBool::new(false),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            next: Atomic::null(),
+        }
+    }
+
+    /// Returns `true` if the segment holds index `index`.
+    fn contains(&self, index: usize) -> bool {
+        self.start <= index && index < self.start + SEGMENT_LEN
+    }
+
+    /// Returns `true` if every item of the segment has been written.
+    fn is_complete(&self) -> bool {
+        self.slots.iter().all(|slot| slot.ready.load(Acquire))
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if slot.ready.load(Relaxed) {
+                unsafe { ptr::drop_in_place(slot.value.get_mut().as_mut_ptr()) }
+            }
+        }
+    }
+}
+
+/// An append-only segmented vector whose old segments are reclaimed through the garbage
+/// collector.
+///
+/// Any number of threads may push items and iterate over the list concurrently. An iteration
+/// sees the items whose push completed before it reached them, up to the length of the list when
+/// it started. Truncation drops whole segments from the front, which are destroyed once no
+/// iteration can still be looking at them.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use crossbeam_epoch::sync::SegmentList;
+///
+/// let log = SegmentList::new();
+///
+/// epoch::pin(|scope| {
+///     for i in 0..100 {
+///         log.push(i, scope);
+///     }
+///     assert_eq!(log.len(), 100);
+///
+///     // Only whole segments are truncated, so some items before index 50 may remain.
+///     log.truncate(50, scope);
+///     let (first, &value) = log.iter(scope).next().unwrap();
+///     assert!(first <= 50 && first == value);
+///     assert_eq!(log.iter(scope).last(), Some((99, &99)));
+/// });
+/// ```
+pub struct SegmentList<T> {
+    /// The first segment.
+    head: CachePadded<Atomic<Segment<T>>>,
+    /// A recent segment, at or after the first one, that pushes start looking from.
+    tail: CachePadded<Atomic<Segment<T>>>,
+    /// The number of indices reserved so far.
+    len: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send + Sync> Sync for SegmentList<T> {}
+unsafe impl<T: Send> Send for SegmentList<T> {}
+
+impl<T: Send + Sync + EpochSafe + 'static> SegmentList<T> {
+    /// Returns a new empty list.
+    pub fn new() -> Self {
+        let list = SegmentList {
+            head: CachePadded::new(Atomic::null()),
+            tail: CachePadded::new(Atomic::null()),
+            len: CachePadded::new(AtomicUsize::new(0)),
+        };
+        unsafe {
+            unprotected(|scope| {
+                let first = Owned::new(Segment::new(0)).into_ptr(scope);
+                list.head.store(first, Relaxed);
+                list.tail.store(first, Relaxed);
+            })
+        }
+        list
+    }
+
+    /// Returns the number of items pushed so far, including those removed by truncation, and
+    /// those whose push is still in progress.
+    pub fn len(&self) -> usize {
+        self.len.load(Acquire)
+    }
+
+    /// Returns `true` if nothing has been pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the index of the first item that hasn't been removed by truncation.
+    pub fn first_index(&self, scope: &Scope) -> usize {
+        unsafe { self.head.load(Acquire, scope).deref() }.start
+    }
+
+    /// Appends `value` to the back of the list, and returns its index.
+    pub fn push(&self, value: T, scope: &Scope) -> usize {
+        let index = self.len.fetch_add(1, Relaxed);
+        let segment = self.segment_for(index, scope);
+        let slot = &segment.slots[index - segment.start];
+        unsafe { (*slot.value.get()).write(value) };
+        slot.ready.store(true, Release);
+        index
+    }
+
+    /// Returns the segment holding `index`, appending segments as needed.
+    fn segment_for<'scope>(&self, index: usize, scope: &'scope Scope) -> &'scope Segment<T> {
+        // The segment of an index that is being pushed is incomplete, so it isn't truncated and
+        // lies at or after the head. It may lie before the tail if the push is lagging behind.
+        let mut current = self.tail.load(Acquire, scope);
+        if unsafe { current.deref() }.start > index {
+            current = self.head.load(Acquire, scope);
+        }
+
+        loop {
+            let segment = unsafe { current.deref() };
+            if segment.contains(index) {
+                return segment;
+            }
+
+            let mut next = segment.next.load(Acquire, scope);
+            if next.is_null() {
+                let new = Owned::new(Segment::new(segment.start + SEGMENT_LEN));
+                next = match segment.next.compare_and_set_owned(Ptr::null(), new, Release, scope) {
+                    Ok(new) => new,
+                    Err((next, _)) => next,
+                };
+            }
+
+            // Move the tail forward, unless another thread already has.
+            let _ = self.tail.compare_and_set(current, next, Release, scope);
+            current = next;
+        }
+    }
+
+    /// Removes the segments whose items all lie before index `index` from the front of the
+    /// list, and defers their destruction.
+    ///
+    /// Segments are removed only as a whole, and only once every push into them has completed.
+    /// The last segment is never removed.
+    pub fn truncate(&self, index: usize, scope: &Scope) {
+        loop {
+            let head = self.head.load(Acquire, scope);
+            let segment = unsafe { head.deref() };
+            let next = segment.next.load(Acquire, scope);
+            let removable = segment.start + SEGMENT_LEN <= index && segment.is_complete();
+
+            // The tail never lies before the head, so the segment is removed only once the tail
+            // has moved past it.
+            let tail = self.tail.load(Acquire, scope);
+            if !removable || next.is_null() || tail.as_raw() == head.as_raw() {
+                return;
+            }
+            if self.head.compare_and_set(head, next, Release, scope).is_ok() {
+                unsafe { scope.defer_drop(head) }
+            }
+        }
+    }
+
+    /// Returns an iterator over the items of the list and their indices, starting from the first
+    /// item that hasn't been removed by truncation.
+    ///
+    /// Items pushed after the iteration started, or whose push hasn't completed when the
+    /// iteration reaches them, are skipped.
+    pub fn iter<'scope>(&'scope self, scope: &'scope Scope) -> Iter<'scope, T> {
+        Iter {
+            segment: self.head.load(Acquire, scope),
+            index: 0,
+            end: self.len(),
+            scope,
+        }
+    }
+}
+
+impl<T: Send + Sync + EpochSafe + 'static> Default for SegmentList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for SegmentList<T> {
+    fn drop(&mut self) {
+        unsafe {
+            unprotected(|scope| {
+                let mut segment = self.head.load(Relaxed, scope);
+                while !segment.is_null() {
+                    let next = segment.deref().next.load(Relaxed, scope);
+                    drop(segment.into_owned());
+                    segment = next;
+                }
+            })
+        }
+    }
+}
+
+impl<T> fmt::Debug for SegmentList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SegmentList")
+            .field("len", &self.len.load(Relaxed))
+            .finish()
+    }
+}
+
+/// An iterator over the items of a [`SegmentList`].
+///
+/// Created by [`SegmentList::iter`].
+///
+/// [`SegmentList`]: struct.SegmentList.html
+/// [`SegmentList::iter`]: struct.SegmentList.html#method.iter
+pub struct Iter<'scope, T: 'scope> {
+    /// The segment being iterated over, or null at the end.
+    segment: Ptr<'scope, Segment<T>>,
+    /// The position in the segment.
+    index: usize,
+    /// The length of the list when the iteration started.
+    end: usize,
+    scope: &'scope Scope,
+}
+
+impl<'scope, T> Iterator for Iter<'scope, T> {
+    type Item = (usize, &'scope T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(segment) = unsafe { self.segment.as_ref() } {
+            if segment.start + self.index >= self.end {
+                self.segment = Ptr::null();
+                return None;
+            }
+            if self.index == SEGMENT_LEN {
+                self.segment = segment.next.load(Acquire, self.scope);
+                self.index = 0;
+                continue;
+            }
+
+            let slot = &segment.slots[self.index];
+            self.index += 1;
+            if slot.ready.load(Acquire) {
+                let value = unsafe { &*(*slot.value.get()).as_ptr() };
+                return Some((segment.start + self.index - 1, value));
+            }
+        }
+        None
+    }
+}
+
+impl<'scope, T> fmt::Debug for Iter<'scope, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Iter").field("end", &self.end).finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use crossbeam_utils::scoped;
+
+    use pin;
+    use testkit::Drops;
+    use super::*;
+
+    #[test]
+    fn concurrent_push_and_iter() {
+        const THREADS: usize = 4;
+        const PUSHES: usize = 1000;
+
+        let list = SegmentList::new();
+        scoped::scope(|s| {
+            for t in 0..THREADS {
+                let list = &list;
+                s.spawn(move || for i in 0..PUSHES {
+                    pin(|scope| list.push(t * PUSHES + i, scope));
+                });
+            }
+            s.spawn(|| for _ in 0..100 {
+                pin(|scope| {
+                    let mut last = None;
+                    for (index, _) in list.iter(scope) {
+                        assert!(last < Some(index));
+                        last = Some(index);
+                    }
+                })
+            });
+        });
+
+        pin(|scope| {
+            let mut values = list.iter(scope).map(|(_, &v)| v).collect::<Vec<_>>();
+            values.sort();
+            assert_eq!(values, (0..THREADS * PUSHES).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn truncation_drops_segments() {
+        let drops = Drops::new();
+        let list = SegmentList::new();
+        pin(|scope| {
+            for _ in 0..SEGMENT_LEN * 3 + 1 {
+                list.push(drops.wrap(()), scope);
+            }
+            list.truncate(SEGMENT_LEN * 2 + 1, scope);
+            assert_eq!(list.first_index(scope), SEGMENT_LEN * 2);
+            assert_eq!(list.iter(scope).count(), SEGMENT_LEN + 1);
+        });
+
+        // The last segment is never removed.
+        pin(|scope| list.truncate(usize::MAX, scope));
+        pin(|scope| assert_eq!(list.first_index(scope), SEGMENT_LEN * 3));
+
+        drop(list);
+        ::assert_reclaimed!(drops, SEGMENT_LEN * 3 + 1);
+    }
+}
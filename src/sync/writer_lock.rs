@@ -0,0 +1,141 @@
+//! A lock for coordinating writers of an epoch-protected data structure.
+//!
+//! Readers of such data structures never block, but writers sometimes need to be serialized. A
+//! common mistake is to pin the current thread and then wait for a mutex: the pinned thread blocks
+//! epoch advancement for as long as it waits, and in turn stalls garbage collection everywhere.
+//! `WriterLock` acquires the lock first and pins the thread only after acquisition.
+
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+use {Scope, is_pinned, pin};
+
+/// A lock that serializes writers of an epoch-protected data structure.
+///
+/// The lock is never waited for while the current thread is pinned.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{Atomic, Owned};
+/// use crossbeam_epoch::sync::WriterLock;
+/// use std::sync::atomic::Ordering::{Acquire, Release};
+///
+/// let lock = WriterLock::new();
+/// let a = Atomic::new(1);
+///
+/// lock.write(|scope| {
+///     let old = a.load(Acquire, scope);
+///     let new = unsafe { old.deref() } + 1;
+///     a.store_owned(Owned::new(new), Release);
+///     unsafe { scope.defer_free(old) }
+/// });
+/// ```
+#[derive(Debug, Default)]
+pub struct WriterLock {
+    mutex: Mutex<()>,
+}
+
+impl WriterLock {
+    /// Returns a new unlocked writer lock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Acquires the lock, pins the current thread, and executes `f` exclusively.
+    ///
+    /// The thread is pinned only after the lock has been acquired, so waiting for other writers
+    /// doesn't hold back epoch advancement.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if the current thread is already pinned, since the pin would then be
+    /// held while waiting for the lock.
+    pub fn write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope) -> R,
+    {
+        debug_assert!(
+            !is_pinned(),
+            "`WriterLock::write` must not be called while the current thread is pinned"
+        );
+
+        let _guard = lock(&self.mutex);
+        pin(f)
+    }
+
+    /// Attempts to acquire the lock without blocking, and if it succeeds, pins the current thread
+    /// and executes `f` exclusively.
+    ///
+    /// Returns `None` if the lock is held by another writer. Unlike [`write`], this method may be
+    /// called while the current thread is pinned.
+    ///
+    /// [`write`]: struct.WriterLock.html#method.write
+    pub fn try_write<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&Scope) -> R,
+    {
+        let _guard = match self.mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(pin(f))
+    }
+}
+
+/// Acquires `mutex`, ignoring poisoning.
+///
+/// The mutex doesn't protect any data by itself, so a writer that panicked can't leave anything in
+/// an inconsistent state that the lock knows about.
+fn lock(mutex: &Mutex<()>) -> MutexGuard<'_, ()> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use crossbeam_utils::scoped;
+
+    use {Atomic, Owned, pin};
+    use super::*;
+
+    #[test]
+    fn exclusive_writers() {
+        let lock = WriterLock::new();
+        let writing = AtomicBool::new(false);
+        let a = Atomic::new(0);
+
+        scoped::scope(|s| for _ in 0..4 {
+            s.spawn(|| for _ in 0..1000 {
+                lock.write(|scope| {
+                    assert!(!writing.swap(true, SeqCst));
+                    let old = a.load(SeqCst, scope);
+                    a.store_owned(Owned::new(unsafe { old.deref() } + 1), SeqCst);
+                    unsafe { scope.defer_drop(old) }
+                    writing.store(false, SeqCst);
+                });
+            });
+        });
+
+        pin(|scope| assert_eq!(unsafe { a.load(SeqCst, scope).deref() }, &4000));
+    }
+
+    #[test]
+    fn try_write_contended() {
+        let lock = WriterLock::new();
+        pin(|_| {
+            assert_eq!(lock.try_write(|_| lock.try_write(|_| ())), Some(None));
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "must not be called while the current thread is pinned")]
+    fn write_while_pinned() {
+        let lock = WriterLock::new();
+        pin(|_| lock.write(|_| ()));
+    }
+}
@@ -0,0 +1,194 @@
+//! Platform-specific pieces
+//!
+//! Everything the collector needs from the operating system or the CPU beyond what `std` provides
+//! portably goes through the [`Platform`] trait: the fence announcing a pinning, the monotonic
+//! clock timing pauses and pinnings, and the memory pressure reading of [`watch_memory_pressure`].
+//! [`Host`] is the implementation for the target being built for, so a port to a new operating
+//! system only implements `Platform` and selects it here, instead of adding `cfg`s at call sites.
+//! The defaults of the trait are portable, so an implementation only overrides what the platform
+//! does better.
+//!
+//! Atomics and thread-locals aren't part of it: they come from [`primitive`], which swaps them for
+//! those of `loom` when model checking.
+//!
+//! [`Platform`]: trait.Platform.html
+//! [`Host`]: type.Host.html
+//! [`watch_memory_pressure`]: ../fn.watch_memory_pressure.html
+//! [`primitive`]: ../primitive/index.html
+
+#[cfg(target_os = "linux")]
+use std::fs;
+use std::io;
+use std::ops::Add;
+use std::time::{Duration, Instant};
+
+use primitive::atomic::{self, AtomicUsize};
+use primitive::atomic::Ordering::{Relaxed, SeqCst};
+
+/// The services the collector takes from the platform.
+pub trait Platform {
+    /// Stores `state` into `slot`, which holds zero, and makes sure that no load following the
+    /// call is reordered before the store.
+    #[inline]
+    fn announce(slot: &AtomicUsize, state: usize) {
+        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+            // On x86 architectures we have a choice:
+            // 1. `atomic::fence(SeqCst)`, which compiles to a `mfence` instruction.
+            // 2. `compare_exchange(_, _, SeqCst, SeqCst)`, which compiles to a `lock cmpxchg`
+            //    instruction.
+            //
+            // Both instructions have the effect of a full barrier, but the second one seems to be
+            // faster in this particular case.
+            let result = slot.compare_exchange(0, state, SeqCst, SeqCst);
+            debug_assert_eq!(Ok(0), result, "the announced slot should hold zero");
+        } else {
+            slot.store(state, Relaxed);
+            atomic::fence(SeqCst);
+        }
+    }
+
+    /// Returns the current time of a monotonic clock.
+    #[inline]
+    fn now() -> Instant {
+        Instant::now()
+    }
+
+    /// Returns the share of time in percent that some task was stalled on memory over the last
+    /// ten seconds, system-wide.
+    fn memory_pressure() -> io::Result<f64> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "memory pressure isn't available"))
+    }
+}
+
+/// Linux.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Linux;
+
+#[cfg(target_os = "linux")]
+impl Platform for Linux {
+    fn memory_pressure() -> io::Result<f64> {
+        // The pressure stall information requires Linux 4.20 or later.
+        let psi = fs::read_to_string("/proc/pressure/memory")?;
+        parse_some_avg10(&psi)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed pressure file"))
+    }
+}
+
+/// Returns the share of time in percent that some task was stalled over the last ten seconds,
+/// given the contents of a pressure stall information file.
+#[cfg(target_os = "linux")]
+fn parse_some_avg10(psi: &str) -> Option<f64> {
+    let line = psi.lines().find(|l| l.starts_with("some "))?;
+    line.split_whitespace()
+        .filter_map(|field| field.strip_prefix("avg10="))
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Any platform `std` supports, using the portable defaults.
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct Generic;
+
+#[cfg(not(target_os = "linux"))]
+impl Platform for Generic {}
+
+/// The platform being built for.
+#[cfg(target_os = "linux")]
+pub type Host = Linux;
+
+/// The platform being built for.
+#[cfg(not(target_os = "linux"))]
+pub type Host = Generic;
+
+/// Stores `state` into `slot` with [`Platform::announce`] of the host.
+///
+/// [`Platform::announce`]: trait.Platform.html#method.announce
+#[inline]
+pub fn announce(slot: &AtomicUsize, state: usize) {
+    Host::announce(slot, state)
+}
+
+/// A point in time, as read from the clock of the host.
+///
+/// Time only ever passes as measured by [`Platform::now`], so that the clock of the host is the
+/// only one the collector goes by.
+///
+/// [`Platform::now`]: trait.Platform.html#method.now
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(Instant);
+
+impl Timestamp {
+    /// Returns the time that passed since `self`.
+    #[inline]
+    pub fn elapsed(self) -> Duration {
+        now().since(self)
+    }
+
+    /// Returns the time that passed from `earlier` to `self`, or zero if `earlier` is later.
+    #[inline]
+    pub fn since(self, earlier: Timestamp) -> Duration {
+        self.0.saturating_duration_since(earlier.0)
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0 + duration)
+    }
+}
+
+impl From<Timestamp> for Instant {
+    fn from(timestamp: Timestamp) -> Instant {
+        timestamp.0
+    }
+}
+
+/// Returns the current time with [`Platform::now`] of the host.
+///
+/// [`Platform::now`]: trait.Platform.html#method.now
+#[inline]
+pub fn now() -> Timestamp {
+    Timestamp(Host::now())
+}
+
+/// Returns the memory pressure with [`Platform::memory_pressure`] of the host.
+///
+/// [`Platform::memory_pressure`]: trait.Platform.html#method.memory_pressure
+pub fn memory_pressure() -> io::Result<f64> {
+    Host::memory_pressure()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_pressure() {
+        let psi = "some avg10=1.53 avg60=0.20 avg300=0.04 total=18265\n\
+                   full avg10=0.50 avg60=0.07 avg300=0.01 total=9022\n";
+        assert_eq!(parse_some_avg10(psi), Some(1.53));
+        assert_eq!(parse_some_avg10("full avg10=0.50"), None);
+    }
+
+    #[test]
+    fn timestamps_measure_forward() {
+        let earlier = now();
+        let later = earlier + Duration::from_millis(5);
+        assert_eq!(later.since(earlier), Duration::from_millis(5));
+        assert_eq!(earlier.since(later), Duration::ZERO);
+        assert!(earlier.elapsed() <= now().since(earlier));
+    }
+
+    #[test]
+    fn announce_stores_state() {
+        let slot = AtomicUsize::new(0);
+        announce(&slot, 5);
+        assert_eq!(slot.load(Relaxed), 5);
+    }
+}
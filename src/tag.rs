@@ -0,0 +1,161 @@
+//! Garbage attribution
+//!
+//! When several data structures share the global garbage collector, it's hard to tell which of
+//! them produces a backlog of garbage. Each data structure can create a [`GarbageTag`] with an id
+//! of its choice and defer its garbage with [`Scope::defer_tagged`]. The number of pending and
+//! reclaimed objects is then counted per id, and can be inspected with [`tagged_garbage`].
+//!
+//! [`GarbageTag`]: struct.GarbageTag.html
+//! [`Scope::defer_tagged`]: struct.Scope.html#method.defer_tagged
+//! [`tagged_garbage`]: fn.tagged_garbage.html
+
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// Garbage counters of a tag.
+#[derive(Debug)]
+struct Counts {
+    id: usize,
+    pending: AtomicUsize,
+    reclaimed: AtomicUsize,
+}
+
+/// Counters of all tags created so far.
+static TAGS: Mutex<Vec<Arc<Counts>>> = Mutex::new(Vec::new());
+
+/// A tag that garbage can be attributed to.
+///
+/// Tags with the same id share their counters.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, GarbageTag};
+///
+/// let tag = GarbageTag::new(42);
+/// epoch::pin(|scope| unsafe { scope.defer_tagged(&tag, || ()) });
+///
+/// let stats = tag.stats();
+/// assert_eq!(stats.pending + stats.reclaimed, 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct GarbageTag {
+    counts: Arc<Counts>,
+}
+
+/// Numbers of objects attributed to a tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TagStats {
+    /// The id of the tag.
+    pub id: usize,
+    /// Number of deferred objects that haven't been destroyed yet.
+    pub pending: usize,
+    /// Number of deferred objects that have been destroyed.
+    pub reclaimed: usize,
+}
+
+impl GarbageTag {
+    /// Returns the tag with the given `id`, creating it if it doesn't exist yet.
+    pub fn new(id: usize) -> Self {
+        let mut tags = TAGS.lock().unwrap_or_else(|e| e.into_inner());
+
+        let counts = match tags.iter().find(|c| c.id == id) {
+            Some(counts) => counts.clone(),
+            None => {
+                let counts = Arc::new(Counts {
+                    id,
+                    pending: AtomicUsize::new(0),
+                    reclaimed: AtomicUsize::new(0),
+                });
+                tags.push(counts.clone());
+                counts
+            }
+        };
+
+        GarbageTag { counts }
+    }
+
+    /// Returns the id of the tag.
+    pub fn id(&self) -> usize {
+        self.counts.id
+    }
+
+    /// Returns the numbers of objects attributed to the tag.
+    pub fn stats(&self) -> TagStats {
+        stats_of(&self.counts)
+    }
+
+    /// Records that an object was deferred, and returns a function to call once it's destroyed.
+    pub(crate) fn deferred(&self) -> impl FnOnce() + Send + 'static {
+        self.counts.pending.fetch_add(1, Relaxed);
+
+        let counts = self.counts.clone();
+        move || {
+            counts.pending.fetch_sub(1, Relaxed);
+            counts.reclaimed.fetch_add(1, Relaxed);
+        }
+    }
+}
+
+/// Returns a snapshot of `counts`.
+fn stats_of(counts: &Counts) -> TagStats {
+    TagStats {
+        id: counts.id,
+        pending: counts.pending.load(Relaxed),
+        reclaimed: counts.reclaimed.load(Relaxed),
+    }
+}
+
+/// Returns the numbers of objects attributed to every tag, with the most pending objects first.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// for stats in epoch::tagged_garbage() {
+///     println!("structure {}: {} pending", stats.id, stats.pending);
+/// }
+/// ```
+pub fn tagged_garbage() -> Vec<TagStats> {
+    let tags = TAGS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut stats = tags.iter().map(|c| stats_of(c)).collect::<Vec<_>>();
+    stats.sort_by_key(|s| Reverse(s.pending));
+    stats
+}
+
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use pin;
+    use super::*;
+
+    #[test]
+    fn counts_per_id() {
+        let a = GarbageTag::new(1001);
+        let b = GarbageTag::new(1002);
+
+        pin(|scope| unsafe {
+            for _ in 0..3 {
+                scope.defer_tagged(&a, || ());
+            }
+            scope.defer_tagged(&b, || ());
+        });
+
+        let total = |s: TagStats| s.pending + s.reclaimed;
+        assert_eq!(total(GarbageTag::new(1001).stats()), 3);
+        assert_eq!(total(b.stats()), 1);
+        assert!(tagged_garbage().iter().any(|s| s.id == 1002));
+
+        for _ in 0..100_000 {
+            if a.stats().pending == 0 && b.stats().pending == 0 {
+                break;
+            }
+            pin(|scope| scope.flush());
+        }
+        assert_eq!(a.stats().reclaimed, 3);
+        assert_eq!(b.stats().reclaimed, 1);
+    }
+}
@@ -0,0 +1,673 @@
+//! Test utilities
+//!
+//! # Simulated thread schedules
+//!
+//! Concurrent data structures often break only under rare interleavings, which plain tests hit by
+//! luck and full model checkers explore at a high cost. This module sits in between: it runs a
+//! set of closures on separate threads, but lets only one of them run at a time. Whenever a thread
+//! reaches a yield point, a pseudorandom number generator seeded by the caller decides which
+//! thread runs next.
+//!
+//! Yield points are injected when a mutator is pinned, before every compare-and-set on an
+//! [`Atomic`], and whenever garbage is deferred. Code that spins waiting for another thread must
+//! call [`yield_now`] in the loop, or the schedule will never switch to that thread.
+//!
+//! Simulated threads must not block on each other in any other way, e.g. on a mutex held across a
+//! yield point, as that deadlocks the simulation.
+//!
+//! Running the same closures with the same seed yields the same schedule, so a failing seed
+//! reported by [`explore`] can be replayed with [`Simulation::run`]. The state of the global
+//! collector is shared with the rest of the program though, so the timing of garbage collection
+//! may still differ between runs.
+//!
+//! # Counting reclaimed values
+//!
+//! Tests that check whether retired objects get destroyed usually loop over flushing and pinning
+//! until a counter reaches the expected value. Values wrapped with [`Drops::wrap`] are counted when
+//! dropped, and [`assert_reclaimed!`] drives garbage collection until the count is reached or a
+//! timeout expires.
+//!
+//! This module is only available with the `testkit` feature enabled.
+//!
+//! [`Atomic`]: ../struct.Atomic.html
+//! [`yield_now`]: fn.yield_now.html
+//! [`explore`]: fn.explore.html
+//! [`Simulation::run`]: struct.Simulation.html#method.run
+//! [`Drops::wrap`]: struct.Drops.html#method.wrap
+//! [`assert_reclaimed!`]: ../macro.assert_reclaimed.html
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(test)]
+use std::sync::Once;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+use std::time::Duration;
+
+use collector::LocalHandle;
+use global::{self, pin};
+use mutator::Scope;
+use sys;
+
+/// Only one simulation runs at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The scheduler of the simulated thread, and its index.
+    static CURRENT: RefCell<Option<(Arc<Scheduler>, usize)>> = const { RefCell::new(None) };
+
+    /// Number of live `Quiet` guards on the thread.
+    static QUIET: Cell<usize> = const { Cell::new(0) };
+}
+
+/// State of a simulation, shared by its threads.
+struct State {
+    /// Index of the thread that is allowed to run.
+    running: Option<usize>,
+    /// Whether each thread is still running its closure.
+    alive: Vec<bool>,
+    /// State of the pseudorandom number generator.
+    rng: u64,
+}
+
+impl State {
+    /// Picks the next thread to run among the live ones.
+    fn pick(&mut self) -> Option<usize> {
+        let alive = self.alive.iter().filter(|&&a| a).count();
+        if alive == 0 {
+            return None;
+        }
+
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let r = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d);
+
+        let n = (r % alive as u64) as usize;
+        self.alive.iter().enumerate().filter(|&(_, &a)| a).map(|(i, _)| i).nth(n)
+    }
+}
+
+/// Passes control between the threads of a simulation.
+struct Scheduler {
+    state: Mutex<State>,
+    turn: Condvar,
+}
+
+impl Scheduler {
+    fn new(seed: u64, threads: usize) -> Self {
+        Scheduler {
+            state: Mutex::new(State {
+                running: None,
+                alive: vec![true; threads],
+                // The generator must not start at zero.
+                rng: seed ^ 0x9e37_79b9_7f4a_7c15 | 1,
+            }),
+            turn: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Blocks until thread `id` is allowed to run.
+    fn wait(&self, id: usize, mut state: MutexGuard<State>) {
+        while state.running != Some(id) {
+            state = self.turn.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Lets the next thread run.
+    fn start(&self) {
+        let mut state = self.lock();
+        state.running = state.pick();
+        self.turn.notify_all();
+    }
+
+    /// Lets thread `id` yield to the next thread, and blocks until it's allowed to run again.
+    fn switch(&self, id: usize) {
+        let mut state = self.lock();
+        state.running = state.pick();
+        if state.running != Some(id) {
+            self.turn.notify_all();
+            self.wait(id, state);
+        }
+    }
+
+    /// Marks thread `id` as finished, and lets the next thread run.
+    fn finish(&self, id: usize) {
+        let mut state = self.lock();
+        state.alive[id] = false;
+        state.running = state.pick();
+        self.turn.notify_all();
+    }
+}
+
+/// A set of closures to be run on simulated threads.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic, Owned};
+/// use crossbeam_epoch::testkit::Simulation;
+/// use std::sync::Arc;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Arc::new(Atomic::new(0));
+///
+/// let mut sim = Simulation::new();
+/// for _ in 0..2 {
+///     let a = a.clone();
+///     sim.spawn(move || epoch::pin(|scope| unsafe {
+///         let mut curr = a.load(SeqCst, scope);
+///         let mut new = Owned::new(*curr.deref() + 1);
+///         loop {
+///             match a.compare_and_set_owned(curr, new, SeqCst, scope) {
+///                 Ok(_) => break scope.defer_drop(curr),
+///                 Err((c, n)) => {
+///                     curr = c;
+///                     new = n;
+///                     *new = *curr.deref() + 1;
+///                 }
+///             }
+///         }
+///     }));
+/// }
+/// sim.run(42);
+///
+/// epoch::pin(|scope| unsafe {
+///     let p = a.load(SeqCst, scope);
+///     assert_eq!(*p.deref(), 2);
+///     scope.defer_drop(p);
+/// });
+/// ```
+pub struct Simulation {
+    threads: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl Simulation {
+    /// Returns a new simulation without threads.
+    pub fn new() -> Self {
+        Simulation { threads: Vec::new() }
+    }
+
+    /// Adds a thread running `f` to the simulation.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&mut self, f: F) -> &mut Self {
+        self.threads.push(Box::new(f));
+        self
+    }
+
+    /// Runs the threads under the schedule determined by `seed`, and waits until they finish.
+    ///
+    /// # Panics
+    ///
+    /// If any of the threads panics, panics with a message that includes `seed`, once all the
+    /// threads have finished.
+    pub fn run(self, seed: u64) {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let scheduler = Arc::new(Scheduler::new(seed, self.threads.len()));
+
+        let handles = self
+            .threads
+            .into_iter()
+            .enumerate()
+            .map(|(id, f)| {
+                let scheduler = scheduler.clone();
+                thread::spawn(move || {
+                    CURRENT.with(|c| *c.borrow_mut() = Some((scheduler.clone(), id)));
+                    scheduler.wait(id, scheduler.lock());
+
+                    let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+                    // Yield points hit while the thread exits must not wait for its turn.
+                    CURRENT.with(|c| c.borrow_mut().take());
+                    scheduler.finish(id);
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        scheduler.start();
+
+        let mut failure = None;
+        for handle in handles {
+            let result = handle.join().unwrap_or_else(Err);
+            if let Err(payload) = result {
+                failure.get_or_insert(payload);
+            }
+        }
+
+        if let Some(payload) = failure {
+            panic!("simulated schedule with seed {} failed: {}", seed, message(&*payload));
+        }
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("threads", &self.threads.len())
+            .finish()
+    }
+}
+
+/// Returns the message of a panic.
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<Any>"
+    }
+}
+
+/// Runs the simulation built by `setup` under the schedules with seeds `0..schedules`.
+///
+/// A new simulation is built for every schedule.
+///
+/// # Panics
+///
+/// Panics on the first failing schedule, with a message that includes its seed.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch::{self as epoch, Atomic};
+/// use crossbeam_epoch::testkit;
+/// use std::sync::Arc;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// testkit::explore(100, |sim| {
+///     let a = Arc::new(Atomic::<u64>::null());
+///     for _ in 0..3 {
+///         let a = a.clone();
+///         sim.spawn(move || epoch::pin(|scope| {
+///             let _ = a.load(SeqCst, scope);
+///         }));
+///     }
+/// });
+/// ```
+pub fn explore<F: FnMut(&mut Simulation)>(schedules: u64, mut setup: F) {
+    for seed in 0..schedules {
+        let mut sim = Simulation::new();
+        setup(&mut sim);
+        sim.run(seed);
+    }
+}
+
+/// Lets the scheduler switch to another simulated thread.
+///
+/// Outside of a simulation this is a noop.
+pub fn yield_now() {
+    yield_point();
+}
+
+/// A point at which the current simulated thread may be switched out.
+#[inline]
+pub(crate) fn yield_point() {
+    if QUIET.try_with(|q| q.get() > 0).unwrap_or(true) {
+        return;
+    }
+
+    let current = CURRENT.try_with(|c| c.borrow().clone()).ok().and_then(|c| c);
+    if let Some((scheduler, id)) = current {
+        scheduler.switch(id);
+    }
+}
+
+/// Suppresses yield points on the current thread while alive.
+///
+/// The collector's own work depends on the state of the whole program, e.g. on garbage left by
+/// other threads, so yielding in the middle of it would make schedules irreproducible.
+pub(crate) struct Quiet(());
+
+impl Quiet {
+    pub(crate) fn new() -> Self {
+        let _ = QUIET.try_with(|q| q.set(q.get() + 1));
+        Quiet(())
+    }
+}
+
+impl Drop for Quiet {
+    fn drop(&mut self) {
+        let _ = QUIET.try_with(|q| q.set(q.get() - 1));
+    }
+}
+
+/// A shared count of dropped [`DropCounter`]s.
+///
+/// [`DropCounter`]: struct.DropCounter.html
+#[derive(Clone, Debug, Default)]
+pub struct Drops {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drops {
+    /// Returns a new count, starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of values dropped so far.
+    pub fn count(&self) -> usize {
+        self.count.load(SeqCst)
+    }
+
+    /// Wraps `value` so that dropping it increments this count.
+    pub fn wrap<T>(&self, value: T) -> DropCounter<T> {
+        DropCounter {
+            value,
+            drops: self.clone(),
+        }
+    }
+}
+
+/// A value that increments a [`Drops`] count when dropped.
+///
+/// [`Drops`]: struct.Drops.html
+#[derive(Debug)]
+pub struct DropCounter<T> {
+    value: T,
+    drops: Drops,
+}
+
+impl<T> Deref for DropCounter<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for DropCounter<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for DropCounter<T> {
+    fn drop(&mut self) {
+        self.drops.count.fetch_add(1, SeqCst);
+    }
+}
+
+/// Drives garbage collection until `drops` reaches at least `expected`, or `timeout` expires.
+///
+/// The local bag of the current thread is flushed, and the global epoch is advanced and garbage
+/// collected repeatedly. Returns the final count. Garbage left in the local bags of other threads
+/// isn't reclaimed until they flush it.
+pub fn wait_for_drops(drops: &Drops, expected: usize, timeout: Duration) -> usize {
+    wait(drops, expected, timeout, || pin(flush_and_collect))
+}
+
+/// Like [`wait_for_drops`], but drives garbage collection of the collector `handle` is registered
+/// with, through `handle`.
+///
+/// [`wait_for_drops`]: fn.wait_for_drops.html
+pub fn wait_for_drops_in(
+    handle: &LocalHandle,
+    drops: &Drops,
+    expected: usize,
+    timeout: Duration,
+) -> usize {
+    wait(drops, expected, timeout, || handle.pin(flush_and_collect))
+}
+
+/// Like [`wait_for_drops`], but drives garbage collection by calling `collect` repeatedly, e.g.
+/// to exercise a particular way of collecting.
+///
+/// [`wait_for_drops`]: fn.wait_for_drops.html
+pub fn wait_for_drops_by<F: FnMut()>(
+    drops: &Drops,
+    expected: usize,
+    timeout: Duration,
+    collect: F,
+) -> usize {
+    wait(drops, expected, timeout, collect)
+}
+
+/// Calls `collect` until `drops` reaches at least `expected`, or `timeout` expires.
+fn wait<F: FnMut()>(drops: &Drops, expected: usize, timeout: Duration, mut collect: F) -> usize {
+    let deadline = sys::now() + timeout;
+    loop {
+        let count = drops.count();
+        if count >= expected || sys::now() >= deadline {
+            return count;
+        }
+
+        collect();
+        thread::yield_now();
+    }
+}
+
+/// Flushes the local bag and collects garbage of the realm of `scope`.
+fn flush_and_collect(scope: &Scope) {
+    scope.flush();
+    global::collect(scope);
+}
+
+/// Asserts that a [`Drops`] count reaches the expected number, driving garbage collection for up
+/// to a timeout.
+///
+/// The timeout defaults to 10 seconds, and can be given with `within: duration`. Garbage of the
+/// global collector is collected, unless a [`LocalHandle`] is given with `in: handle`, in which
+/// case garbage of its collector is, or a closure is given with `by: collect`, in which case it's
+/// called to collect garbage.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate crossbeam_epoch as epoch;
+///
+/// use epoch::{Atomic, Collector};
+/// use epoch::testkit::Drops;
+/// use std::sync::atomic::Ordering::SeqCst;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// let drops = Drops::new();
+/// let a = Atomic::new(drops.wrap(1));
+/// epoch::pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+///
+/// assert_reclaimed!(drops, 1, within: Duration::from_secs(5));
+///
+/// let collector = Collector::new();
+/// let handle = collector.register();
+/// let b = Atomic::new(drops.wrap(2));
+/// handle.pin(|scope| unsafe { scope.defer_drop(b.load(SeqCst, scope)) });
+///
+/// assert_reclaimed!(drops, 2, in: handle);
+/// # }
+/// ```
+///
+/// [`Drops`]: testkit/struct.Drops.html
+/// [`LocalHandle`]: struct.LocalHandle.html
+#[macro_export]
+macro_rules! assert_reclaimed {
+    (@check $count:expr, $expected:expr, $timeout:expr) => {
+        assert!(
+            $count >= $expected,
+            "expected {} values to be reclaimed within {:?}, but only {} were",
+            $expected,
+            $timeout,
+            $count
+        )
+    };
+    ($drops:expr, $expected:expr) => {
+        $crate::assert_reclaimed!($drops, $expected, within: ::std::time::Duration::from_secs(10))
+    };
+    ($drops:expr, $expected:expr, within: $timeout:expr) => {{
+        let expected = $expected;
+        let timeout = $timeout;
+        let count = $crate::testkit::wait_for_drops(&$drops, expected, timeout);
+        $crate::assert_reclaimed!(@check count, expected, timeout)
+    }};
+    ($drops:expr, $expected:expr, in: $handle:expr) => {
+        $crate::assert_reclaimed!(
+            $drops,
+            $expected,
+            in: $handle,
+            within: ::std::time::Duration::from_secs(10)
+        )
+    };
+    ($drops:expr, $expected:expr, in: $handle:expr, within: $timeout:expr) => {{
+        let expected = $expected;
+        let timeout = $timeout;
+        let count = $crate::testkit::wait_for_drops_in(&$handle, &$drops, expected, timeout);
+        $crate::assert_reclaimed!(@check count, expected, timeout)
+    }};
+    ($drops:expr, $expected:expr, by: $collect:expr) => {
+        $crate::assert_reclaimed!(
+            $drops,
+            $expected,
+            by: $collect,
+            within: ::std::time::Duration::from_secs(10)
+        )
+    };
+    ($drops:expr, $expected:expr, by: $collect:expr, within: $timeout:expr) => {{
+        let expected = $expected;
+        let timeout = $timeout;
+        let count = $crate::testkit::wait_for_drops_by(&$drops, expected, timeout, $collect);
+        $crate::assert_reclaimed!(@check count, expected, timeout)
+    }};
+}
+
+/// Values reported to a global hook set by tests, with the names of the threads that reported
+/// them.
+///
+/// Hooks are global, and tests run in parallel, so a hook is set once for all the tests that use
+/// it, and each test runs on a thread named after it to pick out its own values.
+#[cfg(test)]
+pub(crate) struct Recorder<T> {
+    set: Once,
+    values: Mutex<Vec<(Option<String>, T)>>,
+}
+
+#[cfg(test)]
+impl<T: Clone> Recorder<T> {
+    pub(crate) const fn new() -> Self {
+        Recorder {
+            set: Once::new(),
+            values: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Calls `set`, which sets the hook, unless it has been called already.
+    pub(crate) fn set_once<F: FnOnce()>(&self, set: F) {
+        self.set.call_once(set);
+    }
+
+    /// Records `value` as reported on the current thread.
+    pub(crate) fn record(&self, value: T) {
+        let name = thread::current().name().map(String::from);
+        self.values.lock().unwrap_or_else(|e| e.into_inner()).push((name, value));
+    }
+
+    /// Returns the values reported on the thread named `name`.
+    pub(crate) fn on_thread(&self, name: &str) -> Vec<T> {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values
+            .iter()
+            .filter(|&(n, _)| n.as_ref().map(|n| &n[..]) == Some(name))
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+}
+
+/// Runs `f` on a new thread named `name`, and returns its result.
+#[cfg(test)]
+pub(crate) fn on_thread<F, R>(name: &str, f: F) -> R
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use Atomic;
+    #[cfg(feature = "testkit")]
+    use Ptr;
+    use super::*;
+
+    /// Returns the order in which two threads hit their yield points under `seed`.
+    #[cfg(feature = "testkit")]
+    fn trace(seed: u64) -> Vec<usize> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let a = Arc::new(Atomic::<u64>::null());
+
+        let mut sim = Simulation::new();
+        for id in 0..2 {
+            let events = events.clone();
+            let a = a.clone();
+            sim.spawn(move || {
+                for _ in 0..10 {
+                    events.lock().unwrap().push(id);
+                    pin(|scope| {
+                        let _ = a.compare_and_set(Ptr::null(), Ptr::null(), SeqCst, scope);
+                    });
+                }
+            });
+        }
+        sim.run(seed);
+
+        let events = events.lock().unwrap();
+        events.clone()
+    }
+
+    // Yield points are only injected with the feature enabled.
+    #[test]
+    #[cfg(feature = "testkit")]
+    fn same_seed_same_schedule() {
+        for seed in 0..20 {
+            assert_eq!(trace(seed), trace(seed));
+        }
+        assert!((0..20).any(|seed| trace(seed) != trace(0)));
+    }
+
+    #[test]
+    fn failure_reports_seed() {
+        let result = panic::catch_unwind(|| {
+            explore(10, |sim| {
+                sim.spawn(yield_now);
+                sim.spawn(|| panic!("boom"));
+            });
+        });
+
+        let payload = result.unwrap_err();
+        assert_eq!(message(&*payload), "simulated schedule with seed 0 failed: boom");
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn reclaimed_values_are_counted() {
+        let drops = Drops::new();
+        for i in 0..100 {
+            let a = Atomic::new(drops.wrap(i));
+            pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+        }
+        assert_reclaimed!(drops, 100);
+    }
+}
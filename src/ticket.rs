@@ -0,0 +1,103 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+/// State shared by the clones of a ticket.
+#[derive(Default)]
+struct State {
+    /// Whether the grace period has elapsed.
+    elapsed: AtomicBool,
+    /// Functions to call once the grace period elapses.
+    callbacks: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+/// A ticket that tells when the grace period of a retired object has elapsed.
+///
+/// Tickets are returned by [`Scope::retire`]. Once the grace period has elapsed, no mutator can
+/// hold a reference to the object loaded before it was retired, so its memory may be reused in
+/// place.
+///
+/// [`Scope::retire`]: struct.Scope.html#method.retire
+#[derive(Clone)]
+pub struct RetireTicket {
+    state: Arc<State>,
+}
+
+impl RetireTicket {
+    /// Returns a new ticket whose grace period hasn't elapsed yet.
+    pub(crate) fn new() -> Self {
+        RetireTicket { state: Arc::new(State::default()) }
+    }
+
+    /// Returns `true` if the grace period has elapsed.
+    pub fn is_reclaimable(&self) -> bool {
+        self.state.elapsed.load(Acquire)
+    }
+
+    /// Calls `f` once the grace period has elapsed.
+    ///
+    /// If it has already elapsed, `f` is called right away on the current thread. Otherwise it is
+    /// called on whichever thread notices the grace period elapsing.
+    pub fn on_reclaim<F: FnOnce() + Send + 'static>(&self, f: F) {
+        {
+            let mut callbacks = self.state.callbacks.lock().unwrap_or_else(|e| e.into_inner());
+            if !self.is_reclaimable() {
+                callbacks.push(Box::new(f));
+                return;
+            }
+        }
+        f();
+    }
+
+    /// Marks the grace period as elapsed, and calls the registered functions.
+    pub(crate) fn elapse(&self) {
+        let callbacks = {
+            let mut callbacks = self.state.callbacks.lock().unwrap_or_else(|e| e.into_inner());
+            self.state.elapsed.store(true, Release);
+            mem::take(&mut *callbacks)
+        };
+
+        for f in callbacks {
+            f();
+        }
+    }
+}
+
+impl ::std::fmt::Debug for RetireTicket {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("RetireTicket")
+            .field("is_reclaimable", &self.is_reclaimable())
+            .finish()
+    }
+}
+
+
+#[cfg(all(test, not(feature = "leak_only")))]
+mod tests {
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use {pin, Atomic};
+    use testkit::Drops;
+
+    #[test]
+    fn callbacks_run_once_elapsed() {
+        let drops = Drops::new();
+        let a = Atomic::new(0u64);
+
+        let ticket = pin(|scope| scope.retire(a.load(SeqCst, scope)));
+        let d = drops.wrap(());
+        ticket.on_reclaim(move || drop(d));
+
+        ::assert_reclaimed!(drops, 1);
+        assert!(ticket.is_reclaimable());
+        assert_eq!(drops.count(), 1);
+
+        // The grace period has elapsed, so this one is called right away.
+        let d = drops.wrap(());
+        ticket.on_reclaim(move || drop(d));
+        assert_eq!(drops.count(), 2);
+
+        pin(|scope| unsafe { scope.defer_drop(a.load(SeqCst, scope)) });
+    }
+}
@@ -0,0 +1,150 @@
+//! Allocator trimming
+//!
+//! Destroying garbage returns its memory to the allocator, but allocators tend to keep freed
+//! memory around for later allocations rather than handing it back to the operating system. After
+//! a data structure is cleared in bulk, reclamation runs as it should, and yet the resident set of
+//! the process doesn't shrink.
+//!
+//! With the `alloc_trim` feature, a collection step that frees at least [`trim_threshold`] bytes
+//! calls the hook set with [`set_trim_hook`], which can ask the allocator to give the memory back,
+//! e.g. with `malloc_trim` on glibc. Only memory whose size is known is counted, i.e. objects
+//! retired with `Scope::defer_drop` and the like, but not whatever deferred functions free.
+//!
+//! [`trim_threshold`]: fn.trim_threshold.html
+//! [`set_trim_hook`]: fn.set_trim_hook.html
+
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+use hook::Slot;
+
+/// The default of `trim_threshold`.
+const DEFAULT_THRESHOLD: usize = 64 << 20;
+
+/// The number of bytes a collection step must free to call the hook.
+static THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_THRESHOLD);
+
+/// The hook set with `set_trim_hook`, if any.
+static HOOK: Slot<dyn Fn(usize) + Send + Sync> = Slot::new();
+
+thread_local! {
+    /// The number of bytes of garbage destroyed on the current thread so far.
+    static FREED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the number of bytes a single collection step must free to call the trim hook.
+pub fn trim_threshold() -> usize {
+    THRESHOLD.load(Relaxed)
+}
+
+/// Sets the number of bytes a single collection step must free to call the trim hook.
+///
+/// The default is 64 MiB.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_trim_threshold(16 << 20);
+/// assert_eq!(epoch::trim_threshold(), 16 << 20);
+/// ```
+pub fn set_trim_threshold(bytes: usize) {
+    THRESHOLD.store(bytes, Relaxed);
+}
+
+/// Sets `hook` to be called with the number of bytes freed whenever a collection step frees at
+/// least [`trim_threshold`] bytes.
+///
+/// The hook runs on the thread that collected the garbage, once the collection step is over, and
+/// it replaces any hook set before. It should be cheap to call repeatedly, as a steady stream of
+/// large collections keeps calling it.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_trim_hook(|freed| {
+///     // E.g. `unsafe { libc::malloc_trim(0) };` on glibc.
+///     eprintln!("freed {} bytes", freed);
+/// });
+/// ```
+///
+/// [`trim_threshold`]: fn.trim_threshold.html
+pub fn set_trim_hook<F>(hook: F)
+where
+    F: Fn(usize) + Send + Sync + 'static,
+{
+    HOOK.set(Some(Arc::new(hook)));
+}
+
+/// Counts `bytes` of garbage as destroyed on the current thread.
+#[inline]
+pub fn freed(bytes: usize) {
+    let _ = FREED.try_with(|f| f.set(f.get().wrapping_add(bytes)));
+}
+
+/// Returns the number of bytes destroyed on the current thread so far, when a collection step
+/// starts.
+#[inline]
+pub fn start() -> usize {
+    FREED.try_with(Cell::get).unwrap_or(0)
+}
+
+/// Calls the hook if the collection step that started at `start` freed enough memory.
+#[inline]
+pub fn finish(start: usize) {
+    let freed = FREED.try_with(Cell::get).unwrap_or(start).wrapping_sub(start);
+    if freed > 0 && freed >= THRESHOLD.load(Relaxed) && HOOK.is_set() {
+        trim(freed);
+    }
+}
+
+/// Hands `freed` to the hook.
+#[cold]
+#[inline(never)]
+fn trim(freed: usize) {
+    if let Some(hook) = HOOK.get() {
+        hook(freed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::Relaxed;
+
+    use testkit::{self, Recorder};
+    use {Atomic, Collector};
+    use super::*;
+
+    /// How many bytes the threads that called the hook freed.
+    static TRIMMED: Recorder<usize> = Recorder::new();
+
+    #[test]
+    fn trims_after_large_collections() {
+        TRIMMED.set_once(|| {
+            set_trim_hook(|freed| TRIMMED.record(freed));
+            set_trim_threshold(64 * 1024);
+        });
+
+        let collector = Collector::new();
+        let name = "trims_after_large_collections";
+        testkit::on_thread(name, move || {
+            let handle = collector.register();
+            handle.pin(|scope| unsafe {
+                for _ in 0..32 {
+                    let a = Atomic::new([0u8; 4096]);
+                    scope.defer_drop(a.load(Relaxed, scope));
+                }
+            });
+            for _ in 0..16 {
+                handle.pin(|scope| scope.flush());
+            }
+        });
+
+        assert_eq!(TRIMMED.on_thread(name), [32 * 4096]);
+    }
+}
@@ -0,0 +1,142 @@
+//! Panics during collection
+//!
+//! Deferred functions and destructors run on whichever thread happens to collect them, in the
+//! middle of an unrelated pinning or flush. If one of them panics, unwinding through the collector
+//! would skip the rest of the bag being destroyed and hit a caller that has nothing to do with the
+//! failure. Instead, each piece of garbage is destroyed under `catch_unwind`: the panic is handed
+//! to the handler set with [`set_collection_panic_handler`], and collection continues with the
+//! rest of the garbage.
+//!
+//! [`set_collection_panic_handler`]: fn.set_collection_panic_handler.html
+
+use std::any::Any;
+use std::sync::Arc;
+
+use hook::Slot;
+
+/// The handler set with `set_collection_panic_handler`, if any.
+static HANDLER: Slot<dyn Fn(Box<dyn Any + Send>) + Send + Sync> = Slot::new();
+
+/// Sets `handler` to be called with the payload of every panic raised by a deferred function or
+/// destructor during collection.
+///
+/// The handler runs on the thread that was collecting the garbage, right after the panic, and
+/// collection continues once it returns. It replaces any handler set before. If no handler is set,
+/// the panic is reported by the panic hook as usual, and then ignored.
+///
+/// A handler may panic itself to propagate the panic to the caller of the pinning or flush that
+/// collected the garbage, but the rest of the garbage being destroyed at that moment is then
+/// leaked.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::sync::atomic::AtomicUsize;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// static PANICS: AtomicUsize = AtomicUsize::new(0);
+///
+/// epoch::set_collection_panic_handler(|_| {
+///     PANICS.fetch_add(1, SeqCst);
+/// });
+///
+/// epoch::pin(|scope| unsafe { scope.defer(|| panic!("oops")) });
+/// for _ in 0..100_000 {
+///     if PANICS.load(SeqCst) > 0 {
+///         break;
+///     }
+///     epoch::pin(|scope| scope.flush());
+/// }
+/// assert_eq!(PANICS.load(SeqCst), 1);
+/// ```
+pub fn set_collection_panic_handler<F>(handler: F)
+where
+    F: Fn(Box<dyn Any + Send>) + Send + Sync + 'static,
+{
+    HANDLER.set(Some(Arc::new(handler)));
+}
+
+/// Hands `payload` of a panic during collection to the handler, if any.
+#[cold]
+pub fn handle(payload: Box<dyn Any + Send>) {
+    if let Some(handler) = HANDLER.get() {
+        handler(payload);
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "leak_only", allow(unused_imports))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use garbage::{Bag, Garbage, MAX_OBJECTS};
+    use testkit::{self, Drops, Recorder};
+    use Collector;
+    use super::*;
+
+    /// Messages of the panics handed to the handler.
+    static MESSAGES: Recorder<&'static str> = Recorder::new();
+
+    /// Sets a handler recording the panic messages, shared by all tests.
+    fn record_panics() {
+        MESSAGES.set_once(|| {
+            set_collection_panic_handler(|payload| {
+                if let Some(&msg) = payload.downcast_ref::<&'static str>() {
+                    MESSAGES.record(msg);
+                }
+            })
+        });
+    }
+
+    #[test]
+    fn panic_does_not_skip_bag() {
+        record_panics();
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let name = "panic_does_not_skip_bag";
+        let c = count.clone();
+        testkit::on_thread(name, move || {
+            // A full bag, with a panic in the middle.
+            let mut bag = Bag::new();
+            for i in 0..MAX_OBJECTS {
+                let count = c.clone();
+                let garbage = Garbage::new(move || {
+                    if i == MAX_OBJECTS / 2 {
+                        panic!("panic_does_not_skip_bag");
+                    }
+                    count.fetch_add(1, SeqCst);
+                });
+                assert!(bag.try_push(garbage).is_ok());
+            }
+            drop(bag);
+        });
+
+        assert_eq!(count.load(SeqCst), MAX_OBJECTS - 1);
+        assert_eq!(MESSAGES.on_thread(name), ["panic_does_not_skip_bag"]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "leak_only"))]
+    fn collector_survives_panic() {
+        record_panics();
+        let drops = Drops::new();
+
+        let name = "collector_survives_panic";
+        let d = drops.clone();
+        testkit::on_thread(name, move || {
+            let collector = Collector::new();
+            let handle = collector.register();
+
+            handle.pin(|scope| unsafe { scope.defer(|| panic!("collector_survives_panic")) });
+            for _ in 0..3 {
+                let value = d.wrap(());
+                handle.pin(|scope| unsafe { scope.defer(move || drop(value)) });
+            }
+            ::assert_reclaimed!(d, 3, in: handle);
+        });
+
+        assert_eq!(MESSAGES.on_thread(name), ["collector_survives_panic"]);
+    }
+}
@@ -0,0 +1,450 @@
+//! Stall watchdog
+//!
+//! If a thread stays pinned for a long time, e.g. because it blocked or got stuck in a loop while
+//! pinned, the global epoch can't advance and no garbage is destroyed. Nothing fails right away:
+//! memory usage just keeps growing until the process runs out of it, possibly hours later.
+//!
+//! [`watch_stalls`] starts a thread that notices when the global epoch hasn't advanced for a while
+//! even though garbage is waiting for destruction, and reports the stall to a callback along with
+//! the registered mutators, one of which is holding it up. [`log_stall`] is a callback that prints
+//! the report to standard error.
+//!
+//! To tell which mutator that is, every pinning bumps a count of the mutator, and records where it
+//! happened, if enabled with [`set_pin_backtraces`]. [`stalled_mutators`] and
+//! [`Collector::stalled_mutators`] note when they first see a mutator pinned under each count, and
+//! list those that have stayed pinned for longer than a given duration since. The watchdog looks
+//! every time it checks the epoch, and stalls include the mutators it found too.
+//!
+//! [`watch_stalls`]: fn.watch_stalls.html
+//! [`log_stall`]: fn.log_stall.html
+//! [`set_pin_backtraces`]: fn.set_pin_backtraces.html
+//! [`stalled_mutators`]: fn.stalled_mutators.html
+//! [`Collector::stalled_mutators`]: struct.Collector.html#method.stalled_mutators
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use global::{self, REALM, Realm, oldest_garbage_age, pin};
+use sys::{self, Timestamp};
+use mutator::Scope;
+use registration::{self, MutatorInfo, registered_mutators};
+use sync::list::IterResult;
+
+/// Whether pinnings capture a backtrace.
+static PIN_BACKTRACES: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether every pinning captures a backtrace, which [`StalledMutator`]s then report.
+///
+/// This is off by default, since capturing a backtrace makes pinning orders of magnitude slower.
+/// It is meant for tracking down a mutator that keeps being reported as stalled, possibly in
+/// production.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+///
+/// epoch::set_pin_backtraces(true);
+/// epoch::pin(|_| ());
+/// epoch::set_pin_backtraces(false);
+/// ```
+///
+/// [`StalledMutator`]: struct.StalledMutator.html
+pub fn set_pin_backtraces(enabled: bool) {
+    PIN_BACKTRACES.store(enabled, Relaxed);
+}
+
+/// The pinnings of a mutator, as far as the watchdog can tell.
+#[derive(Debug, Default)]
+pub(crate) struct Pinning {
+    /// The number of times the mutator got pinned.
+    pins: AtomicUsize,
+    /// The number of times the mutator got pinned or repinned, which changes whenever it stops
+    /// holding back the epoch it was pinned in.
+    restarts: AtomicUsize,
+    /// Registration number of the mutator plus one, or zero if it is temporary.
+    registration: AtomicUsize,
+    /// What has been seen of the pinnings, which pinning only locks to store a backtrace.
+    seen: Mutex<Seen>,
+}
+
+/// What has been seen of the pinnings of a mutator.
+#[derive(Debug, Default)]
+struct Seen {
+    /// The `restarts` count the mutator was first seen pinned at, and when.
+    since: Option<(usize, Timestamp)>,
+    /// Where the mutator got pinned, and the `pins` count it got pinned at.
+    backtrace: Option<(usize, Backtrace)>,
+}
+
+impl Pinning {
+    /// Records a pinning of the mutator with registration number `registration`.
+    #[inline]
+    pub(crate) fn pinned(&self, registration: Option<usize>) {
+        // Only the thread the mutator is pinned on writes the counts.
+        let pins = self.pins.load(Relaxed).wrapping_add(1);
+        self.pins.store(pins, Relaxed);
+        self.repinned();
+        self.registration.store(registration.map_or(0, |r| r + 1), Relaxed);
+        if PIN_BACKTRACES.load(Relaxed) {
+            self.capture(pins);
+        }
+    }
+
+    /// Records a repinning of the mutator, which restarts the clock.
+    #[inline]
+    pub(crate) fn repinned(&self) {
+        self.restarts.store(self.restarts.load(Relaxed).wrapping_add(1), Relaxed);
+    }
+
+    /// Stores where the mutator got pinned for the `pins`th time.
+    #[cold]
+    fn capture(&self, pins: usize) {
+        let backtrace = Backtrace::force_capture();
+        self.seen.lock().unwrap_or_else(|e| e.into_inner()).backtrace = Some((pins, backtrace));
+    }
+
+    /// Returns the registration number of the pinned mutator, how long it has been pinned for
+    /// since it was first seen so, with `now` being the first time if it wasn't, and where it got
+    /// pinned, if known.
+    fn sample(&self, now: Timestamp) -> (Option<usize>, Duration, Option<String>) {
+        let restarts = self.restarts.load(Relaxed);
+        let pins = self.pins.load(Relaxed);
+        let registration = self.registration.load(Relaxed).checked_sub(1);
+
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        let since = match seen.since {
+            Some((r, since)) if r == restarts => since,
+            _ => {
+                seen.since = Some((restarts, now));
+                now
+            }
+        };
+        let backtrace = match seen.backtrace {
+            Some((p, ref b)) if p == pins && b.status() == BacktraceStatus::Captured => {
+                Some(b.to_string())
+            }
+            _ => None,
+        };
+        (registration, now.since(since), backtrace)
+    }
+}
+
+/// A mutator that has been pinned for a long time, as returned by [`stalled_mutators`].
+///
+/// [`stalled_mutators`]: fn.stalled_mutators.html
+#[derive(Clone, Debug)]
+pub struct StalledMutator {
+    /// The registered mutator, or `None` if it is temporary, e.g. a [`PinnedScope`].
+    ///
+    /// [`PinnedScope`]: struct.PinnedScope.html
+    pub mutator: Option<MutatorInfo>,
+    /// The epoch the mutator is pinned in.
+    pub epoch: usize,
+    /// How long the mutator has been pinned in that epoch.
+    pub pinned_for: Duration,
+    /// Where the mutator got pinned, if backtraces were enabled with [`set_pin_backtraces`] at
+    /// the time.
+    ///
+    /// [`set_pin_backtraces`]: fn.set_pin_backtraces.html
+    pub backtrace: Option<String>,
+}
+
+impl fmt::Display for StalledMutator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mutator {
+            Some(ref mutator) => write!(f, "{}", mutator)?,
+            None => write!(f, "temporary mutator")?,
+        }
+        write!(f, ", pinned in epoch {} for {:.1?}", self.epoch, self.pinned_for)?;
+        if let Some(ref backtrace) = self.backtrace {
+            write!(f, ", at:\n{}", backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the mutators of `realm` other than the one of `scope` that have been pinned for at
+/// least `min_duration` since they were first seen so.
+pub(crate) fn stalled_in(
+    realm: &Realm,
+    min_duration: Duration,
+    scope: &Scope,
+) -> Vec<StalledMutator> {
+    let now = sys::now();
+    let mut stalled = Vec::new();
+    let mut registries = realm.registries.iter(scope);
+    while let IterResult::Some(local_epoch) = registries.next() {
+        if ::std::ptr::eq(local_epoch, scope.local_epoch()) {
+            continue;
+        }
+
+        let (pinned, epoch) = local_epoch.get_state();
+        if !pinned {
+            continue;
+        }
+
+        let (registration, pinned_for, backtrace) = local_epoch.pinning().sample(now);
+        if pinned_for >= min_duration {
+            stalled.push(StalledMutator {
+                mutator: registration.and_then(registration::mutator_info),
+                epoch,
+                pinned_for,
+                backtrace,
+            });
+        }
+    }
+    stalled
+}
+
+/// Returns the mutators of the global garbage collector that have been pinned for at least
+/// `min_duration`, not counting the one of the current thread.
+///
+/// A mutator that stays pinned keeps the global epoch from advancing, so that no garbage is
+/// destroyed anymore. Looking for mutators pinned for longer than any pinned section should take
+/// finds the culprit.
+///
+/// Pinning doesn't read the clock, so a mutator is only known to have been pinned since the first
+/// call that saw it pinned, or since the watchdog first saw it, if one is running. Calling this
+/// periodically, e.g. from a health check, finds mutators that stay pinned across calls.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::time::Duration;
+///
+/// for stalled in epoch::stalled_mutators(Duration::from_secs(1)) {
+///     eprintln!("{}", stalled);
+/// }
+/// ```
+pub fn stalled_mutators(min_duration: Duration) -> Vec<StalledMutator> {
+    pin(|scope| stalled_in(&REALM, min_duration, scope))
+}
+
+/// A stall of garbage collection, reported by the watchdog started with [`watch_stalls`].
+///
+/// [`watch_stalls`]: fn.watch_stalls.html
+#[derive(Clone, Debug)]
+pub struct Stall {
+    /// The global epoch, which hasn't advanced since the stall began.
+    pub epoch: usize,
+    /// How long the global epoch has been stuck.
+    pub duration: Duration,
+    /// How many times the global epoch had advanced since the oldest bag of garbage was sealed,
+    /// when the stall began.
+    pub oldest_garbage_age: Option<usize>,
+    /// The mutators that were registered when the stall was reported.
+    pub mutators: Vec<MutatorInfo>,
+    /// The mutators that had been pinned since before the stall began, as far as the watchdog
+    /// could tell from how often it checks, when it was reported.
+    pub stalled: Vec<StalledMutator>,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "global epoch {} hasn't advanced for {:.1?} with garbage pending, registered mutators:",
+            self.epoch,
+            self.duration
+        )?;
+        for mutator in &self.mutators {
+            write!(f, "\n    {}", mutator)?;
+        }
+        if !self.stalled.is_empty() {
+            write!(f, "\npinned since before the stall began:")?;
+            for mutator in &self.stalled {
+                write!(f, "\n    {}", mutator)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints `stall` to standard error.
+///
+/// This can be passed to [`watch_stalls`] as the callback.
+///
+/// [`watch_stalls`]: fn.watch_stalls.html
+pub fn log_stall(stall: &Stall) {
+    eprintln!("crossbeam-epoch: {}", stall);
+}
+
+/// A thread watching for stalls of garbage collection, created by [`watch_stalls`].
+///
+/// The thread is stopped when the watchdog is dropped.
+///
+/// [`watch_stalls`]: fn.watch_stalls.html
+#[derive(Debug)]
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    stalls: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Returns the number of times a stall was reported so far.
+    pub fn stalls(&self) -> usize {
+        self.stalls.load(Relaxed)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts a thread that calls `on_stall` whenever the global epoch hasn't advanced for `timeout`
+/// while garbage is waiting for destruction.
+///
+/// The epoch is checked a few times per `timeout`. Each check also tries to advance the epoch and
+/// destroy expired garbage, so that garbage left behind by threads that simply stopped pinning
+/// isn't mistaken for a stall. While a stall lasts, it is reported again every `timeout`.
+///
+/// # Examples
+///
+/// ```
+/// use crossbeam_epoch as epoch;
+/// use std::time::Duration;
+///
+/// let watchdog = epoch::watch_stalls(Duration::from_secs(30), epoch::log_stall).unwrap();
+/// // Stalls are reported until `watchdog` is dropped.
+/// drop(watchdog);
+/// ```
+pub fn watch_stalls<F>(timeout: Duration, on_stall: F) -> io::Result<Watchdog>
+where
+    F: Fn(&Stall) + Send + 'static,
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let stalls = Arc::new(AtomicUsize::new(0));
+    let (s, n) = (stop.clone(), stalls.clone());
+
+    let thread = thread::Builder::new()
+        .name("epoch-watchdog".to_string())
+        .spawn(move || {
+            let mut epoch = REALM.epoch.load(Relaxed);
+            let mut since = sys::now();
+            let mut age = oldest_garbage_age();
+            let mut reported = since;
+            let interval = timeout / 4;
+
+            while !s.load(Relaxed) {
+                // Seeing the pinned mutators now tells how long they stay pinned later on.
+                let mut stalled = stalled_mutators(Duration::from_secs(0));
+                pin(global::reclaim);
+                let now = REALM.epoch.load(Relaxed);
+                let pending = oldest_garbage_age();
+
+                if now != epoch || pending.is_none() {
+                    epoch = now;
+                    since = sys::now();
+                    age = pending;
+                    reported = since;
+                } else if reported.elapsed() >= timeout {
+                    let duration = since.elapsed();
+                    stalled.retain(|m| m.pinned_for + interval >= duration);
+                    on_stall(&Stall {
+                        epoch,
+                        duration,
+                        oldest_garbage_age: age,
+                        mutators: registered_mutators(),
+                        stalled,
+                    });
+                    n.fetch_add(1, Relaxed);
+                    reported = sys::now();
+                }
+
+                thread::park_timeout(interval);
+            }
+        })?;
+
+    Ok(Watchdog {
+        stop,
+        stalls,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    use super::*;
+    use Collector;
+
+    #[test]
+    fn reports_stalls() {
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let blocker = thread::Builder::new()
+            .name("blocker".to_string())
+            .spawn(move || {
+                pin(|_| {
+                    pinned_tx.send(()).unwrap();
+                    let _ = release_rx.recv();
+                })
+            })
+            .unwrap();
+        pinned_rx.recv().unwrap();
+
+        pin(|scope| unsafe {
+            scope.defer(|| ());
+            scope.flush();
+        });
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let r = reports.clone();
+        let watchdog = watch_stalls(Duration::from_millis(20), move |stall| {
+            r.lock().unwrap().push(stall.to_string());
+        }).unwrap();
+
+        while watchdog.stalls() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(watchdog);
+        drop(release_tx);
+        blocker.join().unwrap();
+
+        let reports = reports.lock().unwrap();
+        assert!(reports[0].contains("thread 'blocker'"));
+        assert!(reports[0].contains("pinned since before the stall began:\n    thread 'blocker'"));
+    }
+
+    #[test]
+    fn reports_long_pinnings() {
+        let collector = Collector::new();
+        let handle = collector.register();
+
+        set_pin_backtraces(true);
+        handle.pin(|scope| {
+            set_pin_backtraces(false);
+            assert!(collector.stalled_mutators(Duration::from_millis(10)).is_empty());
+            thread::sleep(Duration::from_millis(10));
+            let stalled = collector.stalled_mutators(Duration::from_millis(10));
+            assert_eq!(stalled.len(), 1);
+            assert!(stalled[0].mutator.is_some());
+            assert!(stalled[0].backtrace.as_ref().unwrap().contains("reports_long_pinnings"));
+
+            // Repinning restarts the clock, but keeps the backtrace.
+            unsafe { scope.repin() };
+            assert!(collector.stalled_mutators(Duration::from_millis(10)).is_empty());
+            thread::sleep(Duration::from_millis(10));
+            let stalled = collector.stalled_mutators(Duration::from_millis(10));
+            assert_eq!(stalled.len(), 1);
+            assert!(stalled[0].backtrace.is_some());
+        });
+    }
+}
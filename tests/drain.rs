@@ -0,0 +1,99 @@
+//! Checks that dropping a collector destroys all of its garbage.
+//!
+//! Every object retired here counts its drops, but the counts can't tell whether the memory the
+//! collector allocated for itself, e.g. for bags or boxed closures, is freed too. The tests are
+//! also run under AddressSanitizer, whose leak checker catches that:
+//!
+//! ```text
+//! RUSTFLAGS="-Zsanitizer=address" cargo +nightly test --target x86_64-unknown-linux-gnu \
+//!     --test drain
+//! ```
+
+#![cfg(not(feature = "leak_only"))]
+
+extern crate crossbeam_epoch as epoch;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::sync::Arc;
+use std::thread;
+
+use epoch::{Atomic, Collector, Owned};
+
+/// Counts its drops in the counter it holds on to.
+struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, SeqCst);
+    }
+}
+
+/// Replaces the object in a shared pointer `count` times on each of `threads` threads, and retires
+/// the replaced ones, with a reader keeping the collector from getting to most of them.
+fn produce_and_retire(collector: &Collector, drops: &Arc<AtomicUsize>, threads: usize) -> usize {
+    let count = 1000;
+    let a = Arc::new(Atomic::new(Counted(drops.clone())));
+
+    let reader = collector.register();
+    reader.pin(|_| {
+        let handles = (0..threads)
+            .map(|_| {
+                let collector = collector.clone();
+                let drops = drops.clone();
+                let a = a.clone();
+                thread::spawn(move || {
+                    let handle = collector.register();
+                    for _ in 0..count {
+                        handle.pin(|scope| unsafe {
+                            let new = Owned::new(Counted(drops.clone()));
+                            let old = a.swap(new.into_ptr(scope), Relaxed, scope);
+                            scope.defer_drop(old);
+                        });
+                    }
+                    let drops = drops.clone();
+                    handle.pin(|scope| unsafe {
+                        scope.defer(move || {
+                            drops.fetch_add(1, SeqCst);
+                        });
+                    });
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+    });
+
+    let a = Arc::try_unwrap(a).ok().unwrap();
+    unsafe { drop(a.into_owned()) }
+    threads * (count + 1) + 1
+}
+
+#[test]
+fn drop_destroys_all_garbage() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let collector = Collector::new();
+    let retired = produce_and_retire(&collector, &drops, 4);
+    drop(collector);
+    assert_eq!(drops.load(SeqCst), retired);
+}
+
+#[test]
+fn scoped_destroys_all_garbage() {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let retired = Collector::scoped(|collector| {
+        let handle = collector.register();
+        let n = 1000;
+        for _ in 0..n {
+            let drops = drops.clone();
+            handle.pin(|scope| unsafe {
+                scope.defer(move || {
+                    drops.fetch_add(1, SeqCst);
+                })
+            });
+        }
+        n
+    });
+    assert_eq!(drops.load(SeqCst), retired);
+}
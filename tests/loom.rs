@@ -0,0 +1,88 @@
+//! Models of the epoch protocol, checked with `RUSTFLAGS="--cfg loom" cargo test --test loom`.
+
+#![cfg(loom)]
+
+extern crate crossbeam_epoch as epoch;
+extern crate loom;
+
+use std::sync::atomic::Ordering::{AcqRel, Acquire, SeqCst};
+use std::sync::Arc;
+
+use epoch::{Atomic, Collector, CollectorConfig, Owned, RandomSource};
+use loom::sync::atomic::AtomicBool;
+
+/// Sets its flag when it's dropped.
+struct Flagged(Arc<AtomicBool>);
+
+impl Drop for Flagged {
+    fn drop(&mut self) {
+        assert!(!self.0.swap(true, SeqCst), "dropped twice");
+    }
+}
+
+/// Returns a model builder whose preemptions are bounded unless `LOOM_MAX_PREEMPTIONS` is set.
+fn builder() -> loom::model::Builder {
+    let mut builder = loom::model::Builder::new();
+    // Pinning, collecting, and advancing the epoch take a good many atomic operations.
+    builder.max_branches = 100_000;
+    if builder.preemption_bound.is_none() {
+        builder.preemption_bound = Some(2);
+    }
+    builder
+}
+
+/// Returns a collector whose threads start counting pinnings towards their first collection at
+/// the same offset, since `loom` can't replay the random ones.
+fn collector() -> Collector {
+    let mut config = CollectorConfig::default();
+    config.random = RandomSource::new(|| 0);
+    Collector::with_config(config)
+}
+
+/// Spawns a thread of a model with a stack big enough for bags of garbage, which are moved around
+/// on the stack.
+fn spawn<F: FnOnce() + Send + 'static>(f: F) -> loom::thread::JoinHandle<()> {
+    loom::thread::Builder::new().stack_size(1 << 20).spawn(f).unwrap()
+}
+
+/// A value swapped out and deferred while another thread may still be reading it isn't dropped
+/// before the reader unpins, and is dropped once collections go on afterwards.
+#[test]
+fn pin_defer_collect() {
+    builder().check(|| spawn(|| {
+        let collector = collector();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let a = Arc::new(Atomic::new(Flagged(dropped.clone())));
+        let handle = collector.register();
+        let first = handle.pin(|scope| a.load(Acquire, scope).as_raw() as usize);
+
+        let reader = {
+            let collector = collector.clone();
+            let a = a.clone();
+            let dropped = dropped.clone();
+            spawn(move || {
+                collector.register().pin(|scope| {
+                    let p = a.load(Acquire, scope);
+                    if p.as_raw() as usize == first {
+                        assert!(!dropped.load(SeqCst), "dropped while protected");
+                    }
+                });
+            })
+        };
+
+        handle.pin(|scope| unsafe {
+            let new = Owned::new(Flagged(Arc::new(AtomicBool::new(false)))).into_ptr(scope);
+            let old = a.swap(new, AcqRel, scope);
+            scope.defer_drop(old);
+            scope.flush();
+        });
+        reader.join().unwrap();
+
+        for _ in 0..4 {
+            handle.pin(|scope| scope.flush());
+        }
+        assert!(dropped.load(SeqCst));
+
+        handle.pin(|scope| unsafe { drop(a.load(Acquire, scope).into_owned()) });
+    }).join().unwrap());
+}
@@ -0,0 +1,6 @@
+//! Builds the pointer and tag layer on its own, which makes sure that it doesn't depend on the
+//! rest of the crate, and runs its tests.
+
+#[allow(dead_code)]
+#[path = "../src/raw.rs"]
+mod raw;